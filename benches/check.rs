@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use criterion::SamplingMode;
+
+use yrc::compiler_main;
+
+mod synth;
+
+// `check` compared with building the same file through the command line,
+// into assembly and into an executable with the C compiler.
+fn bench(c: &mut Criterion) {
+    let input_file = env::temp_dir().join("yrc_bench_check.rs");
+    let output_file = env::temp_dir().join("yrc_bench_check");
+    let asm_file = output_file.with_extension("s");
+    let input = input_file.to_str().unwrap();
+
+    let mut group = c.benchmark_group("check");
+    // A build takes milliseconds for running the C compiler.
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+    for (shape, size) in synth::cases() {
+        fs::write(&input_file, shape.generate(size)).unwrap();
+        let mut bench = |name: &str, args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let id = format!("{}/{}/{}", shape.name(), size, name);
+            group.bench_function(id, |b| b.iter(|| assert_eq!(compiler_main(args.clone()), 0)));
+        };
+        bench("check", &["yrc", "check", input]);
+        bench("asm", &["yrc", "-s", input, "-o", asm_file.to_str().unwrap()]);
+        bench("build", &["yrc", input, "-o", output_file.to_str().unwrap()]);
+    }
+    group.finish();
+    for path in [&input_file, &output_file, &asm_file].iter() {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = synth::criterion();
+    targets = bench
+}
+criterion_main!(benches);
@@ -0,0 +1,77 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::prelude::*;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::SamplingMode;
+use criterion::Throughput;
+
+use yrc::intern::Interner;
+use yrc::token::tokenize;
+use yrc::token::Tokens;
+use yrc::parse::Parser;
+use yrc::assembly::AsmGenerator;
+use yrc::assembly::CodegenOptions;
+
+mod synth;
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codegen");
+    for (shape, size) in synth::cases() {
+        let source = shape.generate(size);
+        let mut interner = Interner::new();
+        let mut tokens = Tokens::new(tokenize(&source, &mut interner).unwrap());
+        let mut parser = Parser::new(&interner);
+        let ast = parser.program(&mut tokens).unwrap();
+        let literals = parser.literals();
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(BenchmarkId::new(shape.name(), size), |b| b.iter(|| {
+            AsmGenerator::new(&ast, &interner, CodegenOptions::default())
+                .gen_asm_to_string(literals)
+                .unwrap()
+        }));
+    }
+    group.finish();
+}
+
+// The assembly of a large program written to a file with and without a
+// buffer, and to memory.
+fn bench_write(c: &mut Criterion) {
+    let source = synth::Shape::Functions.generate(5000);
+    let mut interner = Interner::new();
+    let mut tokens = Tokens::new(tokenize(&source, &mut interner).unwrap());
+    let mut parser = Parser::new(&interner);
+    let ast = parser.program(&mut tokens).unwrap();
+    let literals = parser.literals();
+    let path = env::temp_dir().join("yrc_bench_codegen.s");
+
+    let mut group = c.benchmark_group("write");
+    // Each iteration takes tens of milliseconds.
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+    let mut bench = |name: &str, out: &dyn Fn() -> Box<dyn Write>| {
+        group.bench_function(name, |b| b.iter(|| {
+            let mut f = out();
+            AsmGenerator::new(&ast, &interner, CodegenOptions::default())
+                .gen_asm(&mut f, literals)
+                .unwrap();
+        }));
+    };
+    bench("file", &|| Box::new(File::create(&path).unwrap()));
+    bench("buffered", &|| Box::new(BufWriter::new(File::create(&path).unwrap())));
+    bench("memory", &|| Box::new(Vec::new()));
+    group.finish();
+    fs::remove_file(&path).unwrap();
+}
+
+criterion_group! {
+    name = benches;
+    config = synth::criterion();
+    targets = bench, bench_write
+}
+criterion_main!(benches);
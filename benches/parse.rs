@@ -0,0 +1,38 @@
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::BatchSize;
+use criterion::Criterion;
+use criterion::Throughput;
+
+use yrc::intern::Interner;
+use yrc::token::tokenize;
+use yrc::token::Tokens;
+use yrc::parse::Parser;
+
+mod synth;
+
+// Only the parser is measured. The tokens are made for each iteration
+// beforehand.
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (shape, size) in synth::cases() {
+        let source = shape.generate(size);
+        let mut interner = Interner::new();
+        let tokens = tokenize(&source, &mut interner).unwrap();
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(BenchmarkId::new(shape.name(), size), |b| b.iter_batched(
+            || Tokens::new(tokens.clone()),
+            |mut tokens| Parser::new(&interner).program(&mut tokens).unwrap(),
+            BatchSize::SmallInput,
+        ));
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = synth::criterion();
+    targets = bench
+}
+criterion_main!(benches);
@@ -0,0 +1,168 @@
+// Synthetic programs for the benchmarks. The same shape and size always
+// generate the same source code.
+
+use std::env;
+use std::time::Duration;
+
+use criterion::Criterion;
+
+#[derive(Clone, Copy)]
+pub enum Shape {
+    // One function with a long body of arithmetic statements.
+    Flat,
+    // One function with many locals, each referenced several times.
+    Locals,
+    // Many small functions calling each other.
+    Functions,
+    // Blocks nested deeply in each other.
+    Nesting,
+    // Functions with comments, long numbers and string literals.
+    Mixed,
+}
+
+// Each shape with the size measured, which takes a few hundred microseconds
+// at most to compile.
+pub const SHAPES: [(Shape, usize); 5] = [
+    (Shape::Flat, 100),
+    (Shape::Locals, 100),
+    (Shape::Functions, 10),
+    (Shape::Nesting, 100),
+    (Shape::Mixed, 10),
+];
+
+// The large programs, measured only when `YRC_BENCH_LARGE` is set since they
+// take far longer than a minute in total: 10,000 statements, 2,000 locals,
+// 500 functions and about 1MB of source code.
+pub const LARGE: [(Shape, usize); 4] = [
+    (Shape::Flat, 10000),
+    (Shape::Locals, 2000),
+    (Shape::Functions, 500),
+    (Shape::Mixed, 4500),
+];
+
+// The shapes and sizes to measure. `YRC_BENCH_SIZE` replaces the size of
+// each of them, e.g. `YRC_BENCH_SIZE=1000 cargo bench`, except for the
+// nesting, which the parser limits.
+pub fn cases() -> Vec<(Shape, usize)> {
+    if let Some(size) = env::var("YRC_BENCH_SIZE").ok().and_then(|size| size.parse().ok()) {
+        return SHAPES.iter()
+            .map(|&(shape, default)| match shape {
+                Shape::Nesting => (shape, default),
+                _ => (shape, size),
+            })
+            .collect();
+    }
+    let mut cases = SHAPES.to_vec();
+    if env::var_os("YRC_BENCH_LARGE").is_some() {
+        cases.extend_from_slice(&LARGE);
+    }
+    cases
+}
+
+impl Shape {
+    pub fn name(self) -> &'static str {
+        match self {
+            Shape::Flat => "flat",
+            Shape::Locals => "locals",
+            Shape::Functions => "functions",
+            Shape::Nesting => "nesting",
+            Shape::Mixed => "mixed",
+        }
+    }
+
+    // The size is the number of statements, locals or nested blocks, or the
+    // number of functions for the shapes made of functions.
+    pub fn generate(self, size: usize) -> String {
+        match self {
+            Shape::Flat => generate_flat(size),
+            Shape::Locals => generate_locals(size),
+            Shape::Functions => generate_functions(size),
+            Shape::Nesting => generate_nesting(size),
+            Shape::Mixed => generate_mixed(size),
+        }
+    }
+}
+
+fn generate_flat(size: usize) -> String {
+    let mut source = String::new();
+    source.push_str("fn main() -> i32 {\n    let a: i32;\n    let b: i32;\n");
+    for i in 0..size {
+        source.push_str(&format!("    a = b * {} + (a - {}) / 2;\n", i % 7, i % 13));
+    }
+    source.push_str("    return a;\n}\n");
+    source
+}
+
+fn generate_locals(size: usize) -> String {
+    let mut source = String::new();
+    source.push_str("fn main() -> i32 {\n");
+    for i in 0..size {
+        source.push_str(&format!("    let local_variable{}: i32;\n", i));
+    }
+    source.push_str("    while local_variable0 < 10 {\n");
+    for i in 0..size {
+        source.push_str(&format!("        local_variable{} = local_variable{} + local_variable{};\n",
+                                 i, i, (i + 1) % size));
+    }
+    source.push_str("    }\n    return local_variable0;\n}\n");
+    source
+}
+
+fn generate_functions(size: usize) -> String {
+    let mut source = String::new();
+    for i in 0..size {
+        source.push_str(&format!("fn func{}(arg_a: i32, arg_b: i32) -> i32 {{\n", i));
+        source.push_str("    let local_variable: i32;\n");
+        for j in 0..4 {
+            source.push_str(&format!("    local_variable = arg_a * {} + (local_variable - arg_b) / 2;\n", j));
+        }
+        if i > 0 {
+            source.push_str(&format!("    local_variable = local_variable + func{}(arg_b, arg_a);\n", i - 1));
+        }
+        source.push_str("    return local_variable;\n}\n");
+    }
+    source.push_str("fn main() {\n    func0(1, 2);\n}\n");
+    source
+}
+
+fn generate_nesting(size: usize) -> String {
+    let mut source = String::new();
+    source.push_str("fn main() -> i32 {\n    let a: i32 = 0;\n");
+    for i in 0..size {
+        if i % 2 == 0 {
+            source.push_str(&format!("if a < {} {{\n", i + 1));
+        } else {
+            source.push_str(&format!("while a < {} {{\n", i + 1));
+        }
+        source.push_str("a = a + 1;\n");
+    }
+    for _ in 0..size {
+        source.push_str("}\n");
+    }
+    source.push_str("    return a;\n}\n");
+    source
+}
+
+fn generate_mixed(size: usize) -> String {
+    let mut source = String::new();
+    for i in 0..size {
+        source.push_str(&format!("fn func{}(arg_a: i32, arg_b: &str) -> i32 {{\n\
+                                  \x20   // Comment for function {}\n\
+                                  \x20   let value{}: i32;\n\
+                                  \x20   value{} = arg_a * 12345 + {} / 7;\n\
+                                  \x20   arg_b = \"string literal \\\"{}\\\"\";\n\
+                                  \x20   if value{} >= 100 {{ return value{}; }}\n\
+                                  \x20   return arg_a;\n\
+                                  }}\n", i, i, i, i, i, i, i, i));
+    }
+    source.push_str("fn main() {\n    func0(1, \"main\");\n}\n");
+    source
+}
+
+// Shorter than the default times, so that all the benchmarks run within a
+// minute. The sample size is kept.
+pub fn criterion() -> Criterion {
+    Criterion::default()
+        .warm_up_time(Duration::from_millis(300))
+        .measurement_time(Duration::from_millis(1500))
+}
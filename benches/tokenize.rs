@@ -0,0 +1,31 @@
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+
+use yrc::intern::Interner;
+use yrc::token::tokenize;
+
+mod synth;
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize");
+    for (shape, size) in synth::cases() {
+        let source = shape.generate(size);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(BenchmarkId::new(shape.name(), size), |b| b.iter(|| {
+            let mut interner = Interner::new();
+            tokenize(black_box(&source), &mut interner).unwrap()
+        }));
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = synth::criterion();
+    targets = bench
+}
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Any source code is either compiled or reported as an error, but never
+// makes the compiler panic.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = yrc::compile_str(source);
+    }
+});
@@ -1,39 +1,198 @@
+use std::collections::HashSet;
 use std::fmt;
 use std::io;
-use std::fs::File;
 use std::io::prelude::*;
 
+use super::parse::Ast;
 use super::parse::Node;
+use super::parse::NodeId;
 use super::parse::BinaryOpKind::*;
 use super::parse::UnaryOpKind::*;
 use super::parse::Type;
+use super::parse::is_unsigned;
 use super::parse::type_size;
+use super::parse::type_align;
+use super::intern::Interner;
+use super::intern::Symbol;
+use super::report::LineTable;
+use super::insn::Insn;
+use super::insn::Operand;
+use super::insn::Width;
+use super::insn::Cond;
+use super::insn::emit_a64;
+use super::insn::emit_att;
+use super::insn::emit_intel;
+use super::insn::emit_riscv;
+use super::elf::ObjectWriter;
+use super::peephole;
+use super::ir;
+use super::ir::Allocation;
+use super::ir::BinOp;
+use super::ir::BlockId;
+use super::ir::CmpOp;
+use super::ir::DivOp;
+use super::ir::Location;
+use super::ir::Op as IrOp;
+use super::ir::Place;
+use super::ir::Term;
+use super::ir::Val;
+use super::ir::VReg;
 
 use AsmError::*;
+use Insn::*;
+use Operand::*;
 
-const ARG_REGS_64: [&str; 6] = ["rdi", "rsi", "rdx", "rcx",  "r8",  "r9"];
-const ARG_REGS_32: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
-const ARG_REGS_16: [&str; 6] = [ "di",  "si",  "dx",  "cx", "r8d", "r9d"];
-const ARG_REGS_8:  [&str; 6] = ["dil", "sil",  "dl",  "cl", "r8d", "r9d"];
+// Argument registers of System V, and of Microsoft x64.
+const ARG_REGS: [[&str; 4]; 6] = [["dil",  "di", "edi", "rdi"],
+                                  ["sil",  "si", "esi", "rsi"],
+                                  [ "dl",  "dx", "edx", "rdx"],
+                                  [ "cl",  "cx", "ecx", "rcx"],
+                                  ["r8b", "r8w", "r8d",  "r8"],
+                                  ["r9b", "r9w", "r9d",  "r9"]];
+const WIN_ARG_REGS: [[&str; 4]; 4] = [[ "cl",  "cx", "ecx", "rcx"],
+                                      [ "dl",  "dx", "edx", "rdx"],
+                                      ["r8b", "r8w", "r8d",  "r8"],
+                                      ["r9b", "r9w", "r9d",  "r9"]];
+// Registers used by the generated code which Microsoft x64 requires the
+// callee to keep.
+const WIN_SAVED_REGS: [&str; 2] = ["rdi", "rsi"];
 
 const CALC_REGS: [[&str; 4]; 2] = [["al",  "ax", "eax", "rax"],
                                    ["dil", "di", "edi", "rdi"]];
 
+// Registers for the virtual registers of the IR. rax and rdx are left for
+// scratch values, cqo and idiv.
+const IR_REGS: [[&str; 4]; 7] = [[ "dil",   "di",  "edi", "rdi"],
+                                 [ "sil",   "si",  "esi", "rsi"],
+                                 [  "cl",   "cx",  "ecx", "rcx"],
+                                 [ "r8b",  "r8w",  "r8d",  "r8"],
+                                 [ "r9b",  "r9w",  "r9d",  "r9"],
+                                 ["r10b", "r10w", "r10d", "r10"],
+                                 ["r11b", "r11w", "r11d", "r11"]];
+
+// AArch64 registers in the same layout, where the 32 bits register is used
+// for 1, 2 and 4 bytes.
+const A64_ARG_REGS: [[&str; 4]; 8] = [["w0", "w0", "w0", "x0"],
+                                      ["w1", "w1", "w1", "x1"],
+                                      ["w2", "w2", "w2", "x2"],
+                                      ["w3", "w3", "w3", "x3"],
+                                      ["w4", "w4", "w4", "x4"],
+                                      ["w5", "w5", "w5", "x5"],
+                                      ["w6", "w6", "w6", "x6"],
+                                      ["w7", "w7", "w7", "x7"]];
+
+// Registers for the virtual registers on AArch64. They are callee-saved, so
+// that nothing is saved around calls. x8, x16 and x17 are left for scratch
+// values and addresses.
+const A64_REGS: [&str; 10] = ["x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28"];
+
+// Loads and stores of 1, 2, 4 and 8 bytes, with a scaled and an unscaled
+// offset.
+const A64_LOADS: [[&str; 2]; 4] = [["ldrsb", "ldursb"], ["ldrsh", "ldursh"], ["ldrsw", "ldursw"], ["ldr", "ldur"]];
+const A64_ULOADS: [[&str; 2]; 4] = [["ldrb", "ldurb"], ["ldrh", "ldurh"], ["ldr", "ldur"], ["ldr", "ldur"]];
+const A64_STORES: [[&str; 2]; 4] = [["strb", "sturb"], ["strh", "sturh"], ["str", "stur"], ["str", "stur"]];
+
+// RISC-V registers in the same layout, where the whole register is used
+// for any size.
+const RV_ARG_REGS: [[&str; 4]; 8] = [["a0", "a0", "a0", "a0"],
+                                     ["a1", "a1", "a1", "a1"],
+                                     ["a2", "a2", "a2", "a2"],
+                                     ["a3", "a3", "a3", "a3"],
+                                     ["a4", "a4", "a4", "a4"],
+                                     ["a5", "a5", "a5", "a5"],
+                                     ["a6", "a6", "a6", "a6"],
+                                     ["a7", "a7", "a7", "a7"]];
+
+// Registers for the virtual registers on RISC-V, which are callee-saved
+// except s0 for the frame. t0, t1 and t2 are left for scratch values and
+// addresses.
+const RV_REGS: [&str; 11] = ["s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11"];
+
+// Loads and stores of 1, 2, 4 and 8 bytes.
+const RV_LOADS: [&str; 4] = ["lb", "lh", "lw", "ld"];
+const RV_ULOADS: [&str; 4] = ["lbu", "lhu", "lwu", "ld"];
+const RV_STORES: [&str; 4] = ["sb", "sh", "sw", "sd"];
+
+// Syntax of the generated assembly.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AsmSyntax {
+    #[default]
+    Intel,
+    Att,
+}
+
+// Platform which the code is generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Target {
+    #[default]
+    Linux,
+    // MinGW, which uses the Microsoft x64 calling convention.
+    WindowsGnu,
+    // Linux on AArch64, which is generated only through the IR.
+    Aarch64,
+    // Linux on RISC-V 64, which is generated only through the IR.
+    Riscv64,
+}
+
+impl Target {
+    // Whether the stack machine and the x86 IR code can be generated.
+    pub fn is_x86(self) -> bool {
+        matches!(self, Target::Linux | Target::WindowsGnu)
+    }
+}
+
+// Settings of the code generation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CodegenOptions {
+    // 0 translates the AST as is. Each pass is enabled from a level.
+    pub opt_level: u8,
+    // `.file` and `.loc` directives for the DWARF line table.
+    pub debug_info: bool,
+    // Comments showing the source of each statement and function.
+    pub comments: bool,
+    pub syntax: AsmSyntax,
+    pub target: Target,
+    // Machine code in an ELF object instead of assembly, for x86-64 Linux.
+    pub object: bool,
+    // `_start` which calls `main`, for a program without the C runtime.
+    pub freestanding: bool,
+}
+
 #[derive(Debug)]
 pub enum AsmError {
     Io(io::Error),
     Context,
     DrfErr,
+    ArgCount,
+    Encode(String),
+}
+
+impl AsmError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Io(_) => "E0201",
+            Context => "E0202",
+            DrfErr => "E0203",
+            ArgCount => "E0204",
+            Encode(_) => "E0205",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Io(e) => format!("IO error! ({})", e),
+            Context => "Lvalue is not assignable!".to_string(),
+            DrfErr => "Dereference target in not pointer type!".to_string(),
+            ArgCount => "Too many arguments for the registers!".to_string(),
+            Encode(e) => format!("Instruction cannot be encoded! ({})", e),
+        }
+    }
 }
 
 impl fmt::Display for AsmError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // TODO: Report error with the code position.
-        match self {
-            Io(e) => write!(f, "IO error! ({})", e),
-            Context => write!(f, "Lvalue is not assignable!"),
-            DrfErr => write!(f, "Dereference target in not pointer type!"),
-        }
+        write!(f, "error[{}]: {}", self.code(), self.message())
     }
 }
 
@@ -43,407 +202,1830 @@ impl From<io::Error> for AsmError {
     }
 }
 
-fn is_call(node: &Box<Node>) -> bool {
-    match &**node {
-        Node::Call { name: _, args: _, ty: _ } => true,
-        _ => false,
+// Size of the value which a node leaves on the stack.
+fn pushed_size(ast: &Ast, node: NodeId) -> usize {
+    match &ast[node] {
+        Node::Number { val: _ } | Node::Bool { bl: _ } |
+        Node::CStrLiteral { s: _, label: _ } => 8,
+        Node::StrLiteral { s: _, label: _ } => 16,
+        Node::BinaryOperator { kind: BinaryOpAsn, lhs: _, rhs: _ } => 0,
+        Node::BinaryOperator { kind: _, lhs: _, rhs: _ } => 8,
+        Node::LocalVariable { offset: _, ty: _ } |
+        Node::GlobalVariable { name: _, offset: _, ty: _ } |
+        Node::UnaryOperator { kind: _, rhs: _ } => {
+            if is_slice(ast, node) { 16 } else { 8 }
+        },
+        Node::Call { name: _, args: _, ty: Type::Slc(_) } => 16,
+        Node::Call { name: _, args: _, ty: Type::Unit } => 0,
+        Node::Call { name: _, args: _, ty: _ } => 8,
+        _ => 0,
     }
 }
 
-fn is_slice(node: &Box<Node>) -> bool {
-    if let Ok(ty) = lval_type(node) {
-        match ty {
-            Type::Slc(_) => true,
-            _ => false,
-        }
-    } else {
-        false
-    }
+fn is_slice(ast: &Ast, node: NodeId) -> bool {
+    matches!(lval_type(ast, node), Ok(Type::Slc(_)))
 }
 
-fn lval_type(node: &Box<Node>) -> Result<&Type, AsmError> {
-    match &**node {
+fn lval_type(ast: &Ast, node: NodeId) -> Result<&Type, AsmError> {
+    match &ast[node] {
         Node::LocalVariable { offset: _, ty } => Ok(ty),
         Node::GlobalVariable { name: _, offset: _, ty } => Ok(ty),
-        Node::UnaryOperator { kind, rhs } => {
-            match kind {
-                UnaryOpDrf => {
-                    if let Ok(Type::Ptr(ty)) = lval_type(rhs) {
-                        Ok(ty)
-                    } else {
-                        Err(DrfErr)
-                    }
-                }
-                _ => Err(Context),
+        Node::UnaryOperator { kind: UnaryOpDrf, rhs } => {
+            if let Ok(Type::Ptr(ty)) = lval_type(ast, *rhs) {
+                Ok(ty)
+            } else {
+                Err(DrfErr)
             }
         },
         _ => Err(Context),
     }
 }
 
-fn lval_size(node: &Box<Node>) -> Result<usize, AsmError> {
-    let ty = lval_type(node)?;
+pub(crate) fn lval_size(ast: &Ast, node: NodeId) -> Result<usize, AsmError> {
+    let ty = lval_type(ast, node)?;
     Ok(type_size(ty))
 }
 
-pub struct AsmGenerator {
+// Bytes of the values which are compared, by the wider operand as for
+// arithmetic, or the whole register. The upper bytes of a result of
+// arithmetic on smaller integers are not cleared.
+pub(crate) fn cmp_size(ast: &Ast, lhs: NodeId, rhs: NodeId) -> usize {
+    match ast.operation_type(lhs, rhs) {
+        Some(Type::Str) | None => 8,
+        Some(ty) => Some(type_size(&ty)).filter(|size| matches!(size, 1 | 2 | 4 | 8)).unwrap_or(8),
+    }
+}
+
+fn mem(size: usize, base: &'static str, disp: i32) -> Operand {
+    Mem { width: Width::from_size(size), base, disp }
+}
+
+// Label scoped to a function, e.g. `.Lmain_end0`.
+fn local_label(func: &str, kind: &str, n: usize) -> String {
+    format!(".L{}_{}{}", func, kind, n)
+}
+
+// Escape a string literal to be embedded in `.ascii` directive.
+fn escape_ascii(s: &str) -> String {
+    let mut escaped = String::new();
+    for b in s.bytes() {
+        match b {
+            b'\"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b if b.is_ascii_graphic() || b == b' ' => escaped.push(b as char),
+            b => escaped.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    escaped
+}
+
+// Locations of the virtual registers of a function in the IR on AArch64
+// and RISC-V, where they are kept in callee-saved registers.
+struct SavedFrame {
+    alloc: Allocation,
+    regs: &'static [&'static str],
+    // Register to compute a spilled value in.
+    spill: &'static str,
+    // Bytes of the local variables.
+    locals: usize,
+    // Bytes of the local variables and the saved registers, below which the
+    // spilled values are.
+    stack: usize,
+    // Registers in use, which are saved below the local variables.
+    saved: Vec<&'static str>,
+}
+
+impl SavedFrame {
+    fn new(func: &ir::Function, regs: &'static [&'static str], spill: &'static str) -> Self {
+        let alloc = ir::allocate(func, regs.len());
+        let mut used: Vec<usize> = alloc.locations.iter()
+            .filter_map(|loc| match loc {
+                Some(Location::Reg(r)) => Some(*r),
+                _ => None,
+            })
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+        let saved: Vec<&'static str> = used.iter().map(|&r| regs[r]).collect();
+        let stack = func.stack + 8 * saved.len();
+        SavedFrame { alloc, regs, spill, locals: func.stack, stack, saved }
+    }
+
+    // Bytes below the frame pointer. sp is always aligned to 16 bytes.
+    fn size(&self) -> usize {
+        let size = self.stack + 8 * self.alloc.slots;
+        size + size % 16
+    }
+
+    // Register to compute the value in.
+    fn target(&self, v: VReg) -> &'static str {
+        match self.alloc.locations[v.0 as usize] {
+            Some(Location::Reg(r)) => self.regs[r],
+            _ => self.spill,
+        }
+    }
+
+    fn slot(&self, i: usize) -> i64 {
+        -((self.stack + 8 * (i + 1)) as i64)
+    }
+
+    fn saved_slot(&self, i: usize) -> i64 {
+        -((self.locals + 8 * (i + 1)) as i64)
+    }
+}
+
+// Locations of the virtual registers of a function in the IR.
+struct IrFrame {
+    alloc: Allocation,
+    // Bytes of the local variables, below which the spilled values are.
+    stack: usize,
+}
+
+impl IrFrame {
+    fn reg(&self, v: VReg) -> Option<&'static [&'static str; 4]> {
+        match self.alloc.locations[v.0 as usize] {
+            Some(Location::Reg(r)) => Some(&IR_REGS[r]),
+            _ => None,
+        }
+    }
+
+    // Register to compute the value in. A spilled value is computed in rax.
+    fn target(&self, v: VReg) -> &'static [&'static str; 4] {
+        self.reg(v).unwrap_or(&CALC_REGS[0])
+    }
+
+    fn operand(&self, v: VReg, size: usize) -> Operand {
+        let index = size.trailing_zeros() as usize;
+        match self.alloc.locations[v.0 as usize] {
+            Some(Location::Reg(r)) => Reg(IR_REGS[r][index]),
+            Some(Location::Slot(i)) => mem(size, "rbp", -((self.stack + 8 * (i + 1)) as i32)),
+            None => unreachable!(),
+        }
+    }
+}
+
+pub struct AsmGenerator<'a> {
+    ast: &'a Ast,
+    interner: &'a Interner,
+    options: CodegenOptions,
+    // Name of the function being generated, and the number of its labels
+    // so far. The labels are scoped to the function by the name.
+    func: &'a str,
     label_count: usize,
+    // Bytes of the local variables of the function being generated, below
+    // which the registers in WIN_SAVED_REGS are saved.
+    saved_at: usize,
+    // Source file and its lines, for debug information and comments.
+    source: Option<(&'a str, LineTable<'a>)>,
+    // Code of the item being generated.
+    insns: Vec<Insn>,
+    // Machine code of the items generated so far, with `options.object`.
+    object: Option<ObjectWriter>,
+    externs: HashSet<Symbol>,
 }
 
-impl AsmGenerator {
-    fn gen_asm_call(&mut self, f: &mut File, node: &Box<Node>) -> Result<(), AsmError> {
-        match &**node {
+impl<'a> AsmGenerator<'a> {
+    fn emit(&mut self, insn: Insn) {
+        self.insns.push(insn);
+    }
+
+    fn arg_regs(&self) -> &'static [[&'static str; 4]] {
+        match self.options.target {
+            Target::Linux => &ARG_REGS,
+            Target::WindowsGnu => &WIN_ARG_REGS,
+            Target::Aarch64 => &A64_ARG_REGS,
+            Target::Riscv64 => &RV_ARG_REGS,
+        }
+    }
+
+    fn arg_reg(&self, index: usize, size: usize) -> &'static str {
+        self.arg_regs()[index][size.trailing_zeros() as usize]
+    }
+
+    fn saved_regs(&self) -> &'static [&'static str] {
+        match self.options.target {
+            Target::Linux | Target::Aarch64 | Target::Riscv64 => &[],
+            Target::WindowsGnu => &WIN_SAVED_REGS,
+        }
+    }
+
+    // Number of the argument registers taken by the arguments. A slice,
+    // which is pushed as the pointer and the length, takes two.
+    fn arg_count(&self, args: &[NodeId]) -> Result<usize, AsmError> {
+        let count = args.iter()
+            .map(|&arg| if pushed_size(self.ast, arg) == 16 { 2 } else { 1 })
+            .sum();
+        if count > self.arg_regs().len() {
+            return Err(ArgCount);
+        }
+        Ok(count)
+    }
+
+    fn gen_asm_call(&mut self, node: NodeId) -> Result<(), AsmError> {
+        let ast = self.ast;
+        match &ast[node] {
             Node::Call { name, args, ty: _ } => {
-                let mut swap = false;
-                let iter = args.iter().enumerate();
-                let mut offset = 0;
-                for (cnt, node) in iter {
-                    let index = cnt + offset;
-                    if is_slice(node) {
-                        self.gen_asm_lval(f, node)?;
-                        writeln!(f, "    pop rax")?;
-                        writeln!(f, "    mov {}, QWORD PTR [rax]", ARG_REGS_64[index])?;
-                        writeln!(f, "    mov {}, QWORD PTR [rax+8]", ARG_REGS_64[index + 1])?;
-                        offset += 1;
+                // Evaluating an argument may break the registers, so the
+                // arguments are all on the stack until the call.
+                let count = self.arg_count(args)?;
+                for &node in args {
+                    if is_slice(ast, node) {
+                        self.gen_asm_lval(node)?;
+                        self.emit(Pop("rax"));
+                        self.emit(Push(mem(8, "rax", 0)));
+                        self.emit(Push(mem(8, "rax", 8)));
                     } else {
-                        self.gen_asm_node(f, node)?;
-                        writeln!(f, "    pop rax")?;
-
-                        // Temporarily use r10 register because above gen_asm_node()
-                        // can break rdi register.
-                        if index == 0 {
-                            swap = true;
-                            writeln!(f, "    mov r10, rax")?;
-                        } else {
-                            writeln!(f, "    mov {}, rax", ARG_REGS_64[index])?;
-                        }
+                        self.gen_asm_node(node)?;
                     }
                 }
-
-                if swap {
-                    writeln!(f, "    mov rdi, r10")?;
+                let regs = self.arg_regs();
+                for index in (0..count).rev() {
+                    self.emit(Pop(regs[index][3]));
                 }
-                writeln!(f, "    call {}@PLT", name)?;
+                self.gen_asm_call_insn(*name);
                 Ok(())
             },
             _ => unreachable!(),
         }
     }
 
-    fn gen_asm_lval(&mut self, f: &mut File, node: &Box<Node>) -> Result<(), AsmError> {
-        match &**node {
+    fn gen_asm_call_insn(&mut self, name: Symbol) {
+        let label = self.interner.resolve(name);
+        match self.options.target {
+            Target::Linux => {
+                // A variadic function such as `printf` takes the number of
+                // vector registers used in al, which is none. The others
+                // ignore it.
+                if self.externs.contains(&name) {
+                    self.emit(Mov(Reg("eax"), Imm(0)));
+                }
+                self.emit(Call(format!("{}@PLT", label)));
+            },
+            Target::WindowsGnu => {
+                // The callee may use 32 bytes above the return address as
+                // it likes.
+                self.emit(Sub(Reg("rsp"), Imm(32)));
+                self.emit(Call(label.to_string()));
+                self.emit(Add(Reg("rsp"), Imm(32)));
+            },
+            Target::Aarch64 | Target::Riscv64 => unreachable!(),
+        }
+    }
+
+    fn gen_asm_lval(&mut self, node: NodeId) -> Result<(), AsmError> {
+        let ast = self.ast;
+        match &ast[node] {
             Node::LocalVariable { offset, ty: _ } => {
-                writeln!(f, "    mov rax, rbp")?;
-                writeln!(f, "    sub rax, {}", offset)?;
-                writeln!(f, "    push rax")?;
+                self.emit(Mov(Reg("rax"), Reg("rbp")));
+                self.emit(Sub(Reg("rax"), Imm(*offset as u64)));
+                self.emit(Push(Reg("rax")));
                 Ok(())
             },
             Node::GlobalVariable { name, offset, ty: _ } => {
-                writeln!(f, "    lea rax, QWORD PTR {}[rip+{}]", name, offset)?;
-                writeln!(f, "    push rax")?;
+                let label = self.interner.resolve(*name).to_string();
+                self.emit(Lea("rax", Rip { width: Width::Qword, label, offset: Some(*offset) }));
+                self.emit(Push(Reg("rax")));
                 Ok(())
             },
-            Node::UnaryOperator { kind, rhs } => {
+            Node::UnaryOperator { kind: UnaryOpDrf, rhs } => {
+                self.gen_asm_node(*rhs)?;
+                Ok(())
+            },
+            _ => Err(Context),
+        }
+    }
+
+    // Divides rax by the divisor in the integers of 4 or 8 bytes, into the
+    // quotient in rax and the remainder in rdx.
+    fn gen_div(&mut self, size: usize, unsigned: bool, divisor: Operand) {
+        match (size, unsigned) {
+            (_, true) => {
+                self.emit(Mov(Reg("edx"), Imm(0)));
+                self.emit(Div(divisor));
+            },
+            (8, false) => {
+                self.emit(Cqo);
+                self.emit(Idiv(divisor));
+            },
+            (_, false) => {
+                self.emit(Cdq);
+                self.emit(Idiv(divisor));
+            },
+        }
+    }
+
+    // Sign or zero extends the lower `size` bytes of the registers to 64
+    // bits, as a load of the type does.
+    fn gen_extend(&mut self, regs: &[&'static str; 4], size: usize, unsigned: bool) {
+        let index = size.trailing_zeros() as usize;
+        match (size, unsigned) {
+            (8, _) => {},
+            (4, true) => self.emit(Mov(Reg(regs[2]), Reg(regs[2]))),
+            (_, true) => self.emit(Movzx(regs[2], Reg(regs[index]))),
+            (_, false) => self.emit(Movsx(regs[3], Reg(regs[index]))),
+        }
+    }
+
+    // Loads the variable whose address is on the stack.
+    fn gen_asm_load(&mut self, node: NodeId) -> Result<(), AsmError> {
+        let ast = self.ast;
+        self.emit(Pop("rax"));
+        self.emit(Blank);
+        if is_slice(ast, node) {
+            self.emit(Mov(Reg("rdi"), mem(8, "rax", 0)));
+            self.emit(Mov(Reg("rax"), mem(8, "rax", 8)));
+            self.emit(Push(Reg("rdi")));
+            self.emit(Push(Reg("rax")));
+        } else {
+            // Signed integers are sign extended to 64 bits, and unsigned
+            // ones are zero extended.
+            let unsigned = lval_type(ast, node).is_ok_and(is_unsigned);
+            match lval_size(ast, node)? {
+                size @ (1 | 2) if unsigned => self.emit(Movzx("eax", mem(size, "rax", 0))),
+                4 if unsigned => self.emit(Mov(Reg("eax"), mem(4, "rax", 0))),
+                size @ (1 | 2 | 4) => self.emit(Movsx("rax", mem(size, "rax", 0))),
+                8 => self.emit(Mov(Reg("rax"), mem(8, "rax", 0))),
+                _ => unreachable!(),
+            }
+            self.emit(Push(Reg("rax")));
+        }
+        Ok(())
+    }
+
+
+    // Directives and the label at the beginning of a function.
+    fn gen_asm_label(&mut self, node: NodeId, name: Symbol, frame: usize) {
+        let name = self.interner.resolve(name);
+        self.emit(Directive(format!(".global {}", name)));
+        self.gen_asm_type(name, "function");
+        if self.options.target == Target::WindowsGnu {
+            self.emit(Directive(format!(".def {}; .scl 2; .type 32; .endef", name)));
+        }
+        let source = self.source_line(node);
+        if let (Some((_, _, text)), true) = (source, self.options.comments) {
+            self.emit(Comment(format!("{} (frame: {} bytes)", text, frame)));
+        }
+        self.emit(Label(name.to_string()));
+        if let (Some((line, column, _)), true) = (source, self.options.debug_info) {
+            self.emit(Loc(line, column));
+        }
+    }
+
+    // Symbol type for ELF, which tools like `nm` and debuggers show. The
+    // size follows the function or the variable.
+    fn gen_asm_type(&mut self, name: &str, kind: &str) {
+        let prefix = match self.options.target {
+            Target::WindowsGnu => return,
+            // `@` begins a comment on AArch64.
+            Target::Aarch64 => '%',
+            Target::Linux | Target::Riscv64 => '@',
+        };
+        self.emit(Directive(format!(".type {}, {}{}", name, prefix, kind)));
+    }
+
+    fn gen_asm_size(&mut self, name: &str, size: &str) {
+        if self.options.target != Target::WindowsGnu {
+            self.emit(Directive(format!(".size {}, {}", name, size)));
+        }
+    }
+
+    // Entry point of a program without the C runtime. The stack is aligned
+    // to 16 bytes here, and `main` is called as usual. Its value is the exit
+    // status, which the `exit` system call takes in edi.
+    fn gen_asm_start(&mut self) {
+        self.emit(Directive(".global _start".to_string()));
+        self.gen_asm_type("_start", "function");
+        self.emit(Label("_start".to_string()));
+        self.emit(Call("main".to_string()));
+        self.emit(Mov(Reg("edi"), Reg("eax")));
+        self.emit(Mov(Reg("eax"), Imm(60)));
+        self.emit(Syscall);
+        self.gen_asm_size("_start", ".-_start");
+    }
+
+    // Beginning of a function, which has `locals` bytes of local variables
+    // and `extra` bytes below the saved registers.
+    fn gen_asm_prologue(&mut self, node: NodeId, name: Symbol, locals: usize, extra: usize) {
+        let frame = locals + 8 * self.saved_regs().len() + extra;
+        self.gen_asm_label(node, name, frame);
+
+        self.emit(Push(Reg("rbp")));
+        self.emit(Mov(Reg("rbp"), Reg("rsp")));
+        if frame > 0 || self.options.opt_level == 0 {
+            self.emit(Sub(Reg("rsp"), Imm(frame as u64)));
+        }
+        self.saved_at = locals;
+        for (i, reg) in self.saved_regs().iter().enumerate() {
+            let disp = -((locals + 8 * (i + 1)) as i32);
+            self.emit(Mov(mem(8, "rbp", disp), Reg(reg)));
+        }
+    }
+
+    fn gen_asm_epilogue(&mut self) {
+        for (i, reg) in self.saved_regs().iter().enumerate() {
+            let disp = -((self.saved_at + 8 * (i + 1)) as i32);
+            self.emit(Mov(Reg(reg), mem(8, "rbp", disp)));
+        }
+        self.emit(Mov(Reg("rsp"), Reg("rbp")));
+        self.emit(Pop("rbp"));
+        self.emit(Ret);
+    }
+
+    // Memory operand of a place. A spilled base pointer is loaded into rdx.
+    fn ir_place(&mut self, frame: &IrFrame, place: Place, size: usize) -> Operand {
+        match place {
+            Place::Frame(disp) => mem(size, "rbp", disp),
+            Place::Global(name, offset) => {
+                let label = self.interner.resolve(name).to_string();
+                Rip { width: Width::from_size(size), label, offset: Some(offset) }
+            },
+            Place::Ptr(base, offset) => {
+                let base = match frame.reg(base) {
+                    Some(r) => r[3],
+                    None => {
+                        self.emit(Mov(Reg("rdx"), frame.operand(base, 8)));
+                        "rdx"
+                    },
+                };
+                mem(size, base, offset as i32)
+            },
+        }
+    }
+
+    // Stores the value computed in `reg`, if it is not the register of `dst`.
+    fn ir_result(&mut self, frame: &IrFrame, dst: VReg, reg: &'static str) {
+        let operand = frame.operand(dst, 8);
+        if operand != Reg(reg) {
+            self.emit(Mov(operand, Reg(reg)));
+        }
+    }
+
+    // `pos` is the position of the op counted by the register allocation.
+    fn gen_ir_op(&mut self, frame: &IrFrame, op: &IrOp, pos: usize) {
+        match op {
+            IrOp::Const(d, val) => {
+                let t = frame.target(*d);
+                self.emit(Mov(Reg(t[3]), Imm(*val)));
+                self.ir_result(frame, *d, t[3]);
+            },
+            IrOp::Str(d, label) => {
+                let t = frame.target(*d);
+                let label = format!(".LC{}", label);
+                self.emit(Lea(t[3], Rip { width: Width::Qword, label, offset: None }));
+                self.ir_result(frame, *d, t[3]);
+            },
+            IrOp::Addr(d, place) => {
+                let t = frame.target(*d);
+                let src = self.ir_place(frame, *place, 8);
+                self.emit(Lea(t[3], src));
+                self.ir_result(frame, *d, t[3]);
+            },
+            IrOp::Load(d, place, size, signed) => {
+                let t = frame.target(*d);
+                let src = self.ir_place(frame, *place, *size);
+                match size {
+                    1 | 2 if !*signed => self.emit(Movzx(t[2], src)),
+                    4 if !*signed => self.emit(Mov(Reg(t[2]), src)),
+                    1 | 2 | 4 => self.emit(Movsx(t[3], src)),
+                    _ => self.emit(Mov(Reg(t[3]), src)),
+                }
+                self.ir_result(frame, *d, t[3]);
+            },
+            IrOp::Store(place, v, size) => {
+                let index = size.trailing_zeros() as usize;
+                let src = match frame.reg(*v) {
+                    Some(r) => r[index],
+                    None => {
+                        self.emit(Mov(Reg("rax"), frame.operand(*v, 8)));
+                        CALC_REGS[0][index]
+                    },
+                };
+                let dst = self.ir_place(frame, *place, *size);
+                self.emit(Mov(dst, Reg(src)));
+            },
+            IrOp::StoreArg(place, index, size) => {
+                let dst = self.ir_place(frame, *place, *size);
+                self.emit(Mov(dst, Reg(self.arg_reg(*index, *size))));
+            },
+            IrOp::Div(kind, size, d, a, b) => {
+                self.emit(Mov(Reg("rax"), frame.operand(*a, 8)));
+                match b {
+                    Val::Reg(b) => self.gen_div(*size, kind.is_unsigned(), frame.operand(*b, *size)),
+                    // The IR passes never make the divisor an immediate.
+                    Val::Imm(_) => unreachable!(),
+                }
+                let (result, result32) = if matches!(kind, DivOp::Div | DivOp::Udiv) { ("rax", "eax") } else { ("rdx", "edx") };
+                if *size == 4 && !kind.is_unsigned() {
+                    self.emit(Movsx(result, Reg(result32)));
+                }
+                self.ir_result(frame, *d, result);
+            },
+            IrOp::Bin(kind, d, a, b) => {
+                // The operands of a commutative operation are swapped when
+                // the result goes to the register of the rhs.
+                let (a, b) = match (kind, b) {
+                    (BinOp::Add | BinOp::Mul | BinOp::And, Val::Reg(r)) if frame.reg(*d).is_some() && frame.reg(*d) == frame.reg(*r) => {
+                        (r, Val::Reg(*a))
+                    },
+                    _ => (a, *b),
+                };
+                let src = match b {
+                    Val::Reg(b) => frame.operand(b, 8),
+                    // Only sign-extended 32 bits immediates can be operands.
+                    Val::Imm(val) if val > i32::MAX as u64 => {
+                        self.emit(Mov(Reg("rdx"), Imm(val)));
+                        Reg("rdx")
+                    },
+                    Val::Imm(val) => Imm(val),
+                };
+                // The result cannot go to the register of the rhs before it
+                // is used.
+                let t = match frame.reg(*d) {
+                    Some(r) if src != Reg(r[3]) => r[3],
+                    _ => "rax",
+                };
+                let lhs = frame.operand(*a, 8);
+                if lhs != Reg(t) {
+                    self.emit(Mov(Reg(t), lhs));
+                }
+                let dst = Reg(t);
                 match kind {
-                    UnaryOpDrf => {
-                        self.gen_asm_node(f, rhs)?;
-                        Ok(())
+                    BinOp::Add => self.emit(Add(dst, src)),
+                    BinOp::Sub => self.emit(Sub(dst, src)),
+                    BinOp::Mul => self.emit(Imul(dst, src)),
+                    BinOp::And => self.emit(And(dst, src)),
+                    BinOp::Shl => self.emit(Sal(dst, src)),
+                    BinOp::Sar => self.emit(Sar(dst, src)),
+                    BinOp::Shr => self.emit(Shr(dst, src)),
+                }
+                self.ir_result(frame, *d, t);
+            },
+            IrOp::Cmp(kind, size, d, a, b) => {
+                let index = size.trailing_zeros() as usize;
+                let rhs = match b {
+                    Val::Reg(b) => frame.operand(*b, *size),
+                    Val::Imm(val) => Imm(*val),
+                };
+                self.emit(Mov(Reg("rax"), frame.operand(*a, 8)));
+                self.emit(Cmp(Reg(CALC_REGS[0][index]), rhs));
+                let cond = match kind {
+                    CmpOp::Eq => Cond::E,
+                    CmpOp::Ne => Cond::Ne,
+                    CmpOp::Lt => Cond::L,
+                    CmpOp::Le => Cond::Le,
+                    CmpOp::Gt => Cond::G,
+                    CmpOp::Ge => Cond::Ge,
+                    CmpOp::Ult => Cond::B,
+                    CmpOp::Ule => Cond::Be,
+                    CmpOp::Ugt => Cond::A,
+                    CmpOp::Uge => Cond::Ae,
+                };
+                self.emit(Set(cond, "al"));
+                let t = frame.target(*d);
+                self.emit(Movzb(t[3], "al"));
+                self.ir_result(frame, *d, t[3]);
+            },
+            IrOp::Call(dsts, name, args) => {
+                // All the registers are broken by the call. rsp is kept
+                // aligned to 16 bytes.
+                let live = frame.alloc.live_across(pos);
+                for &r in live.iter() {
+                    self.emit(Push(Reg(IR_REGS[r][3])));
+                }
+                let pad = live.len() % 2 == 1;
+                if pad {
+                    self.emit(Sub(Reg("rsp"), Imm(8)));
+                }
+                // The arguments may be in the argument registers of each
+                // other, so they are moved through the stack.
+                for &arg in args.iter() {
+                    self.emit(Push(frame.operand(arg, 8)));
+                }
+                for index in (0..args.len()).rev() {
+                    self.emit(Pop(self.arg_regs()[index][3]));
+                }
+                self.gen_asm_call_insn(*name);
+                match dsts.as_slice() {
+                    [val] => self.ir_result(frame, *val, "rax"),
+                    [ptr, len] => {
+                        self.ir_result(frame, *ptr, "rax");
+                        self.ir_result(frame, *len, "rdx");
+                    },
+                    _ => {},
+                }
+                if pad {
+                    self.emit(Add(Reg("rsp"), Imm(8)));
+                }
+                for &r in live.iter().rev() {
+                    self.emit(Pop(IR_REGS[r][3]));
+                }
+            },
+            IrOp::Line(node) => self.gen_asm_line(*node),
+        }
+    }
+
+    // `next` is the block placed right after.
+    fn gen_ir_term(&mut self, frame: &IrFrame, term: &Term, next: BlockId) {
+        let func = self.func;
+        let label = |b: &BlockId| local_label(func, "block", b.0 as usize);
+        match term {
+            Term::Jmp(b) => {
+                if *b != next {
+                    self.emit(Jmp(label(b)));
+                }
+            },
+            Term::Br(cond, t, e) => {
+                self.emit(Cmp(frame.operand(*cond, 8), Imm(0)));
+                if *t == next {
+                    self.emit(Jcc(Cond::E, label(e)));
+                } else {
+                    self.emit(Jcc(Cond::Ne, label(t)));
+                    if *e != next {
+                        self.emit(Jmp(label(e)));
                     }
-                    _ => Err(Context),
                 }
             },
-            _ => Err(Context),
+            Term::Ret(vals) => {
+                match vals.as_slice() {
+                    [val] => self.emit(Mov(Reg("rax"), frame.operand(*val, 8))),
+                    // As at -O0, the pointer in rax and the length in rdx.
+                    [ptr, len] => {
+                        self.emit(Mov(Reg("rax"), frame.operand(*ptr, 8)));
+                        self.emit(Mov(Reg("rdx"), frame.operand(*len, 8)));
+                    },
+                    _ => {},
+                }
+                self.gen_asm_epilogue();
+            },
         }
     }
 
-    fn gen_asm_node(&mut self, f: &mut File, node: &Box<Node>) -> Result<(), AsmError> {
-        match &**node {
-            Node::Number { val } => {
-                writeln!(f, "    push {}", val)?;
+    // Generates a function through the IR, where the optimizations are
+    // done at -O1 and above.
+    fn gen_ir_function(&mut self, node: NodeId) -> Result<(), AsmError> {
+        let mut func = ir::lower(self.ast, self.interner, node)?;
+        if self.options.opt_level >= 1 {
+            ir::optimize(&mut func);
+        }
+        let regs = self.arg_regs().len();
+        for op in func.blocks.iter().flat_map(|block| block.ops.iter()) {
+            match op {
+                IrOp::Call(_, _, args) if args.len() > regs => return Err(ArgCount),
+                IrOp::StoreArg(_, index, _) if *index >= regs => return Err(ArgCount),
+                _ => {},
+            }
+        }
+        match self.options.target {
+            Target::Aarch64 => {
+                self.gen_a64_function(node, &func);
+                return Ok(());
             },
-            Node::Bool { bl } => {
-                if *bl {
-                    writeln!(f, "    push 1")?;
+            Target::Riscv64 => {
+                self.gen_rv_function(node, &func);
+                return Ok(());
+            },
+            _ => {},
+        }
+        let alloc = ir::allocate(&func, IR_REGS.len());
+        // The local variables and the saved registers are already aligned
+        // to 16 bytes.
+        let saved = 8 * self.saved_regs().len();
+        let extra = 8 * (alloc.slots + alloc.slots % 2);
+        let frame = IrFrame { alloc, stack: func.stack + saved };
+
+        self.gen_asm_prologue(node, func.name, func.stack, extra);
+
+        let targets: HashSet<BlockId> = func.blocks.iter()
+            .flat_map(|block| block.term.targets())
+            .collect();
+        let mut pos = 0;
+        for (i, block) in func.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            if targets.contains(&id) {
+                self.emit(Label(local_label(self.func, "block", i)));
+            }
+            for op in block.ops.iter() {
+                self.gen_ir_op(&frame, op, pos);
+                pos += 1;
+            }
+            self.gen_ir_term(&frame, &block.term, BlockId(id.0 + 1));
+            pos += 1;
+        }
+
+        self.emit(Blank);
+        Ok(())
+    }
+
+    fn a64(&mut self, mnemonic: &'static str, operands: &[&str]) {
+        self.emit(Text(mnemonic, operands.iter().map(|op| op.to_string()).collect()));
+    }
+
+    // Moves a 64 bits immediate into the register, 16 bits at a time.
+    fn a64_imm(&mut self, reg: &str, val: u64) {
+        let signed = val as i64;
+        if val <= 0xffff || (-0x10000..0).contains(&signed) {
+            self.a64("mov", &[reg, &format!("#{}", signed)]);
+            return;
+        }
+        self.a64("movz", &[reg, &format!("#{}", val & 0xffff)]);
+        for shift in [16, 32, 48].iter() {
+            let part = (val >> shift) & 0xffff;
+            if part != 0 {
+                self.a64("movk", &[reg, &format!("#{}", part), &format!("lsl #{}", shift)]);
+            }
+        }
+    }
+
+    // `dst = base + disp` for any displacement.
+    fn a64_add(&mut self, dst: &str, base: &str, disp: i64) {
+        let (mnemonic, abs) = if disp < 0 { ("sub", -disp) } else { ("add", disp) };
+        if abs <= 4095 {
+            self.a64(mnemonic, &[dst, base, &format!("#{}", abs)]);
+        } else {
+            self.a64_imm("x17", abs as u64);
+            self.a64(mnemonic, &[dst, base, "x17"]);
+        }
+    }
+
+    // Memory operand of `base + disp` accessing `size` bytes, and whether
+    // the offset is unscaled. Otherwise the address is computed in x8.
+    fn a64_mem(&mut self, base: &str, disp: i64, size: usize) -> (String, bool) {
+        let scaled = disp >= 0 && disp % size as i64 == 0 && disp / (size as i64) < 4096;
+        if disp == 0 {
+            (format!("[{}]", base), false)
+        } else if scaled {
+            (format!("[{}, #{}]", base, disp), false)
+        } else if (-256..256).contains(&disp) {
+            (format!("[{}, #{}]", base, disp), true)
+        } else {
+            self.a64_add("x8", base, disp);
+            ("[x8]".to_string(), false)
+        }
+    }
+
+    // Memory operand of a place. A spilled base pointer is loaded into x8.
+    fn a64_place(&mut self, frame: &SavedFrame, place: Place, size: usize) -> (String, bool) {
+        match place {
+            Place::Frame(disp) => self.a64_mem("x29", disp as i64, size),
+            Place::Global(name, offset) => {
+                let name = self.interner.resolve(name);
+                let label = if offset == 0 { name.to_string() } else { format!("{}+{}", name, offset) };
+                self.a64("adrp", &["x8", &label]);
+                self.a64("add", &["x8", "x8", &format!(":lo12:{}", label)]);
+                ("[x8]".to_string(), false)
+            },
+            Place::Ptr(base, offset) => {
+                let base = self.a64_value(frame, base, "x8");
+                self.a64_mem(base, offset as i64, size)
+            },
+        }
+    }
+
+    fn a64_load(&mut self, dst: &str, mem: (String, bool), size: usize, signed: bool) {
+        let index = size.trailing_zeros() as usize;
+        let loads = if signed { A64_LOADS } else { A64_ULOADS };
+        self.a64(loads[index][mem.1 as usize], &[dst, &mem.0]);
+    }
+
+    fn a64_store(&mut self, src: &str, mem: (String, bool), size: usize) {
+        let index = size.trailing_zeros() as usize;
+        self.a64(A64_STORES[index][mem.1 as usize], &[src, &mem.0]);
+    }
+
+    // 64 bits register holding the value. A spilled value is loaded into
+    // `scratch`.
+    fn a64_value(&mut self, frame: &SavedFrame, v: VReg, scratch: &'static str) -> &'static str {
+        match frame.alloc.locations[v.0 as usize] {
+            Some(Location::Reg(r)) => frame.regs[r],
+            Some(Location::Slot(i)) => {
+                let mem = self.a64_mem("x29", frame.slot(i), 8);
+                self.a64_load(scratch, mem, 8, true);
+                scratch
+            },
+            None => unreachable!(),
+        }
+    }
+
+    // Register or immediate operand of an arithmetic instruction, which
+    // takes 12 bits immediates only if `imm12` is true.
+    fn a64_operand(&mut self, frame: &SavedFrame, val: Val, imm12: bool) -> String {
+        match val {
+            Val::Reg(v) => self.a64_value(frame, v, "x17").to_string(),
+            Val::Imm(imm) if imm12 && imm <= 4095 => format!("#{}", imm),
+            Val::Imm(imm) => {
+                self.a64_imm("x17", imm);
+                "x17".to_string()
+            },
+        }
+    }
+
+    // Stores the value computed in `reg`, if it is not the register of `dst`.
+    fn a64_result(&mut self, frame: &SavedFrame, dst: VReg, reg: &str) {
+        match frame.alloc.locations[dst.0 as usize] {
+            Some(Location::Reg(r)) if frame.regs[r] == reg => {},
+            Some(Location::Reg(r)) => self.a64("mov", &[frame.regs[r], reg]),
+            Some(Location::Slot(i)) => {
+                let mem = self.a64_mem("x29", frame.slot(i), 8);
+                self.a64_store(reg, mem, 8);
+            },
+            None => unreachable!(),
+        }
+    }
+
+    fn gen_a64_op(&mut self, frame: &SavedFrame, op: &IrOp) {
+        match op {
+            IrOp::Const(d, val) => {
+                let t = frame.target(*d);
+                self.a64_imm(t, *val);
+                self.a64_result(frame, *d, t);
+            },
+            IrOp::Str(d, label) => {
+                let t = frame.target(*d);
+                let label = format!(".LC{}", label);
+                self.a64("adrp", &[t, &label]);
+                self.a64("add", &[t, t, &format!(":lo12:{}", label)]);
+                self.a64_result(frame, *d, t);
+            },
+            IrOp::Addr(d, place) => {
+                let t = frame.target(*d);
+                match place {
+                    Place::Frame(disp) => self.a64_add(t, "x29", *disp as i64),
+                    Place::Global(_, _) => {
+                        self.a64_place(frame, *place, 1);
+                        self.a64("mov", &[t, "x8"]);
+                    },
+                    Place::Ptr(base, offset) => {
+                        let base = self.a64_value(frame, *base, "x8");
+                        self.a64_add(t, base, *offset as i64);
+                    },
+                }
+                self.a64_result(frame, *d, t);
+            },
+            IrOp::Load(d, place, size, signed) => {
+                // Signed integers are sign extended into the 64 bits
+                // register, and the unsigned ones are loaded into the 32
+                // bits one, which clears the upper 32 bits.
+                let t = frame.target(*d);
+                let mem = self.a64_place(frame, *place, *size);
+                let dst = if *size == 8 || *signed { t.to_string() } else { format!("w{}", &t[1..]) };
+                self.a64_load(&dst, mem, *size, *signed);
+                self.a64_result(frame, *d, t);
+            },
+            IrOp::Store(place, v, size) => {
+                let src = self.a64_value(frame, *v, "x16");
+                let src = if *size == 8 { src.to_string() } else { format!("w{}", &src[1..]) };
+                let mem = self.a64_place(frame, *place, *size);
+                self.a64_store(&src, mem, *size);
+            },
+            IrOp::StoreArg(place, index, size) => {
+                let mem = self.a64_place(frame, *place, *size);
+                self.a64_store(self.arg_reg(*index, *size), mem, *size);
+            },
+            IrOp::Div(kind, size, d, a, b) => {
+                let a = self.a64_value(frame, *a, "x16");
+                let b = self.a64_operand(frame, *b, false);
+                let t = frame.target(*d);
+                // 4 bytes are divided in the 32 bits registers, which clears
+                // the upper 32 bits, and a signed result is sign extended.
+                let w = |reg: &str| if *size == 4 { format!("w{}", &reg[1..]) } else { reg.to_string() };
+                let div = if kind.is_unsigned() { "udiv" } else { "sdiv" };
+                if matches!(kind, DivOp::Div | DivOp::Udiv) {
+                    self.a64(div, &[&w(t), &w(a), &w(&b)]);
                 } else {
-                    writeln!(f, "    push 0")?;
+                    self.a64(div, &[&w("x8"), &w(a), &w(&b)]);
+                    self.a64("msub", &[&w(t), &w("x8"), &w(&b), &w(a)]);
+                }
+                if *size == 4 && !kind.is_unsigned() {
+                    self.a64("sxtw", &[t, &w(t)]);
+                }
+                self.a64_result(frame, *d, t);
+            },
+            IrOp::Bin(kind, d, a, b) => {
+                let a = self.a64_value(frame, *a, "x16");
+                let imm12 = matches!(kind, BinOp::Add | BinOp::Sub | BinOp::Shl | BinOp::Sar | BinOp::Shr);
+                let b = self.a64_operand(frame, *b, imm12);
+                let t = frame.target(*d);
+                let mnemonic = match kind {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    BinOp::And => "and",
+                    BinOp::Shl => "lsl",
+                    BinOp::Sar => "asr",
+                    BinOp::Shr => "lsr",
+                };
+                self.a64(mnemonic, &[t, a, &b]);
+                self.a64_result(frame, *d, t);
+            },
+            IrOp::Cmp(kind, size, d, a, b) => {
+                let a = self.a64_value(frame, *a, "x16");
+                let b = self.a64_operand(frame, *b, *size == 8);
+                // The lower bytes are sign or zero extended to compare.
+                let w = |reg: &str| format!("w{}", &reg[1..]);
+                match size {
+                    1 | 2 => {
+                        let extend = match (size, kind.is_unsigned()) {
+                            (1, false) => "sxtb",
+                            (_, false) => "sxth",
+                            (1, true) => "uxtb",
+                            (_, true) => "uxth",
+                        };
+                        self.a64(extend, &["w16", &w(a)]);
+                        self.a64(extend, &["w17", &w(&b)]);
+                        self.a64("cmp", &["w16", "w17"]);
+                    },
+                    4 => self.a64("cmp", &[&w(a), &w(&b)]),
+                    _ => self.a64("cmp", &[a, &b]),
+                }
+                let cond = match kind {
+                    CmpOp::Eq => "eq",
+                    CmpOp::Ne => "ne",
+                    CmpOp::Lt => "lt",
+                    CmpOp::Le => "le",
+                    CmpOp::Gt => "gt",
+                    CmpOp::Ge => "ge",
+                    CmpOp::Ult => "lo",
+                    CmpOp::Ule => "ls",
+                    CmpOp::Ugt => "hi",
+                    CmpOp::Uge => "hs",
+                };
+                let t = frame.target(*d);
+                self.a64("cset", &[t, cond]);
+                self.a64_result(frame, *d, t);
+            },
+            IrOp::Call(dsts, name, args) => {
+                // The virtual registers are not in the argument registers.
+                for (i, &arg) in args.iter().enumerate() {
+                    let src = self.a64_value(frame, arg, "x16");
+                    self.a64("mov", &[A64_ARG_REGS[i][3], src]);
+                }
+                self.a64("bl", &[self.interner.resolve(*name)]);
+                match dsts.as_slice() {
+                    [val] => self.a64_result(frame, *val, "x0"),
+                    [ptr, len] => {
+                        self.a64_result(frame, *ptr, "x0");
+                        self.a64_result(frame, *len, "x1");
+                    },
+                    _ => {},
+                }
+            },
+            IrOp::Line(node) => self.gen_asm_line(*node),
+        }
+    }
+
+    fn gen_a64_epilogue(&mut self, frame: &SavedFrame) {
+        for (i, reg) in frame.saved.iter().enumerate() {
+            let mem = self.a64_mem("x29", frame.saved_slot(i), 8);
+            self.a64_load(reg, mem, 8, true);
+        }
+        self.a64("mov", &["sp", "x29"]);
+        self.a64("ldp", &["x29", "x30", "[sp]", "#16"]);
+        self.a64("ret", &[]);
+    }
+
+    // `next` is the block placed right after.
+    fn gen_a64_term(&mut self, frame: &SavedFrame, term: &Term, next: BlockId) {
+        let func = self.func;
+        let label = |b: &BlockId| local_label(func, "block", b.0 as usize);
+        match term {
+            Term::Jmp(b) => {
+                if *b != next {
+                    self.a64("b", &[&label(b)]);
+                }
+            },
+            Term::Br(cond, t, e) => {
+                let cond = self.a64_value(frame, *cond, "x16");
+                if *t == next {
+                    self.a64("cbz", &[cond, &label(e)]);
+                } else {
+                    self.a64("cbnz", &[cond, &label(t)]);
+                    if *e != next {
+                        self.a64("b", &[&label(e)]);
+                    }
+                }
+            },
+            Term::Ret(vals) => {
+                // A slice is returned like a struct of two words, the
+                // pointer in x0 and the length in x1, as on x86-64.
+                for (i, &val) in vals.iter().enumerate() {
+                    let src = self.a64_value(frame, val, "x16");
+                    self.a64("mov", &[A64_ARG_REGS[i][3], src]);
+                }
+                self.gen_a64_epilogue(frame);
+            },
+        }
+    }
+
+    // Generates a function of the IR for AArch64. The frame is x29, and
+    // the callee-saved registers in use are saved below the local
+    // variables.
+    fn gen_a64_function(&mut self, node: NodeId, func: &ir::Function) {
+        let frame = SavedFrame::new(func, &A64_REGS, "x16");
+        let size = frame.size();
+
+        self.gen_asm_label(node, func.name, size);
+        self.a64("stp", &["x29", "x30", "[sp, #-16]!"]);
+        self.a64("mov", &["x29", "sp"]);
+        if size > 0 {
+            self.a64_add("sp", "sp", -(size as i64));
+        }
+        for (i, reg) in frame.saved.iter().enumerate() {
+            let mem = self.a64_mem("x29", frame.saved_slot(i), 8);
+            self.a64_store(reg, mem, 8);
+        }
+
+        let targets: HashSet<BlockId> = func.blocks.iter()
+            .flat_map(|block| block.term.targets())
+            .collect();
+        for (i, block) in func.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            if targets.contains(&id) {
+                self.emit(Label(local_label(self.func, "block", i)));
+            }
+            for op in block.ops.iter() {
+                self.gen_a64_op(&frame, op);
+            }
+            self.gen_a64_term(&frame, &block.term, BlockId(id.0 + 1));
+        }
+
+        self.emit(Blank);
+    }
+
+    fn rv(&mut self, mnemonic: &'static str, operands: &[&str]) {
+        self.emit(Text(mnemonic, operands.iter().map(|op| op.to_string()).collect()));
+    }
+
+    // `dst = base + disp` for any displacement.
+    fn rv_add(&mut self, dst: &str, base: &str, disp: i64) {
+        if (-2048..2048).contains(&disp) {
+            self.rv("addi", &[dst, base, &disp.to_string()]);
+        } else {
+            self.rv("li", &["t1", &disp.to_string()]);
+            self.rv("add", &[dst, base, "t1"]);
+        }
+    }
+
+    // Memory operand of `base + disp`, whose address is computed in t2 if
+    // the displacement does not fit in 12 bits.
+    fn rv_mem(&mut self, base: &str, disp: i64) -> String {
+        if (-2048..2048).contains(&disp) {
+            format!("{}({})", disp, base)
+        } else {
+            self.rv_add("t2", base, disp);
+            "0(t2)".to_string()
+        }
+    }
+
+    // Memory operand of a place. A spilled base pointer is loaded into t2.
+    fn rv_place(&mut self, frame: &SavedFrame, place: Place) -> String {
+        match place {
+            Place::Frame(disp) => self.rv_mem("s0", disp as i64),
+            Place::Global(name, offset) => {
+                let name = self.interner.resolve(name);
+                let label = if offset == 0 { name.to_string() } else { format!("{}+{}", name, offset) };
+                self.rv("lla", &["t2", &label]);
+                "0(t2)".to_string()
+            },
+            Place::Ptr(base, offset) => {
+                let base = self.rv_value(frame, base, "t2");
+                self.rv_mem(base, offset as i64)
+            },
+        }
+    }
+
+    // Register holding the value. A spilled value is loaded into `scratch`.
+    fn rv_value(&mut self, frame: &SavedFrame, v: VReg, scratch: &'static str) -> &'static str {
+        match frame.alloc.locations[v.0 as usize] {
+            Some(Location::Reg(r)) => frame.regs[r],
+            Some(Location::Slot(i)) => {
+                let mem = self.rv_mem("s0", frame.slot(i));
+                self.rv("ld", &[scratch, &mem]);
+                scratch
+            },
+            None => unreachable!(),
+        }
+    }
+
+    // Register holding the value, where an immediate is loaded into t1.
+    fn rv_operand(&mut self, frame: &SavedFrame, val: Val) -> &'static str {
+        match val {
+            Val::Reg(v) => self.rv_value(frame, v, "t1"),
+            Val::Imm(imm) => {
+                self.rv("li", &["t1", &(imm as i64).to_string()]);
+                "t1"
+            },
+        }
+    }
+
+    // Stores the value computed in `reg`, if it is not the register of `dst`.
+    fn rv_result(&mut self, frame: &SavedFrame, dst: VReg, reg: &str) {
+        match frame.alloc.locations[dst.0 as usize] {
+            Some(Location::Reg(r)) if frame.regs[r] == reg => {},
+            Some(Location::Reg(r)) => self.rv("mv", &[frame.regs[r], reg]),
+            Some(Location::Slot(i)) => {
+                let mem = self.rv_mem("s0", frame.slot(i));
+                self.rv("sd", &[reg, &mem]);
+            },
+            None => unreachable!(),
+        }
+    }
+
+    fn gen_rv_op(&mut self, frame: &SavedFrame, op: &IrOp) {
+        match op {
+            IrOp::Const(d, val) => {
+                let t = frame.target(*d);
+                self.rv("li", &[t, &(*val as i64).to_string()]);
+                self.rv_result(frame, *d, t);
+            },
+            IrOp::Str(d, label) => {
+                let t = frame.target(*d);
+                self.rv("lla", &[t, &format!(".LC{}", label)]);
+                self.rv_result(frame, *d, t);
+            },
+            IrOp::Addr(d, place) => {
+                let t = frame.target(*d);
+                match place {
+                    Place::Frame(disp) => self.rv_add(t, "s0", *disp as i64),
+                    Place::Global(name, offset) => {
+                        let name = self.interner.resolve(*name);
+                        let label = if *offset == 0 { name.to_string() } else { format!("{}+{}", name, offset) };
+                        self.rv("lla", &[t, &label]);
+                    },
+                    Place::Ptr(base, offset) => {
+                        let base = self.rv_value(frame, *base, "t2");
+                        self.rv_add(t, base, *offset as i64);
+                    },
                 }
+                self.rv_result(frame, *d, t);
+            },
+            IrOp::Load(d, place, size, signed) => {
+                let t = frame.target(*d);
+                let mem = self.rv_place(frame, *place);
+                let loads = if *signed { RV_LOADS } else { RV_ULOADS };
+                self.rv(loads[size.trailing_zeros() as usize], &[t, &mem]);
+                self.rv_result(frame, *d, t);
+            },
+            IrOp::Store(place, v, size) => {
+                let src = self.rv_value(frame, *v, "t0");
+                let mem = self.rv_place(frame, *place);
+                self.rv(RV_STORES[size.trailing_zeros() as usize], &[src, &mem]);
+            },
+            IrOp::StoreArg(place, index, size) => {
+                let mem = self.rv_place(frame, *place);
+                self.rv(RV_STORES[size.trailing_zeros() as usize], &[self.arg_reg(*index, *size), &mem]);
+            },
+            IrOp::Div(kind, size, d, a, b) => {
+                let a = self.rv_value(frame, *a, "t0");
+                let b = self.rv_operand(frame, *b);
+                let t = frame.target(*d);
+                let mnemonic = match (kind, size) {
+                    (DivOp::Div, 4) => "divw",
+                    (DivOp::Rem, 4) => "remw",
+                    (DivOp::Udiv, 4) => "divuw",
+                    (DivOp::Urem, 4) => "remuw",
+                    (DivOp::Div, _) => "div",
+                    (DivOp::Rem, _) => "rem",
+                    (DivOp::Udiv, _) => "divu",
+                    (DivOp::Urem, _) => "remu",
+                };
+                self.rv(mnemonic, &[t, a, b]);
+                // The 32 bits result is sign extended, and the upper 32 bits
+                // of an unsigned one are cleared.
+                if *size == 4 && kind.is_unsigned() {
+                    self.rv("slli", &[t, t, "32"]);
+                    self.rv("srli", &[t, t, "32"]);
+                }
+                self.rv_result(frame, *d, t);
+            },
+            IrOp::Bin(kind, d, a, b) => {
+                let a = self.rv_value(frame, *a, "t0");
+                let t = frame.target(*d);
+                let (mnemonic, imm_mnemonic) = match kind {
+                    BinOp::Add => ("add", Some("addi")),
+                    BinOp::Sub => ("sub", None),
+                    BinOp::Mul => ("mul", None),
+                    BinOp::And => ("and", Some("andi")),
+                    BinOp::Shl => ("sll", Some("slli")),
+                    BinOp::Sar => ("sra", Some("srai")),
+                    BinOp::Shr => ("srl", Some("srli")),
+                };
+                match (imm_mnemonic, *b) {
+                    (Some(imm_mnemonic), Val::Imm(imm)) if imm < 2048 => {
+                        self.rv(imm_mnemonic, &[t, a, &imm.to_string()]);
+                    },
+                    _ => {
+                        let b = self.rv_operand(frame, *b);
+                        self.rv(mnemonic, &[t, a, b]);
+                    },
+                }
+                self.rv_result(frame, *d, t);
+            },
+            IrOp::Cmp(kind, size, d, a, b) => {
+                let mut a = self.rv_value(frame, *a, "t0");
+                let mut b = self.rv_operand(frame, *b);
+                // The lower bytes are sign or zero extended to compare.
+                if *size < 8 {
+                    let shift = (64 - 8 * size).to_string();
+                    let extend = if kind.is_unsigned() { "srli" } else { "srai" };
+                    self.rv("slli", &["t0", a, &shift]);
+                    self.rv(extend, &["t0", "t0", &shift]);
+                    self.rv("slli", &["t1", b, &shift]);
+                    self.rv(extend, &["t1", "t1", &shift]);
+                    a = "t0";
+                    b = "t1";
+                }
+                let t = frame.target(*d);
+                match kind {
+                    CmpOp::Eq => {
+                        self.rv("xor", &[t, a, b]);
+                        self.rv("seqz", &[t, t]);
+                    },
+                    CmpOp::Ne => {
+                        self.rv("xor", &[t, a, b]);
+                        self.rv("snez", &[t, t]);
+                    },
+                    CmpOp::Lt => self.rv("slt", &[t, a, b]),
+                    CmpOp::Le => {
+                        self.rv("slt", &[t, b, a]);
+                        self.rv("xori", &[t, t, "1"]);
+                    },
+                    CmpOp::Gt => self.rv("slt", &[t, b, a]),
+                    CmpOp::Ge => {
+                        self.rv("slt", &[t, a, b]);
+                        self.rv("xori", &[t, t, "1"]);
+                    },
+                    CmpOp::Ult => self.rv("sltu", &[t, a, b]),
+                    CmpOp::Ule => {
+                        self.rv("sltu", &[t, b, a]);
+                        self.rv("xori", &[t, t, "1"]);
+                    },
+                    CmpOp::Ugt => self.rv("sltu", &[t, b, a]),
+                    CmpOp::Uge => {
+                        self.rv("sltu", &[t, a, b]);
+                        self.rv("xori", &[t, t, "1"]);
+                    },
+                }
+                self.rv_result(frame, *d, t);
+            },
+            IrOp::Call(dsts, name, args) => {
+                // The virtual registers are not in the argument registers.
+                for (i, &arg) in args.iter().enumerate() {
+                    let src = self.rv_value(frame, arg, "t0");
+                    self.rv("mv", &[RV_ARG_REGS[i][3], src]);
+                }
+                self.rv("call", &[self.interner.resolve(*name)]);
+                match dsts.as_slice() {
+                    [val] => self.rv_result(frame, *val, "a0"),
+                    [ptr, len] => {
+                        self.rv_result(frame, *ptr, "a0");
+                        self.rv_result(frame, *len, "a1");
+                    },
+                    _ => {},
+                }
+            },
+            IrOp::Line(node) => self.gen_asm_line(*node),
+        }
+    }
+
+    fn gen_rv_epilogue(&mut self, frame: &SavedFrame) {
+        for (i, reg) in frame.saved.iter().enumerate() {
+            let mem = self.rv_mem("s0", frame.saved_slot(i));
+            self.rv("ld", &[reg, &mem]);
+        }
+        self.rv("mv", &["sp", "s0"]);
+        self.rv("ld", &["ra", "8(sp)"]);
+        self.rv("ld", &["s0", "0(sp)"]);
+        self.rv("addi", &["sp", "sp", "16"]);
+        self.rv("ret", &[]);
+    }
+
+    // `next` is the block placed right after.
+    fn gen_rv_term(&mut self, frame: &SavedFrame, term: &Term, next: BlockId) {
+        let func = self.func;
+        let label = |b: &BlockId| local_label(func, "block", b.0 as usize);
+        match term {
+            Term::Jmp(b) => {
+                if *b != next {
+                    self.rv("j", &[&label(b)]);
+                }
+            },
+            Term::Br(cond, t, e) => {
+                let cond = self.rv_value(frame, *cond, "t0");
+                if *t == next {
+                    self.rv("beqz", &[cond, &label(e)]);
+                } else {
+                    self.rv("bnez", &[cond, &label(t)]);
+                    if *e != next {
+                        self.rv("j", &[&label(e)]);
+                    }
+                }
+            },
+            Term::Ret(vals) => {
+                // A slice is returned with the pointer in a0 and the
+                // length in a1.
+                for (i, &val) in vals.iter().enumerate() {
+                    let src = self.rv_value(frame, val, "t0");
+                    self.rv("mv", &[RV_ARG_REGS[i][3], src]);
+                }
+                self.gen_rv_epilogue(frame);
+            },
+        }
+    }
+
+    // Generates a function of the IR for RISC-V in the same frame as
+    // AArch64, where s0 points to the saved s0 and ra.
+    fn gen_rv_function(&mut self, node: NodeId, func: &ir::Function) {
+        let frame = SavedFrame::new(func, &RV_REGS, "t0");
+        let size = frame.size();
+
+        self.gen_asm_label(node, func.name, size);
+        self.rv("addi", &["sp", "sp", "-16"]);
+        self.rv("sd", &["ra", "8(sp)"]);
+        self.rv("sd", &["s0", "0(sp)"]);
+        self.rv("mv", &["s0", "sp"]);
+        if size > 0 {
+            self.rv_add("sp", "sp", -(size as i64));
+        }
+        for (i, reg) in frame.saved.iter().enumerate() {
+            let mem = self.rv_mem("s0", frame.saved_slot(i));
+            self.rv("sd", &[reg, &mem]);
+        }
+
+        let targets: HashSet<BlockId> = func.blocks.iter()
+            .flat_map(|block| block.term.targets())
+            .collect();
+        for (i, block) in func.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            if targets.contains(&id) {
+                self.emit(Label(local_label(self.func, "block", i)));
+            }
+            for op in block.ops.iter() {
+                self.gen_rv_op(&frame, op);
+            }
+            self.gen_rv_term(&frame, &block.term, BlockId(id.0 + 1));
+        }
+
+        self.emit(Blank);
+    }
+
+    fn gen_asm_node(&mut self, node: NodeId) -> Result<(), AsmError> {
+        let ast = self.ast;
+        match &ast[node] {
+            Node::Number { val } => {
+                // push can take only a sign-extended 32 bits immediate.
+                if *val > i32::MAX as u64 {
+                    self.emit(Mov(Reg("rax"), Imm(*val)));
+                    self.emit(Push(Reg("rax")));
+                } else {
+                    self.emit(Push(Imm(*val)));
+                }
+            },
+            Node::Bool { bl } => {
+                self.emit(Push(Imm(*bl as u64)));
             },
             Node::StrLiteral { s, label } => {
-                writeln!(f, "    lea rax, QWORD PTR .LC{}[rip]", label)?;
-                writeln!(f, "    push rax")?;
-                writeln!(f, "    push {}", s.len())?;
+                let label = format!(".LC{}", label);
+                self.emit(Lea("rax", Rip { width: Width::Qword, label, offset: None }));
+                self.emit(Push(Reg("rax")));
+                self.emit(Push(Imm(self.interner.resolve(*s).len() as u64)));
+            },
+            Node::CStrLiteral { s: _, label } => {
+                let label = format!(".LC{}", label);
+                self.emit(Lea("rax", Rip { width: Width::Qword, label, offset: None }));
+                self.emit(Push(Reg("rax")));
             },
             Node::BinaryOperator { kind, lhs, rhs } => {
                 if *kind == BinaryOpAsn {
-                    self.gen_asm_lval(f, lhs)?;
+                    self.gen_asm_lval(*lhs)?;
                 } else {
-                    self.gen_asm_node(f, lhs)?;
+                    self.gen_asm_node(*lhs)?;
                 }
-                self.gen_asm_node(f, rhs)?;
-                if is_slice(lhs) {
-                    writeln!(f, "    pop rdx")?;
-                    writeln!(f, "    pop rdi")?;
+                self.gen_asm_node(*rhs)?;
+                if is_slice(ast, *lhs) {
+                    self.emit(Pop("rdx"));
+                    self.emit(Pop("rdi"));
                 } else {
-                    writeln!(f, "    pop rdi")?;
+                    self.emit(Pop("rdi"));
                 }
-                writeln!(f, "    pop rax")?;
+                self.emit(Pop("rax"));
+                if *kind != BinaryOpAsn {
+                    if let Some((narrower, size, unsigned)) = ast.narrower_operand(*lhs, *rhs) {
+                        let regs = if narrower == *lhs { &CALC_REGS[0] } else { &CALC_REGS[1] };
+                        self.gen_extend(regs, size, unsigned);
+                    }
+                }
+                let unsigned = ast.is_unsigned_cmp(*lhs, *rhs);
+                let cond = match (kind, unsigned) {
+                    (BinaryOpEq, _) => Some(Cond::E),
+                    (BinaryOpNe, _) => Some(Cond::Ne),
+                    (BinaryOpLt, false) => Some(Cond::L),
+                    (BinaryOpLe, false) => Some(Cond::Le),
+                    (BinaryOpGr, false) => Some(Cond::G),
+                    (BinaryOpGe, false) => Some(Cond::Ge),
+                    (BinaryOpLt, true) => Some(Cond::B),
+                    (BinaryOpLe, true) => Some(Cond::Be),
+                    (BinaryOpGr, true) => Some(Cond::A),
+                    (BinaryOpGe, true) => Some(Cond::Ae),
+                    _ => None,
+                };
                 match kind {
                     BinaryOpAdd => {
-                        writeln!(f, "    add rax, rdi")?;
+                        self.emit(Add(Reg("rax"), Reg("rdi")));
                     },
                     BinaryOpSub => {
-                        writeln!(f, "    sub rax, rdi")?;
+                        self.emit(Sub(Reg("rax"), Reg("rdi")));
                     },
                     BinaryOpMul => {
-                        writeln!(f, "    imul rax, rdi")?;
-                    },
-                    BinaryOpDiv => {
-                        writeln!(f, "    cqo")?;
-                        writeln!(f, "    idiv rdi")?;
-                    },
-                    BinaryOpEq => {
-                        let lsize = lval_size(lhs).unwrap_or(8);
-                        let index = lsize.trailing_zeros() as usize;
-                        writeln!(f, "    cmp {}, {}", CALC_REGS[0][index], CALC_REGS[1][index])?;
-                        writeln!(f, "    sete al")?;
-                        writeln!(f, "    movzb rax, al")?;
+                        self.emit(Imul(Reg("rax"), Reg("rdi")));
                     },
-                    BinaryOpNe => {
-                        let lsize = lval_size(lhs).unwrap_or(8);
-                        let index = lsize.trailing_zeros() as usize;
-                        writeln!(f, "    cmp {}, {}", CALC_REGS[0][index], CALC_REGS[1][index])?;
-                        writeln!(f, "    setne al")?;
-                        writeln!(f, "    movzb rax, al")?;
-                    },
-                    BinaryOpGr => {
-                        let lsize = lval_size(lhs).unwrap_or(8);
-                        let index = lsize.trailing_zeros() as usize;
-                        writeln!(f, "    cmp {}, {}", CALC_REGS[0][index], CALC_REGS[1][index])?;
-                        writeln!(f, "    setl al")?;
-                        writeln!(f, "    movzb rax, al")?;
+                    BinaryOpDiv | BinaryOpRem => {
+                        let (size, unsigned) = ast.int_type(*lhs, *rhs);
+                        if size < 4 {
+                            // 1 and 2 bytes are extended to be divided in 4
+                            // bytes.
+                            let index = size.trailing_zeros() as usize;
+                            let (lhs, rhs) = (Reg(CALC_REGS[0][index]), Reg(CALC_REGS[1][index]));
+                            if unsigned {
+                                self.emit(Movzx("eax", lhs));
+                                self.emit(Movzx("edi", rhs));
+                            } else {
+                                self.emit(Movsx("eax", lhs));
+                                self.emit(Movsx("edi", rhs));
+                            }
+                        }
+                        let size = size.max(4);
+                        let index = size.trailing_zeros() as usize;
+                        self.gen_div(size, unsigned, Reg(CALC_REGS[1][index]));
+                        if *kind == BinaryOpRem {
+                            self.emit(Mov(Reg("rax"), Reg("rdx")));
+                        }
+                        if size == 4 && !unsigned {
+                            self.emit(Movsx("rax", Reg("eax")));
+                        }
                     },
-                    BinaryOpGe => {
-                        let lsize = lval_size(lhs).unwrap_or(8);
-                        let index = lsize.trailing_zeros() as usize;
-                        writeln!(f, "    cmp {}, {}", CALC_REGS[0][index], CALC_REGS[1][index])?;
-                        writeln!(f, "    setle al")?;
-                        writeln!(f, "    movzb rax, al")?;
+                    BinaryOpEq | BinaryOpNe | BinaryOpLt | BinaryOpLe | BinaryOpGr | BinaryOpGe => {
+                        let index = cmp_size(ast, *lhs, *rhs).trailing_zeros() as usize;
+                        self.emit(Cmp(Reg(CALC_REGS[0][index]), Reg(CALC_REGS[1][index])));
+                        self.emit(Set(cond.unwrap(), "al"));
+                        self.emit(Movzb("rax", "al"));
                     },
                     BinaryOpAsn => {
-                        if is_slice(lhs) {
-                            writeln!(f, "    mov QWORD PTR [rax], rdi")?;
-                            writeln!(f, "    mov QWORD PTR [rax+8], rdx")?;
+                        if is_slice(ast, *lhs) {
+                            self.emit(Mov(mem(8, "rax", 0), Reg("rdi")));
+                            self.emit(Mov(mem(8, "rax", 8), Reg("rdx")));
                         } else {
-                            match lval_size(lhs)? {
-                                1 => writeln!(f, "    mov BYTE PTR [rax], dil")?,
-                                2 => writeln!(f, "    mov WORD PTR [rax], di")?,
-                                4 => writeln!(f, "    mov DWORD PTR [rax], edi")?,
-                                8 => writeln!(f, "    mov QWORD PTR [rax], rdi")?,
-                                _ => unreachable!(),
+                            let size = lval_size(ast, *lhs)?;
+                            let index = size.trailing_zeros() as usize;
+                            if let Some((from, unsigned)) = ast.widens(*rhs, lval_type(ast, *lhs)?) {
+                                self.gen_extend(&CALC_REGS[1], from, unsigned);
+                            }
+                            if ast.normalizes_bool(*lhs, *rhs) {
+                                self.emit(Cmp(Reg("rdi"), Imm(0)));
+                                self.emit(Set(Cond::Ne, "dil"));
                             }
+                            self.emit(Mov(mem(size, "rax", 0), Reg(CALC_REGS[1][index])));
                         }
                     },
                 }
                 if *kind != BinaryOpAsn {
-                    writeln!(f, "    push rax\n")?;
+                    self.emit(Push(Reg("rax")));
+                    self.emit(Blank);
                 }
             },
             Node::UnaryOperator { kind, rhs } => {
                 match kind {
                     UnaryOpRf => {
-                        self.gen_asm_lval(f, rhs)?;
+                        self.gen_asm_lval(*rhs)?;
                     }
                     UnaryOpDrf => {
-                        self.gen_asm_node(f, rhs)?;
-                        writeln!(f, "    pop rax")?;
-                        writeln!(f, "    mov rax, QWORD PTR [rax]")?;
-                        writeln!(f, "    push rax")?;
+                        self.gen_asm_node(*rhs)?;
+                        // The pointee is loaded in its own size like a variable.
+                        if is_slice(ast, node) || matches!(lval_size(ast, node), Ok(1 | 2 | 4 | 8)) {
+                            self.gen_asm_load(node)?;
+                        } else {
+                            self.emit(Pop("rax"));
+                            self.emit(Mov(Reg("rax"), mem(8, "rax", 0)));
+                            self.emit(Push(Reg("rax")));
+                        }
                     }
                 }
             },
             Node::LocalVariable { offset: _, ty: _ } => {
-                self.gen_asm_lval(f, node)?;
-                writeln!(f, "    pop rax\n")?;
-                if is_slice(node) {
-                    writeln!(f, "    mov rdi, QWORD PTR [rax]")?;
-                    writeln!(f, "    mov rax, QWORD PTR [rax+8]")?;
-                    writeln!(f, "    push rdi")?;
-                    writeln!(f, "    push rax")?;
-                } else {
-                    match lval_size(node)? {
-                        1 => writeln!(f, "    movsx eax, BYTE PTR [rax]")?,
-                        2 => writeln!(f, "    movsx eax, WORD PTR [rax]")?,
-                        4 => writeln!(f, "    mov eax, DWORD PTR [rax]")?,
-                        8 => writeln!(f, "    mov rax, QWORD PTR [rax]")?,
-                        _ => unreachable!(),
-                    }
-                    writeln!(f, "    push rax")?;
-                }
+                self.gen_asm_lval(node)?;
+                self.gen_asm_load(node)?;
             },
             Node::DeclareLocal { offset: _, ty: _ } => {
                 // Do nothing
             },
             Node::GlobalVariable { name: _, offset: _, ty: _ } => {
-                self.gen_asm_lval(f, node)?;
-                writeln!(f, "    pop rax\n")?;
-                if is_slice(node) {
-                    writeln!(f, "    mov rdi, QWORD PTR [rax]")?;
-                    writeln!(f, "    mov rax, QWORD PTR [rax+8]")?;
-                    writeln!(f, "    push rdi")?;
-                    writeln!(f, "    push rax")?;
-                } else {
-                    match lval_size(node)? {
-                        1 => writeln!(f, "    movsx eax, BYTE PTR [rax]")?,
-                        2 => writeln!(f, "    movsx eax, WORD PTR [rax]")?,
-                        4 => writeln!(f, "    mov eax, DWORD PTR [rax]")?,
-                        8 => writeln!(f, "    mov rax, QWORD PTR [rax]")?,
-                        _ => unreachable!(),
-                    }
-                    writeln!(f, "    push rax")?;
-                }
+                self.gen_asm_lval(node)?;
+                self.gen_asm_load(node)?;
             },
-            Node::DeclareGlobal { name, size, ty: _ } => {
-                writeln!(f, ".bss")?;
-                writeln!(f, ".global {}", name)?;
-                writeln!(f, "{}:", name)?;
-                writeln!(f, "    .zero {}", size)?;
-                writeln!(f)?;
+            Node::DeclareGlobal { name, size, ty, init, mutable: _ } => {
+                let name = self.interner.resolve(*name);
+                self.emit(Directive(format!(".global {}", name)));
+                self.gen_asm_type(name, "object");
+                let align = type_align(ty);
+                if align > 1 {
+                    self.emit(Directive(format!(".balign {}", align)));
+                }
+                self.emit(Label(name.to_string()));
+                match init {
+                    Some(val) => self.emit(Data(*size, *val)),
+                    None => self.emit(Zero(*size)),
+                }
+                self.gen_asm_size(name, &size.to_string());
+                self.emit(Blank);
             },
             Node::Block { nodes } => {
-                self.gen_asm_node_stream(f, nodes)?;
+                self.gen_asm_node_stream(nodes)?;
             },
             Node::Function { name, args, stack, block } => {
-                writeln!(f, ".text")?;
-                writeln!(f, ".global {}", name)?;
-                writeln!(f, "{}:", name)?;
-
-                writeln!(f, "    push rbp")?;
-                writeln!(f, "    mov rbp, rsp")?;
-                writeln!(f, "    sub rsp, {}", stack)?;
+                self.func = self.interner.resolve(*name);
+                self.label_count = 0;
+                if self.options.opt_level >= 1 || !self.options.target.is_x86() {
+                    return self.gen_ir_function(node);
+                }
+                self.gen_asm_prologue(node, *name, *stack, 0);
+                self.arg_count(args)?;
+                let regs = self.arg_regs();
 
                 let iter = args.iter().enumerate();
                 let mut offset = 0;
-                for (cnt, node) in iter {
+                for (cnt, &node) in iter {
                     let index = cnt + offset;
-                    self.gen_asm_lval(f, node)?;
-                    writeln!(f, "    pop rax")?;
-                    if is_slice(node) {
-                        writeln!(f, "    mov QWORD PTR [rax], {}", ARG_REGS_64[index])?;
-                        writeln!(f, "    mov QWORD PTR [rax+8], {}", ARG_REGS_64[index+1])?;
+                    self.gen_asm_lval(node)?;
+                    self.emit(Pop("rax"));
+                    if is_slice(ast, node) {
+                        self.emit(Mov(mem(8, "rax", 0), Reg(regs[index][3])));
+                        self.emit(Mov(mem(8, "rax", 8), Reg(regs[index + 1][3])));
                         offset += 1;
                     } else {
-                        match lval_size(node)? {
-                            1 => writeln!(f, "    mov BYTE PTR [rax], {}", ARG_REGS_8[index])?,
-                            2 => writeln!(f, "    mov WORD PTR [rax], {}", ARG_REGS_16[index])?,
-                            4 => writeln!(f, "    mov DWORD PTR [rax], {}", ARG_REGS_32[index])?,
-                            8 => writeln!(f, "    mov QWORD PTR [rax], {}", ARG_REGS_64[index])?,
-                            _ => unreachable!(),
-                        }
+                        let size = lval_size(ast, node)?;
+                        self.emit(Mov(mem(size, "rax", 0), Reg(self.arg_reg(index, size))));
                     }
                 }
 
-                self.gen_asm_node(f, block)?;
+                self.gen_asm_node(*block)?;
 
-                writeln!(f)?;
+                // A function which reaches its end returns 0, which is the
+                // exit status of `main`.
+                self.emit(Mov(Reg("rax"), Imm(0)));
+                self.gen_asm_epilogue();
+                self.emit(Blank);
             },
             Node::DeclareFunc { name: _, args: _ } => {
                 // Do nothing
             }
             Node::Call { name: _, args: _, ty } => {
-                self.gen_asm_call(f, node)?;
-                if let Type::Slc(_) = ty {
-                    writeln!(f, "    push rdx")?;
-                    writeln!(f, "    push rax")?;
-                } else {
-                    writeln!(f, "    push rax")?;
+                self.gen_asm_call(node)?;
+                match ty {
+                    Type::Slc(_) => {
+                        self.emit(Push(Reg("rax")));
+                        self.emit(Push(Reg("rdx")));
+                    },
+                    Type::Unit => {},
+                    _ => self.emit(Push(Reg("rax"))),
                 }
             },
             Node::If { cond, ibody } => {
                 let lcnt = self.label_count;
                 self.label_count += 1;
 
-                self.gen_asm_node(f, cond)?;
-                writeln!(f, "    pop rax")?;
-                writeln!(f, "    cmp rax, 0")?;
-                writeln!(f, "    je  .Lend{}", lcnt)?;
-                self.gen_asm_node(f, ibody)?;
-                writeln!(f, ".Lend{}:", lcnt)?;
+                self.gen_asm_node(*cond)?;
+                self.emit(Pop("rax"));
+                self.emit(Cmp(Reg("rax"), Imm(0)));
+                self.emit(Jcc(Cond::E, local_label(self.func, "end", lcnt)));
+                self.gen_asm_node(*ibody)?;
+                self.emit(Label(local_label(self.func, "end", lcnt)));
             },
             Node::IfElse { cond, ibody, ebody } => {
                 let lcnt = self.label_count;
                 self.label_count += 1;
 
-                self.gen_asm_node(f, cond)?;
-                writeln!(f, "    pop rax")?;
-                writeln!(f, "    cmp rax, 0")?;
-                writeln!(f, "    je  .Lelse{}", lcnt)?;
-                self.gen_asm_node(f, ibody)?;
-                writeln!(f, "    jmp  .Lend{}", lcnt)?;
-                writeln!(f, ".Lelse{}:", lcnt)?;
-                self.gen_asm_node(f, ebody)?;
-                writeln!(f, ".Lend{}:", lcnt)?;
+                self.gen_asm_node(*cond)?;
+                self.emit(Pop("rax"));
+                self.emit(Cmp(Reg("rax"), Imm(0)));
+                self.emit(Jcc(Cond::E, local_label(self.func, "else", lcnt)));
+                self.gen_asm_node(*ibody)?;
+                self.emit(Jmp(local_label(self.func, "end", lcnt)));
+                self.emit(Label(local_label(self.func, "else", lcnt)));
+                self.gen_asm_node(*ebody)?;
+                self.emit(Label(local_label(self.func, "end", lcnt)));
             },
             Node::While { cond, body } => {
                 let lcnt = self.label_count;
                 self.label_count += 1;
 
-                writeln!(f, ".Lbegin{}:", lcnt)?;
-                self.gen_asm_node(f, cond)?;
-                writeln!(f, "    pop rax")?;
-                writeln!(f, "    cmp rax, 0")?;
-                writeln!(f, "    je  .Lend{}", lcnt)?;
-                self.gen_asm_node(f, body)?;
-                writeln!(f, "    jmp  .Lbegin{}", lcnt)?;
-                writeln!(f, ".Lend{}:", lcnt)?;
+                self.emit(Label(local_label(self.func, "begin", lcnt)));
+                self.gen_asm_node(*cond)?;
+                self.emit(Pop("rax"));
+                self.emit(Cmp(Reg("rax"), Imm(0)));
+                self.emit(Jcc(Cond::E, local_label(self.func, "end", lcnt)));
+                self.gen_asm_node(*body)?;
+                self.emit(Jmp(local_label(self.func, "begin", lcnt)));
+                self.emit(Label(local_label(self.func, "end", lcnt)));
             },
             Node::Return { rhs, ty } => {
-                self.gen_asm_node(f, rhs)?;
+                self.gen_asm_node(*rhs)?;
                 if let Type::Slc(_) = ty {
-                    writeln!(f, "    pop rax")?;
-                    writeln!(f, "    pop rdx")?;
+                    // The pointer and the length as the C ABI returns a
+                    // pair of words.
+                    self.emit(Pop("rdx"));
+                    self.emit(Pop("rax"));
                 } else {
-                    writeln!(f, "    pop rax")?;
+                    self.emit(Pop("rax"));
+                    if let Some((from, unsigned)) = ast.widens(*rhs, ty) {
+                        self.gen_extend(&CALC_REGS[0], from, unsigned);
+                    }
+                    if ast.normalizes_to_bool(ty, *rhs) {
+                        self.emit(Cmp(Reg("rax"), Imm(0)));
+                        self.emit(Set(Cond::Ne, "al"));
+                    }
+                    // The value is returned in the declared width, extended
+                    // as it would be loaded from a variable of the type.
+                    if let Type::Int8 | Type::Int16 | Type::Int32 |
+                           Type::Uint8 | Type::Uint16 | Type::Uint32 | Type::Bool = ty {
+                        self.gen_extend(&CALC_REGS[0], type_size(ty), is_unsigned(ty));
+                    }
                 }
-                writeln!(f, "    mov rsp, rbp")?;
-                writeln!(f, "    pop rbp")?;
-                writeln!(f, "    ret")?;
+                self.gen_asm_epilogue();
             },
         }
 
         Ok(())
     }
 
-    fn gen_asm_node_stream(&mut self, f: &mut File, nodes: &Vec<Box<Node>>) -> Result<(), AsmError> {
+    // Line, column and the first line of the source of a statement or a
+    // function.
+    fn source_line(&self, node: NodeId) -> Option<(usize, usize, &'a str)> {
+        let (_, lines) = self.source.as_ref()?;
+        let span = self.ast.span(node)?;
+        let (line, column) = lines.line_col(span.start);
+        Some((line, column, lines.first_line(span)))
+    }
+
+    // Comment and `.loc` for the beginning of a statement.
+    fn gen_asm_line(&mut self, node: NodeId) {
+        if let Some((line, column, text)) = self.source_line(node) {
+            if self.options.comments {
+                self.emit(LineComment(line, text.to_string()));
+            }
+            if self.options.debug_info {
+                self.emit(Loc(line, column));
+            }
+        }
+    }
+
+    fn gen_asm_node_stream(&mut self, nodes: &[NodeId]) -> Result<(), AsmError> {
         let iter = nodes.iter();
-        for node in iter {
-            if is_call(node) {
-                // Do not handle return value when a function is called alone.
-                self.gen_asm_call(f, node)?;
-            } else {
-                self.gen_asm_node(f, node)?;
+        for &node in iter {
+            self.gen_asm_line(node);
+            self.gen_asm_node(node)?;
+            // Drop the value of an expression statement.
+            let size = pushed_size(self.ast, node);
+            if size > 0 {
+                self.emit(Add(Reg("rsp"), Imm(size as u64)));
             }
         }
         Ok(())
     }
 
-    pub fn gen_asm(&mut self, f: &mut File, nodes: &Vec<Box<Node>>, literals: &Vec<String>) -> Result<(), AsmError> {
-        writeln!(f, ".intel_syntax noprefix")?;
+    // Renders the code built so far, or encodes it into the object.
+    fn flush(&mut self, f: &mut impl Write) -> Result<(), AsmError> {
+        if let Some(object) = &mut self.object {
+            if self.options.opt_level >= 1 {
+                peephole::optimize(&mut self.insns);
+            }
+            object.assemble(&self.insns).map_err(Encode)?;
+            self.insns.clear();
+            return Ok(());
+        }
+        match (self.options.target, self.options.syntax) {
+            (Target::Aarch64, _) => emit_a64(&self.insns, f)?,
+            (Target::Riscv64, _) => emit_riscv(&self.insns, f)?,
+            (_, syntax) => {
+                if self.options.opt_level >= 1 {
+                    peephole::optimize(&mut self.insns);
+                }
+                match syntax {
+                    AsmSyntax::Intel => emit_intel(&self.insns, f)?,
+                    AsmSyntax::Att => emit_att(&self.insns, f)?,
+                }
+            },
+        }
+        self.insns.clear();
+        Ok(())
+    }
+
+    pub fn gen_asm(&mut self, f: &mut impl Write, literals: &[Symbol]) -> Result<(), AsmError> {
+        if self.options.syntax == AsmSyntax::Intel && self.options.target.is_x86() {
+            self.emit(Directive(".intel_syntax noprefix".to_string()));
+        }
+        if let (Some((file, _)), true) = (&self.source, self.options.debug_info) {
+            let file = format!(".file 1 \"{}\"", escape_ascii(file));
+            self.emit(Directive(file));
+        }
 
-        writeln!(f, ".section .rodata")?;
+        match self.options.target {
+            Target::WindowsGnu => self.emit(Directive(".section .rdata,\"dr\"".to_string())),
+            _ => self.emit(Directive(".section .rodata".to_string())),
+        }
         let iter = literals.iter().enumerate();
         for (cnt, lit) in iter {
-            writeln!(f, ".LC{}:", cnt)?;
-            writeln!(f, "    .ascii \"{}\"", lit)?;
+            self.emit(Label(format!(".LC{}", cnt)));
+            self.emit(Ascii(escape_ascii(self.interner.resolve(*lit))));
+        }
+        self.flush(f)?;
+
+        // The globals and the functions are grouped into their sections,
+        // each of which is switched to once. Each item is generated and
+        // rendered in turn. The initialized statics which are not `mut`
+        // follow the literals, so that writes to them fault.
+        let ast = self.ast;
+        let (globals, items): (Vec<NodeId>, Vec<NodeId>) = ast.items().iter()
+            .partition(|&&item| matches!(ast[item], Node::DeclareGlobal { name: _, size: _, ty: _, init: _, mutable: _ }));
+        let (rodata, globals): (Vec<NodeId>, Vec<NodeId>) = globals.iter()
+            .partition(|&&item| matches!(ast[item], Node::DeclareGlobal { name: _, size: _, ty: _, init: Some(_), mutable: false }));
+        let (data, bss): (Vec<NodeId>, Vec<NodeId>) = globals.iter()
+            .partition(|&&item| matches!(ast[item], Node::DeclareGlobal { name: _, size: _, ty: _, init: Some(_), mutable: _ }));
+        for (section, globals) in [(None, rodata), (Some(".data"), data), (Some(".bss"), bss)] {
+            if let (Some(section), false) = (section, globals.is_empty()) {
+                self.emit(Directive(section.to_string()));
+            }
+            for item in globals {
+                self.gen_asm_node(item)?;
+                self.flush(f)?;
+            }
+        }
+        if items.iter().any(|&item| matches!(ast[item], Node::Function { name: _, args: _, stack: _, block: _ })) {
+            self.emit(Directive(".text".to_string()));
+        }
+        let mut has_main = false;
+        for item in items {
+            self.gen_asm_node(item)?;
+            // The function ends here whichever code generator made it.
+            if let Node::Function { name, args: _, stack: _, block: _ } = &ast[item] {
+                let name = self.interner.resolve(*name);
+                self.gen_asm_size(name, &format!(".-{}", name));
+                has_main |= name == "main";
+            }
+            self.flush(f)?;
+        }
+        // Only the object of `main` has the entry point, so that the other
+        // objects can be linked with it.
+        if self.options.freestanding && has_main {
+            self.gen_asm_start();
+            self.flush(f)?;
         }
 
-        self.gen_asm_node_stream(f, nodes)?;
+        self.emit(Directive(format!(".ident \"yrc {}\"", env!("CARGO_PKG_VERSION"))));
 
+        // Without this, the linker warns that the stack is executable.
+        if self.options.target != Target::WindowsGnu {
+            self.emit(Directive(".section .note.GNU-stack,\"\",@progbits".to_string()));
+        }
+        self.flush(f)?;
+        if let Some(object) = self.object.take() {
+            f.write_all(&object.finish().map_err(Encode)?)?;
+        }
+        // A buffered writer reports a failed write only when flushed.
+        f.flush()?;
         Ok(())
     }
 
-    pub fn new() -> Self {
+    pub fn new(ast: &'a Ast, interner: &'a Interner, options: CodegenOptions) -> Self {
         AsmGenerator {
+            ast,
+            interner,
+            options,
+            func: "",
             label_count: 0,
+            saved_at: 0,
+            source: None,
+            insns: Vec::new(),
+            object: if options.object { Some(ObjectWriter::new()) } else { None },
+            externs: ast.items().iter()
+                .filter_map(|&item| match ast[item] {
+                    Node::DeclareFunc { name, args: _ } => Some(name),
+                    _ => None,
+                })
+                .collect(),
         }
     }
+
+    pub fn gen_asm_to_string(&mut self, literals: &[Symbol]) -> Result<String, AsmError> {
+        let mut out = Vec::new();
+        self.gen_asm(&mut out, literals)?;
+        // Everything written is either ASCII or a part of the source.
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    // Source code which the AST is parsed from.
+    pub fn set_source(&mut self, file: &'a str, source: &'a str) {
+        self.source = Some((file, LineTable::new(source)));
+    }
 }
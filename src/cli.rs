@@ -0,0 +1,403 @@
+use getopts::Fail;
+use getopts::Options;
+
+use super::assembly::AsmSyntax;
+use super::assembly::Target;
+use super::lint::Level;
+use super::lint::LintLevels;
+use super::report::ColorChoice;
+use super::report::ErrorFormat;
+use super::trace::Tracer;
+
+const USAGE: &str = "\
+Usage: yrc [build] [options] FILE...
+       yrc run [options] FILE [-- ARGS...]
+       yrc check [options] FILE...
+
+Examples:
+    yrc hello.rs               build ./hello
+    yrc -s -o - hello.rs       print the assembly
+    yrc -c foo.rs bar.rs       build foo.o and bar.o
+    yrc run hello.rs -- a b    build and run with arguments
+    yrc -e '1 + 2 * 3'         run the expression and print its value
+    yrc check hello.rs         only report errors (or --check)
+
+Run `yrc SUBCOMMAND -h` for the options of each subcommand.";
+
+const RUN_USAGE: &str = "\
+Usage: yrc run [options] FILE [-- ARGS...]
+
+Builds the program into a temporary file and runs it with ARGS.";
+
+const CHECK_USAGE: &str = "\
+Usage: yrc check [options] FILE...
+
+Reports errors and warnings without generating any code.";
+
+// Intermediate forms which can be output instead of an executable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    AstJson,
+    Ir,
+}
+
+// What is made of the generated assembly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputKind {
+    Asm,
+    Object,
+    Executable,
+}
+
+// External programs which make objects and executables of the assembly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assembler {
+    // The C compiler driver, which also links the C runtime.
+    Cc,
+    // `as` and `ld` of binutils, with the C compiler only for linking the
+    // C runtime if it is found.
+    As,
+}
+
+// What the command line asks for. `build` is the default subcommand.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    Build,
+    Run,
+    Check,
+    Help(String),
+    Version,
+    Explain(String),
+}
+
+// Settings from the command line.
+#[derive(Debug)]
+pub struct Config {
+    pub mode: Mode,
+    pub input_files: Vec<String>,
+    pub output_file: Option<String>,
+    pub kind: OutputKind,
+    pub emit: Option<Emit>,
+    pub save_temps: bool,
+    // Overwrite existing files which do not look like outputs of yrc.
+    pub force: bool,
+    pub opt_level: u8,
+    pub debug_info: bool,
+    pub asm_syntax: AsmSyntax,
+    pub target: Target,
+    // Encode objects without an external assembler.
+    pub integrated_as: bool,
+    pub assembler: Assembler,
+    // Start from `_start` of the program itself without the C runtime.
+    pub freestanding: bool,
+    pub time_passes: bool,
+    pub cc: Option<String>,
+    pub link_args: Vec<String>,
+    // Arguments passed to the program by `run`.
+    pub run_args: Vec<String>,
+    // Snippets of `-e` which are run instead of the input files, with the
+    // items of the included files.
+    pub eval: Vec<String>,
+    pub includes: Vec<String>,
+    pub(crate) error_format: ErrorFormat,
+    pub(crate) color: ColorChoice,
+    pub(crate) lints: LintLevels,
+    pub(crate) tracer: Tracer,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::new(Mode::Build)
+    }
+}
+
+impl Config {
+    fn new(mode: Mode) -> Self {
+        Config {
+            mode,
+            input_files: Vec::new(),
+            output_file: None,
+            kind: OutputKind::Executable,
+            emit: None,
+            save_temps: false,
+            force: false,
+            opt_level: 0,
+            debug_info: false,
+            asm_syntax: AsmSyntax::Intel,
+            target: Target::Linux,
+            integrated_as: false,
+            assembler: Assembler::Cc,
+            freestanding: false,
+            time_passes: false,
+            cc: None,
+            link_args: Vec::new(),
+            run_args: Vec::new(),
+            eval: Vec::new(),
+            includes: Vec::new(),
+            error_format: ErrorFormat::Human,
+            color: ColorChoice::Auto,
+            lints: LintLevels::new(),
+            tracer: Tracer::default(),
+        }
+    }
+}
+
+fn build_options(opts: &mut Options) {
+    opts.optopt("o", "output", "set output file name", "NAME");
+    opts.optflag("s", "asm", "output assemble code");
+    opts.optflag("c", "", "output object files without linking");
+    opts.optopt("", "emit", "output an intermediate form instead (tokens|ast-json|ir)", "KIND");
+    opts.optflag("", "save-temps", "keep the intermediate assembly next to the output");
+    opts.optflag("f", "force", "overwrite existing output files");
+}
+
+fn link_options(opts: &mut Options) {
+    opts.optflagopt("O", "", "set optimization level (0|1|2, 1 if omitted)", "LEVEL");
+    opts.optflag("g", "", "generate debug line information");
+    opts.optopt("", "asm-syntax", "set assembly syntax (intel|att, intel by default)", "SYNTAX");
+    opts.optopt("", "target", "set target platform (x86_64-linux|windows-gnu|aarch64|riscv64)", "TARGET");
+    opts.optopt("", "cc", "set C compiler to assemble and link with", "PATH");
+    opts.optflag("", "integrated-as", "encode objects without an external assembler (x86_64-linux only)");
+    opts.optopt("", "assembler", "set programs to assemble and link with (cc|as, cc by default)", "KIND");
+    opts.optflag("", "freestanding", "link without the C runtime, starting from a `_start` which calls `main`");
+    opts.optmulti("l", "", "link with the library", "LIB");
+    opts.optmulti("L", "", "add the directory to the library search path", "DIR");
+    opts.optmulti("", "link-arg", "pass the argument to the linker command", "ARG");
+    opts.optflag("", "no-pie", "link a position dependent executable");
+    opts.optmulti("e", "", "run the statements and print the value of the last expression", "EXPR");
+    opts.optmulti("", "include", "declare the items of the file for -e", "FILE");
+}
+
+fn common_options(opts: &mut Options) {
+    opts.optflag("h", "help", "print this help message");
+    opts.optflag("V", "version", "print version");
+    opts.optflagmulti("v", "verbose", "print progress of each phase (-vv for dumps)");
+    opts.optflagmulti("", "trace", "same as -v");
+    opts.optflag("", "time-passes", "print time taken by each phase");
+    opts.optopt("", "explain", "print detailed description of an error code", "CODE");
+    opts.optopt("", "error-format", "set diagnostic format (human|json)", "FORMAT");
+    opts.optopt("", "color", "colorize diagnostics (auto|always|never)", "WHEN");
+    opts.optmulti("W", "warn", "set lint warned", "LINT");
+    opts.optmulti("A", "allow", "set lint allowed", "LINT");
+    opts.optmulti("D", "deny", "set lint denied", "LINT");
+}
+
+fn option_name(name: &str) -> String {
+    if name.len() == 1 {
+        format!("-{}", name)
+    } else {
+        format!("--{}", name)
+    }
+}
+
+fn fail_message(fail: &Fail) -> String {
+    match fail {
+        Fail::ArgumentMissing(name) => format!("Option `{}` needs an argument!", option_name(name)),
+        Fail::UnrecognizedOption(name) => format!("Unknown option `{}`!", option_name(name)),
+        Fail::OptionDuplicated(name) => format!("Option `{}` is given more than once!", option_name(name)),
+        Fail::UnexpectedArgument(name) => format!("Option `{}` does not take an argument!", option_name(name)),
+        Fail::OptionMissing(name) => format!("Option `{}` is needed!", option_name(name)),
+    }
+}
+
+pub fn parse_args(args: Vec<String>) -> Result<Config, String> {
+    let rest = args.get(1..).unwrap_or(&[]);
+    let (mode, rest) = match rest.first().map(String::as_str) {
+        Some("build") => (Mode::Build, &rest[1..]),
+        Some("run") => (Mode::Run, &rest[1..]),
+        Some("check") => (Mode::Check, &rest[1..]),
+        _ => (Mode::Build, rest),
+    };
+    // `--check` in place of the subcommand, for tools which only add flags.
+    let unflagged: Vec<String>;
+    let (mode, rest) = if mode == Mode::Build && rest.iter().any(|arg| arg == "--check") {
+        unflagged = rest.iter().filter(|arg| *arg != "--check").cloned().collect();
+        (Mode::Check, &unflagged[..])
+    } else {
+        (mode, rest)
+    };
+    // The arguments after "--" are passed to the program.
+    let (rest, run_args) = match rest.iter().position(|arg| arg == "--") {
+        Some(pos) if mode == Mode::Run => (&rest[..pos], &rest[pos + 1..]),
+        _ => (rest, &[][..]),
+    };
+
+    let mut opts = Options::new();
+    let usage = match mode {
+        Mode::Run => {
+            link_options(&mut opts);
+            RUN_USAGE
+        },
+        Mode::Check => CHECK_USAGE,
+        _ => {
+            build_options(&mut opts);
+            link_options(&mut opts);
+            USAGE
+        },
+    };
+    common_options(&mut opts);
+
+    let matches = opts.parse(rest).map_err(|f| fail_message(&f))?;
+
+    if matches.opt_present("h") {
+        return Ok(Config::new(Mode::Help(opts.usage(usage))));
+    }
+    if matches.opt_present("V") {
+        return Ok(Config::new(Mode::Version));
+    }
+    if let Some(code) = matches.opt_str("explain") {
+        return Ok(Config::new(Mode::Explain(code)));
+    }
+
+    let mut config = Config::new(mode.clone());
+    config.error_format = match matches.opt_str("error-format").as_deref() {
+        None | Some("human") => ErrorFormat::Human,
+        Some("json") => ErrorFormat::Json,
+        Some(s) => return Err(format!("Invalid error format! ({})", s)),
+    };
+    config.color = match matches.opt_str("color").as_deref() {
+        None | Some("auto") => ColorChoice::Auto,
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        Some(s) => return Err(format!("Invalid color choice! ({})", s)),
+    };
+    // Lint flags are applied in the order given in the command line.
+    let mut lint_flags: Vec<(usize, String, Level)> = Vec::new();
+    for (opt, level) in [("W", Level::Warn), ("A", Level::Allow), ("D", Level::Deny)].iter() {
+        let iter = matches.opt_strs_pos(opt).into_iter();
+        lint_flags.extend(iter.map(|(pos, name)| (pos, name, *level)));
+    }
+    lint_flags.sort_by_key(|(pos, _, _)| *pos);
+    for (_, name, level) in lint_flags {
+        config.lints.set(&name, level).map_err(|e| e.to_string())?;
+    }
+    config.tracer = Tracer::new(matches.opt_count("v") + matches.opt_count("trace"));
+    config.time_passes = matches.opt_present("time-passes");
+
+    if mode == Mode::Build {
+        if matches.opt_present("c") && matches.opt_present("s") {
+            return Err("-c and -s cannot be used together!".to_string());
+        }
+        config.kind = if matches.opt_present("s") {
+            OutputKind::Asm
+        } else if matches.opt_present("c") {
+            OutputKind::Object
+        } else {
+            OutputKind::Executable
+        };
+        config.emit = match matches.opt_str("emit").as_deref() {
+            None => None,
+            Some("tokens") => Some(Emit::Tokens),
+            Some("ast-json") => Some(Emit::AstJson),
+            Some("ir") => Some(Emit::Ir),
+            Some(s) => return Err(format!("Invalid emit kind! ({})", s)),
+        };
+        config.output_file = matches.opt_str("o");
+        config.save_temps = matches.opt_present("save-temps");
+        config.force = matches.opt_present("f");
+    }
+
+    if mode == Mode::Run {
+        // `run` builds into a temporary file created beforehand.
+        config.force = true;
+    }
+
+    if mode != Mode::Check {
+        // Linker flags are passed in the order given in the command line.
+        let mut link_flags: Vec<(usize, String)> = Vec::new();
+        for (opt, prefix) in [("link-arg", ""), ("l", "-l"), ("L", "-L")].iter() {
+            let iter = matches.opt_strs_pos(opt).into_iter();
+            link_flags.extend(iter.map(|(pos, arg)| (pos, format!("{}{}", prefix, arg))));
+        }
+        link_flags.sort_by_key(|(pos, _)| *pos);
+        config.link_args = link_flags.into_iter().map(|(_, arg)| arg).collect();
+        if matches.opt_present("no-pie") {
+            config.link_args.insert(0, "-no-pie".to_string());
+        }
+        config.cc = matches.opt_str("cc");
+        config.debug_info = matches.opt_present("g");
+        config.opt_level = match matches.opt_default("O", "1").as_deref() {
+            None | Some("0") => 0,
+            Some("1") => 1,
+            Some("2") => 2,
+            Some(s) => return Err(format!("Invalid optimization level! ({})", s)),
+        };
+        config.asm_syntax = match matches.opt_str("asm-syntax").as_deref() {
+            None | Some("intel") => AsmSyntax::Intel,
+            Some("att") => AsmSyntax::Att,
+            Some(s) => return Err(format!("Invalid assembly syntax! ({})", s)),
+        };
+        config.target = match matches.opt_str("target").as_deref() {
+            None | Some("x86_64-linux") => Target::Linux,
+            Some("windows-gnu") => Target::WindowsGnu,
+            Some("aarch64") => Target::Aarch64,
+            Some("riscv64") => Target::Riscv64,
+            Some(s) => return Err(format!("Invalid target! ({})", s)),
+        };
+        if config.asm_syntax == AsmSyntax::Att && !config.target.is_x86() {
+            return Err("--asm-syntax=att is supported only for x86_64 targets!".to_string());
+        }
+        config.integrated_as = matches.opt_present("integrated-as");
+        if config.integrated_as && config.target != Target::Linux {
+            return Err("--integrated-as is supported only for x86_64-linux!".to_string());
+        }
+        if config.integrated_as && config.debug_info {
+            return Err("--integrated-as and -g cannot be used together!".to_string());
+        }
+        config.assembler = match matches.opt_str("assembler").as_deref() {
+            None | Some("cc") => Assembler::Cc,
+            Some("as") => Assembler::As,
+            Some(s) => return Err(format!("Invalid assembler! ({})", s)),
+        };
+        if config.assembler == Assembler::As && config.target != Target::Linux {
+            return Err("--assembler=as is supported only for x86_64-linux!".to_string());
+        }
+        if config.assembler == Assembler::As && config.integrated_as {
+            return Err("--assembler=as and --integrated-as cannot be used together!".to_string());
+        }
+        config.freestanding = matches.opt_present("freestanding");
+        if config.freestanding && config.target != Target::Linux {
+            return Err("--freestanding is supported only for x86_64-linux!".to_string());
+        }
+    }
+
+    if mode != Mode::Check {
+        config.eval = matches.opt_strs("e");
+        config.includes = matches.opt_strs("include");
+    }
+    let outputs = ["o", "s", "c", "emit", "save-temps"];
+    let has_output = mode == Mode::Build && outputs.iter().any(|opt| matches.opt_present(opt));
+    config.input_files = matches.free;
+    config.run_args = run_args.to_vec();
+    if !config.eval.is_empty() {
+        // The snippets are built and run in the same way as `run`.
+        if has_output {
+            return Err("-e makes no output file!".to_string());
+        }
+        if !config.input_files.is_empty() {
+            return Err("-e takes no input file!".to_string());
+        }
+        config.mode = Mode::Run;
+        config.kind = OutputKind::Executable;
+        config.force = true;
+        return Ok(config);
+    }
+    if !config.includes.is_empty() {
+        return Err("--include is only for -e!".to_string());
+    }
+    if config.input_files.is_empty() {
+        return Err("Input file is needed!".to_string());
+    }
+    let single = match mode {
+        Mode::Run => true,
+        Mode::Check => false,
+        // Each input is compiled to its own object file.
+        _ => config.kind != OutputKind::Object || config.emit.is_some() || config.output_file.is_some(),
+    };
+    if single && config.input_files.len() > 1 {
+        return Err("Multiple input files are allowed only with -c and without -o, or with check!".to_string());
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use super::intern::Interner;
+use super::intern::Symbol;
+use super::intern::SymbolMap;
+use super::parse::Ast;
+use super::parse::Node;
+use super::parse::NodeId;
+
+// Whether the statement after `node` is never reached.
+fn diverges(ast: &Ast, node: NodeId) -> bool {
+    match &ast[node] {
+        Node::Return { rhs: _, ty: _ } => true,
+        Node::Block { nodes } => nodes.iter().any(|&node| diverges(ast, node)),
+        Node::IfElse { cond: _, ibody, ebody } => diverges(ast, *ibody) && diverges(ast, *ebody),
+        // There is no `break`, so a loop with a constant condition never ends.
+        Node::While { cond, body: _ } => matches!(ast.const_value(*cond), Some(val) if val != 0),
+        _ => false,
+    }
+}
+
+// Drops the statements after a diverging one in each block. Returns the
+// number of the dropped statements.
+fn prune(ast: &mut Ast, node: NodeId) -> usize {
+    let mut count = ast.children(node).into_iter()
+        .map(|child| prune(ast, child))
+        .sum();
+
+    let keep = match &ast[node] {
+        Node::Block { nodes } => nodes.iter().position(|&node| diverges(ast, node)),
+        _ => None,
+    };
+    if let (Some(pos), Node::Block { nodes }) = (keep, &mut ast[node]) {
+        count += nodes.len() - pos - 1;
+        nodes.truncate(pos + 1);
+    }
+    count
+}
+
+fn calls(ast: &Ast, node: NodeId, names: &mut Vec<Symbol>) {
+    if let Node::Call { name, args: _, ty: _ } = &ast[node] {
+        names.push(*name);
+    }
+    for child in ast.children(node) {
+        calls(ast, child, names);
+    }
+}
+
+// Names of the functions called directly or indirectly from `main` and
+// `pub` functions, which are the entry points from outside.
+fn reachable(ast: &Ast, interner: &Interner) -> HashSet<Symbol> {
+    let mut funcs: SymbolMap<Vec<NodeId>> = SymbolMap::default();
+    let mut work: Vec<Symbol> = Vec::new();
+    for &item in ast.items() {
+        if let Node::Function { name, args: _, stack: _, block: _ } = &ast[item] {
+            funcs.entry(*name).or_default().push(item);
+            if ast.is_public(item) || interner.resolve(*name) == "main" {
+                work.push(*name);
+            }
+        }
+    }
+
+    let mut reached: HashSet<Symbol> = HashSet::new();
+    while let Some(name) = work.pop() {
+        if !reached.insert(name) {
+            continue;
+        }
+        for &item in funcs.get(&name).into_iter().flatten() {
+            calls(ast, item, &mut work);
+        }
+    }
+    reached
+}
+
+// Removes the statements which are never executed, and the functions which
+// are never called. Globals and declarations are all kept. Returns the
+// numbers of the removed statements and functions.
+pub fn eliminate(ast: &mut Ast, interner: &Interner) -> (usize, usize) {
+    let items = ast.items().to_vec();
+    let statements = items.iter()
+        .map(|&item| prune(ast, item))
+        .sum();
+
+    let reached = reachable(ast, interner);
+    let dead: HashSet<NodeId> = items.into_iter()
+        .filter(|&item| match &ast[item] {
+            Node::Function { name, args: _, stack: _, block: _ } => !reached.contains(name),
+            _ => false,
+        })
+        .collect();
+    ast.retain_items(|item| !dead.contains(item));
+
+    (statements, dead.len())
+}
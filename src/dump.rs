@@ -0,0 +1,285 @@
+use super::parse::Ast;
+use super::parse::Node;
+use super::parse::NodeId;
+use super::intern::Interner;
+use super::ir::BinOp;
+use super::ir::CmpOp;
+use super::ir::DivOp;
+use super::ir::Function;
+use super::ir::Op;
+use super::ir::Place;
+use super::ir::Term;
+use super::ir::Val;
+use super::ir::VReg;
+use super::token::Tokens;
+use super::token::TokenKind::*;
+use super::report::json_str;
+use super::report::line_col;
+use super::report::LineTable;
+
+// One token per line with its position, kind and text.
+pub fn token_lines(source: &str, tokens: &Tokens) -> String {
+    let mut out = String::new();
+    for (kind, span) in tokens {
+        let (line, column, _) = line_col(source, span.start);
+        let kind = match kind {
+            TokenOp(_) => "op",
+            TokenNum(_) => "num",
+            TokenIdt(_) => "idt",
+            TokenStr(_) => "str",
+            TokenCStr(_) => "cstr",
+            TokenRsv(_) => "rsv",
+            TokenDoc(_) => "doc",
+            TokenEnd => "end",
+        };
+        let text = source[span.start..span.end].replace('\n', "\\n");
+        let line = format!("{}:{} {} {}", line, column, kind, text);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn json_list(ast: &Ast, interner: &Interner, nodes: &[NodeId]) -> String {
+    let items: Vec<String> = nodes.iter()
+        .map(|node| node_json(ast, interner, *node))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+fn doc_json(ast: &Ast, node: NodeId) -> String {
+    match ast.doc(node) {
+        Some(doc) => json_str(doc),
+        None => "null".to_string(),
+    }
+}
+
+fn node_json(ast: &Ast, interner: &Interner, node: NodeId) -> String {
+    let json = |node: &NodeId| node_json(ast, interner, *node);
+    match &ast[node] {
+        Node::BinaryOperator { kind, lhs, rhs } => {
+            format!("{{\"kind\":\"BinaryOperator\",\"op\":\"{:?}\",\"lhs\":{},\"rhs\":{}}}",
+                    kind, json(lhs), json(rhs))
+        },
+        Node::UnaryOperator { kind, rhs } => {
+            format!("{{\"kind\":\"UnaryOperator\",\"op\":\"{:?}\",\"rhs\":{}}}",
+                    kind, json(rhs))
+        },
+        Node::Number { val } => {
+            format!("{{\"kind\":\"Number\",\"val\":{}}}", val)
+        },
+        Node::Bool { bl } => {
+            format!("{{\"kind\":\"Bool\",\"bl\":{}}}", bl)
+        },
+        Node::StrLiteral { s, label } => {
+            format!("{{\"kind\":\"StrLiteral\",\"s\":{},\"label\":{}}}",
+                    json_str(interner.resolve(*s)), label)
+        },
+        Node::CStrLiteral { s, label } => {
+            format!("{{\"kind\":\"CStrLiteral\",\"s\":{},\"label\":{}}}",
+                    json_str(interner.resolve(*s)), label)
+        },
+        Node::LocalVariable { offset, ty } => {
+            format!("{{\"kind\":\"LocalVariable\",\"offset\":{},\"ty\":{}}}",
+                    offset, json_str(&ty.to_string()))
+        },
+        Node::DeclareLocal { offset, ty } => {
+            format!("{{\"kind\":\"DeclareLocal\",\"offset\":{},\"ty\":{}}}",
+                    offset, json_str(&ty.to_string()))
+        },
+        Node::GlobalVariable { name, offset, ty } => {
+            format!("{{\"kind\":\"GlobalVariable\",\"name\":{},\"offset\":{},\"ty\":{}}}",
+                    json_str(interner.resolve(*name)), offset, json_str(&ty.to_string()))
+        },
+        Node::DeclareGlobal { name, size, ty, init: _, mutable: _ } => {
+            format!("{{\"kind\":\"DeclareGlobal\",\"name\":{},\"doc\":{},\"size\":{},\"ty\":{}}}",
+                    json_str(interner.resolve(*name)), doc_json(ast, node), size,
+                    json_str(&ty.to_string()))
+        },
+        Node::Block { nodes } => {
+            format!("{{\"kind\":\"Block\",\"nodes\":{}}}", json_list(ast, interner, nodes))
+        },
+        Node::Function { name, args, stack, block } => {
+            format!("{{\"kind\":\"Function\",\"name\":{},\"doc\":{},\"args\":{},\"stack\":{},\"block\":{}}}",
+                    json_str(interner.resolve(*name)), doc_json(ast, node),
+                    json_list(ast, interner, args), stack, json(block))
+        },
+        Node::DeclareFunc { name, args } => {
+            format!("{{\"kind\":\"DeclareFunc\",\"name\":{},\"doc\":{},\"args\":{}}}",
+                    json_str(interner.resolve(*name)), doc_json(ast, node),
+                    json_list(ast, interner, args))
+        },
+        Node::Call { name, args, ty } => {
+            format!("{{\"kind\":\"Call\",\"name\":{},\"args\":{},\"ty\":{}}}",
+                    json_str(interner.resolve(*name)), json_list(ast, interner, args),
+                    json_str(&ty.to_string()))
+        },
+        Node::If { cond, ibody } => {
+            format!("{{\"kind\":\"If\",\"cond\":{},\"ibody\":{}}}", json(cond), json(ibody))
+        },
+        Node::IfElse { cond, ibody, ebody } => {
+            format!("{{\"kind\":\"IfElse\",\"cond\":{},\"ibody\":{},\"ebody\":{}}}",
+                    json(cond), json(ibody), json(ebody))
+        },
+        Node::While { cond, body } => {
+            format!("{{\"kind\":\"While\",\"cond\":{},\"body\":{}}}", json(cond), json(body))
+        },
+        Node::Return { rhs, ty } => {
+            format!("{{\"kind\":\"Return\",\"rhs\":{},\"ty\":{}}}", json(rhs), json_str(&ty.to_string()))
+        },
+    }
+}
+
+// Whole program as JSON for tools. Each node is an object with "kind" and
+// the fields of the node, and top level items also have "doc".
+pub fn ast_json(ast: &Ast, interner: &Interner) -> String {
+    format!("{{\"items\":{}}}\n", json_list(ast, interner, ast.items()))
+}
+
+fn tree_node(ast: &Ast, interner: &Interner, node: NodeId, depth: usize, out: &mut String) {
+    let name = |sym| interner.resolve(sym);
+    let (label, children): (String, Vec<NodeId>) = match &ast[node] {
+        Node::BinaryOperator { kind, lhs, rhs } => (format!("{:?}", kind), vec![*lhs, *rhs]),
+        Node::UnaryOperator { kind, rhs } => (format!("{:?}", kind), vec![*rhs]),
+        Node::Number { val } => (format!("Number {}", val), vec![]),
+        Node::Bool { bl } => (format!("Bool {}", bl), vec![]),
+        Node::StrLiteral { s, label } => (format!("StrLiteral {:?} .LC{}", name(*s), label), vec![]),
+        Node::CStrLiteral { s, label } => (format!("CStrLiteral {:?} .LC{}", name(*s), label), vec![]),
+        Node::LocalVariable { offset, ty } => (format!("LocalVariable {} [rbp-{}]", ty, offset), vec![]),
+        Node::DeclareLocal { offset, ty } => (format!("DeclareLocal {} [rbp-{}]", ty, offset), vec![]),
+        Node::GlobalVariable { name: n, offset, ty } => {
+            (format!("GlobalVariable {} {}+{}", ty, name(*n), offset), vec![])
+        },
+        Node::DeclareGlobal { name: n, size, ty, init: _, mutable: _ } => {
+            (format!("DeclareGlobal {}: {} ({} bytes)", name(*n), ty, size), vec![])
+        },
+        Node::Block { nodes } => ("Block".to_string(), nodes.clone()),
+        Node::Function { name: n, args, stack, block } => {
+            let mut children = args.clone();
+            children.push(*block);
+            (format!("Function {} (stack {})", name(*n), stack), children)
+        },
+        Node::DeclareFunc { name: n, args } => (format!("DeclareFunc {}", name(*n)), args.clone()),
+        Node::Call { name: n, args, ty } => (format!("Call {} -> {}", name(*n), ty), args.clone()),
+        Node::If { cond, ibody } => ("If".to_string(), vec![*cond, *ibody]),
+        Node::IfElse { cond, ibody, ebody } => ("IfElse".to_string(), vec![*cond, *ibody, *ebody]),
+        Node::While { cond, body } => ("While".to_string(), vec![*cond, *body]),
+        Node::Return { rhs, ty } => (format!("Return {}", ty), vec![*rhs]),
+    };
+
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(&label);
+    out.push('\n');
+    for child in children {
+        tree_node(ast, interner, child, depth + 1, out);
+    }
+}
+
+// Whole program as an indented tree, a node per line.
+pub fn ast_tree(ast: &Ast, interner: &Interner) -> String {
+    let mut out = String::new();
+    for item in ast.items() {
+        tree_node(ast, interner, *item, 0, &mut out);
+    }
+    out
+}
+
+fn vregs(regs: &[VReg]) -> String {
+    let regs: Vec<String> = regs.iter().map(|r| format!("v{}", r.0)).collect();
+    regs.join(", ")
+}
+
+fn place_text(place: &Place, interner: &Interner) -> String {
+    match place {
+        Place::Frame(disp) => format!("[rbp{:+}]", disp),
+        Place::Global(name, offset) => format!("[{}+{}]", interner.resolve(*name), offset),
+        Place::Ptr(base, offset) => format!("[v{}+{}]", base.0, offset),
+    }
+}
+
+fn val_text(val: &Val) -> String {
+    match val {
+        Val::Reg(r) => format!("v{}", r.0),
+        Val::Imm(i) => (*i as i64).to_string(),
+    }
+}
+
+fn op_text(op: &Op, interner: &Interner, ast: &Ast, lines: &LineTable) -> String {
+    let name = |sym| interner.resolve(sym);
+    match op {
+        Op::Const(d, val) => format!("v{} = const {}", d.0, *val as i64),
+        Op::Str(d, label) => format!("v{} = str .LC{}", d.0, label),
+        Op::Addr(d, place) => format!("v{} = addr {}", d.0, place_text(place, interner)),
+        Op::Load(d, place, size, signed) => {
+            let kind = if *signed { "load" } else { "loadu" };
+            format!("v{} = {}{} {}", d.0, kind, size, place_text(place, interner))
+        },
+        Op::Store(place, v, size) => format!("store{} {}, v{}", size, place_text(place, interner), v.0),
+        Op::StoreArg(place, index, size) => format!("store{} {}, arg{}", size, place_text(place, interner), index),
+        Op::Bin(kind, d, a, b) => {
+            let kind = match kind {
+                BinOp::Add => "add",
+                BinOp::Sub => "sub",
+                BinOp::Mul => "mul",
+                BinOp::And => "and",
+                BinOp::Shl => "shl",
+                BinOp::Sar => "sar",
+                BinOp::Shr => "shr",
+            };
+            format!("v{} = {} v{}, {}", d.0, kind, a.0, val_text(b))
+        },
+        Op::Div(kind, size, d, a, b) => {
+            let kind = match kind {
+                DivOp::Div => "div",
+                DivOp::Rem => "rem",
+                DivOp::Udiv => "udiv",
+                DivOp::Urem => "urem",
+            };
+            format!("v{} = {}{} v{}, {}", d.0, kind, size, a.0, val_text(b))
+        },
+        Op::Cmp(kind, size, d, a, b) => {
+            let kind = match kind {
+                CmpOp::Eq => "eq",
+                CmpOp::Ne => "ne",
+                CmpOp::Lt => "lt",
+                CmpOp::Le => "le",
+                CmpOp::Gt => "gt",
+                CmpOp::Ge => "ge",
+                CmpOp::Ult => "ult",
+                CmpOp::Ule => "ule",
+                CmpOp::Ugt => "ugt",
+                CmpOp::Uge => "uge",
+            };
+            format!("v{} = {}{} v{}, {}", d.0, kind, size, a.0, val_text(b))
+        },
+        Op::Call(dsts, callee, args) if dsts.is_empty() => format!("call {}({})", name(*callee), vregs(args)),
+        Op::Call(dsts, callee, args) => format!("{} = call {}({})", vregs(dsts), name(*callee), vregs(args)),
+        Op::Line(node) => match ast.span(*node) {
+            Some(span) => format!("# line {}: {}", lines.line_col(span.start).0, lines.first_line(span)),
+            None => "#".to_string(),
+        },
+    }
+}
+
+// Function in the IR, an op per line under the label of each block.
+pub fn ir_text(func: &Function, ast: &Ast, interner: &Interner, lines: &LineTable) -> String {
+    let mut out = format!("fn {} (stack {})\n", interner.resolve(func.name), func.stack);
+    for (i, block) in func.blocks.iter().enumerate() {
+        out.push_str(&format!("bb{}:\n", i));
+        for op in block.ops.iter() {
+            out.push_str("    ");
+            out.push_str(&op_text(op, interner, ast, lines));
+            out.push('\n');
+        }
+        let term = match &block.term {
+            Term::Jmp(b) => format!("jmp bb{}", b.0),
+            Term::Br(cond, t, e) => format!("br v{}, bb{}, bb{}", cond.0, t.0, e.0),
+            Term::Ret(vals) if vals.is_empty() => "ret".to_string(),
+            Term::Ret(vals) => format!("ret {}", vregs(vals)),
+        };
+        out.push_str("    ");
+        out.push_str(&term);
+        out.push('\n');
+    }
+    out
+}
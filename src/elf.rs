@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use super::insn::Cond;
+use super::insn::Insn;
+use super::insn::Operand;
+use super::insn::Width;
+
+use Operand::*;
+
+// Registers by their encoding, in the same layout as the other register
+// tables.
+const REGS: [[&str; 4]; 16] = [[  "al",   "ax",  "eax", "rax"],
+                               [  "cl",   "cx",  "ecx", "rcx"],
+                               [  "dl",   "dx",  "edx", "rdx"],
+                               [  "bl",   "bx",  "ebx", "rbx"],
+                               [ "spl",   "sp",  "esp", "rsp"],
+                               [ "bpl",   "bp",  "ebp", "rbp"],
+                               [ "sil",   "si",  "esi", "rsi"],
+                               [ "dil",   "di",  "edi", "rdi"],
+                               [ "r8b",  "r8w",  "r8d",  "r8"],
+                               [ "r9b",  "r9w",  "r9d",  "r9"],
+                               ["r10b", "r10w", "r10d", "r10"],
+                               ["r11b", "r11w", "r11d", "r11"],
+                               ["r12b", "r12w", "r12d", "r12"],
+                               ["r13b", "r13w", "r13d", "r13"],
+                               ["r14b", "r14w", "r14d", "r14"],
+                               ["r15b", "r15w", "r15d", "r15"]];
+
+const R_X86_64_PC32: u64 = 2;
+const R_X86_64_PLT32: u64 = 4;
+
+// Indexes of the sections in the section header table.
+const SHN_TEXT: u16 = 1;
+const SHN_RODATA: u16 = 2;
+const SHN_DATA: u16 = 3;
+const SHN_BSS: u16 = 4;
+const SHN_SYMTAB: u16 = 8;
+const SHN_STRTAB: u16 = 9;
+const SHN_SHSTRTAB: u16 = 10;
+const SECTION_NAMES: [&str; 11] = ["", ".text", ".rodata", ".data", ".bss", ".note.GNU-stack", ".comment",
+                                   ".rela.text", ".symtab", ".strtab", ".shstrtab"];
+// Sections which have their symbols, after the null symbol.
+const SECTION_SYMBOLS: [u16; 4] = [SHN_TEXT, SHN_RODATA, SHN_DATA, SHN_BSS];
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    Text,
+    Rodata,
+    Data,
+    Bss,
+}
+
+impl Section {
+    fn index(self) -> u16 {
+        match self {
+            Section::Text => SHN_TEXT,
+            Section::Rodata => SHN_RODATA,
+            Section::Data => SHN_DATA,
+            Section::Bss => SHN_BSS,
+        }
+    }
+}
+
+// Register field of the ModRM byte, which is an opcode extension for the
+// instructions with one register or memory operand.
+enum Field {
+    Reg(&'static str),
+    Ext(u8),
+}
+
+// Reference from .text to a label, resolved when all labels are known.
+struct Fixup {
+    pos: usize,
+    label: String,
+    addend: i64,
+    kind: FixupKind,
+}
+
+#[derive(PartialEq)]
+enum FixupKind {
+    // rel32 of a jump within .text.
+    Jump,
+    // RIP-relative address of data.
+    Data,
+    Call,
+}
+
+fn cond_code(cond: Cond) -> u8 {
+    match cond {
+        Cond::E => 0x4,
+        Cond::Ne => 0x5,
+        Cond::L => 0xc,
+        Cond::Le => 0xe,
+        Cond::G => 0xf,
+        Cond::Ge => 0xd,
+        Cond::B => 0x2,
+        Cond::Be => 0x6,
+        Cond::A => 0x7,
+        Cond::Ae => 0x3,
+    }
+}
+
+// Number and width of a register.
+fn reg(name: &str) -> Result<(u8, Width), String> {
+    for (num, names) in REGS.iter().enumerate() {
+        if let Some(i) = names.iter().position(|&r| r == name) {
+            return Ok((num as u8, Width::from_size(1 << i)));
+        }
+    }
+    Err(format!("unknown register {}", name))
+}
+
+// Byte registers which can be used only with a REX prefix.
+fn needs_rex(name: &str) -> bool {
+    matches!(name, "spl" | "bpl" | "sil" | "dil")
+}
+
+fn operand_width(op: &Operand) -> Result<Width, String> {
+    match op {
+        Reg(r) => Ok(reg(r)?.1),
+        Mem { width, base: _, disp: _ } | Rip { width, label: _, offset: _ } => Ok(*width),
+        Imm(_) => Err("immediate operand has no size".to_string()),
+    }
+}
+
+// Bytes of an immediate operand of the width. Byte and word immediates are
+// truncated as the assembler does, but the others must fit in 32 bits,
+// signed for 64 bits operations.
+fn imm_bytes(v: u64, width: Width) -> Result<Vec<u8>, String> {
+    let signed = v as i64;
+    match width {
+        Width::Byte => Ok(vec![v as u8]),
+        Width::Word => Ok((v as u16).to_le_bytes().to_vec()),
+        Width::Dword if v <= u32::MAX as u64 || i32::try_from(signed).is_ok() => Ok((v as u32).to_le_bytes().to_vec()),
+        Width::Qword if i32::try_from(signed).is_ok() => Ok((v as u32).to_le_bytes().to_vec()),
+        _ => Err(format!("immediate {} is out of range", v)),
+    }
+}
+
+fn fits_i8(v: u64) -> bool {
+    i8::try_from(v as i64).is_ok()
+}
+
+// Encodes the instruction list into a relocatable ELF object for x86-64
+// Linux. Code is added item by item, and the object is written once all
+// the labels are known.
+pub struct ObjectWriter {
+    section: Section,
+    text: Vec<u8>,
+    rodata: Vec<u8>,
+    data: Vec<u8>,
+    bss: usize,
+    // The largest `.balign` of each section, in the order of `Section`.
+    aligns: [usize; 4],
+    labels: HashMap<String, (Section, usize)>,
+    // Symbols in the order of their first appearance, which makes the
+    // output reproducible.
+    symbols: Vec<String>,
+    globals: Vec<String>,
+    // Types and sizes of the symbols by `.type` and `.size`.
+    types: HashMap<String, u8>,
+    sizes: HashMap<String, u64>,
+    // Strings of `.ident`.
+    comment: Vec<u8>,
+    fixups: Vec<Fixup>,
+}
+
+impl ObjectWriter {
+    pub fn new() -> Self {
+        ObjectWriter {
+            section: Section::Text,
+            text: Vec::new(),
+            rodata: Vec::new(),
+            data: Vec::new(),
+            bss: 0,
+            aligns: [1; 4],
+            labels: HashMap::new(),
+            symbols: Vec::new(),
+            globals: Vec::new(),
+            types: HashMap::new(),
+            sizes: HashMap::new(),
+            comment: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    fn add_symbol(&mut self, name: &str) {
+        if !name.starts_with(".L") && !self.symbols.iter().any(|s| s == name) {
+            self.symbols.push(name.to_string());
+        }
+    }
+
+    // Emits [0x66] [REX] opcode ModRM [SIB] [disp] imm. `width` is the
+    // operand size, where a byte is distinguished by the opcode.
+    fn encode(&mut self, width: Width, opcode: &[u8], field: Field, rm: &Operand, imm: &[u8]) -> Result<(), String> {
+        let (field, mut rex) = match field {
+            Field::Reg(r) => (reg(r)?.0, needs_rex(r)),
+            Field::Ext(ext) => (ext, false),
+        };
+        let mut rex_bits = if width == Width::Qword { 0x8 } else { 0 };
+        if field >= 8 {
+            rex_bits |= 0x4;
+        }
+        let base = match rm {
+            Reg(r) => {
+                rex |= needs_rex(r);
+                Some(reg(r)?.0)
+            },
+            Mem { width: _, base, disp: _ } => Some(reg(base)?.0),
+            Rip { width: _, label: _, offset: _ } => None,
+            Imm(_) => return Err("immediate cannot be a memory operand".to_string()),
+        };
+        if base.is_some_and(|b| b >= 8) {
+            rex_bits |= 0x1;
+        }
+
+        let code = &mut self.text;
+        if width == Width::Word {
+            code.push(0x66);
+        }
+        if rex || rex_bits != 0 {
+            code.push(0x40 | rex_bits);
+        }
+        code.extend_from_slice(opcode);
+        let field = (field & 7) << 3;
+        match rm {
+            Reg(_) => code.push(0xc0 | field | (base.unwrap() & 7)),
+            Mem { width: _, base: _, disp } => {
+                let base = base.unwrap() & 7;
+                // rbp and r13 always take a displacement, and rsp and r12
+                // need the SIB byte.
+                let mode = if *disp == 0 && base != 5 {
+                    0x00
+                } else if i8::try_from(*disp).is_ok() {
+                    0x40
+                } else {
+                    0x80
+                };
+                code.push(mode | field | base);
+                if base == 4 {
+                    code.push(0x24);
+                }
+                match mode {
+                    0x40 => code.push(*disp as u8),
+                    0x80 => code.extend_from_slice(&disp.to_le_bytes()),
+                    _ => {},
+                }
+            },
+            Rip { width: _, label, offset } => {
+                code.push(field | 0x05);
+                let pos = code.len();
+                code.extend_from_slice(&[0; 4]);
+                // The displacement is relative to the end of the
+                // instruction.
+                let addend = offset.unwrap_or(0) as i64 - 4 - imm.len() as i64;
+                let label = label.clone();
+                self.add_symbol(&label);
+                self.fixups.push(Fixup { pos, label, addend, kind: FixupKind::Data });
+            },
+            Imm(_) => unreachable!(),
+        }
+        self.text.extend_from_slice(imm);
+        Ok(())
+    }
+
+    // Emits an opcode which takes the register in its lower 3 bits.
+    fn encode_short(&mut self, width: Width, opcode: u8, r: &str, imm: &[u8]) -> Result<(), String> {
+        let (num, _) = reg(r)?;
+        let mut rex_bits = if width == Width::Qword { 0x8 } else { 0 };
+        if num >= 8 {
+            rex_bits |= 0x1;
+        }
+        if width == Width::Word {
+            self.text.push(0x66);
+        }
+        if needs_rex(r) || rex_bits != 0 {
+            self.text.push(0x40 | rex_bits);
+        }
+        self.text.push(opcode | (num & 7));
+        self.text.extend_from_slice(imm);
+        Ok(())
+    }
+
+    // add, sub, and and cmp, which share the encodings with `base` of the
+    // opcode and `ext` of the immediate forms.
+    fn encode_alu(&mut self, base: u8, ext: u8, dst: &Operand, src: &Operand) -> Result<(), String> {
+        let width = operand_width(dst)?;
+        let byte = (width == Width::Byte) as u8;
+        match (dst, src) {
+            (_, Reg(s)) => self.encode(width, &[base + 1 - byte], Field::Reg(s), dst, &[]),
+            (Reg(d), Mem { width: _, base: _, disp: _ } | Rip { width: _, label: _, offset: _ }) => {
+                self.encode(width, &[base + 3 - byte], Field::Reg(d), src, &[])
+            },
+            (_, Imm(v)) if width == Width::Byte => self.encode(width, &[0x80], Field::Ext(ext), dst, &[*v as u8]),
+            (_, Imm(v)) if fits_i8(*v) => self.encode(width, &[0x83], Field::Ext(ext), dst, &[*v as u8]),
+            (_, Imm(v)) => self.encode(width, &[0x81], Field::Ext(ext), dst, &imm_bytes(*v, width)?),
+            _ => Err("invalid operands".to_string()),
+        }
+    }
+
+    // sal, shr and sar by an immediate or cl.
+    fn encode_shift(&mut self, ext: u8, dst: &Operand, src: &Operand) -> Result<(), String> {
+        let width = operand_width(dst)?;
+        let byte = (width == Width::Byte) as u8;
+        match src {
+            Imm(1) => self.encode(width, &[0xd1 - byte], Field::Ext(ext), dst, &[]),
+            Imm(v) => self.encode(width, &[0xc1 - byte], Field::Ext(ext), dst, &[*v as u8]),
+            Reg("cl") => self.encode(width, &[0xd3 - byte], Field::Ext(ext), dst, &[]),
+            _ => Err("shift count must be an immediate or cl".to_string()),
+        }
+    }
+
+    // rel32 to a label, which is filled in later.
+    fn encode_rel32(&mut self, opcode: &[u8], label: &str, kind: FixupKind) {
+        self.text.extend_from_slice(opcode);
+        let pos = self.text.len();
+        self.text.extend_from_slice(&[0; 4]);
+        self.fixups.push(Fixup { pos, label: label.to_string(), addend: -4, kind });
+    }
+
+    fn encode_insn(&mut self, insn: &Insn) -> Result<(), String> {
+        match insn {
+            Insn::Push(Reg(r)) => self.encode_short(Width::Dword, 0x50, r, &[]),
+            Insn::Push(Imm(v)) if fits_i8(*v) => {
+                self.text.extend_from_slice(&[0x6a, *v as u8]);
+                Ok(())
+            },
+            Insn::Push(Imm(v)) => {
+                self.text.push(0x68);
+                self.text.extend_from_slice(&imm_bytes(*v, Width::Qword)?);
+                Ok(())
+            },
+            // push takes 64 bits without REX.W.
+            Insn::Push(src) => self.encode(Width::Dword, &[0xff], Field::Ext(6), src, &[]),
+            Insn::Pop(r) => self.encode_short(Width::Dword, 0x58, r, &[]),
+            Insn::Mov(dst, src) => {
+                let width = operand_width(dst)?;
+                let byte = (width == Width::Byte) as u8;
+                match (dst, src) {
+                    (Reg(d), Imm(v)) if width == Width::Qword && i32::try_from(*v as i64).is_err() => {
+                        self.encode_short(width, 0xb8, d, &v.to_le_bytes())
+                    },
+                    (Reg(d), Imm(v)) if width != Width::Qword => {
+                        let opcode = if byte == 1 { 0xb0 } else { 0xb8 };
+                        self.encode_short(width, opcode, d, &imm_bytes(*v, width)?)
+                    },
+                    (_, Imm(v)) => self.encode(width, &[0xc7 - byte], Field::Ext(0), dst, &imm_bytes(*v, width)?),
+                    (_, Reg(s)) => self.encode(width, &[0x89 - byte], Field::Reg(s), dst, &[]),
+                    (Reg(d), _) => self.encode(width, &[0x8b - byte], Field::Reg(d), src, &[]),
+                    _ => Err("invalid operands".to_string()),
+                }
+            },
+            Insn::Movsx(dst, src) => {
+                let opcode: &[u8] = match operand_width(src)? {
+                    Width::Byte => &[0x0f, 0xbe],
+                    Width::Word => &[0x0f, 0xbf],
+                    // movsxd
+                    Width::Dword => &[0x63],
+                    _ => return Err("movsx takes a byte, a word or a dword".to_string()),
+                };
+                self.encode(reg(dst)?.1, opcode, Field::Reg(dst), src, &[])
+            },
+            Insn::Movzx(dst, src) => {
+                let opcode = match operand_width(src)? {
+                    Width::Byte => 0xb6,
+                    Width::Word => 0xb7,
+                    _ => return Err("movzx takes a byte or a word".to_string()),
+                };
+                self.encode(reg(dst)?.1, &[0x0f, opcode], Field::Reg(dst), src, &[])
+            },
+            Insn::Movzb(dst, src) => self.encode(reg(dst)?.1, &[0x0f, 0xb6], Field::Reg(dst), &Reg(src), &[]),
+            Insn::Lea(dst, src) => self.encode(reg(dst)?.1, &[0x8d], Field::Reg(dst), src, &[]),
+            Insn::Add(dst, src) => self.encode_alu(0x00, 0, dst, src),
+            Insn::And(dst, src) => self.encode_alu(0x20, 4, dst, src),
+            Insn::Sub(dst, src) => self.encode_alu(0x28, 5, dst, src),
+            Insn::Cmp(dst, src) => self.encode_alu(0x38, 7, dst, src),
+            Insn::Imul(Reg(d), Imm(v)) => {
+                let width = reg(d)?.1;
+                if fits_i8(*v) {
+                    self.encode(width, &[0x6b], Field::Reg(d), &Reg(d), &[*v as u8])
+                } else {
+                    self.encode(width, &[0x69], Field::Reg(d), &Reg(d), &imm_bytes(*v, width)?)
+                }
+            },
+            Insn::Imul(Reg(d), src) => self.encode(reg(d)?.1, &[0x0f, 0xaf], Field::Reg(d), src, &[]),
+            Insn::Sal(dst, src) => self.encode_shift(4, dst, src),
+            Insn::Shr(dst, src) => self.encode_shift(5, dst, src),
+            Insn::Sar(dst, src) => self.encode_shift(7, dst, src),
+            Insn::Cdq => {
+                self.text.push(0x99);
+                Ok(())
+            },
+            Insn::Cqo => {
+                self.text.extend_from_slice(&[0x48, 0x99]);
+                Ok(())
+            },
+            Insn::Div(src) => {
+                let width = operand_width(src)?;
+                let opcode = if width == Width::Byte { 0xf6 } else { 0xf7 };
+                self.encode(width, &[opcode], Field::Ext(6), src, &[])
+            },
+            Insn::Idiv(src) => {
+                let width = operand_width(src)?;
+                let opcode = if width == Width::Byte { 0xf6 } else { 0xf7 };
+                self.encode(width, &[opcode], Field::Ext(7), src, &[])
+            },
+            Insn::Set(cond, dst) => self.encode(Width::Byte, &[0x0f, 0x90 | cond_code(*cond)], Field::Ext(0), &Reg(dst), &[]),
+            Insn::Jmp(label) => {
+                self.encode_rel32(&[0xe9], label, FixupKind::Jump);
+                Ok(())
+            },
+            Insn::Jcc(cond, label) => {
+                self.encode_rel32(&[0x0f, 0x80 | cond_code(*cond)], label, FixupKind::Jump);
+                Ok(())
+            },
+            Insn::Call(name) => {
+                let name = name.trim_end_matches("@PLT");
+                self.add_symbol(name);
+                self.encode_rel32(&[0xe8], name, FixupKind::Call);
+                Ok(())
+            },
+            Insn::Ret => {
+                self.text.push(0xc3);
+                Ok(())
+            },
+            Insn::Syscall => {
+                self.text.extend_from_slice(&[0x0f, 0x05]);
+                Ok(())
+            },
+            _ => Err(format!("{:?} is not an instruction", insn)),
+        }
+    }
+
+    // Current offset in the section.
+    fn offset(&self) -> usize {
+        match self.section {
+            Section::Text => self.text.len(),
+            Section::Rodata => self.rodata.len(),
+            Section::Data => self.data.len(),
+            Section::Bss => self.bss,
+        }
+    }
+
+    // Pads the section to a multiple of the alignment. Code is padded with
+    // nops, and the others with zeros.
+    fn align(&mut self, align: usize) {
+        let padding = self.offset().next_multiple_of(align) - self.offset();
+        match self.section {
+            Section::Text => self.text.resize(self.text.len() + padding, 0x90),
+            Section::Rodata => self.rodata.resize(self.rodata.len() + padding, 0),
+            Section::Data => self.data.resize(self.data.len() + padding, 0),
+            Section::Bss => self.bss += padding,
+        }
+        let max = &mut self.aligns[self.section as usize];
+        *max = (*max).max(align);
+    }
+
+    fn define_label(&mut self, label: &str) -> Result<(), String> {
+        let offset = self.offset();
+        if self.labels.insert(label.to_string(), (self.section, offset)).is_some() {
+            return Err(format!("label {} is defined twice", label));
+        }
+        self.add_symbol(label);
+        Ok(())
+    }
+
+    fn directive(&mut self, s: &str) -> Result<(), String> {
+        match s {
+            ".intel_syntax noprefix" => {},
+            ".text" => self.section = Section::Text,
+            ".section .rodata" => self.section = Section::Rodata,
+            ".data" => self.section = Section::Data,
+            ".bss" => self.section = Section::Bss,
+            // The section is always written, and is empty.
+            _ if s.starts_with(".section .note.GNU-stack") => {},
+            _ if s.starts_with(".balign ") => {
+                match s[".balign ".len()..].parse::<usize>() {
+                    Ok(align) if align.is_power_of_two() => self.align(align),
+                    _ => return Err(format!("malformed directive {}", s)),
+                }
+            },
+            _ if s.starts_with(".global ") => {
+                let name = &s[".global ".len()..];
+                self.add_symbol(name);
+                self.globals.push(name.to_string());
+            },
+            _ if s.starts_with(".type ") => {
+                let (name, kind) = s[".type ".len()..].split_once(", ")
+                    .ok_or_else(|| format!("malformed directive {}", s))?;
+                let kind = match kind {
+                    "@function" => STT_FUNC,
+                    "@object" => STT_OBJECT,
+                    _ => return Err(format!("unsupported symbol type {}", kind)),
+                };
+                self.types.insert(name.to_string(), kind);
+            },
+            // The size is either a number, or `.-name` from the label to
+            // here.
+            _ if s.starts_with(".size ") => {
+                let (name, size) = s[".size ".len()..].split_once(", ")
+                    .ok_or_else(|| format!("malformed directive {}", s))?;
+                let size = match size.strip_prefix(".-") {
+                    Some(label) => match self.labels.get(label) {
+                        Some((section, offset)) if *section == self.section => (self.offset() - offset) as u64,
+                        _ => return Err(format!("size from undefined label {}", label)),
+                    },
+                    None => size.parse().map_err(|_| format!("malformed directive {}", s))?,
+                };
+                self.sizes.insert(name.to_string(), size);
+            },
+            // The section begins with an empty string as the assembler
+            // makes it.
+            _ if s.starts_with(".ident \"") && s.ends_with('\"') => {
+                if self.comment.is_empty() {
+                    self.comment.push(0);
+                }
+                self.comment.extend(unescape_ascii(&s[".ident \"".len()..s.len() - 1]));
+                self.comment.push(0);
+            },
+            _ => return Err(format!("unsupported directive {}", s)),
+        }
+        Ok(())
+    }
+
+    // Adds the code of an item.
+    pub fn assemble(&mut self, insns: &[Insn]) -> Result<(), String> {
+        for insn in insns {
+            match insn {
+                Insn::Label(label) => self.define_label(label)?,
+                Insn::Directive(s) => self.directive(s)?,
+                Insn::Zero(size) if self.section == Section::Bss => self.bss += size,
+                Insn::Ascii(s) if self.section == Section::Rodata => self.rodata.extend(unescape_ascii(s)),
+                Insn::Data(size, val) if self.section == Section::Rodata => {
+                    self.rodata.extend_from_slice(&val.to_le_bytes()[..*size]);
+                },
+                Insn::Data(size, val) if self.section == Section::Data => {
+                    self.data.extend_from_slice(&val.to_le_bytes()[..*size]);
+                },
+                Insn::Comment(_) | Insn::LineComment(_, _) | Insn::Blank => {},
+                _ if self.section == Section::Text => self.encode_insn(insn)?,
+                _ => return Err(format!("{:?} is not allowed in the section", insn)),
+            }
+        }
+        Ok(())
+    }
+
+    // Resolves the jumps, and makes the object with the relocations of the
+    // other references.
+    pub fn finish(mut self) -> Result<Vec<u8>, String> {
+        // Symbol table: the null symbol, the sections, the local symbols
+        // and the global ones.
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 24];
+        for index in SECTION_SYMBOLS.iter() {
+            symtab.extend(symbol(0, 0x03, *index, 0, 0));
+        }
+        let first_symbol = 1 + SECTION_SYMBOLS.len();
+        let mut indexes: HashMap<&str, u32> = HashMap::new();
+        // Undefined symbols are global as well.
+        let labels = &self.labels;
+        let globals = &self.globals;
+        let (globals, locals): (Vec<&String>, Vec<&String>) = self.symbols.iter()
+            .partition(|name| globals.contains(name) || !labels.contains_key(*name));
+        let first_global = first_symbol + locals.len();
+        for (i, name) in locals.iter().chain(globals.iter()).enumerate() {
+            let name_offset = strtab.len() as u32;
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            let bind = if i + first_symbol >= first_global { 0x10 } else { 0x00 };
+            let kind = self.types.get(*name).copied().unwrap_or(0);
+            let size = self.sizes.get(*name).copied().unwrap_or(0);
+            let (shndx, value) = match self.labels.get(*name) {
+                Some((section, offset)) => (section.index(), *offset as u64),
+                None => (0, 0),
+            };
+            symtab.extend(symbol(name_offset, bind | kind, shndx, value, size));
+            indexes.insert(name.as_str(), (first_symbol + i) as u32);
+        }
+
+        let mut rela = Vec::new();
+        for fixup in self.fixups.iter() {
+            let label = self.labels.get(&fixup.label);
+            let (sym, addend, kind) = match (&fixup.kind, label) {
+                (FixupKind::Jump, Some((Section::Text, offset))) => {
+                    let rel = *offset as i64 - (fixup.pos as i64 + 4);
+                    self.text[fixup.pos..fixup.pos + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+                    continue;
+                },
+                (FixupKind::Jump, _) => return Err(format!("jump to undefined label {}", fixup.label)),
+                // Local labels are referred through their section.
+                (_, Some((section, offset))) if fixup.label.starts_with(".L") => {
+                    (section.index() as u32, fixup.addend + *offset as i64, R_X86_64_PC32)
+                },
+                (_, None) if fixup.label.starts_with(".L") => {
+                    return Err(format!("reference to undefined label {}", fixup.label));
+                },
+                (FixupKind::Call, _) => (indexes[fixup.label.as_str()], fixup.addend, R_X86_64_PLT32),
+                (FixupKind::Data, _) => (indexes[fixup.label.as_str()], fixup.addend, R_X86_64_PC32),
+            };
+            rela.extend_from_slice(&(fixup.pos as u64).to_le_bytes());
+            rela.extend_from_slice(&(((sym as u64) << 32) | kind).to_le_bytes());
+            rela.extend_from_slice(&addend.to_le_bytes());
+        }
+
+        let mut shstrtab = vec![0u8];
+        let mut names = Vec::new();
+        for name in SECTION_NAMES.iter() {
+            names.push(if name.is_empty() { 0 } else { shstrtab.len() as u32 });
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+
+        // The contents follow the ELF header, and the section headers are
+        // at the end.
+        let contents: [&[u8]; 11] = [&[], &self.text, &self.rodata, &self.data, &[], &[], &self.comment,
+                                     &rela, &symtab, &strtab, &shstrtab];
+        let mut offsets = Vec::new();
+        let mut out = vec![0u8; 64];
+        for content in contents.iter() {
+            // The tables of fixed size entries are aligned to 8 bytes.
+            out.resize(out.len().next_multiple_of(8), 0);
+            offsets.push(out.len() as u64);
+            out.extend_from_slice(content);
+        }
+        out.resize(out.len().next_multiple_of(8), 0);
+        let shoff = out.len() as u64;
+
+        // (type, flags, size, link, info, align, entsize)
+        let headers: [(u32, u64, u64, u32, u32, u64, u64); 11] = [
+            (0, 0, 0, 0, 0, 0, 0),
+            (1, 0x6, self.text.len() as u64, 0, 0, self.aligns[0] as u64, 0),
+            (1, 0x2, self.rodata.len() as u64, 0, 0, self.aligns[1] as u64, 0),
+            (1, 0x3, self.data.len() as u64, 0, 0, self.aligns[2] as u64, 0),
+            (8, 0x3, self.bss as u64, 0, 0, self.aligns[3] as u64, 0),
+            (1, 0x0, 0, 0, 0, 1, 0),
+            (1, 0x30, self.comment.len() as u64, 0, 0, 1, 1),
+            (4, 0x40, rela.len() as u64, SHN_SYMTAB as u32, SHN_TEXT as u32, 8, 24),
+            (2, 0x0, symtab.len() as u64, SHN_STRTAB as u32, first_global as u32, 8, 24),
+            (3, 0x0, strtab.len() as u64, 0, 0, 1, 0),
+            (3, 0x0, shstrtab.len() as u64, 0, 0, 1, 0),
+        ];
+        for (i, (kind, flags, size, link, info, align, entsize)) in headers.iter().enumerate() {
+            out.extend_from_slice(&names[i].to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&flags.to_le_bytes());
+            out.extend_from_slice(&0u64.to_le_bytes());
+            out.extend_from_slice(&(if i == 0 { 0 } else { offsets[i] }).to_le_bytes());
+            out.extend_from_slice(&size.to_le_bytes());
+            out.extend_from_slice(&link.to_le_bytes());
+            out.extend_from_slice(&info.to_le_bytes());
+            out.extend_from_slice(&align.to_le_bytes());
+            out.extend_from_slice(&entsize.to_le_bytes());
+        }
+
+        let header = &mut out[..64];
+        header[..16].copy_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        header[16..18].copy_from_slice(&1u16.to_le_bytes());
+        header[18..20].copy_from_slice(&62u16.to_le_bytes());
+        header[20..24].copy_from_slice(&1u32.to_le_bytes());
+        header[40..48].copy_from_slice(&shoff.to_le_bytes());
+        header[52..54].copy_from_slice(&64u16.to_le_bytes());
+        header[58..60].copy_from_slice(&64u16.to_le_bytes());
+        header[60..62].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        header[62..64].copy_from_slice(&SHN_SHSTRTAB.to_le_bytes());
+
+        Ok(out)
+    }
+}
+
+// Entry of the symbol table, where `info` is the binding and the type.
+fn symbol(name: u32, info: u8, shndx: u16, value: u64, size: u64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(24);
+    entry.extend_from_slice(&name.to_le_bytes());
+    entry.push(info);
+    entry.push(0);
+    entry.extend_from_slice(&shndx.to_le_bytes());
+    entry.extend_from_slice(&value.to_le_bytes());
+    entry.extend_from_slice(&size.to_le_bytes());
+    entry
+}
+
+// Bytes of a string escaped for `.ascii`.
+fn unescape_ascii(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut iter = s.bytes().peekable();
+    while let Some(b) = iter.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(d @ b'0'..=b'7') => {
+                let mut v = (d - b'0') as u32;
+                for _ in 0..2 {
+                    match iter.peek() {
+                        Some(d @ b'0'..=b'7') => {
+                            v = v * 8 + (d - b'0') as u32;
+                            iter.next();
+                        },
+                        _ => break,
+                    }
+                }
+                bytes.push(v as u8);
+            },
+            Some(c) => bytes.push(c),
+            None => {},
+        }
+    }
+    bytes
+}
@@ -0,0 +1,259 @@
+// Detailed descriptions of each error code.
+//
+// E00xx: Tokenize errors
+// E01xx: Parse errors
+// E02xx: Assembly generation errors
+pub const ERROR_CODES: [(&str, &str); 35] = [
+    ("E0001",
+     "The source code contains a character which cannot start any token.\n\
+      \n\
+      Only ASCII letters, digits, operators, string literals and comments\n\
+      are allowed in the source code. Non-ASCII characters can be used only\n\
+      in string literals and comments."),
+    ("E0002",
+     "A string literal is not terminated.\n\
+      \n\
+      Every string literal must be closed by `\"` before the end of file."),
+    ("E0003",
+     "A block comment is not terminated.\n\
+      \n\
+      Every `/*` must be closed by `*/` before the end of file."),
+    ("E0004",
+     "An unknown escape sequence is used in a string literal.\n\
+      \n\
+      Supported escape sequences are \\n, \\r, \\t, \\0, \\\\, \\\" and \\'."),
+    ("E0005",
+     "A number literal is too large.\n\
+      \n\
+      Number literals must fit in the largest supported integer type."),
+    ("E0006",
+     "A number literal contains an invalid digit.\n\
+      \n\
+      Only decimal digits are allowed in number literals, and a number must\n\
+      be separated from a following identifier."),
+    ("E0101",
+     "A statement is not terminated by a semicolon.\n\
+      \n\
+      Expression statements, `let`, `static` and `return` must end with `;`:\n\
+      \n\
+      let a: i32;\n\
+      return a;"),
+    ("E0102",
+     "A colon is missing between a name and its type.\n\
+      \n\
+      Variables and arguments are declared as `name: type`:\n\
+      \n\
+      let a: i32;"),
+    ("E0103",
+     "A parenthesis, bracket or brace is not closed.\n\
+      \n\
+      Each `(`, `[` and `{` must have a matching `)`, `]` and `}`."),
+    ("E0104",
+     "A function has neither a body nor a terminating semicolon.\n\
+      \n\
+      A function definition needs a block, and a declaration of an external\n\
+      function needs `;`:\n\
+      \n\
+      fn foo(a: i32) -> i32;"),
+    ("E0105",
+     "A number literal is expected here.\n\
+      \n\
+      Array lengths and array indexes must be number literals:\n\
+      \n\
+      let a: [i32; 4];\n\
+      a[2] = 1;"),
+    ("E0106",
+     "A function name is expected after `fn`."),
+    ("E0107",
+     "A variable name is expected here.\n\
+      \n\
+      `let`, `static` and function arguments need an identifier as a name."),
+    ("E0108",
+     "A type is expected here.\n\
+      \n\
+      Supported types are i8, i16, i32, i64, u8, u16, u32, u64, bool,\n\
+      arrays `[T; N]`, references `&T` and slices `&str`."),
+    ("E0109",
+     "The argument list of a function is missing.\n\
+      \n\
+      A function name must be followed by `(`, even without arguments:\n\
+      \n\
+      fn main() {}"),
+    ("E0110",
+     "The type of the variable does not allow this operation.\n\
+      \n\
+      For example, only arrays can be indexed with `[]`. An array itself\n\
+      is not a value: it can be indexed or referenced with `&`, but not\n\
+      initialized, assigned, passed or returned as a whole."),
+    ("E0111",
+     "The variable or function is not declared.\n\
+      \n\
+      Variables must be declared with `let` or `static`, and functions must\n\
+      be defined or declared before they are used."),
+    ("E0112",
+     "This item cannot be used at the top level.\n\
+      \n\
+      Only `fn` and `static` items are allowed outside of functions."),
+    ("E0113",
+     "The size of the type is not known at compile time.\n\
+      \n\
+      `str` cannot be used as a variable type. Use `&str` instead.\n\
+      \n\
+      An array cannot be larger than 2147483647 bytes either."),
+    ("E0114",
+     "The expression is invalid.\n\
+      \n\
+      An operand such as a number, a variable or a function call is\n\
+      expected here."),
+    ("E0115",
+     "A keyword is used where an identifier is expected.\n\
+      \n\
+      Keywords such as `if`, `return` or `i32` cannot be used as names of\n\
+      variables, parameters or functions. Choose another name."),
+    ("E0116",
+     "A number is divided by a constant zero with `/` or `%`.\n\
+      \n\
+      The divisor is evaluated at compile time, and the division would\n\
+      always trap at runtime:\n\
+      \n\
+      let a: i32 = 6 / (2 - 2);"),
+    ("E0117",
+     "An assignment is used where a value is needed.\n\
+      \n\
+      As in Rust, an assignment has no value, so it cannot be chained or\n\
+      be a part of another expression:\n\
+      \n\
+      a = b = 3;\n\
+      if (n = next()) != 0 { ... }\n\
+      \n\
+      Write each assignment as a statement of its own."),
+    ("E0118",
+     "A function without a return type is called where a value is needed.\n\
+      \n\
+      A function without `->` returns nothing, so a call to it can be only\n\
+      a statement of its own:\n\
+      \n\
+      fn log(n: i32) { ... }\n\
+      let a: i32 = log(1);\n\
+      \n\
+      Declare the return type if the function computes a value:\n\
+      \n\
+      fn log(n: i32) -> i32 { ... }"),
+    ("E0119",
+     "An array is indexed past its end.\n\
+      \n\
+      The index of an array must be less than its length:\n\
+      \n\
+      let a: [i32; 3];\n\
+      a[3] = 1;\n\
+      \n\
+      The elements of this array are `a[0]`, `a[1]` and `a[2]`."),
+    ("E0120",
+     "The code is nested too deeply.\n\
+      \n\
+      Parentheses, operators, blocks and types can be nested up to 128\n\
+      levels, and a chain of binary operators counts a level for each\n\
+      operator. Split a long expression with local variables:\n\
+      \n\
+      let a: i64 = x1 + x2 + x3;\n\
+      let b: i64 = a + x4 + x5;"),
+    ("E0121",
+     "The initializer of a static is not a constant.\n\
+      \n\
+      A static is initialized before the program starts, so its value is\n\
+      computed at compile time from literals and operators only. Variables\n\
+      and function calls cannot be used:\n\
+      \n\
+      static SIZE: i32 = 4 * 1024;\n\
+      static LIMIT: i32 = SIZE * 2;"),
+    ("E0122",
+     "A static is initialized with an address.\n\
+      \n\
+      The address of another static is not known at compile time here, so\n\
+      `&` cannot be used in the initializer of a static:\n\
+      \n\
+      static A: i32;\n\
+      static P: &i32 = &A;\n\
+      \n\
+      Assign the address in a function instead."),
+    ("E0123",
+     "The initializer of a static does not fit in its type.\n\
+      \n\
+      The value computed at compile time must be in the range of the type\n\
+      of the static, as it is stored without truncation:\n\
+      \n\
+      static B: u8 = 256;\n\
+      static C: u32 = 0 - 1;"),
+    ("E0124",
+     "A static which is not `mut` is assigned to.\n\
+      \n\
+      As in Rust, a static is read-only unless it is declared with `mut`:\n\
+      \n\
+      static COUNT: i32;\n\
+      COUNT = COUNT + 1;\n\
+      \n\
+      Declare it as `static mut COUNT: i32;` to change it."),
+    ("E0201",
+     "The assembly code could not be written to the output file."),
+    ("E0202",
+     "The left-hand side of the assignment is not assignable.\n\
+      \n\
+      Only variables, array elements and dereferenced pointers can be\n\
+      assigned to or referenced with `&`."),
+    ("E0203",
+     "The dereferenced expression is not a pointer.\n\
+      \n\
+      `*` can only be applied to a reference type such as `&i32`."),
+    ("E0204",
+     "A function takes or is called with more arguments than the argument\n\
+      registers of the target.\n\
+      \n\
+      Arguments are passed only in registers: six on Linux and four on\n\
+      Windows. A slice takes two registers."),
+    ("E0205",
+     "The generated code cannot be encoded into an object file by\n\
+      --integrated-as.\n\
+      \n\
+      The integrated assembler supports only the instructions which yrc\n\
+      generates for x86_64-linux. Build without --integrated-as to use the\n\
+      external assembler instead."),
+];
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn codes_are_unique() -> bool {
+    let mut i = 0;
+    while i < ERROR_CODES.len() {
+        let mut j = i + 1;
+        while j < ERROR_CODES.len() {
+            if str_eq(ERROR_CODES[i].0, ERROR_CODES[j].0) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const _: () = assert!(codes_are_unique(), "Error codes must be unique!");
+
+pub fn explain(code: &str) -> Option<&'static str> {
+    ERROR_CODES.iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, text)| *text)
+}
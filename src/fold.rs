@@ -0,0 +1,61 @@
+use std::mem;
+
+use super::parse::Ast;
+use super::parse::BinaryOpKind::*;
+use super::parse::Node;
+use super::parse::NodeId;
+
+fn empty() -> Node {
+    Node::Block { nodes: Vec::new() }
+}
+
+// Returns the number of the folded nodes.
+fn fold_node(ast: &mut Ast, node: NodeId) -> usize {
+    let mut count = ast.children(node).into_iter()
+        .map(|child| fold_node(ast, child))
+        .sum();
+
+    let folded = match ast[node] {
+        Node::BinaryOperator { kind: BinaryOpAsn, lhs: _, rhs: _ } => None,
+        Node::BinaryOperator { ref kind, lhs: _, rhs: _ } => {
+            let compare = matches!(kind, BinaryOpEq | BinaryOpNe | BinaryOpLt | BinaryOpLe | BinaryOpGr | BinaryOpGe);
+            ast.const_value(node).map(|val| {
+                if compare {
+                    Node::Bool { bl: val != 0 }
+                } else {
+                    Node::Number { val }
+                }
+            })
+        },
+        // The taken branch is moved into the place of the `if`, so that
+        // it keeps the source line of the statement.
+        Node::If { cond, ibody } => match ast.const_value(cond) {
+            Some(0) => Some(empty()),
+            Some(_) => Some(mem::replace(&mut ast[ibody], empty())),
+            None => None,
+        },
+        Node::IfElse { cond, ibody, ebody } => match ast.const_value(cond) {
+            Some(0) => Some(mem::replace(&mut ast[ebody], empty())),
+            Some(_) => Some(mem::replace(&mut ast[ibody], empty())),
+            None => None,
+        },
+        // An endless loop still needs the jump back.
+        Node::While { cond, body: _ } if ast.const_value(cond) == Some(0) => Some(empty()),
+        _ => None,
+    };
+
+    if let Some(new) = folded {
+        ast[node] = new;
+        count += 1;
+    }
+    count
+}
+
+// Evaluates the expressions made only of literals at compile time, and
+// removes the branches which are never taken.
+pub fn fold(ast: &mut Ast) -> usize {
+    let items = ast.items().to_vec();
+    items.into_iter()
+        .map(|item| fold_node(ast, item))
+        .sum()
+}
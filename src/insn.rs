@@ -0,0 +1,418 @@
+use std::fmt::Write as _;
+use std::io;
+use std::io::prelude::*;
+
+use Operand::*;
+
+// Size of a memory operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl Width {
+    pub fn from_size(size: usize) -> Self {
+        match size {
+            1 => Width::Byte,
+            2 => Width::Word,
+            4 => Width::Dword,
+            8 => Width::Qword,
+            _ => unreachable!(),
+        }
+    }
+
+    // Size of a register by its name.
+    fn of_reg(reg: &str) -> Self {
+        let numbered = reg.len() > 1 && reg.as_bytes()[1].is_ascii_digit();
+        match reg.as_bytes() {
+            _ if numbered && reg.ends_with('b') => Width::Byte,
+            _ if numbered && reg.ends_with('w') => Width::Word,
+            _ if numbered && reg.ends_with('d') => Width::Dword,
+            _ if numbered => Width::Qword,
+            [b'r', ..] => Width::Qword,
+            [b'e', ..] => Width::Dword,
+            [.., b'l'] => Width::Byte,
+            _ => Width::Word,
+        }
+    }
+
+    fn suffix(self) -> char {
+        match self {
+            Width::Byte => 'b',
+            Width::Word => 'w',
+            Width::Dword => 'l',
+            Width::Qword => 'q',
+        }
+    }
+
+    fn intel(self) -> &'static str {
+        match self {
+            Width::Byte => "BYTE PTR",
+            Width::Word => "WORD PTR",
+            Width::Dword => "DWORD PTR",
+            Width::Qword => "QWORD PTR",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Reg(&'static str),
+    Imm(u64),
+    // [base+disp]
+    Mem { width: Width, base: &'static str, disp: i32 },
+    // label[rip+offset], or label[rip] without the offset.
+    Rip { width: Width, label: String, offset: Option<usize> },
+}
+
+// Condition of `set` and conditional jumps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cond {
+    E,
+    Ne,
+    L,
+    Le,
+    G,
+    Ge,
+    // Unsigned conditions.
+    B,
+    Be,
+    A,
+    Ae,
+}
+
+impl Cond {
+    fn suffix(self) -> &'static str {
+        match self {
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::L => "l",
+            Cond::Le => "le",
+            Cond::G => "g",
+            Cond::Ge => "ge",
+            Cond::B => "b",
+            Cond::Be => "be",
+            Cond::A => "a",
+            Cond::Ae => "ae",
+        }
+    }
+}
+
+// One line of the generated assembly. The code generator builds a list of
+// these, and it is rendered into text as the last step.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Insn {
+    Push(Operand),
+    Pop(&'static str),
+    Mov(Operand, Operand),
+    // Sign and zero extension, and zero extension of a byte register.
+    Movsx(&'static str, Operand),
+    Movzx(&'static str, Operand),
+    Movzb(&'static str, &'static str),
+    Lea(&'static str, Operand),
+    Add(Operand, Operand),
+    Sub(Operand, Operand),
+    Imul(Operand, Operand),
+    Sal(Operand, Operand),
+    Sar(Operand, Operand),
+    Shr(Operand, Operand),
+    And(Operand, Operand),
+    Cdq,
+    Cqo,
+    Div(Operand),
+    Idiv(Operand),
+    Cmp(Operand, Operand),
+    Set(Cond, &'static str),
+    Jmp(String),
+    Jcc(Cond, String),
+    Call(String),
+    Ret,
+    Syscall,
+    Label(String),
+    // Directives like `.text` at the beginning of the line.
+    Directive(String),
+    Zero(usize),
+    // Integer of the size in bytes.
+    Data(usize, i64),
+    Ascii(String),
+    // `.loc` of the line and the column in the source file.
+    Loc(usize, usize),
+    Comment(String),
+    // Source line of the following code.
+    LineComment(usize, String),
+    Blank,
+    // AArch64 or RISC-V instruction with the operands as written.
+    Text(&'static str, Vec<String>),
+}
+
+// Lines are built with push_str as far as possible, because the formatting
+// machinery is several times slower for this many small lines.
+fn intel_operand(l: &mut String, op: &Operand) {
+    match op {
+        Reg(r) => l.push_str(r),
+        Imm(v) => {
+            let _ = write!(l, "{}", v);
+        },
+        Mem { width, base, disp } => {
+            l.push_str(width.intel());
+            l.push_str(" [");
+            l.push_str(base);
+            if *disp != 0 {
+                let _ = write!(l, "{:+}", disp);
+            }
+            l.push(']');
+        },
+        Rip { width, label, offset } => {
+            l.push_str(width.intel());
+            l.push(' ');
+            l.push_str(label);
+            l.push_str("[rip");
+            if let Some(offset) = offset {
+                let _ = write!(l, "+{}", offset);
+            }
+            l.push(']');
+        },
+    }
+}
+
+fn intel_insn(l: &mut String, mnemonic: &str, operands: &[&Operand]) {
+    l.push_str("    ");
+    l.push_str(mnemonic);
+    for (i, op) in operands.iter().enumerate() {
+        l.push_str(if i == 0 { " " } else { ", " });
+        intel_operand(l, op);
+    }
+}
+
+// Directive of an integer of the size, which all the targets take.
+fn data_directive(size: usize) -> &'static str {
+    match size {
+        1 => ".byte",
+        2 => ".short",
+        4 => ".long",
+        _ => ".quad",
+    }
+}
+
+// Lines which are the same in both syntaxes.
+fn plain_line(l: &mut String, insn: &Insn) {
+    match insn {
+        Insn::Jmp(label) => {
+            l.push_str("    jmp  ");
+            l.push_str(label);
+        },
+        Insn::Jcc(cond, label) => {
+            l.push_str("    j");
+            l.push_str(cond.suffix());
+            l.push_str("  ");
+            l.push_str(label);
+        },
+        Insn::Call(name) => {
+            l.push_str("    call ");
+            l.push_str(name);
+        },
+        Insn::Ret => l.push_str("    ret"),
+        Insn::Syscall => l.push_str("    syscall"),
+        Insn::Label(label) => {
+            l.push_str(label);
+            l.push(':');
+        },
+        Insn::Directive(s) => l.push_str(s),
+        Insn::Zero(size) => {
+            let _ = write!(l, "    .zero {}", size);
+        },
+        Insn::Data(size, val) => {
+            let _ = write!(l, "    {} {}", data_directive(*size), val);
+        },
+        Insn::Ascii(s) => {
+            let _ = write!(l, "    .ascii \"{}\"", s);
+        },
+        Insn::Loc(line, column) => {
+            let _ = write!(l, "    .loc 1 {} {}", line, column);
+        },
+        Insn::Comment(s) => {
+            let _ = write!(l, "# {}", s);
+        },
+        Insn::LineComment(line, s) => {
+            let _ = write!(l, "    # line {}: {}", line, s);
+        },
+        Insn::Text(mnemonic, operands) => {
+            l.push_str("    ");
+            l.push_str(mnemonic);
+            for (i, op) in operands.iter().enumerate() {
+                l.push_str(if i == 0 { " " } else { ", " });
+                l.push_str(op);
+            }
+        },
+        Insn::Blank => {},
+        _ => unreachable!(),
+    }
+}
+
+// Renders the instructions in Intel syntax.
+pub fn emit_intel(insns: &[Insn], f: &mut impl Write) -> io::Result<()> {
+    // All lines are written at once, even if `f` is not buffered.
+    let mut text = String::new();
+    for insn in insns {
+        let l = &mut text;
+        match insn {
+            Insn::Push(src) => intel_insn(l, "push", &[src]),
+            Insn::Pop(dst) => intel_insn(l, "pop", &[&Reg(dst)]),
+            Insn::Mov(dst, src) => intel_insn(l, "mov", &[dst, src]),
+            // 4 bytes are sign extended by its own mnemonic.
+            Insn::Movsx(dst, src) if width(src) == Some(Width::Dword) => intel_insn(l, "movsxd", &[&Reg(dst), src]),
+            Insn::Movsx(dst, src) => intel_insn(l, "movsx", &[&Reg(dst), src]),
+            Insn::Movzx(dst, src) => intel_insn(l, "movzx", &[&Reg(dst), src]),
+            Insn::Movzb(dst, src) => intel_insn(l, "movzb", &[&Reg(dst), &Reg(src)]),
+            Insn::Lea(dst, src) => intel_insn(l, "lea", &[&Reg(dst), src]),
+            Insn::Add(dst, src) => intel_insn(l, "add", &[dst, src]),
+            Insn::Sub(dst, src) => intel_insn(l, "sub", &[dst, src]),
+            Insn::Imul(dst, src) => intel_insn(l, "imul", &[dst, src]),
+            Insn::Sal(dst, src) => intel_insn(l, "sal", &[dst, src]),
+            Insn::Sar(dst, src) => intel_insn(l, "sar", &[dst, src]),
+            Insn::Shr(dst, src) => intel_insn(l, "shr", &[dst, src]),
+            Insn::And(dst, src) => intel_insn(l, "and", &[dst, src]),
+            Insn::Cdq => intel_insn(l, "cdq", &[]),
+            Insn::Cqo => intel_insn(l, "cqo", &[]),
+            Insn::Div(src) => intel_insn(l, "div", &[src]),
+            Insn::Idiv(src) => intel_insn(l, "idiv", &[src]),
+            Insn::Cmp(lhs, rhs) => intel_insn(l, "cmp", &[lhs, rhs]),
+            Insn::Set(cond, dst) => {
+                l.push_str("    set");
+                l.push_str(cond.suffix());
+                l.push(' ');
+                l.push_str(dst);
+            },
+            _ => plain_line(l, insn),
+        }
+        l.push('\n');
+    }
+    f.write_all(text.as_bytes())
+}
+
+fn att_operand(l: &mut String, op: &Operand) {
+    match op {
+        Reg(r) => {
+            l.push('%');
+            l.push_str(r);
+        },
+        Imm(v) => {
+            let _ = write!(l, "${}", v);
+        },
+        Mem { width: _, base, disp } => {
+            if *disp != 0 {
+                let _ = write!(l, "{}", disp);
+            }
+            l.push_str("(%");
+            l.push_str(base);
+            l.push(')');
+        },
+        Rip { width: _, label, offset } => {
+            l.push_str(label);
+            if let Some(offset) = offset {
+                let _ = write!(l, "+{}", offset);
+            }
+            l.push_str("(%rip)");
+        },
+    }
+}
+
+fn width(op: &Operand) -> Option<Width> {
+    match op {
+        Reg(r) => Some(Width::of_reg(r)),
+        Imm(_) => None,
+        Mem { width, base: _, disp: _ } | Rip { width, label: _, offset: _ } => Some(*width),
+    }
+}
+
+// The size suffix is taken from the operands, and the operands are in
+// the reverse order of Intel syntax.
+fn att_insn(l: &mut String, mnemonic: &str, operands: &[&Operand]) {
+    l.push_str("    ");
+    l.push_str(mnemonic);
+    if let Some(width) = operands.iter().find_map(|op| width(op)) {
+        l.push(width.suffix());
+    }
+    for (i, op) in operands.iter().rev().enumerate() {
+        l.push_str(if i == 0 { " " } else { ", " });
+        att_operand(l, op);
+    }
+}
+
+// Renders the instructions in AT&T syntax, for the assemblers which do not
+// take `.intel_syntax`.
+pub fn emit_att(insns: &[Insn], f: &mut impl Write) -> io::Result<()> {
+    let mut text = String::new();
+    for insn in insns {
+        let l = &mut text;
+        match insn {
+            Insn::Push(src) => att_insn(l, "push", &[src]),
+            Insn::Pop(dst) => att_insn(l, "pop", &[&Reg(dst)]),
+            Insn::Mov(dst, src) => att_insn(l, "mov", &[dst, src]),
+            Insn::Movsx(dst, src) => {
+                let mnemonic = format!("movs{}", width(src).unwrap().suffix());
+                att_insn(l, &mnemonic, &[&Reg(dst), src]);
+            },
+            Insn::Movzx(dst, src) => {
+                let mnemonic = format!("movz{}", width(src).unwrap().suffix());
+                att_insn(l, &mnemonic, &[&Reg(dst), src]);
+            },
+            Insn::Movzb(dst, src) => att_insn(l, "movzb", &[&Reg(dst), &Reg(src)]),
+            Insn::Lea(dst, src) => att_insn(l, "lea", &[&Reg(dst), src]),
+            Insn::Add(dst, src) => att_insn(l, "add", &[dst, src]),
+            Insn::Sub(dst, src) => att_insn(l, "sub", &[dst, src]),
+            Insn::Imul(dst, src) => att_insn(l, "imul", &[dst, src]),
+            Insn::Sal(dst, src) => att_insn(l, "sal", &[dst, src]),
+            Insn::Sar(dst, src) => att_insn(l, "sar", &[dst, src]),
+            Insn::Shr(dst, src) => att_insn(l, "shr", &[dst, src]),
+            Insn::And(dst, src) => att_insn(l, "and", &[dst, src]),
+            Insn::Cdq => l.push_str("    cltd"),
+            Insn::Cqo => l.push_str("    cqto"),
+            Insn::Div(src) => att_insn(l, "div", &[src]),
+            Insn::Idiv(src) => att_insn(l, "idiv", &[src]),
+            Insn::Cmp(lhs, rhs) => att_insn(l, "cmp", &[lhs, rhs]),
+            Insn::Set(cond, dst) => {
+                l.push_str("    set");
+                l.push_str(cond.suffix());
+                l.push_str(" %");
+                l.push_str(dst);
+            },
+            _ => plain_line(l, insn),
+        }
+        l.push('\n');
+    }
+    f.write_all(text.as_bytes())
+}
+
+// Renders AArch64 instructions, where `#` begins immediates and comments
+// begin with `//`.
+pub fn emit_a64(insns: &[Insn], f: &mut impl Write) -> io::Result<()> {
+    let mut text = String::new();
+    for insn in insns {
+        let l = &mut text;
+        match insn {
+            Insn::Comment(s) => {
+                let _ = write!(l, "// {}", s);
+            },
+            Insn::LineComment(line, s) => {
+                let _ = write!(l, "    // line {}: {}", line, s);
+            },
+            _ => plain_line(l, insn),
+        }
+        l.push('\n');
+    }
+    f.write_all(text.as_bytes())
+}
+
+// Renders RISC-V instructions, which take the same comments as x86.
+pub fn emit_riscv(insns: &[Insn], f: &mut impl Write) -> io::Result<()> {
+    let mut text = String::new();
+    for insn in insns {
+        plain_line(&mut text, insn);
+        text.push('\n');
+    }
+    f.write_all(text.as_bytes())
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::hash::Hasher;
+
+// Handle of an interned string. Identifiers and string literals are
+// compared and copied as symbols, and resolved only to emit them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// FNV-1a, which is much faster than the default hasher for short names.
+#[derive(Default)]
+pub struct FnvHasher(u64);
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut hash = if self.0 == 0 { 0xcbf29ce484222325 } else { self.0 };
+        for b in bytes {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        self.0 = hash;
+    }
+
+    // Symbols are small consecutive integers. Spread them over the high
+    // bits too, which the hash map relies on.
+    fn write_u32(&mut self, i: u32) {
+        self.0 = (i as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Hash map keyed by symbols, for symbol tables.
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<FnvHasher>>;
+
+#[derive(Default)]
+pub struct Interner {
+    names: Vec<String>,
+    map: HashMap<String, Symbol, BuildHasherDefault<FnvHasher>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.map.get(name) {
+            return *sym;
+        }
+
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.map.insert(name.to_string(), sym);
+        sym
+    }
+
+    pub fn resolve(&self, sym: Symbol) -> &str {
+        &self.names[sym.0 as usize]
+    }
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,1017 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use super::assembly::AsmError;
+use super::assembly::cmp_size;
+use super::assembly::lval_size;
+use super::intern::Interner;
+use super::intern::Symbol;
+use super::parse::Ast;
+use super::parse::BinaryOpKind::*;
+use super::parse::Node;
+use super::parse::NodeId;
+use super::parse::Type;
+use super::parse::UnaryOpKind::*;
+use super::parse::is_unsigned;
+use super::parse::type_size;
+
+use AsmError::*;
+
+// Virtual register. Each one is assigned exactly once, and is used only in
+// the block where it is assigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VReg(pub u32);
+
+// Index of a block in `Function::blocks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockId(pub u32);
+
+// Memory operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Place {
+    // [rbp+disp]
+    Frame(i32),
+    // label+offset
+    Global(Symbol, usize),
+    // [vreg+offset]
+    Ptr(VReg, usize),
+}
+
+impl Place {
+    fn offset(self, n: usize) -> Place {
+        match self {
+            Place::Frame(disp) => Place::Frame(disp + n as i32),
+            Place::Global(name, offset) => Place::Global(name, offset + n),
+            Place::Ptr(base, offset) => Place::Ptr(base, offset + n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Val {
+    Reg(VReg),
+    Imm(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    And,
+    // Shifts take only immediates.
+    Shl,
+    Sar,
+    Shr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DivOp {
+    Div,
+    Rem,
+    Udiv,
+    Urem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Ult,
+    Ule,
+    Ugt,
+    Uge,
+}
+
+impl DivOp {
+    pub fn is_unsigned(self) -> bool {
+        matches!(self, DivOp::Udiv | DivOp::Urem)
+    }
+}
+
+impl CmpOp {
+    pub fn is_unsigned(self) -> bool {
+        matches!(self, CmpOp::Ult | CmpOp::Ule | CmpOp::Ugt | CmpOp::Uge)
+    }
+}
+
+// All values are 64 bits, and arithmetic wraps around.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Const(VReg, u64),
+    // Address of the string literal `.LC<n>`.
+    Str(VReg, usize),
+    Addr(VReg, Place),
+    // Loads `size` bytes like the stack machine does: sign extended to 64
+    // bits if the flag is set, or zero extended.
+    Load(VReg, Place, usize, bool),
+    Store(Place, VReg, usize),
+    // Stores the `index`th argument register, at the beginning of a
+    // function.
+    StoreArg(Place, usize, usize),
+    Bin(BinOp, VReg, VReg, Val),
+    // Divides the lower 4 or 8 bytes as signed or unsigned integers by the
+    // kind. A 4 bytes result is extended to 64 bits in the same way.
+    Div(DivOp, usize, VReg, VReg, Val),
+    // Compares the lower `size` bytes as signed or unsigned integers by
+    // the kind, into 0 or 1.
+    Cmp(CmpOp, usize, VReg, VReg, Val),
+    // No result, the value, or the pointer and the length of a slice.
+    Call(Vec<VReg>, Symbol, Vec<VReg>),
+    // Beginning of a statement, for comments and debug information.
+    Line(NodeId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Jmp(BlockId),
+    // Jumps to the first block if the value is not 0.
+    Br(VReg, BlockId, BlockId),
+    // No value, the value, or the pointer and the length of a slice.
+    Ret(Vec<VReg>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub ops: Vec<Op>,
+    pub term: Term,
+}
+
+// A function in the IR. The first block is the entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Symbol,
+    pub node: NodeId,
+    // Bytes of the local variables below rbp.
+    pub stack: usize,
+    pub blocks: Vec<Block>,
+    pub vregs: u32,
+}
+
+impl Op {
+    pub fn def(&self) -> Vec<VReg> {
+        match self {
+            Op::Const(d, _) | Op::Str(d, _) | Op::Addr(d, _) | Op::Load(d, _, _, _) => vec![*d],
+            Op::Bin(_, d, _, _) | Op::Div(_, _, d, _, _) | Op::Cmp(_, _, d, _, _) => vec![*d],
+            Op::Call(dsts, _, _) => dsts.clone(),
+            Op::Store(_, _, _) | Op::StoreArg(_, _, _) | Op::Line(_) => Vec::new(),
+        }
+    }
+
+    pub fn uses(&self) -> Vec<VReg> {
+        let place = |p: &Place| match p {
+            Place::Ptr(base, _) => vec![*base],
+            _ => Vec::new(),
+        };
+        let val = |v: &Val| match v {
+            Val::Reg(r) => vec![*r],
+            Val::Imm(_) => Vec::new(),
+        };
+        match self {
+            Op::Const(_, _) | Op::Str(_, _) | Op::Line(_) => Vec::new(),
+            Op::Addr(_, p) | Op::Load(_, p, _, _) | Op::StoreArg(p, _, _) => place(p),
+            Op::Store(p, v, _) => [place(p), vec![*v]].concat(),
+            Op::Bin(_, _, a, b) | Op::Div(_, _, _, a, b) | Op::Cmp(_, _, _, a, b) => [vec![*a], val(b)].concat(),
+            Op::Call(_, _, args) => args.clone(),
+        }
+    }
+
+    fn rename(&mut self, f: &mut impl FnMut(VReg) -> VReg) {
+        let place = |p: &mut Place, f: &mut dyn FnMut(VReg) -> VReg| {
+            if let Place::Ptr(base, _) = p {
+                *base = f(*base);
+            }
+        };
+        match self {
+            Op::Const(d, _) | Op::Str(d, _) => *d = f(*d),
+            Op::Addr(d, p) | Op::Load(d, p, _, _) => {
+                place(p, f);
+                *d = f(*d);
+            },
+            Op::Store(p, v, _) => {
+                place(p, f);
+                *v = f(*v);
+            },
+            Op::StoreArg(p, _, _) => place(p, f),
+            Op::Bin(_, d, a, b) | Op::Div(_, _, d, a, b) | Op::Cmp(_, _, d, a, b) => {
+                *a = f(*a);
+                if let Val::Reg(b) = b {
+                    *b = f(*b);
+                }
+                *d = f(*d);
+            },
+            Op::Call(dsts, _, args) => {
+                for arg in args.iter_mut() {
+                    *arg = f(*arg);
+                }
+                for dst in dsts.iter_mut() {
+                    *dst = f(*dst);
+                }
+            },
+            Op::Line(_) => {},
+        }
+    }
+
+    // Whether the op can be removed when its result is not used. Division
+    // may trap.
+    fn is_pure(&self) -> bool {
+        matches!(self, Op::Const(_, _) | Op::Str(_, _) | Op::Addr(_, _) | Op::Load(_, _, _, _) |
+                       Op::Bin(_, _, _, _) | Op::Cmp(_, _, _, _, _))
+    }
+}
+
+impl Term {
+    pub fn uses(&self) -> Vec<VReg> {
+        match self {
+            Term::Jmp(_) => Vec::new(),
+            Term::Br(cond, _, _) => vec![*cond],
+            Term::Ret(vals) => vals.clone(),
+        }
+    }
+
+    pub fn targets(&self) -> Vec<BlockId> {
+        match self {
+            Term::Jmp(b) => vec![*b],
+            Term::Br(_, t, e) => vec![*t, *e],
+            Term::Ret(_) => Vec::new(),
+        }
+    }
+}
+
+fn is_slice(ast: &Ast, node: NodeId) -> bool {
+    match &ast[node] {
+        Node::StrLiteral { s: _, label: _ } => true,
+        Node::Call { name: _, args: _, ty } => matches!(ty, Type::Slc(_)),
+        Node::LocalVariable { offset: _, ty } |
+        Node::GlobalVariable { name: _, offset: _, ty } => matches!(ty, Type::Slc(_)),
+        Node::UnaryOperator { kind: UnaryOpDrf, rhs } => {
+            matches!(var_type(ast, *rhs), Some(Type::Ptr(ty)) if matches!(**ty, Type::Slc(_)))
+        },
+        _ => false,
+    }
+}
+
+fn var_type(ast: &Ast, node: NodeId) -> Option<&Type> {
+    match &ast[node] {
+        Node::LocalVariable { offset: _, ty } |
+        Node::GlobalVariable { name: _, offset: _, ty } => Some(ty),
+        Node::UnaryOperator { kind: UnaryOpDrf, rhs } => match var_type(ast, *rhs) {
+            Some(Type::Ptr(ty)) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct Builder<'a> {
+    ast: &'a Ast,
+    interner: &'a Interner,
+    blocks: Vec<Block>,
+    cur: usize,
+    vregs: u32,
+}
+
+impl<'a> Builder<'a> {
+    fn vreg(&mut self) -> VReg {
+        self.vregs += 1;
+        VReg(self.vregs - 1)
+    }
+
+    fn push(&mut self, op: Op) {
+        self.blocks[self.cur].ops.push(op);
+    }
+
+    fn value(&mut self, f: impl FnOnce(VReg) -> Op) -> VReg {
+        let dst = self.vreg();
+        self.push(f(dst));
+        dst
+    }
+
+    // Sign or zero extends the lower `size` bytes to 64 bits.
+    fn extend(&mut self, v: VReg, size: usize, unsigned: bool) -> VReg {
+        let shift = Val::Imm(64 - 8 * size as u64);
+        let shifted = self.value(|d| Op::Bin(BinOp::Shl, d, v, shift));
+        let op = if unsigned { BinOp::Shr } else { BinOp::Sar };
+        self.value(|d| Op::Bin(op, d, shifted, shift))
+    }
+
+    // Keeps the lower bytes of a value of `ty` in the same form as a load
+    // of the type, extended to 64 bits by the signedness.
+    fn narrow(&mut self, v: VReg, ty: &Type) -> VReg {
+        match ty {
+            Type::Int8 | Type::Int16 | Type::Int32 |
+            Type::Uint8 | Type::Uint16 | Type::Uint32 | Type::Bool => self.extend(v, type_size(ty), is_unsigned(ty)),
+            _ => v,
+        }
+    }
+
+    fn block(&mut self) -> BlockId {
+        self.blocks.push(Block { ops: Vec::new(), term: Term::Ret(Vec::new()) });
+        BlockId(self.blocks.len() as u32 - 1)
+    }
+
+    // Ends the current block, and continues in `next`.
+    fn terminate(&mut self, term: Term, next: BlockId) {
+        self.blocks[self.cur].term = term;
+        self.cur = next.0 as usize;
+    }
+
+    fn place(&mut self, node: NodeId) -> Result<Place, AsmError> {
+        match &self.ast[node] {
+            Node::LocalVariable { offset, ty: _ } => Ok(Place::Frame(-(*offset as i32))),
+            Node::GlobalVariable { name, offset, ty: _ } => Ok(Place::Global(*name, *offset)),
+            Node::UnaryOperator { kind: UnaryOpDrf, rhs } => {
+                let ptr = self.expr(*rhs)?;
+                Ok(Place::Ptr(ptr, 0))
+            },
+            _ => Err(Context),
+        }
+    }
+
+    fn call(&mut self, node: NodeId, results: usize) -> Result<Vec<VReg>, AsmError> {
+        let ast = self.ast;
+        if let Node::Call { name, args, ty: _ } = &ast[node] {
+            let mut values = Vec::new();
+            for &arg in args {
+                if is_slice(ast, arg) {
+                    let (ptr, len) = self.slice(arg)?;
+                    values.push(ptr);
+                    values.push(len);
+                } else {
+                    values.push(self.expr(arg)?);
+                }
+            }
+            let dsts: Vec<VReg> = (0..results).map(|_| self.vreg()).collect();
+            self.push(Op::Call(dsts.clone(), *name, values));
+            Ok(dsts)
+        } else {
+            unreachable!()
+        }
+    }
+
+    // Pointer and length of a slice.
+    fn slice(&mut self, node: NodeId) -> Result<(VReg, VReg), AsmError> {
+        let ast = self.ast;
+        match &ast[node] {
+            Node::StrLiteral { s, label } => {
+                let ptr = self.value(|d| Op::Str(d, *label));
+                let len = self.interner.resolve(*s).len() as u64;
+                let len = self.value(|d| Op::Const(d, len));
+                Ok((ptr, len))
+            },
+            Node::Call { name: _, args: _, ty: Type::Slc(_) } => {
+                let dsts = self.call(node, 2)?;
+                Ok((dsts[0], dsts[1]))
+            },
+            _ if is_slice(ast, node) => {
+                let place = self.place(node)?;
+                let ptr = self.value(|d| Op::Load(d, place, 8, true));
+                let len = self.value(|d| Op::Load(d, place.offset(8), 8, true));
+                Ok((ptr, len))
+            },
+            _ => Err(Context),
+        }
+    }
+
+    fn expr(&mut self, node: NodeId) -> Result<VReg, AsmError> {
+        let ast = self.ast;
+        match &ast[node] {
+            Node::Number { val } => Ok(self.value(|d| Op::Const(d, *val))),
+            Node::Bool { bl } => Ok(self.value(|d| Op::Const(d, *bl as u64))),
+            Node::CStrLiteral { s: _, label } => Ok(self.value(|d| Op::Str(d, *label))),
+            Node::LocalVariable { offset: _, ty } |
+            Node::GlobalVariable { name: _, offset: _, ty } => {
+                let size = type_size(ty);
+                if !matches!(size, 1 | 2 | 4 | 8) || is_slice(ast, node) {
+                    return Err(Context);
+                }
+                let signed = !is_unsigned(ty);
+                let place = self.place(node)?;
+                Ok(self.value(|d| Op::Load(d, place, size, signed)))
+            },
+            Node::UnaryOperator { kind: UnaryOpRf, rhs } => {
+                let place = self.place(*rhs)?;
+                Ok(self.value(|d| Op::Addr(d, place)))
+            },
+            Node::UnaryOperator { kind: UnaryOpDrf, rhs } => {
+                // The pointee is loaded in its own size like a variable.
+                let size = var_type(ast, node).map(type_size)
+                    .filter(|size| matches!(size, 1 | 2 | 4))
+                    .unwrap_or(8);
+                let signed = !var_type(ast, node).is_some_and(is_unsigned);
+                let ptr = self.expr(*rhs)?;
+                Ok(self.value(|d| Op::Load(d, Place::Ptr(ptr, 0), size, signed)))
+            },
+            Node::BinaryOperator { kind: BinaryOpAsn, lhs, rhs } => {
+                // The address is evaluated first.
+                let place = self.place(*lhs)?;
+                if is_slice(ast, *lhs) {
+                    let (ptr, len) = self.slice(*rhs)?;
+                    self.push(Op::Store(place, ptr, 8));
+                    self.push(Op::Store(place.offset(8), len, 8));
+                    Ok(len)
+                } else {
+                    let size = lval_size(ast, *lhs)?;
+                    let mut val = self.expr(*rhs)?;
+                    if let Some((from, unsigned)) = var_type(ast, *lhs).and_then(|ty| ast.widens(*rhs, ty)) {
+                        val = self.extend(val, from, unsigned);
+                    }
+                    if ast.normalizes_bool(*lhs, *rhs) {
+                        val = self.value(|d| Op::Cmp(CmpOp::Ne, 8, d, val, Val::Imm(0)));
+                    }
+                    self.push(Op::Store(place, val, size));
+                    Ok(val)
+                }
+            },
+            Node::BinaryOperator { kind, lhs, rhs } => {
+                if is_slice(ast, *lhs) {
+                    return Err(Context);
+                }
+                let mut a = self.expr(*lhs)?;
+                let mut b = self.expr(*rhs)?;
+                if let Some((narrower, size, unsigned)) = ast.narrower_operand(*lhs, *rhs) {
+                    if narrower == *lhs {
+                        a = self.extend(a, size, unsigned);
+                    } else {
+                        b = self.extend(b, size, unsigned);
+                    }
+                }
+                let op = match kind {
+                    BinaryOpAdd => BinOp::Add,
+                    BinaryOpSub => BinOp::Sub,
+                    BinaryOpMul => BinOp::Mul,
+                    BinaryOpDiv | BinaryOpRem => {
+                        let (size, unsigned) = ast.int_type(*lhs, *rhs);
+                        let div = match (kind, unsigned) {
+                            (BinaryOpDiv, false) => DivOp::Div,
+                            (_, false) => DivOp::Rem,
+                            (BinaryOpDiv, true) => DivOp::Udiv,
+                            (_, true) => DivOp::Urem,
+                        };
+                        if size >= 4 {
+                            return Ok(self.value(|d| Op::Div(div, size, d, a, Val::Reg(b))));
+                        }
+                        // 1 and 2 bytes are extended to be divided in 4
+                        // bytes.
+                        let a = self.extend(a, size, unsigned);
+                        let b = self.extend(b, size, unsigned);
+                        return Ok(self.value(|d| Op::Div(div, 4, d, a, Val::Reg(b))));
+                    },
+                    _ => {
+                        let unsigned = ast.is_unsigned_cmp(*lhs, *rhs);
+                        let cmp = match (kind, unsigned) {
+                            (BinaryOpEq, _) => CmpOp::Eq,
+                            (BinaryOpNe, _) => CmpOp::Ne,
+                            (BinaryOpLt, false) => CmpOp::Lt,
+                            (BinaryOpLe, false) => CmpOp::Le,
+                            (BinaryOpGr, false) => CmpOp::Gt,
+                            (_, false) => CmpOp::Ge,
+                            (BinaryOpLt, true) => CmpOp::Ult,
+                            (BinaryOpLe, true) => CmpOp::Ule,
+                            (BinaryOpGr, true) => CmpOp::Ugt,
+                            (_, true) => CmpOp::Uge,
+                        };
+                        let size = cmp_size(ast, *lhs, *rhs);
+                        return Ok(self.value(|d| Op::Cmp(cmp, size, d, a, Val::Reg(b))));
+                    },
+                };
+                Ok(self.value(|d| Op::Bin(op, d, a, Val::Reg(b))))
+            },
+            Node::Call { name: _, args: _, ty } if !matches!(ty, Type::Slc(_)) => {
+                Ok(self.call(node, 1)?[0])
+            },
+            _ => Err(Context),
+        }
+    }
+
+    fn stmt(&mut self, node: NodeId) -> Result<(), AsmError> {
+        let ast = self.ast;
+        match &ast[node] {
+            Node::Block { nodes } => {
+                for &node in nodes {
+                    if ast.span(node).is_some() {
+                        self.push(Op::Line(node));
+                    }
+                    self.stmt(node)?;
+                }
+            },
+            Node::If { cond, ibody } => {
+                let cond = self.expr(*cond)?;
+                let (then, end) = (self.block(), self.block());
+                self.terminate(Term::Br(cond, then, end), then);
+                self.stmt(*ibody)?;
+                self.terminate(Term::Jmp(end), end);
+            },
+            Node::IfElse { cond, ibody, ebody } => {
+                let cond = self.expr(*cond)?;
+                let (then, other, end) = (self.block(), self.block(), self.block());
+                self.terminate(Term::Br(cond, then, other), then);
+                self.stmt(*ibody)?;
+                self.terminate(Term::Jmp(end), other);
+                self.stmt(*ebody)?;
+                self.terminate(Term::Jmp(end), end);
+            },
+            Node::While { cond, body } => {
+                let (head, inner, end) = (self.block(), self.block(), self.block());
+                self.terminate(Term::Jmp(head), head);
+                let cond = self.expr(*cond)?;
+                self.terminate(Term::Br(cond, inner, end), inner);
+                self.stmt(*body)?;
+                self.terminate(Term::Jmp(head), end);
+            },
+            Node::Return { rhs, ty } => {
+                let vals = if let Type::Slc(_) = ty {
+                    let (ptr, len) = self.slice(*rhs)?;
+                    vec![ptr, len]
+                } else {
+                    let mut val = self.expr(*rhs)?;
+                    if let Some((from, unsigned)) = ast.widens(*rhs, ty) {
+                        val = self.extend(val, from, unsigned);
+                    }
+                    if ast.normalizes_to_bool(ty, *rhs) {
+                        val = self.value(|d| Op::Cmp(CmpOp::Ne, 8, d, val, Val::Imm(0)));
+                    }
+                    vec![self.narrow(val, ty)]
+                };
+                // The code after `return` goes to a block which is never
+                // reached.
+                let next = self.block();
+                self.terminate(Term::Ret(vals), next);
+            },
+            Node::DeclareLocal { offset: _, ty: _ } => {},
+            Node::Call { name: _, args: _, ty: _ } => {
+                self.call(node, 0)?;
+            },
+            _ if is_slice(ast, node) => {
+                self.slice(node)?;
+            },
+            _ => {
+                self.expr(node)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+// Translates a function of the AST.
+pub fn lower(ast: &Ast, interner: &Interner, item: NodeId) -> Result<Function, AsmError> {
+    let (name, args, stack, block) = match &ast[item] {
+        Node::Function { name, args, stack, block } => (*name, args, *stack, *block),
+        _ => unreachable!(),
+    };
+    let mut builder = Builder {
+        ast,
+        interner,
+        blocks: Vec::new(),
+        cur: 0,
+        vregs: 0,
+    };
+    builder.block();
+
+    let mut index = 0;
+    for &arg in args {
+        let place = builder.place(arg)?;
+        if is_slice(ast, arg) {
+            builder.push(Op::StoreArg(place, index, 8));
+            builder.push(Op::StoreArg(place.offset(8), index + 1, 8));
+            index += 2;
+        } else {
+            builder.push(Op::StoreArg(place, index, lval_size(ast, arg)?));
+            index += 1;
+        }
+    }
+    builder.stmt(block)?;
+    // A function which reaches its end returns 0, which is the exit status
+    // of `main`.
+    let zero = builder.value(|d| Op::Const(d, 0));
+    builder.blocks[builder.cur].term = Term::Ret(vec![zero]);
+
+    Ok(Function {
+        name,
+        node: item,
+        stack,
+        blocks: builder.blocks,
+        vregs: builder.vregs,
+    })
+}
+
+fn eval_bin(op: BinOp, a: u64, b: u64) -> u64 {
+    let (x, y) = (a as i64, b as i64);
+    let val = match op {
+        BinOp::Add => x.wrapping_add(y),
+        BinOp::Sub => x.wrapping_sub(y),
+        BinOp::Mul => x.wrapping_mul(y),
+        BinOp::And => x & y,
+        BinOp::Shl => x << b,
+        BinOp::Sar => x >> b,
+        BinOp::Shr => (a >> b) as i64,
+    };
+    val as u64
+}
+
+// A division which traps at runtime has no value.
+fn eval_div(op: DivOp, size: usize, a: u64, b: u64) -> Option<u64> {
+    let val = match (op, size) {
+        (DivOp::Div, 4) => (a as i32).checked_div(b as i32)? as i64 as u64,
+        (DivOp::Rem, 4) => (a as i32).checked_rem(b as i32)? as i64 as u64,
+        (DivOp::Udiv, 4) => (a as u32).checked_div(b as u32)? as u64,
+        (DivOp::Urem, 4) => (a as u32).checked_rem(b as u32)? as u64,
+        (DivOp::Div, _) => (a as i64).checked_div(b as i64)? as u64,
+        (DivOp::Rem, _) => (a as i64).checked_rem(b as i64)? as u64,
+        (DivOp::Udiv, _) => a.checked_div(b)?,
+        (DivOp::Urem, _) => a.checked_rem(b)?,
+    };
+    Some(val)
+}
+
+// Sign extends the lower `size` bytes.
+fn sign_extend(val: u64, size: usize) -> i64 {
+    let shift = 64 - size * 8;
+    ((val << shift) as i64) >> shift
+}
+
+// Zero extends the lower `size` bytes.
+fn zero_extend(val: u64, size: usize) -> u64 {
+    val & (u64::MAX >> (64 - size * 8))
+}
+
+fn eval_cmp(op: CmpOp, size: usize, a: u64, b: u64) -> u64 {
+    let (x, y) = (sign_extend(a, size), sign_extend(b, size));
+    let (ux, uy) = (zero_extend(a, size), zero_extend(b, size));
+    let val = match op {
+        CmpOp::Eq => x == y,
+        CmpOp::Ne => x != y,
+        CmpOp::Lt => x < y,
+        CmpOp::Le => x <= y,
+        CmpOp::Gt => x > y,
+        CmpOp::Ge => x >= y,
+        CmpOp::Ult => ux < uy,
+        CmpOp::Ule => ux <= uy,
+        CmpOp::Ugt => ux > uy,
+        CmpOp::Uge => ux >= uy,
+    };
+    val as u64
+}
+
+// Whether the immediate can be an operand of the instruction of `size`
+// bytes, which sign extends it.
+fn fits_imm(val: u64, size: usize) -> bool {
+    match size {
+        1 => val <= i8::MAX as u64,
+        2 => val <= i16::MAX as u64,
+        _ => val <= i32::MAX as u64,
+    }
+}
+
+// Evaluates the ops whose operands are constants, and makes the other
+// constant operands immediates.
+fn fold(func: &mut Function) -> bool {
+    let mut changed = false;
+    let mut consts: HashMap<VReg, u64> = HashMap::new();
+    for block in func.blocks.iter_mut() {
+        for op in block.ops.iter_mut() {
+            let value = |v: &Val| match v {
+                Val::Reg(r) => consts.get(r).copied(),
+                Val::Imm(i) => Some(*i),
+            };
+            let new = match op {
+                Op::Bin(kind, d, a, b) => match (consts.get(a), value(b)) {
+                    (Some(&x), Some(y)) => Some(Op::Const(*d, eval_bin(*kind, x, y))),
+                    (_, Some(y)) if matches!(b, Val::Reg(_)) && fits_imm(y, 8) => {
+                        Some(Op::Bin(*kind, *d, *a, Val::Imm(y)))
+                    },
+                    // Constants go to the right of commutative operators.
+                    (Some(&x), None) if matches!(kind, BinOp::Add | BinOp::Mul) && fits_imm(x, 8) => {
+                        if let Val::Reg(b) = b {
+                            Some(Op::Bin(*kind, *d, *b, Val::Imm(x)))
+                        } else {
+                            None
+                        }
+                    },
+                    _ => None,
+                },
+                Op::Div(kind, size, d, a, b) => match (consts.get(a), value(b)) {
+                    (Some(&x), Some(y)) => eval_div(*kind, *size, x, y).map(|val| Op::Const(*d, val)),
+                    _ => None,
+                },
+                Op::Cmp(kind, size, d, a, b) => match (consts.get(a), value(b)) {
+                    (Some(&x), Some(y)) => Some(Op::Const(*d, eval_cmp(*kind, *size, x, y))),
+                    (_, Some(y)) if matches!(b, Val::Reg(_)) && fits_imm(y, *size) => {
+                        Some(Op::Cmp(*kind, *size, *d, *a, Val::Imm(y)))
+                    },
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(new) = new {
+                *op = new;
+                changed = true;
+            }
+            if let Op::Const(d, val) = op {
+                consts.insert(*d, *val);
+            }
+        }
+        if let Term::Br(cond, t, e) = block.term {
+            if let Some(&val) = consts.get(&cond) {
+                block.term = Term::Jmp(if val != 0 { t } else { e });
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// Replaces multiplication, division and remainder by powers of two with
+// shifts. idiv rounds toward zero, so a negative dividend is biased by
+// 2^k - 1 before the arithmetic shift.
+fn reduce_strength(func: &mut Function) -> bool {
+    let mut changed = false;
+    let mut vregs = func.vregs;
+    let mut vreg = || {
+        vregs += 1;
+        VReg(vregs - 1)
+    };
+    let power = |val: u64| {
+        if val > 1 && val <= i64::MAX as u64 && val.is_power_of_two() {
+            Some(val.trailing_zeros() as u64)
+        } else {
+            None
+        }
+    };
+    for block in func.blocks.iter_mut() {
+        let mut consts: HashMap<VReg, u64> = HashMap::new();
+        let mut ops = Vec::with_capacity(block.ops.len());
+        for op in block.ops.drain(..) {
+            if let Op::Const(d, val) = op {
+                consts.insert(d, val);
+            }
+            let divisor = match op {
+                Op::Bin(BinOp::Mul, d, a, Val::Imm(val)) if power(val).is_some() => {
+                    ops.push(Op::Bin(BinOp::Shl, d, a, Val::Imm(power(val).unwrap())));
+                    changed = true;
+                    continue;
+                },
+                Op::Div(DivOp::Div | DivOp::Rem, 8, _, _, Val::Reg(b)) => consts.get(&b).and_then(|&val| power(val)),
+                _ => None,
+            };
+            match (op, divisor) {
+                (Op::Div(kind, _, d, a, _), Some(k)) => {
+                    // bias = a < 0 ? 2^k - 1 : 0
+                    let (sign, bias, biased) = (vreg(), vreg(), vreg());
+                    ops.push(Op::Bin(BinOp::Sar, sign, a, Val::Imm(63)));
+                    ops.push(Op::Bin(BinOp::Shr, bias, sign, Val::Imm(64 - k)));
+                    ops.push(Op::Bin(BinOp::Add, biased, a, Val::Reg(bias)));
+                    if kind == DivOp::Div {
+                        ops.push(Op::Bin(BinOp::Sar, d, biased, Val::Imm(k)));
+                    } else {
+                        let masked = vreg();
+                        ops.push(Op::Bin(BinOp::And, masked, biased, Val::Imm((1 << k) - 1)));
+                        ops.push(Op::Bin(BinOp::Sub, d, masked, Val::Reg(bias)));
+                    }
+                    changed = true;
+                },
+                (op, _) => ops.push(op),
+            }
+        }
+        block.ops = ops;
+    }
+    func.vregs = vregs;
+    changed
+}
+
+// Removes the ops whose results are never used.
+fn remove_dead(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let used: HashSet<VReg> = func.blocks.iter()
+            .flat_map(|b| b.ops.iter().flat_map(|op| op.uses()).chain(b.term.uses()))
+            .collect();
+        let mut removed = false;
+        for block in func.blocks.iter_mut() {
+            let before = block.ops.len();
+            block.ops.retain(|op| !op.is_pure() || op.def().iter().any(|d| used.contains(d)));
+            removed |= block.ops.len() != before;
+        }
+        if !removed {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+// Removes the blocks which are never reached, and merges a block into the
+// only block jumping to it.
+fn simplify_cfg(func: &mut Function) -> bool {
+    let count = func.blocks.len();
+    let mut preds = vec![0; count];
+    let mut reached = vec![false; count];
+    let mut work = vec![0];
+    while let Some(b) = work.pop() {
+        if reached[b] {
+            continue;
+        }
+        reached[b] = true;
+        for t in func.blocks[b].term.targets() {
+            preds[t.0 as usize] += 1;
+            work.push(t.0 as usize);
+        }
+    }
+
+    let mut changed = reached.iter().any(|r| !r);
+    for b in 0..count {
+        if !reached[b] {
+            continue;
+        }
+        // Follow the chain of merged blocks.
+        while let Term::Jmp(t) = func.blocks[b].term {
+            let t = t.0 as usize;
+            if t == b || t == 0 || preds[t] != 1 {
+                break;
+            }
+            let next = std::mem::replace(&mut func.blocks[t], Block { ops: Vec::new(), term: Term::Ret(Vec::new()) });
+            func.blocks[b].ops.extend(next.ops);
+            func.blocks[b].term = next.term;
+            reached[t] = false;
+            changed = true;
+        }
+    }
+
+    let mut index = vec![0; count];
+    let mut n = 0;
+    for b in 0..count {
+        index[b] = n;
+        if reached[b] {
+            n += 1;
+        }
+    }
+    let mut b = 0;
+    func.blocks.retain(|_| {
+        b += 1;
+        reached[b - 1]
+    });
+    for block in func.blocks.iter_mut() {
+        let id = |t: BlockId| BlockId(index[t.0 as usize]);
+        block.term = match block.term.clone() {
+            Term::Jmp(t) => Term::Jmp(id(t)),
+            Term::Br(c, t, e) => Term::Br(c, id(t), id(e)),
+            term => term,
+        };
+    }
+    changed
+}
+
+// Numbers the virtual registers again in the order of the definitions.
+fn renumber(func: &mut Function) {
+    let mut numbers: HashMap<VReg, VReg> = HashMap::new();
+    let mut next = |v: VReg| {
+        let count = numbers.len() as u32;
+        *numbers.entry(v).or_insert(VReg(count))
+    };
+    for block in func.blocks.iter_mut() {
+        for op in block.ops.iter_mut() {
+            op.rename(&mut next);
+        }
+        match &mut block.term {
+            Term::Br(cond, _, _) => *cond = next(*cond),
+            Term::Ret(vals) => {
+                for val in vals.iter_mut() {
+                    *val = next(*val);
+                }
+            },
+            Term::Jmp(_) => {},
+        }
+    }
+    func.vregs = numbers.len() as u32;
+}
+
+// Runs the passes until nothing changes.
+pub fn optimize(func: &mut Function) {
+    loop {
+        let mut changed = fold(func);
+        changed |= reduce_strength(func);
+        changed |= remove_dead(func);
+        changed |= simplify_cfg(func);
+        if !changed {
+            break;
+        }
+    }
+    renumber(func);
+}
+
+// Where a virtual register lives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    // Index of the register.
+    Reg(usize),
+    // Index of the 8 bytes slot below the local variables.
+    Slot(usize),
+}
+
+pub struct Allocation {
+    pub locations: Vec<Option<Location>>,
+    pub slots: usize,
+    // Live range of each virtual register, in positions of the ops and
+    // the terminators counted through the blocks.
+    ranges: Vec<Option<(usize, usize)>>,
+}
+
+impl Allocation {
+    // Registers whose values are still needed after the op at `pos`.
+    pub fn live_across(&self, pos: usize) -> Vec<usize> {
+        let mut regs: Vec<usize> = self.ranges.iter()
+            .zip(self.locations.iter())
+            .filter_map(|(range, loc)| match (range, loc) {
+                (Some((start, end)), Some(Location::Reg(r))) if *start < pos && pos < *end => Some(*r),
+                _ => None,
+            })
+            .collect();
+        regs.sort_unstable();
+        regs
+    }
+}
+
+// Assigns `regs` registers to the virtual registers by linear scan. The
+// ones with the farthest end are spilled.
+pub fn allocate(func: &Function, regs: usize) -> Allocation {
+    let count = func.vregs as usize;
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; count];
+    // The result of an operation preferably goes to the register of the
+    // lhs, so that it is not moved.
+    let mut hints: Vec<Option<usize>> = vec![None; count];
+    let mut pos = 0;
+    for block in func.blocks.iter() {
+        for op in block.ops.iter() {
+            if let Op::Bin(_, d, a, _) = op {
+                hints[d.0 as usize] = Some(a.0 as usize);
+            }
+            for u in op.uses() {
+                if let Some((_, end)) = &mut ranges[u.0 as usize] {
+                    *end = pos;
+                }
+            }
+            for d in op.def() {
+                ranges[d.0 as usize] = Some((pos, pos));
+            }
+            pos += 1;
+        }
+        for u in block.term.uses() {
+            if let Some((_, end)) = &mut ranges[u.0 as usize] {
+                *end = pos;
+            }
+        }
+        pos += 1;
+    }
+
+    let mut order: Vec<usize> = (0..count).filter(|&v| ranges[v].is_some()).collect();
+    order.sort_by_key(|&v| ranges[v].unwrap().0);
+
+    let mut locations: Vec<Option<Location>> = vec![None; count];
+    let mut slots = 0;
+    let mut free: Vec<usize> = (0..regs).rev().collect();
+    // (end, vreg) of the virtual registers in registers.
+    let mut active: Vec<(usize, usize)> = Vec::new();
+    for v in order {
+        let (start, end) = ranges[v].unwrap();
+        // A register can be reused by the result of the op using it last.
+        active.retain(|&(e, a)| {
+            if e <= start {
+                if let Some(Location::Reg(r)) = locations[a] {
+                    free.push(r);
+                }
+                false
+            } else {
+                true
+            }
+        });
+        let hint = hints[v].and_then(|a| match locations[a] {
+            Some(Location::Reg(r)) => free.iter().position(|&f| f == r),
+            _ => None,
+        });
+        if let Some(i) = hint {
+            locations[v] = Some(Location::Reg(free.remove(i)));
+            active.push((end, v));
+            continue;
+        }
+        if let Some(r) = free.pop() {
+            locations[v] = Some(Location::Reg(r));
+            active.push((end, v));
+            continue;
+        }
+        let farthest = active.iter().enumerate().max_by_key(|(_, (e, _))| *e).map(|(i, &a)| (i, a));
+        match farthest {
+            Some((i, (e, a))) if e > end => {
+                locations[v] = locations[a];
+                locations[a] = Some(Location::Slot(slots));
+                active[i] = (end, v);
+            },
+            _ => locations[v] = Some(Location::Slot(slots)),
+        }
+        slots += 1;
+    }
+
+    Allocation { locations, slots, ranges }
+}
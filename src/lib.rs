@@ -1,35 +1,87 @@
-mod token;
-mod parse;
-mod assembly;
-
-use std::str;
+pub mod intern;
+pub mod token;
+pub mod parse;
+pub mod cli;
+pub mod assembly;
+mod dump;
+mod dce;
+mod elf;
+mod explain;
+mod fold;
+mod insn;
+mod ir;
+mod lint;
+mod peephole;
+mod report;
+mod temp;
+mod trace;
+
+use std::env;
 use std::fmt;
 use std::io;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
 use std::fs;
 use std::fs::File;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
 use std::process::Output;
 
-use rand::prelude::*;
-use getopts::Options;
-
+use intern::Interner;
+use intern::Symbol;
 use token::tokenize;
+use token::Span;
+use token::tokenize_with_docs;
 use token::Tokens;
+use token::TokenKind::TokenOp;
 use token::TokenError;
 use parse::Parser;
 use parse::ParseError;
+use parse::Ast;
+use parse::Node;
 use assembly::AsmGenerator;
-use assembly::AsmError;
+use assembly::CodegenOptions;
+use assembly::Target;
+use cli::Assembler;
+use cli::Config;
+use cli::Emit;
+use cli::Mode;
+use cli::OutputKind;
+use lint::Level;
+use lint::LintLevels;
+use report::Diagnostic;
+use report::Reporter;
+use report::Segment;
+use temp::TempFile;
+use trace::CountingWriter;
+use trace::PhaseTimer;
+use trace::Tracer;
+
+pub use assembly::AsmError;
+pub use cli::parse_args;
+pub use explain::explain;
+pub use report::LineTable;
 
 use CompileError::*;
 
+const EXIT_SUCCESS: i32 = 0;
+// Exit status for errors in the source code or the options.
+const EXIT_FAILURE: i32 = 1;
+// Exit status for IO errors and other problems of the environment.
+const EXIT_INTERNAL: i32 = 2;
+
+// Maximum number of parse errors reported at once.
+const MAX_ERRORS: usize = 20;
+
 #[derive(Debug)]
-enum CompileError {
+pub enum CompileError {
     Env(io::Error),
     Token(TokenError),
-    Parse(ParseError),
+    Parse(Vec<ParseError>),
     Asm(AsmError),
+    Denied(usize),
 }
 
 impl From<io::Error> for CompileError {
@@ -44,8 +96,8 @@ impl From<TokenError> for CompileError {
     }
 }
 
-impl From<ParseError> for CompileError {
-    fn from(e: ParseError) -> Self {
+impl From<Vec<ParseError>> for CompileError {
+    fn from(e: Vec<ParseError>) -> Self {
         Parse(e)
     }
 }
@@ -61,159 +113,872 @@ impl fmt::Display for CompileError {
         match self {
             Env(e) => write!(f, "{}", e),
             Token(e) => write!(f, "{}", e),
-            Parse(e) => write!(f, "{}", e),
+            Parse(errors) => {
+                let iter = errors.iter().enumerate();
+                for (cnt, e) in iter {
+                    if cnt > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            },
             Asm(e) => write!(f, "{}", e),
+            Denied(n) => write!(f, "{} denied lints are found", n),
+        }
+    }
+}
+
+impl CompileError {
+    // Code of the first error, if it has one.
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            Token(e) => Some(e.code()),
+            Parse(errors) => errors.first().map(|e| e.code()),
+            Asm(e) => Some(e.code()),
+            Env(_) | Denied(_) => None,
         }
     }
+
+    // Where the first error is in the source code. Errors after parsing
+    // have no position.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Token(e) => Some(e.span()),
+            Parse(errors) => errors.first().map(|e| e.span()),
+            _ => None,
+        }
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Env(e) => vec![Diagnostic::error(None, e.to_string(), None)],
+            Token(e) => vec![Diagnostic::error(Some(e.code()), e.message(), Some(e.span()))],
+            Parse(errors) => {
+                let mut diags: Vec<Diagnostic> = errors.iter()
+                    .take(MAX_ERRORS)
+                    .map(|e| Diagnostic::error(Some(e.code()), e.message(), Some(e.span())))
+                    .collect();
+                if errors.len() > MAX_ERRORS {
+                    let message = format!("{} more errors are not shown", errors.len() - MAX_ERRORS);
+                    diags.push(Diagnostic::error(None, message, None));
+                }
+                diags
+            },
+            Asm(e) => vec![Diagnostic::error(Some(e.code()), e.message(), None)],
+            // Denied lints are reported as they are found.
+            Denied(_) => Vec::new(),
+        }
+    }
+
+    fn exit_status(&self) -> i32 {
+        match self {
+            Env(_) | Asm(AsmError::Io(_)) => EXIT_INTERNAL,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+fn report_lints(parser: &Parser, reporter: &mut Reporter) -> Result<(), CompileError> {
+    let mut denied = 0;
+    for w in parser.warnings() {
+        let diag = if w.level() == Level::Deny {
+            denied += 1;
+            Diagnostic::error(Some(w.lint().name()), w.message().to_string(), Some(w.span()))
+        } else {
+            Diagnostic::warning(Some(w.lint().name()), w.message().to_string(), Some(w.span()))
+        };
+        reporter.report(&diag);
+    }
+
+    if denied > 0 {
+        return Err(Denied(denied));
+    }
+    Ok(())
+}
+
+// Reports a compile error, and returns the exit status for it.
+fn report_error(e: &CompileError, reporter: &mut Reporter) -> i32 {
+    for diag in e.diagnostics() {
+        reporter.report(&diag);
+    }
+    e.exit_status()
 }
 
-fn random_string(len: usize) -> String {
-    let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                   abcdefghijklmnopqrstuvwxyz\
-                   0123456789";
-    let mut rng = rand::thread_rng();
+fn trace_items(ast: &Ast, interner: &Interner, tracer: &Tracer) {
+    for item in ast.items() {
+        match &ast[*item] {
+            Node::Function { name, args, stack, block: _ } => {
+                let summary = format!("fn {} ({} args, stack {})", interner.resolve(*name), args.len(), stack);
+                tracer.phase("parse", &summary);
+            },
+            Node::DeclareGlobal { name, size, ty: _, init: _, mutable: _ } => {
+                let summary = format!("static {} ({} bytes)", interner.resolve(*name), size);
+                tracer.phase("parse", &summary);
+            },
+            _ => (),
+        }
+    }
+}
 
-    String::from_utf8(
-        source.choose_multiple(&mut rng, len)
-            .cloned()
-            .collect()
-    ).unwrap()
+// Runs the passes over the AST enabled at the optimization level.
+fn optimize_ast(ast: &mut Ast, interner: &Interner, config: &Config, timer: &mut PhaseTimer) {
+    let tracer = &config.tracer;
+    if config.opt_level >= 1 {
+        let count = timer.time("fold", || fold::fold(ast));
+        timer.note(&format!("{} nodes", count));
+        tracer.phase("fold", &format!("{} nodes folded", count));
+    }
+    if config.opt_level >= 2 {
+        let (statements, functions) = timer.time("dce", || dce::eliminate(ast, interner));
+        timer.note(&format!("{} statements, {} functions", statements, functions));
+        tracer.phase("dce", &format!("{} statements, {} functions removed", statements, functions));
+    }
 }
 
-fn compile_to_fname(formula: &str, fname: &str) -> Result<(), CompileError> {
-    let token_list = tokenize(formula)?;
+// Tokenizes and parses the source code, and reports the lints. `check`
+// stops here, and a build goes on to generate code from the AST and the
+// string literals.
+fn parse_source(formula: &str, interner: &mut Interner, config: &Config, reporter: &mut Reporter, timer: &mut PhaseTimer) -> Result<(Ast, Vec<Symbol>), CompileError> {
+    let tracer = &config.tracer;
+    let token_list = timer.time("tokenize", || tokenize(formula, interner))?;
     let mut tokens = Tokens::new(token_list);
+    timer.note(&format!("{} tokens", tokens.len()));
+    tracer.phase("tokenize", &format!("{} tokens", tokens.len()));
+    tracer.dump(|| dump::token_lines(formula, &tokens));
+
+    let mut parser = Parser::new(interner);
+    parser.set_lint_levels(config.lints.clone());
+    // Lints are not reliable for the code skipped by error recovery.
+    let ast = timer.time("parse", || parser.program(&mut tokens))?;
+    timer.note(&format!("{} items, {} nodes", ast.items().len(), ast.len()));
+    tracer.phase("parse", &format!("{} items, {} nodes", ast.items().len(), ast.len()));
+    trace_items(&ast, interner, tracer);
+    tracer.dump(|| dump::ast_tree(&ast, interner));
+    report_lints(&parser, reporter)?;
+
+    Ok((ast, parser.literals().to_vec()))
+}
 
-    let mut parser = Parser::new();
-    let nodes = parser.program(&mut tokens)?;
+fn compile_to(formula: &str, file: &str, out: &mut impl Write, config: &Config, reporter: &mut Reporter, timer: &mut PhaseTimer) -> Result<(), CompileError> {
+    let tracer = &config.tracer;
+    let mut interner = Interner::new();
+    let (mut ast, literals) = parse_source(formula, &mut interner, config, reporter, timer)?;
+
+    optimize_ast(&mut ast, &interner, config, timer);
+
+    let options = CodegenOptions {
+        opt_level: config.opt_level,
+        debug_info: config.debug_info,
+        // Only for assembly which is read by humans.
+        comments: config.kind == OutputKind::Asm,
+        syntax: config.asm_syntax,
+        target: config.target,
+        object: config.integrated_as && config.kind != OutputKind::Asm,
+        freestanding: config.freestanding,
+    };
+    let mut generator = AsmGenerator::new(&ast, &interner, options);
+    generator.set_source(file, formula);
+    let mut out = CountingWriter::new(out);
+    timer.time("codegen", || generator.gen_asm(&mut out, &literals))?;
+    timer.note(&format!("{} bytes", out.count()));
+    tracer.phase("codegen", &format!("{} string literals", literals.len()));
 
-    let mut f = File::create(fname.to_string())?;
+    Ok(())
+}
 
-    let literals = parser.literals();
-    let mut generator = AsmGenerator::new();
-    generator.gen_asm(&mut f, &nodes, literals)?;
+// Compiles the source code into assembly in memory. Lints are not
+// checked. The output only depends on the source code.
+pub fn compile_str(formula: &str) -> Result<String, CompileError> {
+    let mut config = Config::default();
+    config.lints.set("warnings", Level::Allow).unwrap();
+    let mut reporter = Reporter::new(config.error_format, config.color, "", "");
+    let mut out = Vec::new();
+    compile_to(formula, "", &mut out, &config, &mut reporter, &mut PhaseTimer::new(false))?;
+    Ok(String::from_utf8(out).unwrap())
+}
 
+fn dump_tokens(formula: &str) -> Result<String, CompileError> {
+    let mut interner = Interner::new();
+    let tokens = Tokens::new(tokenize(formula, &mut interner)?);
+    Ok(dump::token_lines(formula, &tokens))
+}
+
+// Only the errors found by parsing are reported, without generating any
+// code. Nothing is written.
+fn check(formula: &str, config: &Config, reporter: &mut Reporter, timer: &mut PhaseTimer) -> Result<(), CompileError> {
+    let mut interner = Interner::new();
+    parse_source(formula, &mut interner, config, reporter, timer)?;
     Ok(())
 }
 
-fn print_output(result: io::Result<Output>) {
+fn ast_to_json(formula: &str, lints: &LintLevels, reporter: &mut Reporter) -> Result<String, CompileError> {
+    let mut interner = Interner::new();
+    let token_list = tokenize_with_docs(formula, &mut interner)?;
+    let mut tokens = Tokens::new(token_list);
+
+    let mut parser = Parser::new(&interner);
+    parser.set_lint_levels(lints.clone());
+    let ast = parser.program(&mut tokens)?;
+    report_lints(&parser, reporter)?;
+
+    Ok(dump::ast_json(&ast, &interner))
+}
+
+// Functions in the IR as they are translated into assembly at the
+// optimization level. The passes over the IR are run from -O1.
+fn dump_ir(formula: &str, config: &Config, reporter: &mut Reporter) -> Result<String, CompileError> {
+    let mut interner = Interner::new();
+    let mut timer = PhaseTimer::new(false);
+    let (mut ast, _) = parse_source(formula, &mut interner, config, reporter, &mut timer)?;
+    optimize_ast(&mut ast, &interner, config, &mut timer);
+
+    let lines = LineTable::new(formula);
+    let mut funcs = Vec::new();
+    for &item in ast.items() {
+        if let Node::Function { name: _, args: _, stack: _, block: _ } = &ast[item] {
+            let mut func = ir::lower(&ast, &interner, item)?;
+            if config.opt_level >= 1 {
+                ir::optimize(&mut func);
+            }
+            funcs.push(dump::ir_text(&func, &ast, &interner, &lines));
+        }
+    }
+    Ok(funcs.join("\n"))
+}
+
+// Forwards the output of a command to the same streams, and returns its
+// exit status.
+fn print_output(result: io::Result<Output>) -> i32 {
     match result {
         Ok(output) => {
-            print!("{}", str::from_utf8(&output.stdout).unwrap());
-            print!("{}", str::from_utf8(&output.stderr).unwrap());
+            let _ = io::stdout().write_all(&output.stdout);
+            let _ = io::stderr().write_all(&output.stderr);
+            output.status.code().unwrap_or(EXIT_INTERNAL)
         },
         Err(e) => {
-            println!("{}", e);
+            eprintln!("{}", e);
+            EXIT_INTERNAL
         },
     }
 }
 
-fn cmd_assemble(src: &str, dst: &str) {
-    let cmd_result = Command::new("gcc")
-        .arg(src)
-        .arg("-o")
-        .arg(dst)
-        .output();
+fn find_program(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        return Some(path.to_path_buf()).filter(|p| p.is_file());
+    }
+    let dirs = env::var_os("PATH")?;
+    env::split_paths(&dirs)
+        .map(|dir| dir.join(name))
+        .find(|p| p.is_file())
+}
 
-    print_output(cmd_result);
+// --cc or $YRC_CC, which is preferred to the C compilers found in PATH.
+fn explicit_cc(cc: Option<String>) -> Option<String> {
+    cc.or_else(|| env::var("YRC_CC").ok().filter(|s| !s.is_empty()))
 }
 
-fn cmd_remove_asm(src: &str) {
-    let cmd_result = Command::new("rm")
-        .arg("-f")
-        .arg(src)
-        .output();
+// Looks for the C compiler to assemble and link with.
+fn find_cc(cc: Option<String>, target: Target) -> Option<PathBuf> {
+    let explicit = explicit_cc(cc);
+    // The other platforms need a cross compiler except on their own hosts.
+    let names: &[&str] = match target {
+        Target::WindowsGnu if !cfg!(windows) => &["x86_64-w64-mingw32-gcc"],
+        Target::Aarch64 if !cfg!(target_arch = "aarch64") => &["aarch64-linux-gnu-gcc"],
+        Target::Riscv64 if !cfg!(target_arch = "riscv64") => &["riscv64-linux-gnu-gcc"],
+        _ => &["cc", "gcc", "clang"],
+    };
+    match explicit {
+        Some(name) => find_program(&name),
+        None => names.iter().find_map(|name| find_program(name)),
+    }
+}
+
+// Directories where the C runtime startup files of glibc are installed for
+// x86-64, and the dynamic linker which an executable linked by `ld` names.
+const CRT_DIRS: [&str; 4] = ["/usr/lib/x86_64-linux-gnu", "/usr/lib64", "/lib/x86_64-linux-gnu", "/usr/lib"];
+const DYNAMIC_LINKER: &str = "/lib64/ld-linux-x86-64.so.2";
 
-    print_output(cmd_result);
+fn find_crt_dir() -> Option<PathBuf> {
+    CRT_DIRS.iter()
+        .map(Path::new)
+        .find(|dir| ["crt1.o", "crti.o", "crtn.o"].iter().all(|name| dir.join(name).is_file()))
+        .map(Path::to_path_buf)
 }
 
-fn cmd_rename_asm(src: &str, dst: &str) {
-    let cmd_result = Command::new("mv")
+// External programs which make the output. Only the ones which the build
+// needs are looked up, before anything is written.
+#[derive(Default)]
+struct Toolchain {
+    cc: Option<PathBuf>,
+    // `as` and `ld` of binutils for --assembler=as.
+    assembler: Option<PathBuf>,
+    linker: Option<PathBuf>,
+    // The C runtime startup files which `ld` links without the C compiler.
+    crt_dir: Option<PathBuf>,
+}
+
+fn find_toolchain(config: &Config) -> Result<Toolchain, String> {
+    let builds = config.mode == Mode::Run || (config.mode == Mode::Build && config.emit.is_none());
+    let links = builds && config.kind == OutputKind::Executable;
+    let assembles = links || (builds && config.kind == OutputKind::Object && !config.integrated_as);
+    let no_cc = "No C compiler found to assemble output! (install gcc or pass --cc)";
+    let mut tools = Toolchain::default();
+    match config.assembler {
+        Assembler::Cc if assembles => {
+            tools.cc = Some(find_cc(config.cc.clone(), config.target).ok_or(no_cc)?);
+        },
+        Assembler::Cc => (),
+        Assembler::As => {
+            if assembles {
+                tools.assembler = Some(find_program("as").ok_or("No assembler found! (install binutils or pass --assembler=cc)")?);
+            }
+            // The C compiler links the C runtime if there is one, and has
+            // to be found if it is given.
+            if links && !config.freestanding {
+                tools.cc = find_cc(config.cc.clone(), config.target);
+                if tools.cc.is_none() && explicit_cc(config.cc.clone()).is_some() {
+                    return Err(no_cc.to_string());
+                }
+            }
+            if links && tools.cc.is_none() {
+                tools.linker = Some(find_program("ld").ok_or("No linker found! (install binutils)")?);
+                if !config.freestanding {
+                    tools.crt_dir = Some(find_crt_dir().ok_or("No C runtime found to link with! (install libc or pass --freestanding)")?);
+                }
+            }
+        },
+    }
+    Ok(tools)
+}
+
+// Assembles the source, and links it with `link_args` unless it is None.
+// `debug_info` makes the assembler output the DWARF line table.
+fn cmd_assemble(cc: &Path, src: &Path, dst: &Path, debug_info: bool, link_args: Option<&[String]>) -> i32 {
+    let mut cmd = Command::new(cc);
+    if link_args.is_none() {
+        cmd.arg("-c");
+    }
+    if debug_info {
+        cmd.arg("-g");
+    }
+    let cmd_result = cmd
         .arg(src)
+        .arg("-o")
         .arg(dst)
+        .args(link_args.unwrap_or_default())
         .output();
 
-    print_output(cmd_result);
+    print_output(cmd_result)
+}
+
+// `as` of binutils, which assembles the source into an object. The line
+// table is made of the `.loc` directives without -g.
+fn as_command(assembler: &Path, src: &Path, dst: &Path) -> Command {
+    let mut cmd = Command::new(assembler);
+    cmd.arg(src).arg("-o").arg(dst);
+    cmd
+}
+
+// `ld`, which links the object with the C runtime in `crt_dir` and libc,
+// or only with `link_args` for a freestanding program.
+fn ld_command(linker: &Path, obj: &Path, dst: &Path, crt_dir: Option<&Path>, link_args: &[String]) -> Command {
+    let mut cmd = Command::new(linker);
+    cmd.arg("-o").arg(dst);
+    // The executable is position dependent unless -pie is given.
+    let link_args = link_args.iter().filter(|arg| *arg != "-no-pie");
+    match crt_dir {
+        Some(dir) => {
+            cmd.arg("-dynamic-linker")
+                .arg(DYNAMIC_LINKER)
+                .arg(dir.join("crt1.o"))
+                .arg(dir.join("crti.o"))
+                .arg(obj)
+                .args(link_args)
+                .arg("-L")
+                .arg(dir)
+                .arg("-lc")
+                .arg(dir.join("crtn.o"));
+        },
+        None => {
+            cmd.arg(obj).args(link_args);
+        },
+    }
+    cmd
 }
 
-pub fn compiler_main(args: Vec<String>) {
-    if args.len() < 2 {
-        println!("Input file is needed!");
-        return;
+// The command as it is traced.
+fn command_line(cmd: &Command) -> String {
+    let mut line = cmd.get_program().to_string_lossy().into_owned();
+    for arg in cmd.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
     }
+    line
+}
 
-    let mut opts = Options::new();
-    opts.optopt("o", "output", "set output file name", "NAME");
-    opts.optflag("s", "asm", "output assemble code");
-    opts.optflag("h", "help", "print this help message");
+// Assembles the source with `as` into a temporary object, and links it
+// with the C compiler, or with `ld` if it is not found.
+fn build_with_as(src: &Path, dst: &str, config: &Config, tools: &Toolchain, timer: &mut PhaseTimer) -> io::Result<i32> {
+    let assembler = tools.assembler.as_deref().unwrap_or_else(|| Path::new("as"));
+    let (obj, _) = TempFile::create(".o")?;
+    let mut command = as_command(assembler, src, obj.path());
+    config.tracer.phase("assemble", &command_line(&command));
+    let status = timer.time("assemble", || print_output(command.output()));
+    if status != EXIT_SUCCESS {
+        return Ok(status);
+    }
 
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        Err(_) => {
-            println!("Invalid option!");
-            return;
+    let link_command = |dst: &Path| match (&tools.cc, &tools.linker) {
+        (Some(cc), _) => {
+            let mut cmd = Command::new(cc);
+            cmd.arg(obj.path()).arg("-o").arg(dst).args(&config.link_args);
+            cmd
+        },
+        (None, linker) => {
+            let linker = linker.as_deref().unwrap_or_else(|| Path::new("ld"));
+            ld_command(linker, obj.path(), dst, tools.crt_dir.as_deref(), &config.link_args)
         },
     };
+    config.tracer.phase("link", &command_line(&link_command(Path::new(dst))));
+    timer.time("link", || make_into(dst, |tmp| print_output(link_command(tmp).output())))
+}
 
-    if matches.opt_present("h") {
-        println!("{}", opts.usage(""));
-        return;
+// Makes the output into a temporary file next to it, which replaces the
+// output only on success. A failure leaves no partial output, and keeps
+// the previous one.
+fn make_into(dst: &str, make: impl FnOnce(&Path) -> i32) -> io::Result<i32> {
+    let dir = Path::new(dst).parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let (tmp, _) = TempFile::create_in(dir, "")?;
+    let status = make(tmp.path());
+    if status == EXIT_SUCCESS {
+        move_file(tmp.path(), dst)?;
     }
-    let asm_out = matches.opt_present("s");
-    let output_file = matches.opt_str("o");
+    Ok(status)
+}
 
-    let input_file = match matches.free.get(0) {
-        Some(s) => s,
-        None => {
-            println!("Input file is needed!");
-            return;
+// Renames the file, or copies it if it is on another file system.
+fn move_file(src: &Path, dst: &str) -> io::Result<()> {
+    match fs::rename(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(src, dst)?;
+            fs::remove_file(src)
+        },
+        result => result,
+    }
+}
+
+// Whether the paths point to the same existing file.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Whether the file looks like what yrc writes as the kind of output, so
+// that rebuilding can overwrite it.
+fn is_previous_output(path: &Path, kind: OutputKind, target: Target) -> bool {
+    let mut head = [0; 22];
+    let n = match File::open(path).and_then(|mut f| f.read(&mut head)) {
+        Ok(n) => n,
+        Err(_) => return false,
+    };
+    match kind {
+        // AT&T syntax has no header, and begins with the string literals.
+        OutputKind::Asm => {
+            let headers: [&[u8]; 4] = [b".intel_syntax noprefix", b".section .rodata", b".section .rdata", b".file 1 "];
+            headers.iter().any(|h| head[..n].starts_with(h))
+        },
+        _ if target != Target::WindowsGnu => head[..n].starts_with(b"\x7fELF"),
+        // A COFF object begins with the machine type of x86-64.
+        OutputKind::Object => head[..n].starts_with(b"\x64\x86"),
+        OutputKind::Executable => head[..n].starts_with(b"MZ"),
+    }
+}
+
+// Checks that writing the output destroys neither the input nor an
+// unrelated file.
+fn check_output(input_file: &str, output_file: &Path, kind: OutputKind, config: &Config) -> Result<(), String> {
+    if same_file(Path::new(input_file), output_file) {
+        return Err(format!("Output file is the same as the input file! ({})", output_file.display()));
+    }
+    if !config.force && fs::symlink_metadata(output_file).is_ok() && !is_previous_output(output_file, kind, config.target) {
+        return Err(format!("Output file already exists! ({}, pass --force to overwrite)", output_file.display()));
+    }
+    Ok(())
+}
+
+// "-" reads the source code from stdin.
+fn read_source(input_file: &str) -> (&str, io::Result<String>) {
+    if input_file == "-" {
+        ("<stdin>", io::read_to_string(io::stdin()))
+    } else {
+        (input_file, fs::read_to_string(input_file))
+    }
+}
+
+fn check_file(input_file: &str, config: &Config) -> i32 {
+    let (input_file, read_result) = read_source(input_file);
+    let source_code = match read_result {
+        Ok(s) => s,
+        Err(e) => {
+            let mut reporter = Reporter::new(config.error_format, config.color, input_file, "");
+            reporter.report(&Diagnostic::error(None, e.to_string(), None));
+            return EXIT_INTERNAL;
         },
     };
+    let mut reporter = Reporter::new(config.error_format, config.color, input_file, &source_code);
+    let mut timer = PhaseTimer::new(config.time_passes);
 
-    let path = Path::new(input_file);
-    let default_name = path.file_stem().unwrap().to_str().unwrap();
+    let status = match check(&source_code, config, &mut reporter, &mut timer) {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) => report_error(&e, &mut reporter),
+    };
+    reporter.summary();
+    timer.report();
+    status
+}
+
+fn compile_file(input_file: &str, output_file: Option<&str>, config: &Config, tools: &Toolchain) -> i32 {
+    let from_stdin = input_file == "-";
+    let (input_file, read_result) = read_source(input_file);
+    let source_code = match read_result {
+        Ok(s) => s,
+        Err(e) => {
+            let mut reporter = Reporter::new(config.error_format, config.color, input_file, "");
+            reporter.report(&Diagnostic::error(None, e.to_string(), None));
+            return EXIT_INTERNAL;
+        },
+    };
+    compile_source(input_file, &source_code, &[], from_stdin, output_file, config, tools)
+}
+
+// Builds the source code, which is named `input_file` in diagnostics.
+fn compile_source(input_file: &str, source_code: &str, segments: &[Segment], from_stdin: bool, output_file: Option<&str>, config: &Config, tools: &Toolchain) -> i32 {
+    let mut reporter = Reporter::new(config.error_format, config.color, input_file, source_code).with_segments(segments);
+    let lints = &config.lints;
+    let tracer = &config.tracer;
+    let mut timer = PhaseTimer::new(config.time_passes);
+
+    if let Some(emit) = config.emit {
+        let result = match emit {
+            Emit::Tokens => dump_tokens(source_code),
+            Emit::AstJson => ast_to_json(source_code, lints, &mut reporter),
+            Emit::Ir => dump_ir(source_code, config, &mut reporter),
+        };
+        let status = match result {
+            Ok(text) => {
+                // Without -o, the output is printed for piping into tools.
+                let written = match output_file {
+                    None | Some("-") => io::stdout().write_all(text.as_bytes()),
+                    Some(fname) if same_file(Path::new(input_file), Path::new(fname)) => {
+                        let msg = format!("Output file is the same as the input file! ({})", fname);
+                        reporter.report(&Diagnostic::error(None, msg, None));
+                        reporter.summary();
+                        return EXIT_FAILURE;
+                    },
+                    Some(fname) => fs::write(fname, text),
+                };
+                match written {
+                    Ok(_) => EXIT_SUCCESS,
+                    Err(e) => {
+                        reporter.report(&Diagnostic::error(None, e.to_string(), None));
+                        EXIT_INTERNAL
+                    },
+                }
+            },
+            Err(e) => report_error(&e, &mut reporter),
+        };
+        reporter.summary();
+        return status;
+    }
+
+    // Input from stdin has no name to derive the output name from.
     let output_file = match output_file {
-        Some(s) => s,
+        Some(s) => s.to_string(),
         None => {
-            if asm_out {
-                let default_asm_name = format!("{}.s", default_name);
-                default_asm_name
+            let default_name = if from_stdin {
+                "out"
             } else {
-                default_name.to_string()
+                let path = Path::new(input_file);
+                path.file_stem().unwrap().to_str().unwrap()
+            };
+            match config.kind {
+                OutputKind::Asm => format!("{}.s", default_name),
+                OutputKind::Object => format!("{}.o", default_name),
+                OutputKind::Executable if config.target == Target::WindowsGnu => {
+                    format!("{}.exe", if from_stdin { "a" } else { default_name })
+                },
+                OutputKind::Executable if from_stdin => "a.out".to_string(),
+                OutputKind::Executable => default_name.to_string(),
             }
         }
     };
 
-    let source_code = match fs::read_to_string(input_file) {
-        Ok(s) => s,
+    // "-o -" writes the assembly to stdout without any temporary file.
+    if output_file == "-" {
+        if config.kind != OutputKind::Asm {
+            reporter.report(&Diagnostic::error(None, "Only assembly can be written to stdout!".to_string(), None));
+            return EXIT_FAILURE;
+        }
+        // Nothing is printed unless the whole assembly is generated.
+        let mut asm = Vec::new();
+        let result = compile_to(source_code, input_file, &mut asm, config, &mut reporter, &mut timer)
+            .and_then(|_| Ok(io::stdout().write_all(&asm)?));
+        let status = match result {
+            Ok(_) => EXIT_SUCCESS,
+            Err(e) => report_error(&e, &mut reporter),
+        };
+        reporter.summary();
+        timer.report();
+        return status;
+    }
+
+    // The integrated assembler leaves no assembly to keep.
+    let object = config.integrated_as && config.kind != OutputKind::Asm;
+    let keep_asm = config.save_temps && config.kind != OutputKind::Asm && !object;
+    let saved_asm = Path::new(&output_file).with_extension("s");
+    let mut outputs = vec![(Path::new(&output_file), config.kind)];
+    if keep_asm {
+        outputs.push((&saved_asm, OutputKind::Asm));
+    }
+    for (path, kind) in outputs {
+        if let Err(msg) = check_output(input_file, path, kind, config) {
+            reporter.report(&Diagnostic::error(None, msg, None));
+            reporter.summary();
+            return EXIT_FAILURE;
+        }
+    }
+    let result = TempFile::create(if object { ".o" } else { ".s" })
+        .map_err(CompileError::from)
+        .and_then(|(tmp, f)| {
+            let mut f = BufWriter::new(f);
+            compile_to(source_code, input_file, &mut f, config, &mut reporter, &mut timer)?;
+            Ok(tmp)
+        });
+    let tmp = match result {
+        Ok(tmp) => tmp,
+        Err(e) => {
+            let status = report_error(&e, &mut reporter);
+            reporter.summary();
+            timer.report();
+            return status;
+        },
+    };
+    let tmp_file = tmp.path();
+    let cc = tools.cc.as_deref().unwrap_or_else(|| Path::new("cc"));
+    let assembler = tools.assembler.as_deref().unwrap_or_else(|| Path::new("as"));
+    let debug_flag = if config.debug_info { " -g" } else { "" };
+
+    let status = match config.kind {
+        OutputKind::Executable if config.assembler == Assembler::As => {
+            build_with_as(tmp_file, &output_file, config, tools, &mut timer)
+        },
+        OutputKind::Executable => {
+            // Only affects the startup code linked by the C compiler.
+            let mut link_args = config.link_args.clone();
+            if config.opt_level > 0 {
+                link_args.insert(0, format!("-O{}", config.opt_level));
+            }
+            // The program has its own entry point, and no library is
+            // loaded for it.
+            if config.freestanding {
+                link_args.splice(0..0, ["-nostdlib".to_string(), "-static".to_string()]);
+            }
+            let mut command = format!("{}{} {} -o {}", cc.display(), debug_flag, tmp_file.display(), output_file);
+            for arg in &link_args {
+                command.push(' ');
+                command.push_str(arg);
+            }
+            tracer.phase("link", &command);
+            timer.time("link", || make_into(&output_file, |tmp| cmd_assemble(cc, tmp_file, tmp, config.debug_info, Some(&link_args))))
+        },
+        OutputKind::Object if object => move_file(tmp_file, &output_file).map(|_| EXIT_SUCCESS),
+        OutputKind::Object if config.assembler == Assembler::As => {
+            let command = |dst: &Path| as_command(assembler, tmp_file, dst);
+            tracer.phase("assemble", &command_line(&command(Path::new(&output_file))));
+            timer.time("assemble", || make_into(&output_file, |tmp| print_output(command(tmp).output())))
+        },
+        OutputKind::Object => {
+            let command = format!("{} -c{} {} -o {}", cc.display(), debug_flag, tmp_file.display(), output_file);
+            tracer.phase("assemble", &command);
+            timer.time("assemble", || make_into(&output_file, |tmp| cmd_assemble(cc, tmp_file, tmp, config.debug_info, None)))
+        },
+        OutputKind::Asm => move_file(tmp_file, &output_file).map(|_| EXIT_SUCCESS),
+    };
+    // The assembly is saved next to the output, only once it is complete.
+    let status = match status {
+        Ok(status) if keep_asm => move_file(tmp_file, &saved_asm.to_string_lossy()).map(|_| status),
+        result => result,
+    };
+    let saved = keep_asm && status.is_ok();
+    let status = status.unwrap_or_else(|e| report_error(&Env(e), &mut reporter));
+    reporter.summary();
+    timer.report();
+    if saved {
+        eprintln!("Assembly is saved to {}", saved_asm.display());
+    }
+    status
+}
+
+// Builds the program into the temporary directory, and runs it. The exit
+// status is the program's one unless the build fails.
+fn run_file(input_file: &str, config: &Config, tools: &Toolchain) -> i32 {
+    run_built(config, |exe_file| compile_file(input_file, Some(exe_file), config, tools))
+}
+
+// Runs the `-e` snippets in `main` after the items of the included files,
+// and prints the value of the last expression.
+fn run_eval(config: &Config, tools: &Toolchain) -> i32 {
+    let mut included = Vec::new();
+    for file in &config.includes {
+        match fs::read_to_string(file) {
+            Ok(s) => included.push((file.as_str(), s)),
+            Err(e) => {
+                let mut reporter = Reporter::new(config.error_format, config.color, file, "");
+                reporter.report(&Diagnostic::error(None, e.to_string(), None));
+                return EXIT_INTERNAL;
+            },
+        }
+    }
+    let snippet = config.eval.join("\n");
+    let (source_code, segments) = eval_source(&included, &snippet);
+    run_built(config, |exe_file| compile_source("<-e>", &source_code, &segments, false, Some(exe_file), config, tools))
+}
+
+// Program of the `-e` snippets, which are lines of `main`, and the segments
+// of the included files and the snippets in it. The statements up to the
+// last `;` or block outside of blocks are run, and the expression after
+// them is printed unless it is empty.
+fn eval_source<'a>(included: &'a [(&'a str, String)], snippet: &'a str) -> (String, Vec<Segment<'a>>) {
+    let mut source = String::new();
+    let mut segments = Vec::new();
+    for (file, text) in included {
+        segments.push(Segment { start: source.len(), len: text.len(), file, text, offset: 0 });
+        source.push_str(text);
+        source.push('\n');
+    }
+    // Broken tokens are reported in the snippet as it is.
+    let (tokens, mut split) = match tokenize(snippet, &mut Interner::new()) {
+        Ok(tokens) => (tokens, 0),
+        Err(_) => (Vec::new(), snippet.len()),
+    };
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind() {
+            TokenOp(op) if op == "{" => depth += 1,
+            TokenOp(op) if op == "}" => {
+                depth -= 1;
+                // `}` followed by an operator may end a value, e.g. of a
+                // struct.
+                let next = tokens.get(i + 1).map(|t| t.kind());
+                if depth == 0 && !matches!(next, Some(TokenOp(_))) {
+                    split = token.span().end;
+                }
+            },
+            TokenOp(op) if op == ";" && depth == 0 => split = token.span().end,
+            _ => (),
+        }
+    }
+    let (statements, value) = snippet.split_at(split);
+    source.push_str("fn printf(format: &i8, value: i64) -> i32;\nfn main() -> i32 {\n");
+    segments.push(Segment { start: source.len(), len: statements.len(), file: "<-e>", text: snippet, offset: 0 });
+    source.push_str(statements);
+    source.push('\n');
+    if !value.trim().is_empty() {
+        source.push_str("printf(c\"%ld\\n\",\n");
+        segments.push(Segment { start: source.len(), len: value.len(), file: "<-e>", text: snippet, offset: split });
+        source.push_str(value);
+        source.push_str("\n);\n");
+    }
+    source.push_str("return 0;\n}\n");
+    (source, segments)
+}
+
+// Builds the program into a temporary file with `build`, and runs it.
+fn run_built(config: &Config, build: impl FnOnce(&str) -> i32) -> i32 {
+    let exe = match TempFile::create("") {
+        Ok((tmp, _)) => tmp,
         Err(e) => {
-            println!("{}", e);
-            return;
+            eprintln!("{}", e);
+            return EXIT_INTERNAL;
         },
     };
+    let exe_file = exe.path().to_string_lossy();
+    let status = build(&exe_file);
+    if status != EXIT_SUCCESS {
+        return status;
+    }
 
-    let tmp_file = format!("tmp{}.s", random_string(8));
+    config.tracer.phase("run", &exe_file);
+    match Command::new(exe.path()).args(&config.run_args).status() {
+        Ok(status) => status.code().unwrap_or(EXIT_INTERNAL),
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_INTERNAL
+        },
+    }
+}
 
-    match compile_to_fname(&source_code, &tmp_file) {
-        Ok(_) => (),
+pub fn compiler_main(args: Vec<String>) -> i32 {
+    let config = match parse_args(args) {
+        Ok(config) => config,
         Err(e) => {
-            println!("Error!");
-            match e {
-                Env(e) => println!("{}", e),
-                _ => {
-                    println!("{}", &source_code.replace("\n", " "));
-                    println!("{}", e);
-                    cmd_remove_asm(&tmp_file);
-                    return;
+            eprintln!("{}", e);
+            return EXIT_FAILURE;
+        },
+    };
+
+    match &config.mode {
+        Mode::Help(text) => {
+            println!("{}", text);
+            return EXIT_SUCCESS;
+        },
+        Mode::Version => {
+            println!("yrc {}", env!("CARGO_PKG_VERSION"));
+            return EXIT_SUCCESS;
+        },
+        Mode::Explain(code) => {
+            match explain(code) {
+                Some(text) => {
+                    println!("{}", text);
+                    return EXIT_SUCCESS;
                 },
-            };
+                None => {
+                    eprintln!("Unknown error code! ({})", code);
+                    return EXIT_FAILURE;
+                },
+            }
+        },
+        _ => (),
+    }
+
+    let tools = match find_toolchain(&config) {
+        Ok(tools) => tools,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            return EXIT_INTERNAL;
         },
     };
 
-    if !asm_out {
-        cmd_assemble(&tmp_file, &output_file);
-    } else {
-        cmd_rename_asm(&tmp_file, &output_file);
+    if !config.eval.is_empty() {
+        return run_eval(&config, &tools);
+    }
+    let output_file = config.output_file.as_deref();
+    let mut status = EXIT_SUCCESS;
+    for input_file in &config.input_files {
+        let result = match config.mode {
+            Mode::Run => run_file(input_file, &config, &tools),
+            Mode::Check => check_file(input_file, &config),
+            _ => compile_file(input_file, output_file, &config, &tools),
+        };
+        if status == EXIT_SUCCESS {
+            status = result;
+        }
     }
-    cmd_remove_asm(&tmp_file);
+    status
 }
@@ -0,0 +1,134 @@
+use std::fmt;
+
+use super::token::Span;
+
+use Lint::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lint {
+    UnusedVariables,
+    UnreachableCode,
+}
+
+pub const ALL_LINTS: [Lint; 2] = [UnusedVariables, UnreachableCode];
+
+// Name of the group which contains all lints.
+pub const WARNINGS: &str = "warnings";
+
+impl Lint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnusedVariables => "unused-variables",
+            UnreachableCode => "unreachable-code",
+        }
+    }
+
+    fn default_level(&self) -> Level {
+        match self {
+            UnusedVariables => Level::Warn,
+            UnreachableCode => Level::Warn,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Lint> {
+        ALL_LINTS.iter()
+            .find(|lint| lint.name() == name)
+            .copied()
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownLint {
+    name: String,
+}
+
+impl fmt::Display for UnknownLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = ALL_LINTS.iter()
+            .map(|lint| lint.name())
+            .collect();
+        write!(f, "Unknown lint! ({}) Available lints are {} and {}.",
+               self.name, names.join(", "), WARNINGS)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintLevels {
+    levels: Vec<(Lint, Level)>,
+}
+
+impl LintLevels {
+    // `name` is a lint name or "warnings" for all lints.
+    pub fn set(&mut self, name: &str, level: Level) -> Result<(), UnknownLint> {
+        if name == WARNINGS {
+            for (_, l) in self.levels.iter_mut() {
+                *l = level;
+            }
+            return Ok(());
+        }
+
+        let lint = Lint::from_name(name)
+            .ok_or_else(|| UnknownLint { name: name.to_string() })?;
+        for (_, l) in self.levels.iter_mut().filter(|(l, _)| *l == lint) {
+            *l = level;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, lint: Lint) -> Level {
+        self.levels.iter()
+            .find(|(l, _)| *l == lint)
+            .map(|(_, level)| *level)
+            .unwrap_or_else(|| lint.default_level())
+    }
+
+    pub fn new() -> Self {
+        LintLevels {
+            levels: ALL_LINTS.iter()
+                .map(|lint| (*lint, lint.default_level()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LintWarning {
+    lint: Lint,
+    level: Level,
+    message: String,
+    span: Span,
+}
+
+impl LintWarning {
+    pub fn new(lint: Lint, level: Level, message: String, span: Span) -> Self {
+        LintWarning {
+            lint,
+            level,
+            message,
+            span,
+        }
+    }
+
+    pub fn lint(&self) -> Lint {
+        self.lint
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
@@ -1,7 +1,8 @@
 use std::env;
+use std::process;
 use yrc::compiler_main;
 
 fn main() {
     let args = env::args().collect();
-    compiler_main(args);
+    process::exit(compiler_main(args));
 }
@@ -1,12 +1,27 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::fmt;
+use std::ops::Index;
+use std::ops::IndexMut;
 
 use super::token::Tokens;
+use super::token::TokenKind;
+use super::token::Span;
+use super::intern::Interner;
+use super::intern::Symbol;
+use super::intern::SymbolMap;
+use super::lint::Lint;
+use super::lint::Level;
+use super::lint::LintLevels;
+use super::lint::LintWarning;
 
 use BinaryOpKind::*;
 use UnaryOpKind::*;
 use ParseErrorKind::*;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ParseErrorKind {
     NumberExpected,
     FuncExpected,
@@ -22,34 +37,98 @@ pub enum ParseErrorKind {
     NotInTop,
     NotSized,
     ExprInvalid,
+    KeywordAsIdentifier(&'static str),
+    DivisionByZero,
+    AssignAsValue,
+    UnitAsValue,
+    IndexOutOfBounds,
+    TooDeep,
+    NotConstant,
+    AddressInStatic,
+    OutOfRange,
+    AssignToStatic,
 }
 
 #[derive(Debug)]
 pub struct ParseError {
     error: ParseErrorKind,
-    pos: usize,
+    span: Span,
 }
 
 impl ParseError {
     fn new(e: ParseErrorKind, toks: &Tokens) -> Self {
         ParseError {
             error: e,
-            pos: toks.head(),
+            span: toks.span(),
         }
     }
 
-    fn new_with_offset(e: ParseErrorKind, toks: &Tokens, offset: usize) -> Self {
+    fn new_with_span(e: ParseErrorKind, span: Span) -> Self {
         ParseError {
             error: e,
-            pos: toks.head_before(offset).unwrap_or(0),
+            span,
+        }
+    }
+}
+
+impl ParseErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScolonExpected => "E0101",
+            ColonExpected => "E0102",
+            ParenExpected => "E0103",
+            BlockExpected => "E0104",
+            NumberExpected => "E0105",
+            FuncExpected => "E0106",
+            VariableExpected => "E0107",
+            TypeExpected => "E0108",
+            ArgExpected => "E0109",
+            TypeInvalid => "E0110",
+            UnknownVariable => "E0111",
+            NotInTop => "E0112",
+            NotSized => "E0113",
+            ExprInvalid => "E0114",
+            KeywordAsIdentifier(_) => "E0115",
+            DivisionByZero => "E0116",
+            AssignAsValue => "E0117",
+            UnitAsValue => "E0118",
+            IndexOutOfBounds => "E0119",
+            TooDeep => "E0120",
+            NotConstant => "E0121",
+            AddressInStatic => "E0122",
+            OutOfRange => "E0123",
+            AssignToStatic => "E0124",
         }
     }
 }
 
+impl ParseError {
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.error
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.error.code()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn message(&self) -> String {
+        self.error.to_string()
+    }
+}
+
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}^ ", " ".repeat(self.pos))?;
-        match &self.error {
+        write!(f, "error[{}]: {}", self.code(), self.error)
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
             NumberExpected => write!(f, "Number is expected here!"),
             FuncExpected => write!(f, "Function is expected here!"),
             VariableExpected => write!(f, "Variable is expected here!"),
@@ -64,11 +143,26 @@ impl fmt::Display for ParseError {
             NotInTop => write!(f, "Cannot use in top level!"),
             NotSized => write!(f, "Cannot get variable size!"),
             ExprInvalid => write!(f, "Invalid expression!"),
+            KeywordAsIdentifier(word) => write!(f, "Keyword `{}` cannot be used as an identifier!", word),
+            DivisionByZero => write!(f, "Division by zero!"),
+            AssignAsValue => write!(f, "Assignment cannot be used as a value!"),
+            UnitAsValue => write!(f, "Function without a return type cannot be used as a value!"),
+            IndexOutOfBounds => write!(f, "Index is out of bounds!"),
+            TooDeep => write!(f, "Nesting is too deep!"),
+            NotConstant => write!(f, "Static must be initialized with a constant!"),
+            AddressInStatic => write!(f, "Address cannot be used to initialize a static!"),
+            OutOfRange => write!(f, "Value does not fit in the type!"),
+            AssignToStatic => write!(f, "Cannot assign to a static which is not `mut`!"),
         }
     }
 }
 
 const WORDSIZE: usize = 8;
+// Largest size of a type in bytes.
+const MAX_SIZE: usize = i32::MAX as usize;
+// Deepest nesting of expressions, statements and types. The recursion in
+// the later passes follows it.
+const MAX_DEPTH: usize = 128;
 
 #[derive(Debug, PartialEq)]
 pub enum BinaryOpKind {
@@ -76,8 +170,11 @@ pub enum BinaryOpKind {
     BinaryOpSub,
     BinaryOpMul,
     BinaryOpDiv,
+    BinaryOpRem,
     BinaryOpEq,
     BinaryOpNe,
+    BinaryOpLt,
+    BinaryOpLe,
     BinaryOpGr,
     BinaryOpGe,
     BinaryOpAsn,
@@ -93,227 +190,478 @@ pub enum UnaryOpKind {
 pub enum Node {
     BinaryOperator {
         kind: BinaryOpKind,
-        lhs: Box<Node>,
-        rhs: Box<Node>,
+        lhs: NodeId,
+        rhs: NodeId,
     },
     UnaryOperator {
         kind: UnaryOpKind,
-        rhs: Box<Node>,
+        rhs: NodeId,
     },
     Number {
-        val: u32,
+        val: u64,
     },
     Bool {
         bl: bool,
     },
     StrLiteral {
-        s: String,
+        s: Symbol,
+        label: usize,
+    },
+    // Pointer to the NUL-terminated bytes of `c"..."`. `s` ends with the
+    // NUL.
+    CStrLiteral {
+        s: Symbol,
         label: usize,
     },
     LocalVariable {
         offset: usize,
         ty: Type,
     },
+    #[allow(dead_code)]
     DeclareLocal {
         offset: usize,
         ty: Type,
     },
     GlobalVariable {
-        name: String,
+        name: Symbol,
         offset: usize,
         ty: Type,
     },
+    #[allow(dead_code)]
     DeclareGlobal {
-        name: String,
+        name: Symbol,
         size: usize,
         ty: Type,
+        // Value computed at compile time, or None to be zero.
+        init: Option<i64>,
+        mutable: bool,
     },
     Block {
-        nodes: Vec<Box<Node>>,
+        nodes: Vec<NodeId>,
     },
     Function {
-        name: String,
-        args: Vec<Box<Node>>,
+        name: Symbol,
+        args: Vec<NodeId>,
         stack: usize,
-        block: Box<Node>,
+        block: NodeId,
     },
+    #[allow(dead_code)]
     DeclareFunc {
-        name: String,
-        args: Vec<Box<Node>>,
+        name: Symbol,
+        args: Vec<NodeId>,
     },
     Call {
-        name: String,
-        args: Vec<Box<Node>>,
+        name: Symbol,
+        args: Vec<NodeId>,
         ty: Type,
     },
     If {
-        cond: Box<Node>,
-        ibody: Box<Node>,
+        cond: NodeId,
+        ibody: NodeId,
     },
     IfElse {
-        cond: Box<Node>,
-        ibody: Box<Node>,
-        ebody: Box<Node>,
+        cond: NodeId,
+        ibody: NodeId,
+        ebody: NodeId,
     },
     While {
-        cond: Box<Node>,
-        body: Box<Node>,
+        cond: NodeId,
+        body: NodeId,
     },
     Return {
-        rhs: Box<Node>,
+        rhs: NodeId,
         ty: Type,
     },
 }
 
-fn new_node_bop(kind: BinaryOpKind, lhs: Box<Node>, rhs: Box<Node>) -> Box<Node> {
-    let node = Node::BinaryOperator {
-        kind,
-        lhs,
-        rhs,
-    };
-    Box::new(node)
+// Handle of a node stored in `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+// All nodes of a program live in one arena and refer to their children by
+// `NodeId`, so building the tree does not allocate per node.
+#[derive(Debug, Default)]
+pub struct Ast {
+    nodes: Vec<Node>,
+    items: Vec<NodeId>,
+    // Doc comments of top level items, which are collected only when
+    // the tokens have them.
+    docs: Vec<(NodeId, String)>,
+    // Source ranges of statements and function signatures, to map the
+    // code back to the source.
+    spans: HashMap<NodeId, Span>,
+    // Items declared with `pub`, which are visible from other objects.
+    public: HashSet<NodeId>,
 }
 
-fn new_node_uop(kind: UnaryOpKind, rhs: Box<Node>) -> Box<Node> {
-    let node = Node::UnaryOperator {
-        kind,
-        rhs,
-    };
-    Box::new(node)
-}
+impl Ast {
+    fn push(&mut self, node: Node) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(node);
+        id
+    }
 
-fn new_node_num(val: u32) -> Box<Node> {
-    let node = Node::Number {
-        val,
-    };
-    Box::new(node)
-}
+    // Top level functions and globals in source order.
+    pub fn items(&self) -> &[NodeId] {
+        &self.items
+    }
 
-fn new_node_bl(bl: bool) -> Box<Node> {
-    let node = Node::Bool {
-        bl,
-    };
-    Box::new(node)
-}
+    pub fn doc(&self, item: NodeId) -> Option<&str> {
+        self.docs.iter()
+            .find(|(id, _)| *id == item)
+            .map(|(_, doc)| doc.as_str())
+    }
 
-fn new_node_str(s: &str, label: usize) -> Box<Node> {
-    let node = Node::StrLiteral {
-        s: s.to_string(),
-        label,
-    };
-    Box::new(node)
-}
+    pub fn span(&self, stmt: NodeId) -> Option<Span> {
+        self.spans.get(&stmt).copied()
+    }
 
-fn new_node_lvar(offset: usize, ty: Type) -> Box<Node> {
-    let node = Node::LocalVariable {
-        offset,
-        ty,
-    };
-    Box::new(node)
-}
+    pub fn is_public(&self, item: NodeId) -> bool {
+        self.public.contains(&item)
+    }
 
-fn new_node_decl(offset: usize, ty: Type) -> Box<Node> {
-    let node = Node::DeclareLocal {
-        offset,
-        ty,
-    };
-    Box::new(node)
-}
+    // Nodes directly under the node.
+    pub fn children(&self, node: NodeId) -> Vec<NodeId> {
+        match &self[node] {
+            Node::BinaryOperator { kind: _, lhs, rhs } => vec![*lhs, *rhs],
+            Node::UnaryOperator { kind: _, rhs } => vec![*rhs],
+            Node::Block { nodes } => nodes.clone(),
+            Node::Function { name: _, args: _, stack: _, block } => vec![*block],
+            Node::Call { name: _, args, ty: _ } => args.clone(),
+            Node::If { cond, ibody } => vec![*cond, *ibody],
+            Node::IfElse { cond, ibody, ebody } => vec![*cond, *ibody, *ebody],
+            Node::While { cond, body } => vec![*cond, *body],
+            Node::Return { rhs, ty: _ } => vec![*rhs],
+            _ => Vec::new(),
+        }
+    }
 
-fn new_node_gvar(name: &str, offset: usize, ty: Type) -> Box<Node> {
-    let node = Node::GlobalVariable {
-        name: name.to_string(),
-        offset,
-        ty,
-    };
-    Box::new(node)
-}
+    pub(crate) fn retain_items(&mut self, f: impl FnMut(&NodeId) -> bool) {
+        self.items.retain(f);
+    }
 
-fn new_node_decg(name: &str, size: usize, ty: Type) -> Box<Node> {
-    let node = Node::DeclareGlobal {
-        name: name.to_string(),
-        size,
-        ty,
-    };
-    Box::new(node)
-}
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
 
-fn new_node_blk(nodes: Vec<Box<Node>>) -> Box<Node> {
-    let node = Node::Block {
-        nodes,
-    };
-    Box::new(node)
-}
+    // Value of an expression made only of literals, as the 64 bits which
+    // the generated code would compute. Comparisons are signed, and a
+    // division which traps at runtime has no value.
+    pub fn const_value(&self, node: NodeId) -> Option<u64> {
+        match &self[node] {
+            Node::Number { val } => Some(*val),
+            Node::Bool { bl } => Some(*bl as u64),
+            Node::BinaryOperator { kind, lhs, rhs } => {
+                let l = self.const_value(*lhs)? as i64;
+                let r = self.const_value(*rhs)? as i64;
+                let val = match kind {
+                    BinaryOpAdd => l.wrapping_add(r),
+                    BinaryOpSub => l.wrapping_sub(r),
+                    BinaryOpMul => l.wrapping_mul(r),
+                    BinaryOpDiv => l.checked_div(r)?,
+                    BinaryOpRem => l.checked_rem(r)?,
+                    BinaryOpEq => (l == r) as i64,
+                    BinaryOpNe => (l != r) as i64,
+                    BinaryOpLt => (l < r) as i64,
+                    BinaryOpLe => (l <= r) as i64,
+                    BinaryOpGr => (l > r) as i64,
+                    BinaryOpGe => (l >= r) as i64,
+                    BinaryOpAsn => return None,
+                };
+                Some(val as u64)
+            },
+            _ => None,
+        }
+    }
 
-fn new_node_func(name: &str, args: Vec<Box<Node>>, stack: usize, block: Box<Node>) -> Box<Node> {
-    let node = Node::Function {
-        name: name.to_string(),
-        args,
-        stack,
-        block,
-    };
-    Box::new(node)
-}
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
 
-fn new_node_decf(name: &str, args: Vec<Box<Node>>) -> Box<Node> {
-    let node = Node::DeclareFunc {
-        name: name.to_string(),
-        args,
-    };
-    Box::new(node)
-}
+    // Type of the value of an expression, if it is known. Integer literals
+    // take the type of the other operand, so they have no type here.
+    pub fn value_type(&self, node: NodeId) -> Option<Type> {
+        match &self[node] {
+            Node::Bool { bl: _ } => Some(Type::Bool),
+            Node::CStrLiteral { s: _, label: _ } => Some(Type::Ptr(Box::new(Type::Int8))),
+            Node::LocalVariable { offset: _, ty } |
+            Node::GlobalVariable { name: _, offset: _, ty } |
+            Node::Call { name: _, args: _, ty } => Some(ty.clone()),
+            Node::UnaryOperator { kind: UnaryOpRf, rhs } => {
+                Some(Type::Ptr(Box::new(self.value_type(*rhs)?)))
+            },
+            Node::UnaryOperator { kind: UnaryOpDrf, rhs } => match self.value_type(*rhs)? {
+                Type::Ptr(ty) => Some(*ty),
+                _ => None,
+            },
+            Node::BinaryOperator { kind: BinaryOpAdd | BinaryOpSub | BinaryOpMul | BinaryOpDiv | BinaryOpRem, lhs, rhs } => {
+                self.operation_type(*lhs, *rhs)
+            },
+            Node::BinaryOperator { kind: BinaryOpAsn, lhs: _, rhs: _ } => None,
+            Node::BinaryOperator { kind: _, lhs: _, rhs: _ } => Some(Type::Bool),
+            _ => None,
+        }
+    }
 
-fn new_node_call(name: &str, args: Vec<Box<Node>>, ty: Type) -> Box<Node> {
-    let node = Node::Call {
-        name: name.to_string(),
-        args,
-        ty,
-    };
-    Box::new(node)
-}
+    // Type which an operation on two operands works in: the wider of two
+    // integers, or else the first operand with a type.
+    pub fn operation_type(&self, lhs: NodeId, rhs: NodeId) -> Option<Type> {
+        match (self.value_type(lhs), self.value_type(rhs)) {
+            (Some(l), Some(r)) if is_integer(&l) && is_integer(&r) && type_size(&r) > type_size(&l) => Some(r),
+            (l, r) => l.or(r),
+        }
+    }
 
-fn new_node_if(cond: Box<Node>, ibody: Box<Node>) -> Box<Node> {
-    let node = Node::If {
-        cond,
-        ibody,
-    };
-    Box::new(node)
-}
+    // Operand which is an integer narrower than the other, with its size and
+    // signedness. It is extended to 64 bits before the operation, since the
+    // upper bytes of a result of arithmetic on smaller integers are not
+    // cleared.
+    pub fn narrower_operand(&self, lhs: NodeId, rhs: NodeId) -> Option<(NodeId, usize, bool)> {
+        match (self.value_type(lhs), self.value_type(rhs)) {
+            (Some(l), Some(r)) if is_integer(&l) && is_integer(&r) => {
+                match type_size(&l).cmp(&type_size(&r)) {
+                    Ordering::Less => Some((lhs, type_size(&l), is_unsigned(&l))),
+                    Ordering::Greater => Some((rhs, type_size(&r), is_unsigned(&r))),
+                    Ordering::Equal => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    // Size and signedness of an integer value narrower than `ty`, which it
+    // is extended from when it is stored or returned as `ty`.
+    pub fn widens(&self, node: NodeId, ty: &Type) -> Option<(usize, bool)> {
+        match self.value_type(node) {
+            Some(from) if is_integer(&from) && is_integer(ty) && type_size(&from) < type_size(ty) => {
+                Some((type_size(&from), is_unsigned(&from)))
+            },
+            _ => None,
+        }
+    }
+
+    // Size and signedness of the integers which an arithmetic operation
+    // works on, by the wider operand or the first one with a type. Literals
+    // alone are 64 bits signed integers.
+    pub fn int_type(&self, lhs: NodeId, rhs: NodeId) -> (usize, bool) {
+        match self.operation_type(lhs, rhs) {
+            Some(ty @ (Type::Int8 | Type::Int16 | Type::Int32 | Type::Uint8 | Type::Uint16 | Type::Uint32 | Type::Uint64)) => {
+                (type_size(&ty), is_unsigned(&ty))
+            },
+            _ => (8, false),
+        }
+    }
+
+    // Operands are compared as unsigned integers if either of them is an
+    // unsigned integer or a pointer.
+    pub fn is_unsigned_cmp(&self, lhs: NodeId, rhs: NodeId) -> bool {
+        let unsigned = |node| match self.value_type(node) {
+            Some(Type::Ptr(_)) => true,
+            Some(ty) => is_unsigned(&ty),
+            None => false,
+        };
+        unsigned(lhs) || unsigned(rhs)
+    }
 
-fn new_node_ifel(cond: Box<Node>, ibody: Box<Node>, ebody: Box<Node>) -> Box<Node> {
-    let node = Node::IfElse {
-        cond,
-        ibody,
-        ebody,
-    };
-    Box::new(node)
+    // A value stored into a `bool` is normalized to 0 or 1, unless it is a
+    // `bool` already.
+    pub fn normalizes_bool(&self, lhs: NodeId, rhs: NodeId) -> bool {
+        self.value_type(lhs).is_some_and(|ty| self.normalizes_to_bool(&ty, rhs))
+    }
+
+    // The same for a value passed or returned as `ty`.
+    pub fn normalizes_to_bool(&self, ty: &Type, node: NodeId) -> bool {
+        *ty == Type::Bool && self.value_type(node) != Some(Type::Bool)
+    }
+
+    fn new_node_bop(&mut self, kind: BinaryOpKind, lhs: NodeId, rhs: NodeId) -> NodeId {
+        let node = Node::BinaryOperator {
+            kind,
+            lhs,
+            rhs,
+        };
+        self.push(node)
+    }
+
+    fn new_node_uop(&mut self, kind: UnaryOpKind, rhs: NodeId) -> NodeId {
+        let node = Node::UnaryOperator {
+            kind,
+            rhs,
+        };
+        self.push(node)
+    }
+
+    fn new_node_num(&mut self, val: u64) -> NodeId {
+        let node = Node::Number {
+            val,
+        };
+        self.push(node)
+    }
+
+    fn new_node_bl(&mut self, bl: bool) -> NodeId {
+        let node = Node::Bool {
+            bl,
+        };
+        self.push(node)
+    }
+
+    fn new_node_str(&mut self, s: Symbol, label: usize) -> NodeId {
+        let node = Node::StrLiteral {
+            s,
+            label,
+        };
+        self.push(node)
+    }
+
+    fn new_node_cstr(&mut self, s: Symbol, label: usize) -> NodeId {
+        let node = Node::CStrLiteral {
+            s,
+            label,
+        };
+        self.push(node)
+    }
+
+    fn new_node_lvar(&mut self, offset: usize, ty: Type) -> NodeId {
+        let node = Node::LocalVariable {
+            offset,
+            ty,
+        };
+        self.push(node)
+    }
+
+    fn new_node_decl(&mut self, offset: usize, ty: Type) -> NodeId {
+        let node = Node::DeclareLocal {
+            offset,
+            ty,
+        };
+        self.push(node)
+    }
+
+    fn new_node_gvar(&mut self, name: Symbol, offset: usize, ty: Type) -> NodeId {
+        let node = Node::GlobalVariable {
+            name,
+            offset,
+            ty,
+        };
+        self.push(node)
+    }
+
+    fn new_node_decg(&mut self, name: Symbol, size: usize, ty: Type, init: Option<i64>, mutable: bool) -> NodeId {
+        let node = Node::DeclareGlobal {
+            name,
+            size,
+            ty,
+            init,
+            mutable,
+        };
+        self.push(node)
+    }
+
+    fn new_node_blk(&mut self, nodes: Vec<NodeId>) -> NodeId {
+        let node = Node::Block {
+            nodes,
+        };
+        self.push(node)
+    }
+
+    fn new_node_func(&mut self, name: Symbol, args: Vec<NodeId>, stack: usize, block: NodeId) -> NodeId {
+        let node = Node::Function {
+            name,
+            args,
+            stack,
+            block,
+        };
+        self.push(node)
+    }
+
+    fn new_node_decf(&mut self, name: Symbol, args: Vec<NodeId>) -> NodeId {
+        let node = Node::DeclareFunc {
+            name,
+            args,
+        };
+        self.push(node)
+    }
+
+    fn new_node_call(&mut self, name: Symbol, args: Vec<NodeId>, ty: Type) -> NodeId {
+        let node = Node::Call {
+            name,
+            args,
+            ty,
+        };
+        self.push(node)
+    }
+
+    fn new_node_if(&mut self, cond: NodeId, ibody: NodeId) -> NodeId {
+        let node = Node::If {
+            cond,
+            ibody,
+        };
+        self.push(node)
+    }
+
+    fn new_node_ifel(&mut self, cond: NodeId, ibody: NodeId, ebody: NodeId) -> NodeId {
+        let node = Node::IfElse {
+            cond,
+            ibody,
+            ebody,
+        };
+        self.push(node)
+    }
+
+    fn new_node_whl(&mut self, cond: NodeId, body: NodeId) -> NodeId {
+        let node = Node::While {
+            cond,
+            body,
+        };
+        self.push(node)
+    }
+
+    fn new_node_ret(&mut self, rhs: NodeId, ty: Type) -> NodeId {
+        let node = Node::Return {
+            rhs,
+            ty,
+        };
+        self.push(node)
+    }
 }
 
-fn new_node_whl(cond: Box<Node>, body: Box<Node>) -> Box<Node> {
-    let node = Node::While {
-        cond,
-        body,
-    };
-    Box::new(node)
+impl Index<NodeId> for Ast {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0 as usize]
+    }
 }
 
-fn new_node_ret(rhs: Box<Node>, ty: Type) -> Box<Node> {
-    let node = Node::Return {
-        rhs,
-        ty,
-    };
-    Box::new(node)
+impl IndexMut<NodeId> for Ast {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0 as usize]
+    }
 }
 
-fn align_double_word(n: usize) -> usize {
-    let dw = WORDSIZE * 2;
-    if n % dw != 0 {
-        n + (dw - n % dw)
+fn align_to(n: usize, align: usize) -> usize {
+    if !n.is_multiple_of(align) {
+        n + (align - n % align)
     } else {
         n
     }
 }
 
+fn align_double_word(n: usize) -> usize {
+    align_to(n, WORDSIZE * 2)
+}
+
+// Unsigned integers are zero extended when they are loaded. So is `bool`,
+// which is always 0 or 1.
+fn is_integer(ty: &Type) -> bool {
+    matches!(ty, Type::Int8 | Type::Int16 | Type::Int32 | Type::Int64 |
+                 Type::Uint8 | Type::Uint16 | Type::Uint32 | Type::Uint64 | Type::Bool)
+}
+
+pub fn is_unsigned(ty: &Type) -> bool {
+    matches!(ty, Type::Uint8 | Type::Uint16 | Type::Uint32 | Type::Uint64 | Type::Bool)
+}
+
 pub fn type_size(ty: &Type) -> usize {
     match ty {
         Type::Int8 => 1,
@@ -325,10 +673,18 @@ pub fn type_size(ty: &Type) -> usize {
         Type::Uint32 => 4,
         Type::Uint64 => 8,
         Type::Bool => 1,
+        Type::Unit => 0,
         Type::Str => unreachable!(), // Str is not first-class type.
         Type::Ptr(_ty) => WORDSIZE,
         Type::Slc(_ty) => WORDSIZE * 2,
-        Type::Ary(ty, len) => type_size(&*ty) * len,
+        Type::Ary(ty, len) => type_size(ty) * len,
+    }
+}
+
+pub fn type_align(ty: &Type) -> usize {
+    match ty {
+        Type::Ary(ty, _) => type_align(ty),
+        _ => type_size(ty).min(WORDSIZE),
     }
 }
 
@@ -343,40 +699,99 @@ pub enum Type {
     Uint32,
     Uint64,
     Bool,
+    // Return type of a function without `->`, which has no value.
+    Unit,
     Str,
     Ptr(Box<Type>),
     Slc(Box<Type>),
     Ary(Box<Type>, usize),
 }
 
+// Types are shown as written in the source code.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int8 => write!(f, "i8"),
+            Type::Int16 => write!(f, "i16"),
+            Type::Int32 => write!(f, "i32"),
+            Type::Int64 => write!(f, "i64"),
+            Type::Uint8 => write!(f, "u8"),
+            Type::Uint16 => write!(f, "u16"),
+            Type::Uint32 => write!(f, "u32"),
+            Type::Uint64 => write!(f, "u64"),
+            Type::Bool => write!(f, "bool"),
+            Type::Unit => write!(f, "()"),
+            Type::Str => write!(f, "str"),
+            Type::Ptr(ty) => write!(f, "&{}", ty),
+            Type::Slc(ty) => write!(f, "&{}", ty),
+            Type::Ary(ty, len) => write!(f, "[{}; {}]", ty, len),
+        }
+    }
+}
+
 struct Lvar {
-    name: String,
+    name: Symbol,
     ty: Type,
     offset: usize,
+    span: Span,
+    used: bool,
 }
 
 struct Gvar {
-    name: String,
+    name: Symbol,
     ty: Type,
+    mutable: bool,
 }
 
 struct Func {
-    name: String,
+    name: Symbol,
     ty: Type,
+    params: Vec<Type>,
+}
+
+struct Scope {
+    lvar_len: usize,
+    offset: usize,
 }
 
 struct VarInfo {
-    name: String,
+    name: Symbol,
     ty: Type,
+    span: Span,
 }
 
-pub struct Parser {
+pub struct Parser<'a> {
+    interner: &'a Interner,
+    ast: Ast,
     lvar_list: Vec<Lvar>,
     gvar_list: Vec<Gvar>,
-    literal_list: Vec<String>,
+    literal_list: Vec<Symbol>,
     func_list: Vec<Func>,
+    // Indices into the lists above by name. A local name maps to a stack
+    // of indices so that the latest declaration shadows the older ones.
+    lvar_map: SymbolMap<Vec<usize>>,
+    gvar_map: SymbolMap<usize>,
+    func_map: SymbolMap<usize>,
+    // Offset from rbp of the last declared local, and the largest one
+    // in the current function which decides the frame size.
+    cur_offset: usize,
+    max_offset: usize,
     block_level: usize,
+    // Nesting of the code being parsed, limited to MAX_DEPTH.
+    depth: usize,
     cur_type: Type,
+    // Calls to the functions without a return type, which can be only
+    // statements.
+    unit_calls: HashMap<NodeId, Span>,
+    // Arrays referred to without an index, which can be only referenced.
+    array_vars: HashMap<NodeId, Span>,
+    // Parsing the initializer of a static, which is computed at compile
+    // time and can refer to nothing.
+    in_static: bool,
+    errors: Vec<ParseError>,
+    recovering: bool,
+    lints: LintLevels,
+    warnings: Vec<LintWarning>,
 }
 
 // Production rules
@@ -393,33 +808,100 @@ pub struct Parser {
 // <sym>  ::= <idt> ("(" <cl_args> ")")?
 // <prim> ::= <num> | <slit> | <sym> | "(" <expr> ")"
 // <una>  ::= "-"? <prim> | "&" <una> | "*" <una>
-// <mul>  ::= <una> ("*" <una> | "/" <una>)*
+// <mul>  ::= <una> ("*" <una> | "/" <una> | "%" <una>)*
 // <add>  ::= <mul> ("+" <mul> | "-" <mul>)*
 // <rel>  ::= <add> ("<" <add> | "<=" <add> | ">" <add> | ">=" <add>)*
 // <eql>  ::= <rel> ("==" <rel> | "!=" <rel>)*
-// <asn>  ::= <eql> ("=" <asn>)?
+// <asn>  ::= <eql> ("=" <expr>)?
 //
-// <expr> ::= <asn>
+// <expr> ::= <eql>
 // <whl>  ::= "while" <expr> <blk>
 // <ifel> ::= "if" <expr> <blk> ("else" <blk>)?
 // <ret>  ::= "return" <expr>
-// <locl> ::= "let" <bind>
+// <locl> ::= "let" <bind> ("=" <expr>)?
 //
-// <stmt> ::= <expr> ";" | <locl> ";" | <ret> ";" | <ifel> | <whl>
+// <stmt> ::= <asn> ";" | <locl> ";" | <ret> ";" | <ifel> | <whl>
 // <blk>  ::= "{" <stmt>* "}"
 // <func> ::= "fn" <idt> "(" <fn_args> ")" "->" <typ> (<blk> | ";")
 // <bind> ::= <idt> ":" <typ>
-// <glbl> ::= "static" <bind>
-// <top>  ::= <func> | <glbl> ";"
+// <glbl> ::= "static" "mut"? <bind> ("=" <expr>)?
+// <top>  ::= "pub"? (<func> | <glbl> ";")
 // <pgrm> ::= <top>*
-impl Parser {
-    pub fn literals(&self) -> &Vec<String> {
+impl<'a> Parser<'a> {
+    pub fn literals(&self) -> &[Symbol] {
         &self.literal_list
     }
 
-    fn stack_size(&mut self) -> usize {
-        self.lvar_list.iter()
-            .fold(0, |total, lvar| total + type_size(&lvar.ty))
+    pub fn warnings(&self) -> &[LintWarning] {
+        &self.warnings
+    }
+
+    pub fn set_lint_levels(&mut self, lints: LintLevels) {
+        self.lints = lints;
+    }
+
+    fn warn(&mut self, lint: Lint, message: String, span: Span) {
+        let level = self.lints.get(lint);
+        if level != Level::Allow {
+            self.warnings.push(LintWarning::new(lint, level, message, span));
+        }
+    }
+
+    fn check_unused(&mut self, start: usize) {
+        let interner = self.interner;
+        let unused: Vec<(&str, Span)> = self.lvar_list[start..].iter()
+            .map(|lv| (interner.resolve(lv.name), lv))
+            .filter(|(name, lv)| !lv.used && !name.starts_with('_'))
+            .map(|(name, lv)| (name, lv.span))
+            .collect();
+        for (name, span) in unused {
+            let message = format!("Variable `{}` is never used!", name);
+            self.warn(Lint::UnusedVariables, message, span);
+        }
+    }
+
+    fn report(&mut self, e: ParseError) {
+        if let Some(last) = self.errors.last() {
+            if last.error == e.error && last.span == e.span {
+                return;
+            }
+        }
+
+        // A missing semicolon right after a recovery point is usually
+        // caused by the skipped tokens, not by the source itself.
+        if self.recovering && e.error == ScolonExpected {
+            return;
+        }
+
+        self.errors.push(e);
+        self.recovering = true;
+    }
+
+    fn enter_scope(&self) -> Scope {
+        Scope {
+            lvar_len: self.lvar_list.len(),
+            offset: self.cur_offset,
+        }
+    }
+
+    // Locals declared in a block are dropped at its end, and the following
+    // blocks reuse their slots. A pointer to such a local must not be used
+    // after the block, which is not checked for now.
+    fn leave_scope(&mut self, scope: Scope) {
+        self.check_unused(scope.lvar_len);
+        for lv in self.lvar_list.drain(scope.lvar_len..) {
+            if let Some(indices) = self.lvar_map.get_mut(&lv.name) {
+                indices.pop();
+            }
+        }
+        self.cur_offset = scope.offset;
+    }
+
+    fn alloc_lvar(&mut self, ty: &Type) -> usize {
+        let offset = align_to(self.cur_offset + type_size(ty), type_align(ty));
+        self.cur_offset = offset;
+        self.max_offset = self.max_offset.max(offset);
+        offset
     }
 
     fn consume_semicolon(&self, tokens: &mut Tokens) -> Result<(), ParseError> {
@@ -438,85 +920,150 @@ impl Parser {
         }
     }
 
-    fn func_type(&mut self, name: &str, tokens: &mut Tokens) -> Result<Type, ParseError> {
-        let func_iter = self.func_list.iter();
-        for f in func_iter {
-            if f.name != name {
-                continue;
-            }
-            return Ok(f.ty.clone());
+    fn is_discard(&self, name: Symbol) -> bool {
+        self.interner.resolve(name) == "_"
+    }
+
+    fn push_lvar(&mut self, lv: Lvar) {
+        self.lvar_map.entry(lv.name).or_default().push(self.lvar_list.len());
+        self.lvar_list.push(lv);
+    }
+
+    fn push_gvar(&mut self, gv: Gvar) {
+        self.gvar_map.entry(gv.name).or_insert(self.gvar_list.len());
+        self.gvar_list.push(gv);
+    }
+
+    fn push_func(&mut self, func: Func) {
+        self.func_map.entry(func.name).or_insert(self.func_list.len());
+        self.func_list.push(func);
+    }
+
+    fn clear_lvars(&mut self) {
+        self.lvar_list.clear();
+        self.lvar_map.clear();
+        self.cur_offset = 0;
+        self.max_offset = 0;
+    }
+
+    // Error for a missing identifier, which is more specific if a keyword
+    // is written instead.
+    fn idt_error(&self, e: ParseErrorKind, tokens: &Tokens) -> ParseError {
+        match tokens.peek_rsv() {
+            Some(word) => ParseError::new(KeywordAsIdentifier(word), tokens),
+            None => ParseError::new(e, tokens),
         }
-        // TODO: Report head of function name as error position.
-        //       (Function tail is reported now.)
-        Err(ParseError::new_with_offset(UnknownVariable, tokens, 1))
     }
 
-    fn var(&mut self, name: &str, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
-        let lvar_iter = self.lvar_list.iter();
-        for lv in lvar_iter {
-            if lv.name != name {
-                continue;
-            }
+    fn func_type(&mut self, name: Symbol, span: Span) -> Result<Type, ParseError> {
+        match self.func_map.get(&name) {
+            Some(&i) => Ok(self.func_list[i].ty.clone()),
+            None => Err(ParseError::new_with_span(UnknownVariable, span)),
+        }
+    }
+
+    fn var(&mut self, name: Symbol, span: Span, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let lvar_index = self.lvar_map.get(&name)
+            .and_then(|indices| indices.last());
+        if let Some(&i) = lvar_index {
+            let lv = &mut self.lvar_list[i];
+            lv.used = true;
 
             if tokens.expect_op("[") {
                 let num = tokens.expect_num()
                     .ok_or_else(|| ParseError::new(NumberExpected, tokens))?;
+                let index = tokens.prev_span();
                 if !tokens.expect_op("]") {
                     return Err(ParseError::new(ParenExpected, tokens));
                 }
 
-                if let Type::Ary(ty, _) = &lv.ty {
-                    let offset = lv.offset - type_size(&**ty) * num as usize;
-                    return Ok(new_node_lvar(offset, *ty.clone()));
+                if let Type::Ary(ty, len) = &lv.ty {
+                    if num >= *len as u64 {
+                        return Err(ParseError::new_with_span(IndexOutOfBounds, index));
+                    }
+                    let offset = lv.offset - type_size(ty) * num as usize;
+                    return Ok(self.ast.new_node_lvar(offset, *ty.clone()));
                 } else {
-                    return Err(ParseError::new_with_offset(TypeInvalid, tokens, 4));
+                    let span = Span::new(span.start, tokens.prev_span().end);
+                    return Err(ParseError::new_with_span(TypeInvalid, span));
                 }
             } else {
-                return Ok(new_node_lvar(lv.offset, lv.ty.clone()));
+                let node = self.ast.new_node_lvar(lv.offset, lv.ty.clone());
+                if let Type::Ary(_, _) = lv.ty {
+                    self.array_vars.insert(node, span);
+                }
+                return Ok(node);
             }
         }
 
-        let gvar_iter = self.gvar_list.iter();
-        for gv in gvar_iter {
-            if gv.name != name {
-                continue;
-            }
+        if let Some(&i) = self.gvar_map.get(&name) {
+            let gv = &self.gvar_list[i];
 
             if tokens.expect_op("[") {
                 let num = tokens.expect_num()
                     .ok_or_else(|| ParseError::new(NumberExpected, tokens))?;
+                let index = tokens.prev_span();
                 if !tokens.expect_op("]") {
                     return Err(ParseError::new(ParenExpected, tokens));
                 }
 
-                if let Type::Ary(ty, _) = &gv.ty {
-                    let offset = type_size(&**ty) * num as usize;
-                    return Ok(new_node_gvar(name, offset, *ty.clone()));
+                if let Type::Ary(ty, len) = &gv.ty {
+                    if num >= *len as u64 {
+                        return Err(ParseError::new_with_span(IndexOutOfBounds, index));
+                    }
+                    let offset = type_size(ty) * num as usize;
+                    return Ok(self.ast.new_node_gvar(name, offset, *ty.clone()));
                 } else {
-                    return Err(ParseError::new_with_offset(TypeInvalid, tokens, 4));
+                    let span = Span::new(span.start, tokens.prev_span().end);
+                    return Err(ParseError::new_with_span(TypeInvalid, span));
                 }
             } else {
-                return Ok(new_node_gvar(name, 0, gv.ty.clone()));
+                let node = self.ast.new_node_gvar(name, 0, gv.ty.clone());
+                if let Type::Ary(_, _) = gv.ty {
+                    self.array_vars.insert(node, span);
+                }
+                return Ok(node);
             }
         }
 
-        Err(ParseError::new_with_offset(UnknownVariable, tokens, 1))
+        Err(ParseError::new_with_span(UnknownVariable, span))
     }
 
     fn bind(&mut self, tokens: &mut Tokens) -> Result<VarInfo, ParseError> {
+        let span = tokens.span();
         let name = tokens.expect_idt()
-            .map(|s| s.to_string()) // Get ownership
-            .ok_or_else(|| ParseError::new(VariableExpected, tokens))?;
+            .ok_or_else(|| self.idt_error(VariableExpected, tokens))?;
 
         self.consume_colon(tokens)?;
-        let ty = self.typ(tokens)?;
+        let ty = self.sized_typ(tokens)?;
+
+        Ok(VarInfo { name, ty, span })
+    }
 
-        Ok(VarInfo { name, ty })
+    // Type of a value, which `str` cannot be without a reference.
+    // Goes a level deeper after the token which opens it, and returns the
+    // depth to go back to.
+    fn nest(&mut self, tokens: &Tokens) -> Result<usize, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(ParseError::new_with_span(TooDeep, tokens.prev_span()));
+        }
+        self.depth += 1;
+        Ok(self.depth - 1)
+    }
+
+    fn sized_typ(&mut self, tokens: &mut Tokens) -> Result<Type, ParseError> {
+        let ty = self.typ(tokens)?;
+        if ty == Type::Str {
+            return Err(ParseError::new_with_span(NotSized, tokens.prev_span()));
+        }
+        Ok(ty)
     }
 
-    fn typ(&self, tokens: &mut Tokens) -> Result<Type, ParseError> {
+    fn typ(&mut self, tokens: &mut Tokens) -> Result<Type, ParseError> {
         if tokens.expect_op("&") {
+            let depth = self.nest(tokens)?;
             let ty = self.typ(tokens)?;
+            self.depth = depth;
             match ty {
                 Type::Int8 | Type::Int16 |
                 Type::Int32 | Type::Int64 |
@@ -529,18 +1076,26 @@ impl Parser {
                 Type::Str | Type::Ary(_, _) => {
                     Ok(Type::Slc(Box::new(ty)))
                 },
+                Type::Unit => unreachable!(), // Unit is not written in the source code.
             }
         } else if tokens.expect_op("[") {
-            let ty = self.typ(tokens)?;
+            let depth = self.nest(tokens)?;
+            let ty = self.sized_typ(tokens)?;
+            self.depth = depth;
             self.consume_semicolon(tokens)?;
             let num = tokens.expect_num()
                 .ok_or_else(|| ParseError::new(NumberExpected, tokens))?;
+            let len = tokens.prev_span();
 
             if !tokens.expect_op("]") {
                 return Err(ParseError::new(ParenExpected, tokens));
             }
 
-            Ok(Type::Ary(Box::new(ty), num as usize))
+            // The whole array must be addressable with a 32-bit offset.
+            match type_size(&ty).checked_mul(num as usize) {
+                Some(size) if size <= MAX_SIZE => Ok(Type::Ary(Box::new(ty), num as usize)),
+                _ => Err(ParseError::new_with_span(NotSized, len)),
+            }
         } else if tokens.expect_rsv("i8") {
             Ok(Type::Int8)
         } else if tokens.expect_rsv("i16") {
@@ -566,215 +1121,415 @@ impl Parser {
         }
     }
 
-    fn primary(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn call(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let name = tokens.expect_idt()
+            .ok_or_else(|| ParseError::new(FuncExpected, tokens))?;
+        let span = tokens.prev_span();
+        let ty = self.func_type(name, span)?;
+        let params = self.func_list[self.func_map[&name]].params.clone();
+
+        let open = tokens.checkpoint();
+        tokens.expect_op("(");
+        let mut args: Vec<NodeId> = Vec::new();
+        while !tokens.expect_op(")") {
+            if !tokens.has_next() {
+                // Point at the unclosed parenthesis, not at the end of file.
+                tokens.rewind(open);
+                return Err(ParseError::new(ParenExpected, tokens));
+            }
+            let depth = self.nest(tokens)?;
+            let mut arg = self.expr(tokens)?;
+            self.depth = depth;
+            // A value passed as a `bool` is normalized as when it is stored
+            // into one.
+            if params.get(args.len()).is_some_and(|param| self.ast.normalizes_to_bool(param, arg)) {
+                let zero = self.ast.new_node_num(0);
+                arg = self.ast.new_node_bop(BinaryOpNe, arg, zero);
+            }
+            args.push(arg);
+            if tokens.expect_op(",") {
+                continue;
+            }
+        }
+
+        let unit = ty == Type::Unit;
+        let node = self.ast.new_node_call(name, args, ty);
+        if unit {
+            self.unit_calls.insert(node, Span::new(span.start, tokens.prev_span().end));
+        }
+        Ok(node)
+    }
+
+    // Span of a call without a value in an expression.
+    fn unit_value(&self, node: NodeId) -> Option<Span> {
+        match &self.ast[node] {
+            Node::Call { name: _, args, ty: _ } => {
+                self.unit_calls.get(&node).copied()
+                    .or_else(|| args.iter().find_map(|&arg| self.unit_value(arg)))
+            },
+            Node::BinaryOperator { kind: _, lhs, rhs } => {
+                self.unit_value(*lhs).or_else(|| self.unit_value(*rhs))
+            },
+            Node::UnaryOperator { kind: _, rhs } => self.unit_value(*rhs),
+            _ => None,
+        }
+    }
+
+    fn primary(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
         if let Some(num) = tokens.expect_num() {
-            Ok(new_node_num(num))
+            Ok(self.ast.new_node_num(num))
         } else if let Some(bl) = tokens.expect_bl() {
-            Ok(new_node_bl(bl))
+            Ok(self.ast.new_node_bl(bl))
         } else if let Some(slit) = tokens.expect_str() {
-            self.literal_list.push(slit.to_string());
-            Ok(new_node_str(slit, self.literal_list.len() - 1))
+            self.literal_list.push(slit);
+            Ok(self.ast.new_node_str(slit, self.literal_list.len() - 1))
+        } else if let Some(slit) = tokens.expect_cstr() {
+            self.literal_list.push(slit);
+            Ok(self.ast.new_node_cstr(slit, self.literal_list.len() - 1))
+        } else if matches!(tokens.peek(), TokenKind::TokenIdt(_)) &&
+                  matches!(tokens.peek_nth(1), TokenKind::TokenOp(op) if op == "(") {
+            let start = tokens.span().start;
+            let node = self.call(tokens)?;
+            self.constant(node, Span::new(start, tokens.prev_span().end))
         } else if let Some(name) = tokens.expect_idt() {
-            let name = name.to_string(); // Get ownership
-            if tokens.expect_op("(") {
-                let mut args: Vec<Box<Node>> = Vec::new();
-                while !tokens.expect_op(")") {
-                    let arg = self.expr(tokens)?;
-                    args.push(arg);
-                    if tokens.expect_op(",") {
-                        continue;
-                    }
-                }
-                let ty = self.func_type(&name, tokens)?;
-                Ok(new_node_call(&name, args, ty))
-            } else {
-                self.var(&name, tokens)
-            }
+            let span = tokens.prev_span();
+            let node = self.var(name, span, tokens)?;
+            self.constant(node, Span::new(span.start, tokens.prev_span().end))
         } else if tokens.expect_op("(") {
+            let depth = self.nest(tokens)?;
             let node = self.expr(tokens)?;
+            self.depth = depth;
             if !tokens.expect_op(")") {
                 return Err(ParseError::new(ParenExpected, tokens))
             }
             Ok(node)
         } else {
-            Err(ParseError::new(ExprInvalid, tokens))
+            Err(self.idt_error(ExprInvalid, tokens))
         }
     }
 
-    fn unary(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
-        if tokens.expect_op("&") {
-            self.unary(tokens)
-                .map(|rhs| new_node_uop(UnaryOpRf, rhs))
+    // Variables and calls have no value at compile time.
+    fn constant(&self, node: NodeId, span: Span) -> Result<NodeId, ParseError> {
+        if self.in_static {
+            Err(ParseError::new_with_span(NotConstant, span))
+        } else {
+            Ok(node)
+        }
+    }
+
+    fn unary(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let start = tokens.span().start;
+        let kind = if tokens.expect_op("&") {
+            if self.in_static {
+                self.in_static = false;
+                let rhs = self.unary(tokens);
+                self.in_static = true;
+                rhs?;
+                let span = Span::new(start, tokens.prev_span().end);
+                return Err(ParseError::new_with_span(AddressInStatic, span));
+            }
+            UnaryOpRf
         } else if tokens.expect_op("*") {
-            self.unary(tokens)
-                .map(|rhs| new_node_uop(UnaryOpDrf, rhs))
+            UnaryOpDrf
         } else if tokens.expect_op("-") {
-            self.primary(tokens)
-                .map(|rhs| new_node_bop(BinaryOpSub, new_node_num(0), rhs))
+            let depth = self.nest(tokens)?;
+            let rhs = self.primary(tokens)?;
+            self.depth = depth;
+            let zero = self.ast.new_node_num(0);
+            return Ok(self.ast.new_node_bop(BinaryOpSub, zero, rhs));
         } else {
-            self.primary(tokens)
+            return self.primary(tokens);
+        };
+        let depth = self.nest(tokens)?;
+        let rhs = self.unary(tokens)?;
+        self.depth = depth;
+        Ok(self.ast.new_node_uop(kind, rhs))
+    }
+
+    // Span of an array used as a value in an expression.
+    fn array_value(&self, node: NodeId) -> Option<Span> {
+        match &self.ast[node] {
+            Node::Call { name: _, args, ty: _ } => {
+                args.iter().find_map(|&arg| self.array_value(arg))
+            },
+            Node::BinaryOperator { kind: _, lhs, rhs } => {
+                self.array_value(*lhs).or_else(|| self.array_value(*rhs))
+            },
+            Node::UnaryOperator { kind: UnaryOpRf, rhs: _ } => None,
+            Node::UnaryOperator { kind: _, rhs } => self.array_value(*rhs),
+            _ => self.array_vars.get(&node).copied(),
         }
     }
 
-    fn mul(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn mul(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let depth = self.depth;
         let mut node = self.unary(tokens)?;
         while tokens.has_next() {
-            if tokens.expect_op("*") {
-                let rhs = self.unary(tokens)?;
-                node = new_node_bop(BinaryOpMul, node, rhs);
+            let kind = if tokens.expect_op("*") {
+                BinaryOpMul
             } else if tokens.expect_op("/") {
-                let rhs = self.unary(tokens)?;
-                node = new_node_bop(BinaryOpDiv, node, rhs);
+                BinaryOpDiv
+            } else if tokens.expect_op("%") {
+                BinaryOpRem
             } else {
                 break;
+            };
+            self.nest(tokens)?;
+            let start = tokens.span().start;
+            let rhs = self.unary(tokens)?;
+            if kind != BinaryOpMul && self.ast.const_value(rhs) == Some(0) {
+                let span = Span::new(start, tokens.prev_span().end);
+                return Err(ParseError::new_with_span(DivisionByZero, span));
             }
+            node = self.ast.new_node_bop(kind, node, rhs);
         }
+        self.depth = depth;
         Ok(node)
     }
 
-    fn add(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn add(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let depth = self.depth;
         let mut node = self.mul(tokens)?;
         while tokens.has_next() {
-            if tokens.expect_op("+") {
-                let rhs = self.mul(tokens)?;
-                node = new_node_bop(BinaryOpAdd, node, rhs);
+            let kind = if tokens.expect_op("+") {
+                BinaryOpAdd
             } else if tokens.expect_op("-") {
-                let rhs = self.mul(tokens)?;
-                node = new_node_bop(BinaryOpSub, node, rhs);
+                BinaryOpSub
             } else {
                 break;
-            }
+            };
+            self.nest(tokens)?;
+            let rhs = self.mul(tokens)?;
+            node = self.ast.new_node_bop(kind, node, rhs);
         }
+        self.depth = depth;
         Ok(node)
     }
 
-    fn relational(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn relational(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let depth = self.depth;
         let mut node = self.add(tokens)?;
         while tokens.has_next() {
-            if tokens.expect_op("<") {
-                let rhs = self.add(tokens)?;
-                node = new_node_bop(BinaryOpGr, node, rhs);
+            let kind = if tokens.expect_op("<") {
+                BinaryOpLt
             } else if tokens.expect_op("<=") {
-                let rhs = self.add(tokens)?;
-                node = new_node_bop(BinaryOpGe, node, rhs);
+                BinaryOpLe
             } else if tokens.expect_op(">") {
-                let lhs = self.add(tokens)?;
-                node = new_node_bop(BinaryOpGr, lhs, node);
+                BinaryOpGr
             } else if tokens.expect_op(">=") {
-                let lhs = self.add(tokens)?;
-                node = new_node_bop(BinaryOpGe, lhs, node);
+                BinaryOpGe
             } else {
                 break;
-            }
+            };
+            self.nest(tokens)?;
+            let rhs = self.add(tokens)?;
+            node = self.ast.new_node_bop(kind, node, rhs);
         }
+        self.depth = depth;
         Ok(node)
     }
 
-    fn equality(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn equality(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let depth = self.depth;
         let mut node = self.relational(tokens)?;
         while tokens.has_next() {
-            if tokens.expect_op("==") {
-                let rhs = self.relational(tokens)?;
-                node = new_node_bop(BinaryOpEq, node, rhs);
+            let kind = if tokens.expect_op("==") {
+                BinaryOpEq
             } else if tokens.expect_op("!=") {
-                let rhs = self.relational(tokens)?;
-                node = new_node_bop(BinaryOpNe, node, rhs);
+                BinaryOpNe
             } else {
                 break;
-            }
+            };
+            self.nest(tokens)?;
+            let rhs = self.relational(tokens)?;
+            node = self.ast.new_node_bop(kind, node, rhs);
         }
+        self.depth = depth;
         Ok(node)
     }
 
-    fn assign(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn assign(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        // Assignment to `_` only evaluates the right-hand side.
+        if let TokenKind::TokenIdt(name) = tokens.peek() {
+            if self.is_discard(*name) && matches!(tokens.peek_nth(1), TokenKind::TokenOp(op) if op == "=") {
+                tokens.expect_idt();
+                tokens.expect_op("=");
+                let depth = self.nest(tokens)?;
+                let node = self.assign(tokens)?;
+                self.depth = depth;
+                return Ok(node);
+            }
+        }
+
+        let start = tokens.span().start;
         let node = self.equality(tokens)?;
+        let lhs = Span::new(start, tokens.prev_span().end);
 
         if tokens.expect_op("=") {
-            self.assign(tokens)
-                .map(|rhs| new_node_bop(BinaryOpAsn, node, rhs))
+            // Statics are read-only as in Rust unless declared `mut`.
+            if let Node::GlobalVariable { name, offset: _, ty: _ } = &self.ast[node] {
+                if !self.gvar_list[self.gvar_map[name]].mutable {
+                    return Err(ParseError::new_with_span(AssignToStatic, lhs));
+                }
+            }
+            let depth = self.nest(tokens)?;
+            let rhs = self.expr(tokens)?;
+            self.depth = depth;
+            Ok(self.ast.new_node_bop(BinaryOpAsn, node, rhs))
         } else {
             Ok(node)
         }
     }
 
-    fn expr(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
-        self.assign(tokens)
+    // Assignment has no value as in Rust, and it can be only a statement.
+    fn expr(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let start = tokens.span().start;
+        let node = self.assign(tokens)?;
+        if let Node::BinaryOperator { kind: BinaryOpAsn, lhs: _, rhs: _ } = &self.ast[node] {
+            let span = Span::new(start, tokens.prev_span().end);
+            return Err(ParseError::new_with_span(AssignAsValue, span));
+        }
+        if let Some(span) = self.unit_value(node) {
+            return Err(ParseError::new_with_span(UnitAsValue, span));
+        }
+        if let Some(span) = self.array_value(node) {
+            return Err(ParseError::new_with_span(TypeInvalid, span));
+        }
+        Ok(node)
     }
 
-    fn blk(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn blk(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
         self.block_level += 1;
+        let scope = self.enter_scope();
 
-        let mut nodes: Vec<Box<Node>> = Vec::new();
+        let mut nodes: Vec<NodeId> = Vec::new();
+        let mut returned = false;
+        let mut warned = false;
+        let depth = self.depth;
         while !tokens.expect_op("}") {
+            if !tokens.has_next() {
+                return Err(ParseError::new(ParenExpected, tokens));
+            }
+
+            // Doc comments in a block do not document anything.
+            if tokens.expect_doc().is_some() {
+                continue;
+            }
+
+            let start = tokens.span().start;
             match self.stmt(tokens) {
-                Ok(node) => nodes.push(node),
-                Err(e) => return Err(e),
+                Ok(node) => {
+                    self.recovering = false;
+                    if returned && !warned {
+                        warned = true;
+                        let message = "Unreachable statement!".to_string();
+                        let span = Span::new(start, tokens.prev_span().end);
+                        self.warn(Lint::UnreachableCode, message, span);
+                    }
+                    if let Node::Return { rhs: _, ty: _ } = self.ast[node] {
+                        returned = true;
+                    }
+                    nodes.push(node);
+                },
+                Err(e) => {
+                    self.report(e);
+                    self.depth = depth;
+                    tokens.synchronize();
+                },
             }
         }
 
+        self.leave_scope(scope);
         self.block_level -= 1;
 
-        Ok(new_node_blk(nodes))
+        Ok(self.ast.new_node_blk(nodes))
     }
 
-    fn func(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn func(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        // From `fn`, which is already consumed.
+        let start = tokens.prev_span().start;
         let name = tokens.expect_idt()
-            .map(|s| s.to_string())
-            .ok_or_else(|| ParseError::new(FuncExpected, tokens))?;
+            .ok_or_else(|| self.idt_error(FuncExpected, tokens))?;
 
+        let open = tokens.checkpoint();
         if !tokens.expect_op("(") {
             return Err(ParseError::new(ArgExpected, tokens));
         }
 
-        let mut args: Vec<Box<Node>> = Vec::new();
+        let mut args: Vec<NodeId> = Vec::new();
+        let mut params: Vec<Type> = Vec::new();
         while !tokens.expect_op(")") {
+            if !tokens.has_next() {
+                tokens.rewind(open);
+                return Err(ParseError::new(ParenExpected, tokens));
+            }
             let vi = self.bind(tokens)?;
+            // Arrays are passed by reference.
+            if let Type::Ary(_, _) = vi.ty {
+                let binding = Span::new(vi.span.start, tokens.prev_span().end);
+                return Err(ParseError::new_with_span(TypeInvalid, binding));
+            }
 
-            let offset = self.stack_size() + type_size(&vi.ty);
-            let new = Lvar {
-                name: vi.name.clone(),
-                ty: vi.ty,
-                offset,
-            };
-            self.lvar_list.push(new);
+            // A `_` parameter still takes its register and slot, but
+            // cannot be referred to.
+            let offset = self.alloc_lvar(&vi.ty);
+            if !self.is_discard(vi.name) {
+                let new = Lvar {
+                    name: vi.name,
+                    ty: vi.ty.clone(),
+                    offset,
+                    span: vi.span,
+                    used: false,
+                };
+                self.push_lvar(new);
+            }
 
-            let node = self.var(&vi.name, tokens)?;
-            args.push(node);
+            params.push(vi.ty.clone());
+            args.push(self.ast.new_node_lvar(offset, vi.ty));
             if tokens.expect_op(",") {
                 continue;
             }
         }
 
         self.cur_type = if tokens.expect_op("->") {
-            self.typ(tokens)?
+            let start = tokens.span().start;
+            let ty = self.sized_typ(tokens)?;
+            if let Type::Ary(_, _) = ty {
+                return Err(ParseError::new_with_span(TypeInvalid, Span::new(start, tokens.prev_span().end)));
+            }
+            ty
         } else {
-            Type::Uint8
+            Type::Unit
         };
 
         let new = Func {
-            name: name.clone(),
+            name,
             ty: self.cur_type.clone(),
+            params,
         };
-        self.func_list.push(new);
+        self.push_func(new);
+        let signature = Span::new(start, tokens.prev_span().end);
 
         if tokens.expect_op(";") {
-            Ok(new_node_decf(&name, args))
+            self.clear_lvars();
+            Ok(self.ast.new_node_decf(name, args))
         } else if tokens.expect_op("{") {
             let block = self.blk(tokens)?;
 
-            let stack = align_double_word(self.stack_size());
-            self.lvar_list.clear();
+            let stack = align_double_word(self.max_offset);
+            self.check_unused(0);
+            self.clear_lvars();
 
-            Ok(new_node_func(&name, args, stack, block))
+            let node = self.ast.new_node_func(name, args, stack, block);
+            self.ast.spans.insert(node, signature);
+            Ok(node)
         } else {
             Err(ParseError::new(BlockExpected, tokens))
         }
 
     }
 
-    fn ifel(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
-        let node: Box<Node>;
-
+    fn ifel(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
         let cond = self.expr(tokens)?;
 
         let ibody = if tokens.expect_op("{") {
@@ -789,15 +1544,13 @@ impl Parser {
             } else {
                 self.stmt(tokens)?
             };
-            node = new_node_ifel(cond, ibody, ebody);
+            Ok(self.ast.new_node_ifel(cond, ibody, ebody))
         } else {
-            node = new_node_if(cond, ibody);
+            Ok(self.ast.new_node_if(cond, ibody))
         }
-
-        Ok(node)
     }
 
-    fn whl(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn whl(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
         let cond = self.expr(tokens)?;
 
         let body = if tokens.expect_op("{") {
@@ -806,101 +1559,226 @@ impl Parser {
             self.stmt(tokens)?
         };
 
-        Ok(new_node_whl(cond, body))
+        Ok(self.ast.new_node_whl(cond, body))
     }
 
-    fn locl(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn locl(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
         let vi = self.bind(tokens)?;
+        let binding = Span::new(vi.span.start, tokens.prev_span().end);
 
-        if vi.ty == Type::Str {
-            return Err(ParseError::new_with_offset(NotSized, tokens, 3));
+        // The initializer cannot see the variable being declared.
+        let init = if tokens.expect_op("=") {
+            if let Type::Ary(_, _) = vi.ty {
+                return Err(ParseError::new_with_span(TypeInvalid, binding));
+            }
+            Some(self.expr(tokens)?)
+        } else {
+            None
+        };
+
+        // `_` has no slot. Its initializer is evaluated and discarded.
+        if self.is_discard(vi.name) {
+            return match init {
+                Some(rhs) => Ok(rhs),
+                None => Ok(self.ast.new_node_blk(Vec::new())),
+            };
         }
 
-        let offset = self.stack_size() + type_size(&vi.ty);
+        let offset = self.alloc_lvar(&vi.ty);
         let new = Lvar {
             name: vi.name,
             ty: vi.ty.clone(),
             offset,
+            span: vi.span,
+            used: false,
         };
-        self.lvar_list.push(new);
-
-        Ok(new_node_decl(offset, vi.ty))
+        self.push_lvar(new);
+
+        match init {
+            Some(rhs) => {
+                let lhs = self.ast.new_node_lvar(offset, vi.ty);
+                Ok(self.ast.new_node_bop(BinaryOpAsn, lhs, rhs))
+            },
+            None => Ok(self.ast.new_node_decl(offset, vi.ty)),
+        }
     }
 
-    fn stmt(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
-        let node: Box<Node>;
+    fn stmt(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let start = tokens.span().start;
+        let node: NodeId;
 
         if tokens.expect_rsv("if") {
+            let depth = self.nest(tokens)?;
             node = self.ifel(tokens)?;
+            self.depth = depth;
         } else if tokens.expect_rsv("while") {
+            let depth = self.nest(tokens)?;
             node = self.whl(tokens)?;
+            self.depth = depth;
         } else if tokens.expect_rsv("let") {
             node = self.locl(tokens)?;
             self.consume_semicolon(tokens)?;
         } else if tokens.expect_rsv("return") {
             let rhs = self.expr(tokens)?;
-            node = new_node_ret(rhs, self.cur_type.clone());
+            node = self.ast.new_node_ret(rhs, self.cur_type.clone());
             self.consume_semicolon(tokens)?;
         } else {
-            node = self.expr(tokens)?;
+            node = self.assign(tokens)?;
+            // A call alone has no value to use.
+            let operands = match &self.ast[node] {
+                Node::Call { name: _, args, ty: _ } => args.clone(),
+                _ => vec![node],
+            };
+            if let Some(span) = operands.iter().find_map(|&node| self.unit_value(node)) {
+                return Err(ParseError::new_with_span(UnitAsValue, span));
+            }
+            if let Some(span) = self.array_value(node) {
+                return Err(ParseError::new_with_span(TypeInvalid, span));
+            }
             self.consume_semicolon(tokens)?;
         }
 
+        self.ast.spans.insert(node, Span::new(start, tokens.prev_span().end));
         Ok(node)
     }
 
-    fn glbl(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
+    fn glbl(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let mutable = tokens.expect_rsv("mut");
         let vi = self.bind(tokens)?;
-
-        if vi.ty == Type::Str {
-            return Err(ParseError::new_with_offset(NotSized, tokens, 3));
-        }
+        let binding = Span::new(vi.span.start, tokens.prev_span().end);
+
+        // The value is put into the data as it is, so the initializer is
+        // computed here.
+        let init = if tokens.expect_op("=") {
+            let start = tokens.span().start;
+            self.in_static = true;
+            let node = self.expr(tokens);
+            self.in_static = false;
+            let node = node?;
+            let span = Span::new(start, tokens.prev_span().end);
+            Some(self.static_value(&vi.ty, node, binding, span)?)
+        } else {
+            None
+        };
 
         let size = type_size(&vi.ty);
         let new = Gvar {
-            name: vi.name.clone(),
+            name: vi.name,
             ty: vi.ty.clone(),
+            mutable,
         };
-        self.gvar_list.push(new);
+        self.push_gvar(new);
+
+        Ok(self.ast.new_node_decg(vi.name, size, vi.ty, init, mutable))
+    }
 
-        Ok(new_node_decg(&vi.name, size, vi.ty))
+    // Value of the initializer of a static of the type, which is an integer
+    // or `bool`.
+    fn static_value(&self, ty: &Type, node: NodeId, binding: Span, span: Span) -> Result<i64, ParseError> {
+        let is_bool = self.ast.value_type(node) == Some(Type::Bool);
+        match ty {
+            Type::Bool if !is_bool => return Err(ParseError::new_with_span(TypeInvalid, span)),
+            Type::Bool => {},
+            Type::Ptr(_) | Type::Slc(_) | Type::Ary(_, _) | Type::Str | Type::Unit => {
+                return Err(ParseError::new_with_span(TypeInvalid, binding));
+            },
+            _ if is_bool => return Err(ParseError::new_with_span(TypeInvalid, span)),
+            _ => {},
+        }
+        let val = self.ast.const_value(node)
+            .ok_or_else(|| ParseError::new_with_span(NotConstant, span))? as i64;
+        let fits = match ty {
+            Type::Int8 => i8::try_from(val).is_ok(),
+            Type::Int16 => i16::try_from(val).is_ok(),
+            Type::Int32 => i32::try_from(val).is_ok(),
+            Type::Uint8 => u8::try_from(val).is_ok(),
+            Type::Uint16 => u16::try_from(val).is_ok(),
+            Type::Uint32 => u32::try_from(val).is_ok(),
+            // All the 64 bits are the value.
+            _ => true,
+        };
+        if !fits {
+            return Err(ParseError::new_with_span(OutOfRange, span));
+        }
+        Ok(val)
     }
 
-    fn top(&mut self, tokens: &mut Tokens) -> Result<Box<Node>, ParseError> {
-        if tokens.expect_rsv("fn") {
-            self.func(tokens)
+    fn top(&mut self, tokens: &mut Tokens) -> Result<NodeId, ParseError> {
+        let mut docs: Vec<String> = Vec::new();
+        while let Some(doc) = tokens.expect_doc() {
+            docs.push(doc);
+        }
+
+        let public = tokens.expect_rsv("pub");
+        let node = if tokens.expect_rsv("fn") {
+            self.func(tokens)?
         } else if tokens.expect_rsv("static") {
             let node = self.glbl(tokens)?;
             self.consume_semicolon(tokens)?;
-            Ok(node)
+            node
         } else {
-            Err(ParseError::new(NotInTop, tokens))
+            return Err(ParseError::new(NotInTop, tokens));
+        };
+
+        if !docs.is_empty() {
+            self.ast.docs.push((node, docs.join("\n")));
         }
+        if public {
+            self.ast.public.insert(node);
+        }
+        Ok(node)
     }
 
-    pub fn program(&mut self, tokens: &mut Tokens) -> Result<Vec<Box<Node>>, ParseError> {
-        let mut nodes: Vec<Box<Node>> = Vec::new();
+    pub fn program(&mut self, tokens: &mut Tokens) -> Result<Ast, Vec<ParseError>> {
         while tokens.has_next() {
             match self.top(tokens) {
-                Ok(node) => nodes.push(node),
-                Err(e) => return Err(e),
+                Ok(node) => {
+                    self.recovering = false;
+                    self.ast.items.push(node);
+                },
+                Err(e) => {
+                    self.report(e);
+                    self.clear_lvars();
+                    self.block_level = 0;
+                    self.depth = 0;
+                    tokens.synchronize_top();
+                },
             }
         }
 
-        #[cfg(feature="trace")]
-        println!(" Nodes {:?}", nodes);
+        // Scopes are checked when they end, so put them back in source order.
+        self.warnings.sort_by_key(|w| w.span().start);
 
-        Ok(nodes)
+        if self.errors.is_empty() {
+            Ok(std::mem::take(&mut self.ast))
+        } else {
+            Err(self.errors.drain(..).collect())
+        }
     }
 
-    pub fn new() -> Self {
+    pub fn new(interner: &'a Interner) -> Self {
         Parser {
+            interner,
+            ast: Ast::default(),
             lvar_list: Vec::new(),
             gvar_list: Vec::new(),
             literal_list: Vec::new(),
             func_list: Vec::new(),
+            lvar_map: SymbolMap::default(),
+            gvar_map: SymbolMap::default(),
+            func_map: SymbolMap::default(),
+            cur_offset: 0,
+            max_offset: 0,
             block_level: 0,
-            cur_type: Type::Int8,
+            depth: 0,
+            cur_type: Type::Unit,
+            unit_calls: HashMap::new(),
+            array_vars: HashMap::new(),
+            in_static: false,
+            errors: Vec::new(),
+            recovering: false,
+            lints: LintLevels::new(),
+            warnings: Vec::new(),
         }
     }
 }
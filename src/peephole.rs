@@ -0,0 +1,34 @@
+use super::insn::Insn;
+use super::insn::Operand;
+
+use Insn::*;
+use Operand::*;
+
+// Index of the last instruction in `insns` which is not a blank line.
+fn last_insn(insns: &[Insn]) -> Option<usize> {
+    insns.iter().rposition(|insn| *insn != Blank)
+}
+
+// Rewrites the instructions left by the stack machine into shorter ones.
+// Only adjacent instructions are looked at, so that nothing is moved across
+// labels.
+//
+// - `push X; pop X` is removed.
+// - `push X; pop Y` is turned into `mov Y, X`.
+pub fn optimize(insns: &mut Vec<Insn>) {
+    let mut out: Vec<Insn> = Vec::with_capacity(insns.len());
+    for insn in insns.drain(..) {
+        let last = last_insn(&out);
+        match (last.map(|i| &out[i]), &insn) {
+            (Some(Push(src)), Pop(dst)) => {
+                let src = src.clone();
+                out.remove(last.unwrap());
+                if src != Reg(dst) {
+                    out.push(Mov(Reg(dst), src));
+                }
+            },
+            _ => out.push(insn),
+        }
+    }
+    *insns = out;
+}
@@ -0,0 +1,321 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::IsTerminal;
+
+use super::token::Span;
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: Option<&'static str>,
+    message: String,
+    span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Option<&'static str>, message: String, span: Option<Span>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message,
+            span,
+        }
+    }
+
+    pub fn warning(code: Option<&'static str>, message: String, span: Option<Span>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code,
+            message,
+            span,
+        }
+    }
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, word)
+    } else {
+        format!("{} {}s", n, word)
+    }
+}
+
+pub fn json_str(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32));
+            },
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('\"');
+    escaped
+}
+
+fn json_opt<T: ToString>(v: Option<T>) -> String {
+    match v {
+        Some(v) => v.to_string(),
+        None => "null".to_string(),
+    }
+}
+
+// Line and column (both 1-origin) and the whole line containing `pos`.
+pub fn line_col(source: &str, pos: usize) -> (usize, usize, &str) {
+    let pos = pos.min(source.len());
+    let mut pos = pos;
+    while !source.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    let line_head = source[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_tail = source[pos..].find('\n').map(|i| pos + i).unwrap_or(source.len());
+    let line = source[..pos].matches('\n').count() + 1;
+    let column = source[line_head..pos].chars().count() + 1;
+    (line, column, &source[line_head..line_tail])
+}
+
+// Beginnings of the lines, to look up the lines of many offsets.
+pub struct LineTable<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineTable<'a> {
+    // Line and column (both 1-origin) of `pos`.
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= pos);
+        let head = self.starts[line - 1];
+        let column = self.source.get(head..pos).map_or(1, |s| s.chars().count() + 1);
+        (line, column)
+    }
+
+    // Text of `span` up to the end of its first line.
+    pub fn first_line(&self, span: Span) -> &'a str {
+        let text = self.source.get(span.start..span.end).unwrap_or("");
+        text.lines().next().unwrap_or("").trim_end()
+    }
+
+    pub fn new(source: &'a str) -> Self {
+        let heads = source.match_indices('\n').map(|(i, _)| i + 1);
+        LineTable {
+            source,
+            starts: std::iter::once(0).chain(heads).collect(),
+        }
+    }
+}
+
+// Number of characters in `span`, limited to its first line.
+fn span_width(source: &str, span: Span) -> usize {
+    source.get(span.start..span.end.min(source.len()))
+        .and_then(|s| s.split('\n').next())
+        .map(|s| s.chars().count())
+        .unwrap_or(0)
+        .max(1)
+}
+
+// Part of the source which comes from `text` at `offset`, e.g. a `-e`
+// snippet in the program made of it.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment<'a> {
+    pub start: usize,
+    pub len: usize,
+    pub file: &'a str,
+    pub text: &'a str,
+    pub offset: usize,
+}
+
+pub struct Reporter<'a> {
+    format: ErrorFormat,
+    color: bool,
+    file: &'a str,
+    source: &'a str,
+    // Spans are shown in the text of the segment they start in, or of the
+    // one before it.
+    segments: Vec<Segment<'a>>,
+    error_count: usize,
+    warning_count: usize,
+}
+
+impl<'a> Reporter<'a> {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    // File, text and span in it where `span` of the source is shown.
+    fn locate(&self, span: Span) -> (&'a str, &'a str, Span) {
+        let segment = self.segments.iter().rev()
+            .find(|s| s.start <= span.start)
+            .or(self.segments.first());
+        match segment {
+            Some(s) => {
+                let pos = |pos: usize| s.offset + pos.saturating_sub(s.start).min(s.len);
+                (s.file, s.text, Span { start: pos(span.start), end: pos(span.end) })
+            },
+            None => (self.file, self.source, span),
+        }
+    }
+
+    fn render_human(&self, d: &Diagnostic) -> String {
+        let mut out = String::new();
+        let severity = match d.code {
+            Some(code) => format!("{}[{}]", d.severity.as_str(), code),
+            None => d.severity.as_str().to_string(),
+        };
+        out.push_str(&format!("{}: {}\n", self.paint(d.severity.color(), &severity), d.message));
+
+        if let Some(span) = d.span {
+            let (file, source, span) = self.locate(span);
+            let (line, column, text) = line_col(source, span.start);
+            let gutter = " ".repeat(line.to_string().len());
+            // Keep tabs so that the caret lines up with the source line.
+            let indent: String = text.chars()
+                .take(column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let location = format!("{}:{}:{}", file, line, column);
+            let caret = "^".repeat(span_width(source, span));
+            out.push_str(&format!("{}--> {}\n", gutter, self.paint(DIM, &location)));
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", line, text));
+            out.push_str(&format!("{} | {}{}\n", gutter, indent, self.paint(d.severity.color(), &caret)));
+        }
+        out.push('\n');
+
+        out
+    }
+
+    fn render_json(&self, d: &Diagnostic) -> String {
+        let (file, line, column, width) = match d.span {
+            Some(span) => {
+                let (file, source, span) = self.locate(span);
+                let (line, column, _) = line_col(source, span.start);
+                (file, Some(line), Some(column), Some(span_width(source, span)))
+            },
+            None => (self.file, None, None, None),
+        };
+
+        format!("{{\"code\":{},\"severity\":{},\"message\":{},\"file\":{},\
+                 \"line\":{},\"column\":{},\"span_len\":{}}}\n",
+                json_opt(d.code.map(json_str)),
+                json_str(d.severity.as_str()),
+                json_str(&d.message),
+                json_str(file),
+                json_opt(line),
+                json_opt(column),
+                json_opt(width))
+    }
+
+    pub fn render(&self, d: &Diagnostic) -> String {
+        match self.format {
+            ErrorFormat::Human => self.render_human(d),
+            ErrorFormat::Json => self.render_json(d),
+        }
+    }
+
+    fn write(&self, s: &str) {
+        let stderr = io::stderr();
+        let mut handle = stderr.lock();
+        // Nothing can be done if the diagnostic itself cannot be written.
+        let _ = handle.write_all(s.as_bytes());
+    }
+
+    pub fn report(&mut self, d: &Diagnostic) {
+        match d.severity {
+            Severity::Error => self.error_count += 1,
+            Severity::Warning => self.warning_count += 1,
+        }
+        self.write(&self.render(d));
+    }
+
+    // Print the total number of reported diagnostics.
+    pub fn summary(&self) {
+        if self.format != ErrorFormat::Human {
+            return;
+        }
+
+        let summary = if self.error_count > 0 {
+            let mut message = format!("aborting due to {}", plural(self.error_count, "previous error"));
+            if self.warning_count > 0 {
+                message.push_str(&format!("; {} emitted", plural(self.warning_count, "warning")));
+            }
+            Diagnostic::error(None, message, None)
+        } else if self.warning_count > 0 {
+            let message = format!("{} emitted", plural(self.warning_count, "warning"));
+            Diagnostic::warning(None, message, None)
+        } else {
+            return;
+        };
+        self.write(&self.render(&summary));
+    }
+
+    pub fn new(format: ErrorFormat, color: ColorChoice, file: &'a str, source: &'a str) -> Self {
+        let color = match color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stderr().is_terminal(),
+        };
+
+        Reporter {
+            format,
+            color,
+            file,
+            source,
+            segments: Vec::new(),
+            error_count: 0,
+            warning_count: 0,
+        }
+    }
+
+    pub fn with_segments(mut self, segments: &[Segment<'a>]) -> Self {
+        self.segments = segments.to_vec();
+        self
+    }
+}
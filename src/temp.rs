@@ -0,0 +1,57 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use rand::prelude::*;
+
+fn random_string(len: usize) -> String {
+    let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                   abcdefghijklmnopqrstuvwxyz\
+                   0123456789";
+    let mut rng = rand::thread_rng();
+
+    (0..len)
+        .map(|_| *source.choose(&mut rng).unwrap() as char)
+        .collect()
+}
+
+// Intermediate file which is removed when dropped, so that it is cleaned
+// up on every error path too.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    // Creates a file with a unique name in the temporary directory.
+    pub fn create(suffix: &str) -> io::Result<(Self, File)> {
+        TempFile::create_in(&env::temp_dir(), suffix)
+    }
+
+    // Creates a file with a unique name in the directory.
+    pub fn create_in(dir: &Path, suffix: &str) -> io::Result<(Self, File)> {
+        loop {
+            let name = format!("yrc{}{}", random_string(12), suffix);
+            let path = dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(f) => return Ok((TempFile { path }, f)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // The file may have been moved already.
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -1,61 +1,139 @@
 use std::str;
 use std::fmt;
+use std::slice;
+
+use super::intern::Interner;
+use super::intern::Symbol;
 
 use TokenKind::*;
 use TokenErrorKind::*;
 
-#[derive(Debug)]
+// Byte offsets of the beginning (inclusive) and the end (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub enum TokenErrorKind {
-    CannotTokenize,
+    UnexpectedChar(char),
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidEscape(char),
+    NumberTooLarge,
+    InvalidDigit(char),
 }
 
 #[derive(Debug)]
 pub struct TokenError {
     error: TokenErrorKind,
-    pos: usize,
+    span: Span,
 }
 
 impl TokenError {
-    fn new(e: TokenErrorKind, p: usize) -> Self {
+    fn new(e: TokenErrorKind, start: usize, end: usize) -> Self {
         TokenError {
             error: e,
-            pos: p,
+            span: Span::new(start, end),
         }
     }
 }
 
+impl TokenErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            UnexpectedChar(_) => "E0001",
+            UnterminatedString => "E0002",
+            UnterminatedComment => "E0003",
+            InvalidEscape(_) => "E0004",
+            NumberTooLarge => "E0005",
+            InvalidDigit(_) => "E0006",
+        }
+    }
+}
+
+impl TokenError {
+    pub fn kind(&self) -> &TokenErrorKind {
+        &self.error
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.error.code()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn message(&self) -> String {
+        self.error.to_string()
+    }
+}
+
 impl fmt::Display for TokenError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}^ ", " ".repeat(self.pos))?;
-        match &self.error {
-            CannotTokenize => write!(f, "Cannot tokenize!"),
+        write!(f, "error[{}]: {}", self.code(), self.error)
+    }
+}
+
+impl fmt::Display for TokenErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnexpectedChar(c) => write!(f, "Unexpected character `{}`!", c.escape_debug()),
+            UnterminatedString => write!(f, "String literal is not terminated!"),
+            UnterminatedComment => write!(f, "Block comment is not terminated!"),
+            InvalidEscape(c) => write!(f, "Unknown escape sequence `\\{}`!", c.escape_debug()),
+            NumberTooLarge => write!(f, "Number is too large!"),
+            InvalidDigit(c) => write!(f, "Invalid digit `{}` in number!", c.escape_debug()),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     TokenOp(String),
-    TokenNum(u32),
-    TokenIdt(String),
-    TokenStr(String),
-    TokenRsv(String),
+    TokenNum(u64),
+    TokenIdt(Symbol),
+    TokenStr(Symbol),
+    // C string literal `c"..."`, with the terminating NUL.
+    TokenCStr(Symbol),
+    TokenRsv(&'static str),
+    // Only produced by tokenize_with_docs().
+    TokenDoc(String),
     TokenEnd,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Token {
     kind: TokenKind,
-    pos: usize,
+    span: Span,
 }
 
 impl Token {
-    fn new(k: TokenKind, p: usize) -> Self {
+    fn new(k: TokenKind, start: usize, end: usize) -> Self {
         Token {
             kind: k,
-            pos: p,
+            span: Span::new(start, end),
         }
     }
+
+    pub fn kind(&self) -> &TokenKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
 #[derive(Debug)]
@@ -65,8 +143,14 @@ pub struct Tokens {
 }
 
 impl Tokens {
-    pub fn expect_num(&mut self) -> Option<u32> {
-        let cur_tok = &self.list[self.current];
+    // Index of the next token. An exhausted list is treated as the end of
+    // file, which is always the last token.
+    fn cur_index(&self) -> usize {
+        self.current.min(self.list.len() - 1)
+    }
+
+    pub fn expect_num(&mut self) -> Option<u64> {
+        let cur_tok = &self.list[self.cur_index()];
         match &cur_tok.kind {
             TokenNum(num) => {
                 self.current += 1;
@@ -77,13 +161,13 @@ impl Tokens {
     }
 
     pub fn expect_bl(&mut self) -> Option<bool> {
-        let cur_tok = &self.list[self.current];
+        let cur_tok = &self.list[self.cur_index()];
         match &cur_tok.kind {
             TokenRsv(word) => {
-                if word == "true" {
+                if *word == "true" {
                     self.current += 1;
                     Some(true)
-                } else if word == "false" {
+                } else if *word == "false" {
                     self.current += 1;
                     Some(false)
                 } else {
@@ -95,42 +179,63 @@ impl Tokens {
     }
 
     pub fn expect_op(&mut self, expect: &str) -> bool {
-        let cur_tok = &self.list[self.current];
+        if self.peek_is_op(expect) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn expect_idt(&mut self) -> Option<Symbol> {
+        let cur_tok = &self.list[self.cur_index()];
         match &cur_tok.kind {
-            TokenOp(op) if op == expect => {
+            TokenIdt(name) => {
                 self.current += 1;
-                true
+                Some(*name)
             },
-            _ => false
+            _ => None
         }
     }
 
-    pub fn expect_idt(&mut self) -> Option<&str> {
-        let cur_tok = &self.list[self.current];
+    pub fn expect_str(&mut self) -> Option<Symbol> {
+        let cur_tok = &self.list[self.cur_index()];
         match &cur_tok.kind {
-            TokenIdt(name) => {
+            TokenStr(s) => {
                 self.current += 1;
-                Some(name.as_str())
+                Some(*s)
             },
             _ => None
         }
     }
 
-    pub fn expect_str(&mut self) -> Option<&str> {
-        let cur_tok = &self.list[self.current];
+    pub fn expect_cstr(&mut self) -> Option<Symbol> {
+        let cur_tok = &self.list[self.cur_index()];
         match &cur_tok.kind {
-            TokenStr(s) => {
+            TokenCStr(s) => {
+                self.current += 1;
+                Some(*s)
+            },
+            _ => None
+        }
+    }
+
+    pub fn expect_doc(&mut self) -> Option<String> {
+        let cur_tok = &self.list[self.cur_index()];
+        match &cur_tok.kind {
+            TokenDoc(doc) => {
+                let doc = doc.clone();
                 self.current += 1;
-                Some(s.as_str())
+                Some(doc)
             },
             _ => None
         }
     }
 
     pub fn expect_rsv(&mut self, expect: &str) -> bool {
-        let cur_tok = &self.list[self.current];
+        let cur_tok = &self.list[self.cur_index()];
         match &cur_tok.kind {
-            TokenRsv(word) if word == expect => {
+            TokenRsv(word) if *word == expect => {
                 self.current += 1;
                 true
             },
@@ -139,140 +244,304 @@ impl Tokens {
     }
 
     pub fn has_next(&self) -> bool {
-        let cur_tok = &self.list[self.current];
-        match &cur_tok.kind {
-            TokenEnd => false,
-            _ => true,
+        !matches!(self.peek(), TokenEnd)
+    }
+
+    // Look ahead the next token without consuming it.
+    pub fn peek(&self) -> &TokenKind {
+        self.peek_nth(0)
+    }
+
+    // Look ahead the n-th token from the next one (0-origin).
+    pub fn peek_nth(&self, n: usize) -> &TokenKind {
+        let index = (self.current + n).min(self.list.len() - 1);
+        &self.list[index].kind
+    }
+
+    pub fn peek_rsv(&self) -> Option<&'static str> {
+        match self.peek() {
+            TokenRsv(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    pub fn peek_is_op(&self, expect: &str) -> bool {
+        matches!(self.peek(), TokenOp(op) if op == expect)
+    }
+
+    // Save the current position to go back later. Rewinding is cheap
+    // because consumed tokens are never dropped from the list.
+    pub fn checkpoint(&self) -> usize {
+        self.current
+    }
+
+    pub fn rewind(&mut self, checkpoint: usize) {
+        self.current = checkpoint;
+    }
+
+    // Skip tokens until the end of the current statement so that parsing
+    // can continue after an error. A terminating ";" is consumed, but "}"
+    // is left for the enclosing block.
+    pub fn synchronize(&mut self) {
+        while self.has_next() {
+            match self.peek() {
+                TokenOp(op) if op == ";" => {
+                    self.current += 1;
+                    break;
+                },
+                TokenOp(op) if op == "}" => break,
+                _ => self.current += 1,
+            }
+        }
+    }
+
+    // Skip tokens until the beginning of the next top level item.
+    pub fn synchronize_top(&mut self) {
+        while self.has_next() {
+            match self.peek() {
+                TokenRsv(word) if *word == "fn" || *word == "pub" || *word == "static" => break,
+                _ => self.current += 1,
+            }
         }
     }
 
-    pub fn head(&self) -> usize {
-        let cur_tok = &self.list[self.current];
-        cur_tok.pos
+    // Span of the next token.
+    pub fn span(&self) -> Span {
+        let cur_tok = &self.list[self.cur_index()];
+        cur_tok.span
     }
 
-    pub fn head_before(&self, offset: usize) -> Option<usize> {
-        self.list.get(self.current - offset).map(|tok| tok.pos)
+    // Span of the last consumed token.
+    pub fn prev_span(&self) -> Span {
+        match self.current.checked_sub(1).and_then(|prev| self.list.get(prev)) {
+            Some(tok) => tok.span,
+            None => self.span(),
+        }
     }
 
-    pub fn new(v: Vec<Token>) -> Self {
+    pub fn new(mut v: Vec<Token>) -> Self {
+        if !matches!(v.last(), Some(Token { kind: TokenEnd, .. })) {
+            let end = v.last().map(|tok| tok.span.end).unwrap_or(0);
+            v.push(Token::new(TokenEnd, end, end));
+        }
         Self {
             list: v,
             current: 0,
         }
     }
-}
 
-fn lex_num(bytes: &[u8], cur: &mut usize) -> Token {
-    let mut tmp: Vec<u8> = Vec::new();
-    let pos = *cur;
-    loop {
-        tmp.push(bytes[*cur]);
-        *cur += 1;
-        if (*cur >= bytes.len()) ||
-           (!b"0123456789".contains(&bytes[*cur])) {
-            let num = str::from_utf8(&tmp)
-                .unwrap()
-                .parse()
-                .unwrap();
-            return Token::new(TokenNum(num), pos);
+    // Number of tokens including the end of file.
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> Option<&Token> {
+        self.list.get(i)
+    }
+
+    // Walk all tokens regardless of the current position.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            iter: self.list.iter(),
         }
     }
 }
 
-fn lex_cmp(bytes: &[u8], cur: &mut usize) -> Token {
-    let mut tmp: Vec<u8> = Vec::new();
+pub struct Iter<'a> {
+    iter: slice::Iter<'a, Token>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a TokenKind, Span);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|tok| (&tok.kind, tok.span))
+    }
+}
+
+impl<'a> IntoIterator for &'a Tokens {
+    type Item = (&'a TokenKind, Span);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// Character beginning at `bytes[i]` for error messages.
+fn char_at(bytes: &[u8], i: usize) -> char {
+    let end = (i + 4).min(bytes.len());
+    let valid = match str::from_utf8(&bytes[i..end]) {
+        Ok(s) => s,
+        Err(e) => str::from_utf8(&bytes[i..i + e.valid_up_to()]).unwrap(),
+    };
+    valid.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+fn lex_num(src: &str, cur: &mut usize) -> Result<Token, TokenError> {
+    let bytes = src.as_bytes();
     let pos = *cur;
-    loop {
-        tmp.push(bytes[*cur]);
+    while (*cur < bytes.len()) && bytes[*cur].is_ascii_digit() {
         *cur += 1;
-        if (*cur >= bytes.len()) ||
-           (!b"<>=!".contains(&bytes[*cur])) {
-            let op = str::from_utf8(&tmp)
-                .unwrap()
-                .to_string();
-            return Token::new(TokenOp(op), pos);
-        }
     }
+    if (*cur < bytes.len()) && is_word_byte(bytes[*cur]) {
+        let c = char_at(bytes, *cur);
+        return Err(TokenError::new(InvalidDigit(c), *cur, *cur + c.len_utf8()));
+    }
+    let num = src[pos..*cur]
+        .parse()
+        .map_err(|_| TokenError::new(NumberTooLarge, pos, *cur))?;
+    Ok(Token::new(TokenNum(num), pos, *cur))
+}
+
+fn lex_cmp(src: &str, cur: &mut usize) -> Result<Token, TokenError> {
+    let bytes = src.as_bytes();
+    let pos = *cur;
+    // Each comparison operator is one character optionally followed by `=`.
+    let len = if (*cur + 1 < bytes.len()) && (bytes[*cur + 1] == b'=') {
+        2
+    } else {
+        1
+    };
+    *cur += len;
+    Ok(Token::new(TokenOp(src[pos..*cur].to_string()), pos, *cur))
 }
 
-fn lex_arw(bytes: &[u8], cur: &mut usize) -> Token {
-    let mut tmp: Vec<u8> = Vec::new();
+fn lex_arw(src: &str, cur: &mut usize) -> Result<Token, TokenError> {
+    let bytes = src.as_bytes();
     let pos = *cur;
+    // "-" or "->"
+    let len = if (*cur + 1 < bytes.len()) && (bytes[*cur + 1] == b'>') {
+        2
+    } else {
+        1
+    };
+    *cur += len;
+    Ok(Token::new(TokenOp(src[pos..*cur].to_string()), pos, *cur))
+}
+
+fn lex_str(src: &str, cur: &mut usize, interner: &mut Interner) -> Result<Token, TokenError> {
+    let bytes = src.as_bytes();
+    let pos = *cur;
+    // Skip first "
+    *cur += 1;
+    // Content is copied per run of characters between escape sequences.
+    // A line break is also a part of the literal.
+    let mut s = String::new();
+    let mut run = *cur;
     loop {
-        tmp.push(bytes[*cur]);
-        *cur += 1;
-        if (*cur >= bytes.len()) ||
-           (!b">".contains(&bytes[*cur])) {
-            let op = str::from_utf8(&tmp)
-                .unwrap()
-                .to_string();
-            return Token::new(TokenOp(op), pos);
+        if *cur >= bytes.len() {
+            return Err(TokenError::new(UnterminatedString, pos, pos + 1));
+        }
+        match bytes[*cur] {
+            b'\"' => {
+                s.push_str(&src[run..*cur]);
+                // Skip end "
+                *cur += 1;
+                return Ok(Token::new(TokenStr(interner.intern(&s)), pos, *cur));
+            },
+            b'\\' => {
+                if *cur + 1 >= bytes.len() {
+                    return Err(TokenError::new(UnterminatedString, pos, pos + 1));
+                }
+                let c = match bytes[*cur + 1] {
+                    b'n' => '\n',
+                    b'r' => '\r',
+                    b't' => '\t',
+                    b'0' => '\0',
+                    c @ (b'\\' | b'\"' | b'\'') => c as char,
+                    _ => {
+                        let c = char_at(bytes, *cur + 1);
+                        return Err(TokenError::new(InvalidEscape(c), *cur, *cur + 1 + c.len_utf8()));
+                    },
+                };
+                s.push_str(&src[run..*cur]);
+                s.push(c);
+                *cur += 2;
+                run = *cur;
+            },
+            _ => *cur += 1,
         }
     }
 }
 
-fn lex_str(bytes: &[u8], cur: &mut usize) -> Token {
-    let mut tmp: Vec<u8> = Vec::new();
+// `c"..."` has the same escape sequences as `"..."`, and the NUL is
+// appended.
+fn lex_cstr(src: &str, cur: &mut usize, interner: &mut Interner) -> Result<Token, TokenError> {
     let pos = *cur;
-    // Skip first "
+    // Skip c
     *cur += 1;
+    let token = lex_str(src, cur, interner)?;
+    match token.kind {
+        TokenStr(s) => {
+            let s = format!("{}\0", interner.resolve(s));
+            Ok(Token::new(TokenCStr(interner.intern(&s)), pos, *cur))
+        },
+        _ => unreachable!(),
+    }
+}
+
+// Count # of r#"..."# if a raw string literal starts at cur.
+fn raw_str_hashes(bytes: &[u8], cur: usize) -> Option<usize> {
+    let hashes = bytes[cur + 1..].iter()
+        .take_while(|b| **b == b'#')
+        .count();
+    if bytes.get(cur + 1 + hashes) == Some(&b'\"') {
+        Some(hashes)
+    } else {
+        None
+    }
+}
+
+fn lex_raw_str(src: &str, cur: &mut usize, interner: &mut Interner) -> Result<Token, TokenError> {
+    let bytes = src.as_bytes();
+    let pos = *cur;
+    let hashes = raw_str_hashes(bytes, pos).unwrap_or(0);
+    // Skip r, #s and "
+    *cur += hashes + 2;
+    let start = *cur;
     loop {
-        tmp.push(bytes[*cur]);
-        *cur += 1;
-        if (*cur >= bytes.len()) ||
-           (bytes[*cur] == b'\"') {
-            // Skip end "
-            *cur += 1;
-            let s = str::from_utf8(&tmp)
-                .unwrap()
-                .to_string();
-            return Token::new(TokenStr(s), pos);
+        if *cur >= bytes.len() {
+            return Err(TokenError::new(UnterminatedString, pos, start));
+        }
+        if bytes[*cur] == b'\"' &&
+           bytes[*cur + 1..].iter().take(hashes).filter(|b| **b == b'#').count() == hashes {
+            let s = interner.intern(&src[start..*cur]);
+            // Skip end " and #s
+            *cur += hashes + 1;
+            return Ok(Token::new(TokenStr(s), pos, *cur));
         }
+        *cur += 1;
     }
 }
 
-fn lex_word(bytes: &[u8], cur: &mut usize) -> Token {
-    let mut tmp: Vec<u8> = Vec::new();
+const RESERVED_WORDS: [&str; 24] = [
+    "fn", "pub", "let", "static", "mut",
+    "if", "else", "for", "while",
+    "break", "continue", "return",
+    "i8", "i16", "i32", "i64",
+    "u8", "u16", "u32", "u64",
+    "bool", "str", "true", "false",
+];
+
+fn lex_word(src: &str, cur: &mut usize, interner: &mut Interner) -> Result<Token, TokenError> {
+    let bytes = src.as_bytes();
     let pos = *cur;
-    loop {
-        tmp.push(bytes[*cur]);
+    while (*cur < bytes.len()) && is_word_byte(bytes[*cur]) {
         *cur += 1;
-        if (*cur >= bytes.len()) ||
-           (!b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-               abcdefghijklmnopqrstuvwxyz\
-               0123456789_"
-            .contains(&bytes[*cur])) {
-            let name = str::from_utf8(&tmp)
-                .unwrap()
-                .to_string();
-            if name == "fn"       ||
-               name == "let"      ||
-               name == "static"   ||
-               name == "if"       ||
-               name == "else"     ||
-               name == "for"      ||
-               name == "while"    ||
-               name == "break"    ||
-               name == "continue" ||
-               name == "return"   ||
-               name == "i8"       ||
-               name == "i16"      ||
-               name == "i32"      ||
-               name == "i64"      ||
-               name == "u8"       ||
-               name == "u16"      ||
-               name == "u32"      ||
-               name == "u64"      ||
-               name == "bool"     ||
-               name == "str"      ||
-               name == "true"     ||
-               name == "false"    {
-                return Token::new(TokenRsv(name), pos);
-            } else {
-                return Token::new(TokenIdt(name), pos);
-            }
-        }
+    }
+    let word = &src[pos..*cur];
+    match RESERVED_WORDS.iter().find(|rsv| **rsv == word) {
+        Some(rsv) => Ok(Token::new(TokenRsv(rsv), pos, *cur)),
+        None => Ok(Token::new(TokenIdt(interner.intern(word)), pos, *cur)),
     }
 }
 
@@ -280,8 +549,10 @@ fn skip_line_comment(bytes: &[u8], cur: &mut usize) {
     // Skip // of line top
     *cur += 2;
     loop {
-        if (*cur >= bytes.len()) ||
-           (b"\n".contains(&bytes[*cur])) {
+        if *cur >= bytes.len() {
+            break;
+        }
+        if b"\n".contains(&bytes[*cur]) {
             // Skip \n of line end
             *cur += 1;
             break;
@@ -290,88 +561,138 @@ fn skip_line_comment(bytes: &[u8], cur: &mut usize) {
     }
 }
 
-fn skip_block_comment(bytes: &[u8], cur: &mut usize) {
+fn skip_block_comment(bytes: &[u8], cur: &mut usize) -> Result<(), TokenError> {
+    let pos = *cur;
     // Skip /* of beginning
     *cur += 2;
     loop {
-        if ((*cur >= bytes.len()) ||
-           (b"*".contains(&bytes[*cur]))) &&
-           ((*cur + 1 >= bytes.len()) ||
-           (b"/".contains(&bytes[*cur + 1]))) {
+        if *cur + 1 >= bytes.len() {
+            *cur = bytes.len();
+            return Err(TokenError::new(UnterminatedComment, pos, pos + 2));
+        }
+        if (bytes[*cur] == b'*') && (bytes[*cur + 1] == b'/') {
             // Skip */ of end
             *cur += 2;
-            break;
+            return Ok(());
         }
         *cur += 1;
     }
 }
 
-pub fn tokenize(formula: &str) -> Result<Vec<Token>, TokenError> {
+// "///" but not "////".
+fn is_doc_line(bytes: &[u8], cur: usize) -> bool {
+    bytes[cur..].starts_with(b"///") && !bytes[cur..].starts_with(b"////")
+}
+
+// "/**" but neither "/***" nor the empty comment "/**/".
+fn is_doc_block(bytes: &[u8], cur: usize) -> bool {
+    bytes[cur..].starts_with(b"/**") &&
+        !bytes[cur..].starts_with(b"/***") &&
+        !bytes[cur..].starts_with(b"/**/")
+}
+
+fn lex_doc_line(src: &str, cur: &mut usize) -> Token {
+    let pos = *cur;
+    skip_line_comment(src.as_bytes(), cur);
+    let end = if src[..*cur].ends_with('\n') { *cur - 1 } else { *cur };
+    Token::new(TokenDoc(src[pos + 3..end].to_string()), pos, end)
+}
+
+fn lex_doc_block(src: &str, cur: &mut usize) -> Result<Token, TokenError> {
+    let pos = *cur;
+    skip_block_comment(src.as_bytes(), cur)?;
+    Ok(Token::new(TokenDoc(src[pos + 3..*cur - 2].to_string()), pos, *cur))
+}
+
+pub fn tokenize(formula: &str, interner: &mut Interner) -> Result<Vec<Token>, TokenError> {
+    lex(formula, interner, false)
+}
+
+// Same as tokenize(), but doc comments are kept as TokenDoc for tools.
+pub fn tokenize_with_docs(formula: &str, interner: &mut Interner) -> Result<Vec<Token>, TokenError> {
+    lex(formula, interner, true)
+}
+
+fn lex(formula: &str, interner: &mut Interner, docs: bool) -> Result<Vec<Token>, TokenError> {
     let mut tokens: Vec<Token> = Vec::new();
     let mut cur = 0;
     let bytes = formula.as_bytes();
 
+    // Shebang line is skipped like a line comment.
+    if formula.starts_with("#!") {
+        skip_line_comment(bytes, &mut cur);
+    }
+
     while cur < bytes.len() {
         match bytes[cur] {
             b'0'..=b'9' => {
-                let token = lex_num(bytes, &mut cur);
+                let token = lex_num(formula, &mut cur)?;
                 tokens.push(token);
             },
-            b'+' | b'*' |
+            b'+' | b'*' | b'%' |
             b'(' | b')' |
             b'[' | b']' |
             b'{' | b'}' |
             b'&' | b',' |
             b':' | b';' => {
-                let op = str::from_utf8(&bytes[cur].to_ne_bytes())
-                    .unwrap()
-                    .to_string();
-                tokens.push(Token::new(TokenOp(op), cur));
+                let op = formula[cur..cur + 1].to_string();
+                tokens.push(Token::new(TokenOp(op), cur, cur + 1));
                 cur += 1;
             },
             b'<' | b'>' |
             b'=' | b'!' => {
-                let token = lex_cmp(bytes, &mut cur);
+                let token = lex_cmp(formula, &mut cur)?;
                 tokens.push(token);
             },
             b'-' => {
-                let token = lex_arw(bytes, &mut cur);
+                let token = lex_arw(formula, &mut cur)?;
                 tokens.push(token);
             },
             b'\"' => {
-                let token = lex_str(bytes, &mut cur);
+                let token = lex_str(formula, &mut cur, interner)?;
+                tokens.push(token);
+            },
+            b'r' if raw_str_hashes(bytes, cur).is_some() => {
+                let token = lex_raw_str(formula, &mut cur, interner)?;
+                tokens.push(token);
+            },
+            b'c' if bytes.get(cur + 1) == Some(&b'\"') => {
+                let token = lex_cstr(formula, &mut cur, interner)?;
                 tokens.push(token);
             },
             b'A'..=b'Z' |
             b'a'..=b'z' |
             b'_' => {
-                let token = lex_word(bytes, &mut cur);
+                let token = lex_word(formula, &mut cur, interner)?;
                 tokens.push(token);
             },
             b'/' => {
-                if (cur < bytes.len()) &&
-                   (b"/".contains(&bytes[cur + 1])) {
+                if docs && is_doc_line(bytes, cur) {
+                    tokens.push(lex_doc_line(formula, &mut cur));
+                } else if docs && is_doc_block(bytes, cur) {
+                    let token = lex_doc_block(formula, &mut cur)?;
+                    tokens.push(token);
+                } else if (cur + 1 < bytes.len()) &&
+                          (b"/".contains(&bytes[cur + 1])) {
                     skip_line_comment(bytes, &mut cur);
-                } else if (cur < bytes.len()) &&
+                } else if (cur + 1 < bytes.len()) &&
                           (b"*".contains(&bytes[cur + 1])) {
-                    skip_block_comment(bytes, &mut cur);
+                    skip_block_comment(bytes, &mut cur)?;
                 } else {
-                    let op = str::from_utf8(&bytes[cur].to_ne_bytes())
-                        .unwrap()
-                        .to_string();
-                    tokens.push(Token::new(TokenOp(op), cur));
+                    let op = formula[cur..cur + 1].to_string();
+                    tokens.push(Token::new(TokenOp(op), cur, cur + 1));
                     cur += 1;
                 }
             },
             b' ' | b'\t'| b'\n' => cur += 1,
-            _ => return Err(TokenError::new(CannotTokenize, cur)),
+            _ => {
+                let c = char_at(bytes, cur);
+                return Err(TokenError::new(UnexpectedChar(c), cur, cur + c.len_utf8()));
+            },
         }
     }
 
-    tokens.push(Token::new(TokenEnd, cur));
-
-    #[cfg(feature="trace")]
-    println!(" Tokens {:?}", tokens);
+    tokens.push(Token::new(TokenEnd, cur, cur));
 
     Ok(tokens)
 }
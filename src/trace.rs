@@ -0,0 +1,124 @@
+use std::io;
+use std::io::prelude::*;
+use std::time::Duration;
+use std::time::Instant;
+
+// Progress of the compilation printed to stderr. `-v` shows a line per
+// phase with a summary, and `-vv` also dumps the tokens and the AST.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tracer {
+    level: usize,
+}
+
+impl Tracer {
+    fn write(&self, s: &str) {
+        // Tracing must not stop the compilation.
+        let _ = io::stderr().lock().write_all(s.as_bytes());
+    }
+
+    pub fn phase(&self, phase: &str, summary: &str) {
+        if self.level >= 1 {
+            self.write(&format!("[{}] {}\n", phase, summary));
+        }
+    }
+
+    // The text is only made when it is written.
+    pub fn dump(&self, text: impl FnOnce() -> String) {
+        if self.level >= 2 {
+            self.write(&text());
+        }
+    }
+
+    pub fn new(level: usize) -> Self {
+        Tracer {
+            level,
+        }
+    }
+}
+
+// Wall time of each phase, reported as a table by --time-passes.
+#[derive(Debug, Default)]
+pub struct PhaseTimer {
+    enabled: bool,
+    phases: Vec<(&'static str, Duration, String)>,
+}
+
+impl PhaseTimer {
+    // Runs `f` as the phase. Nothing is measured unless enabled.
+    pub fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        self.phases.push((phase, start.elapsed(), String::new()));
+        result
+    }
+
+    // Attaches counters like "17 tokens" to the last phase.
+    pub fn note(&mut self, counters: &str) {
+        if let Some((_, _, c)) = self.phases.last_mut() {
+            *c = counters.to_string();
+        }
+    }
+
+    pub fn report(&self) {
+        if !self.enabled {
+            return;
+        }
+        let mut table = String::new();
+        let mut total = Duration::ZERO;
+        for (phase, elapsed, counters) in &self.phases {
+            total += *elapsed;
+            table.push_str(&format!("{:<10} {:>10.3}ms", phase, millis(*elapsed)));
+            if !counters.is_empty() {
+                table.push_str(&format!("  {}", counters));
+            }
+            table.push('\n');
+        }
+        table.push_str(&format!("{:<10} {:>10.3}ms\n", "total", millis(total)));
+        let _ = io::stderr().lock().write_all(table.as_bytes());
+    }
+
+    pub fn new(enabled: bool) -> Self {
+        PhaseTimer {
+            enabled,
+            phases: Vec::new(),
+        }
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+// Counts the bytes written through it.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> CountingWriter<W> {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn new(inner: W) -> Self {
+        CountingWriter {
+            inner,
+            count: 0,
+        }
+    }
+}
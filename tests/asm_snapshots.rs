@@ -0,0 +1,144 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use yrc::compile_str;
+
+// Compares the assembly of the source code with the one checked in as
+// tests/snapshots/<name>.s.expected. With UPDATE_SNAPSHOTS=1, the
+// expected file is written instead.
+fn check_snapshot(name: &str, source: &str) {
+    let asm = normalize(&compile_str(source).unwrap());
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "snapshots", &format!("{}.s.expected", name)]
+        .iter()
+        .collect();
+
+    if env::var("UPDATE_SNAPSHOTS").is_ok_and(|v| v == "1") {
+        fs::write(&path, &asm).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_SNAPSHOTS=1 to create it)", path.display(), e));
+    if asm != expected {
+        let line = asm.lines()
+            .zip(expected.lines())
+            .position(|(a, e)| a != e)
+            .unwrap_or_else(|| asm.lines().count().min(expected.lines().count()));
+        panic!("{} differs from line {}:\n--- expected\n{}\n--- generated\n{}",
+               path.display(), line + 1,
+               expected.lines().skip(line).take(5).collect::<Vec<_>>().join("\n"),
+               asm.lines().skip(line).take(5).collect::<Vec<_>>().join("\n"));
+    }
+}
+
+// The version in `.ident` changes with every release.
+fn normalize(asm: &str) -> String {
+    asm.replace(concat!(".ident \"yrc ", env!("CARGO_PKG_VERSION"), "\""), ".ident \"yrc\"")
+}
+
+#[test]
+fn snapshot_arithmetic() {
+    check_snapshot("arithmetic", "\
+        fn main() -> i32 {\n\
+            let a: i32 = 7;\n\
+            let b: i64 = 3;\n\
+            return (a + 2) * b - a / 2 % 3;\n\
+        }\n");
+}
+
+#[test]
+fn snapshot_if_else() {
+    check_snapshot("if_else", "\
+        fn sign(n: i32) -> i32 {\n\
+            if n < 0 {\n\
+                return 0 - 1;\n\
+            } else if n == 0 {\n\
+                return 0;\n\
+            } else {\n\
+                return 1;\n\
+            }\n\
+        }\n\
+        fn main() -> i32 {\n\
+            return sign(5);\n\
+        }\n");
+}
+
+#[test]
+fn snapshot_while() {
+    check_snapshot("while", "\
+        fn main() -> i32 {\n\
+            let i: i32 = 0;\n\
+            let sum: i32 = 0;\n\
+            while i < 10 {\n\
+                sum = sum + i;\n\
+                i = i + 1;\n\
+            }\n\
+            return sum;\n\
+        }\n");
+}
+
+#[test]
+fn snapshot_call_six_args() {
+    check_snapshot("call_six_args", "\
+        fn sum(a: i8, b: i16, c: i32, d: i64, e: u8, f: u32) -> i64 {\n\
+            return a + b + c + d + e + f;\n\
+        }\n\
+        fn main() -> i64 {\n\
+            return sum(1, 2, 3, 4, 5, 6);\n\
+        }\n");
+}
+
+#[test]
+fn snapshot_slice() {
+    check_snapshot("slice", "\
+        fn write(fd: i32, s: &str) -> i64;\n\
+        fn pick(n: i32, a: &str, b: &str) -> &str {\n\
+            if n == 0 {\n\
+                return a;\n\
+            }\n\
+            return b;\n\
+        }\n\
+        fn main() -> i64 {\n\
+            let s: &str = \"abc\";\n\
+            return write(1, pick(1, s, \"de\"));\n\
+        }\n");
+}
+
+#[test]
+fn snapshot_global() {
+    check_snapshot("global", "\
+        static mut COUNT: i32;\n\
+        pub static mut TABLE: [i64; 4];\n\
+        fn bump() {\n\
+            COUNT = COUNT + 1;\n\
+        }\n\
+        fn main() -> i32 {\n\
+            bump();\n\
+            TABLE[1] = 2;\n\
+            return COUNT + TABLE[1];\n\
+        }\n");
+}
+
+#[test]
+fn snapshot_static_data() {
+    check_snapshot("static_data", "\
+        static LIMIT: i32 = 4 * 1024;\n\
+        static mut USED: i64 = 1;\n\
+        static mut LAST: i8;\n\
+        fn main() -> i32 {\n\
+            USED = USED + 1;\n\
+            LAST = 2;\n\
+            return LIMIT / 1024;\n\
+        }\n");
+}
+
+#[test]
+fn snapshot_string_literal() {
+    check_snapshot("string_literal", "\
+        fn puts(s: &i8) -> i32;\n\
+        fn write(fd: i32, s: &str) -> i64;\n\
+        fn main() {\n\
+            puts(c\"hello\");\n\
+            write(1, \"tab\\tquote\\\"\\n\");\n\
+        }\n");
+}
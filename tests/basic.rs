@@ -1,55 +1,178 @@
 extern crate yrc;
 
+mod common;
+
 use std::str;
+use std::env;
 use std::fs;
+use std::io;
+use std::io::BufWriter;
 use std::io::prelude::*;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::process::ExitStatusExt;
 use std::process::Command;
 
-use rand::prelude::*;
+use common::TestArtifact;
 
+use yrc::compile_str;
 use yrc::compiler_main;
+use yrc::intern::Interner;
+use yrc::token::tokenize;
+use yrc::token::Tokens;
+use yrc::parse::Parser;
+use yrc::assembly::AsmGenerator;
+use yrc::assembly::CodegenOptions;
 
-fn random_string(len: usize) -> String {
-    let source = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                   abcdefghijklmnopqrstuvwxyz\
-                   0123456789";
-    let mut rng = rand::thread_rng();
+// Checks the program at every optimization level.
+fn check_return_num(source_code: &str, expect: u8) {
+    check_output(source_code, "", expect as i32);
+}
 
-    String::from_utf8(
-        source.choose_multiple(&mut rng, len)
-            .cloned()
-            .collect()
-    ).unwrap()
+fn check_return_num_at(source_code: &str, expect: u8, level: &str) {
+    check_return_num_with(source_code, expect, &[level]);
 }
 
-fn check_return_num(source_code: &str, expect: u8) {
-    let output_file = format!("tests/tmp{}", random_string(8));
-    let input_file = format!("{}.rs", output_file);
-    let mut f = fs::File::create(&input_file).unwrap();
-    write!(f, "{}", source_code).unwrap();
+fn check_return_num_with(source_code: &str, expect: u8, options: &[&str]) {
+    check_output_with(source_code, "", expect as i32, options);
+}
+
+// How a test program ended.
+#[derive(Debug, PartialEq)]
+enum Exit {
+    Status(i32),
+    Signal(i32),
+}
+
+const SIGFPE: i32 = 8;
+const SIGSEGV: i32 = 11;
+
+struct Run {
+    stdout: String,
+    stderr: String,
+    exit: Exit,
+}
+
+// Builds the program and runs it directly, without a shell.
+fn run_native(source_code: &str, options: &[&str]) -> Run {
+    let output_file = TestArtifact::new("");
+    let _input_file = build_native(source_code, &output_file, options);
+
+    let output = Command::new(output_file.path()).output().unwrap();
+    let exit = match (output.status.code(), output.status.signal()) {
+        (Some(code), _) => Exit::Status(code),
+        (None, Some(signal)) => Exit::Signal(signal),
+        (None, None) => unreachable!(),
+    };
+    let run = Run {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        exit,
+    };
+    println!(" -> {:?} {:?} {:?}", run.exit, run.stdout, run.stderr);
+    run
+}
+
+// Checks what the program prints and the status it exits with, at every
+// optimization level.
+fn check_output(source_code: &str, expect_stdout: &str, expect_status: i32) {
+    for level in ["-O0", "-O1", "-O2"].iter() {
+        check_output_with(source_code, expect_stdout, expect_status, &[level]);
+    }
+}
+
+fn check_output_with(source_code: &str, expect_stdout: &str, expect_status: i32, options: &[&str]) {
+    let run = run_native(source_code, options);
+    assert_eq!(run.stdout, expect_stdout);
+    assert_eq!(run.stderr, "");
+    assert_eq!(run.exit, Exit::Status(expect_status));
+}
+
+// Checks that the program is killed by the signal at every optimization
+// level.
+fn check_signal(source_code: &str, signal: i32) {
+    for level in ["-O0", "-O1", "-O2"].iter() {
+        assert_eq!(run_native(source_code, &[level]).exit, Exit::Signal(signal));
+    }
+}
+
+// Builds an executable which runs on this machine, and returns the source
+// file.
+fn build_native(source_code: &str, output_file: &TestArtifact, options: &[&str]) -> TestArtifact {
+    let input_file = output_file.with_suffix(".rs");
+    fs::write(&input_file, source_code).unwrap();
+    println!("{} ({})", source_code, options.join(" "));
+
+    let mut args = vec!["compiler".to_string(),
+                        input_file.to_string(),
+                        "-o".to_string(),
+                        output_file.to_string()];
+    args.extend(options.iter().map(|opt| opt.to_string()));
+    // The programs run natively on AArch64 and RISC-V.
+    if !options.iter().any(|opt| opt.starts_with("--target")) {
+        if cfg!(target_arch = "aarch64") {
+            args.push("--target=aarch64".to_string());
+        } else if cfg!(target_arch = "riscv64") {
+            args.push("--target=riscv64".to_string());
+        }
+    }
+    assert_eq!(compiler_main(args), 0);
+    input_file
+}
+
+fn compile_asm(source_code: &str) -> String {
+    compile_asm_at(source_code, "-O0")
+}
+
+fn compile_asm_at(source_code: &str, level: &str) -> String {
+    compile_asm_with(source_code, &[level])
+}
+
+fn compile_asm_with(source_code: &str, options: &[&str]) -> String {
+    let input_file = TestArtifact::with_contents(".rs", source_code);
+    let output_file = TestArtifact::new(".s");
+    println!("{}", source_code);
+
+    let mut args = vec!["compiler".to_string(),
+                        input_file.to_string(),
+                        "-s".to_string(),
+                        "-o".to_string(),
+                        output_file.to_string()];
+    args.extend(options.iter().map(|opt| opt.to_string()));
+    assert_eq!(compiler_main(args), 0);
+
+    fs::read_to_string(&output_file).unwrap()
+}
+
+fn emit_ast_json(source_code: &str) -> serde_json::Value {
+    let input_file = TestArtifact::with_contents(".rs", source_code);
+    let output_file = TestArtifact::new(".json");
     println!("{}", source_code);
 
     let args = vec!["compiler".to_string(),
-                    input_file.clone(),
+                    input_file.to_string(),
+                    "--emit=ast-json".to_string(),
                     "-o".to_string(),
-                    output_file.clone()];
-    compiler_main(args);
+                    output_file.to_string()];
+    assert_eq!(compiler_main(args), 0);
 
-    let output = Command::new("bash")
-        .arg("-c")
-        .arg(format!("./tests/run.sh {}", output_file))
-        .output()
-        .unwrap();
-    let answer = String::from_utf8(output.stdout)
-        .unwrap()
-        .trim()
-        .parse()
-        .unwrap();
+    let json = fs::read_to_string(&output_file).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+fn emit_ir(source_code: &str, level: &str) -> String {
+    let input_file = TestArtifact::with_contents(".rs", source_code);
+    let output_file = TestArtifact::new(".ir");
+    println!("{}", source_code);
+
+    let args = vec!["compiler".to_string(),
+                    input_file.to_string(),
+                    "--emit=ir".to_string(),
+                    "-o".to_string(),
+                    output_file.to_string(),
+                    level.to_string()];
+    assert_eq!(compiler_main(args), 0);
 
-    fs::remove_file(&input_file).unwrap();
-    fs::remove_file(&output_file).unwrap();
-    println!(" -> {} (expected: {})", answer, expect);
-    assert_eq!(expect, answer);
+    fs::read_to_string(&output_file).unwrap()
 }
 
 #[test]
@@ -101,6 +224,32 @@ fn calc_bool() {
                           }\
                           return 3;\
                       }", 3);
+    // A bool holds 0 or 1 whatever is stored into it.
+    for x in 0..7 {
+        check_return_num(&format!("fn main() {{\
+                                       let x: i64 = {};\
+                                       let b: bool = x > 3;\
+                                       let c: bool = x * 256 + 2;\
+                                       let p: &bool = &c;\
+                                       *p = x - {};\
+                                       if (b == true) != (x > 3) {{ return 1; }}\
+                                       if c != true {{ return 2; }}\
+                                       if *p != true {{ return 3; }}\
+                                       return 0;\
+                                   }}", x, x + 256), 0);
+    }
+    // The same for a value passed as or returned as a bool.
+    check_return_num("fn id(b: bool) -> bool { return b; }\
+                      fn conv(x: i64) -> bool { return x; }\
+                      fn main() {\
+                          let x: i64 = 256;\
+                          if id(x) != true { return 1; }\
+                          if conv(x) != true { return 2; }\
+                          if id(512) != true { return 3; }\
+                          if conv(0) != false { return 4; }\
+                          if id(x - 256) != false { return 5; }\
+                          return 0;\
+                      }", 0);
 }
 
 #[test]
@@ -142,6 +291,11 @@ fn calc_local_variable() {
                           def = abc + 3;\
                           return def;\
                       }", 5);
+    check_return_num("fn main() {\
+                          let abc: i32 = 2;\
+                          let def: i32 = abc + 3;\
+                          return def;\
+                      }", 5);
 }
 
 #[test]
@@ -211,51 +365,236 @@ fn calc_type() {
                           }
                       }", 1);
     // To check upper bits are cleared.
-    check_return_num("static a: i8;\
+    check_return_num("static mut a: i8;\
                       fn main() {\
                           let b: i8;\
                           a = 1;\
                           b = 1;\
                           return a == b;\
                       }", 1);
-    check_return_num("static a: i16;\
+    check_return_num("static mut a: i16;\
                       fn main() {\
                           let b: i16;\
                           a = 1;\
                           b = 1;\
                           return a == b;\
                       }", 1);
+    // Unsigned values are zero extended.
+    check_return_num("fn main() {\
+                          let a: u8 = 200;\
+                          let b: i64 = a;\
+                          if b < 128 { return 1; }\
+                          return a / 2;\
+                      }", 100);
+    check_return_num("static mut a: u16;\
+                      fn main() {\
+                          a = 60000;\
+                          let b: &u16 = &a;\
+                          return a / 1000 + *b / 1000;\
+                      }", 120);
+    check_return_num("fn main() -> i64 {\
+                          let a: u32 = 4000000000;\
+                          return a / 100000000;\
+                      }", 40);
+    // Mixed signed and unsigned values of the same bits.
+    check_return_num("fn main() {\
+                          let a: i8 = -56;\
+                          let b: u8 = 200;\
+                          return a + b;\
+                      }", 144);
+}
+
+#[test]
+fn calc_widen_signed() {
+    // Negative values stay negative when they are widened into an i64, from
+    // variables, pointers, returns and 4 bytes divisions.
+    check_return_num("fn half(a: i32) -> i32 { return a / 2; }\
+                      fn neg(a: i8) -> i8 { return 0 - a; }\
+                      fn main() {\
+                          let x: i32 = 0 - 1;\
+                          let y: i64 = x;\
+                          let w: i8 = 0 - 5;\
+                          let b: i64 = w;\
+                          let h: i16 = 0 - 300;\
+                          let p: &i16 = &h;\
+                          let u: u32 = 4000000000;\
+                          let v: i64 = u;\
+                          if y != 0 - 1 { return 1; }\
+                          if b >= 0 { return 2; }\
+                          if *p + 0 > 0 { return 3; }\
+                          if v != 4000000000 { return 4; }\
+                          let q: i64 = half(0 - 6);\
+                          if q != 0 - 3 { return 5; }\
+                          let n: i64 = neg(7);\
+                          if n != 0 - 7 { return 6; }\
+                          let r: i64 = x / 1 + x % 2;\
+                          if r != 0 - 2 { return 7; }\
+                          return 0;\
+                      }", 0);
+}
+
+#[test]
+fn calc_unsigned_compare() {
+    let cases: [(&str, &[u64]); 3] = [
+        ("u8", &[0, 1, 127, 128, 255]),
+        ("u32", &[0, 127, 128, 255, 1 << 31, u32::MAX as u64]),
+        ("u64", &[0, 128, 1 << 31, (1 << 63) - 1, 1 << 63, u64::MAX]),
+    ];
+    for &(ty, values) in cases.iter() {
+        for &op in ["<", "<=", ">", ">="].iter() {
+            // Returns the number of the first wrong comparison.
+            let mut body = format!("let a: {}; let b: {};", ty, ty);
+            let mut count = 0;
+            for &a in values {
+                for &b in values {
+                    count += 1;
+                    let expect = match op {
+                        "<" => a < b,
+                        "<=" => a <= b,
+                        ">" => a > b,
+                        _ => a >= b,
+                    };
+                    body += &format!("a = {}; b = {};\
+                                      if (a {} b) != {} {{ return {}; }}",
+                                     a, b, op, expect, count);
+                }
+            }
+            check_return_num(&format!("fn main() {{ {} return 0; }}", body), 0);
+        }
+    }
+
+    // A literal takes the type of the other side.
+    check_return_num("fn main() {\
+                          let a: u8 = 200;\
+                          if a > 100 { return 1; }\
+                          return 0;\
+                      }", 1);
+    // Signed and unsigned operands are compared as unsigned.
+    check_return_num("fn main() {\
+                          let a: i8 = -1;\
+                          let b: u8 = 1;\
+                          if a < b { return 1; }\
+                          return 0;\
+                      }", 0);
+    // Pointers are compared as unsigned.
+    check_return_num("static g: [i32; 2];\
+                      fn main() {\
+                          let a: [i32; 2];\
+                          let p: &i32 = &a[0];\
+                          let q: &i32 = &a[1];\
+                          let r: &i32 = &g[0];\
+                          if p >= q { return 1; }\
+                          if q <= p { return 2; }\
+                          if (&g[0] < &g[1]) != true { return 3; }\
+                          if (r > p) == (r < p) { return 4; }\
+                          return 0;\
+                      }", 0);
+}
+
+#[test]
+fn calc_division() {
+    // Division rounds toward zero in the type of the operands.
+    for &ty in ["i8", "i16", "i32", "i64"].iter() {
+        check_return_num(&format!("fn main() {{\
+                                       let a: {} = 0 - 7;\
+                                       let b: {} = 2;\
+                                       let q: {} = a / b;\
+                                       let r: {} = a % b;\
+                                       let z: {} = b / a;\
+                                       if q != 0 - 3 {{ return 1; }}\
+                                       if r != 0 - 1 {{ return 2; }}\
+                                       if z != 0 {{ return 3; }}\
+                                       return 0;\
+                                   }}", ty, ty, ty, ty, ty), 0);
+    }
+    check_return_num("fn main() -> i64 {\
+                          let a: u64 = 18446744073709551615;\
+                          let b: u64 = 10;\
+                          if a / b != 1844674407370955161 { return 1; }\
+                          if a % 1000 != 615 { return 2; }\
+                          if a / 4294967296 != 4294967295 { return 3; }\
+                          return 0;\
+                      }", 0);
+    check_return_num("fn main() -> i64 {\
+                          let a: u32 = 4294967295;\
+                          let b: u32 = 2;\
+                          return a / b % 256 + a % b;\
+                      }", 0);
+    // The upper bits left by the arithmetic of narrow integers are
+    // ignored.
+    check_return_num("fn main() {\
+                          let a: i8 = 100;\
+                          let b: i16 = 30000;\
+                          let c: u8 = 200;\
+                          a = (a + a) / 2;\
+                          b = (b + b) / 2;\
+                          c = (c + c) / 4;\
+                          if a != 0 - 28 { return 1; }\
+                          if b != 0 - 2768 { return 2; }\
+                          if c != 36 { return 3; }\
+                          return 0;\
+                      }", 0);
+    // Operands of different widths are divided in the wider type.
+    check_return_num("fn main() {\
+                          let a: i8 = 1;\
+                          let b: i64 = 256;\
+                          let x: i32 = 100;\
+                          let y: i64 = 4294967298;\
+                          let h: i16 = 5;\
+                          let m: i64 = 65539;\
+                          let two: i64 = 2;\
+                          if a / b != 0 { return 1; }\
+                          if x / y != 0 { return 2; }\
+                          if h % m != 5 { return 3; }\
+                          if y / x != 42949672 { return 4; }\
+                          if m % h != 4 { return 5; }\
+                          let big: i32 = 100000;\
+                          if (big * big) / two != 705032704 { return 6; }\
+                          if (a - 2) % b != 0 - 1 { return 7; }\
+                          return 0;\
+                      }", 0);
+    // Overflow traps like division by zero.
+    check_signal("fn main() {\
+                      let a: i32 = 0 - 2147483648;\
+                      let b: i32 = 0 - 1;\
+                      return a / b;\
+                  }", SIGFPE);
+    check_signal("fn main() {\
+                      let a: i64 = 7;\
+                      let b: i64 = 0;\
+                      return a % b;\
+                  }", SIGFPE);
 }
 
 #[test]
 fn calc_global_variable() {
-    check_return_num("static a: i32;\
+    check_return_num("static mut a: i32;\
                       fn main() {\
                           a = 1;\
                           return a;\
                       }", 1);
-    check_return_num("static a: [i32; 10];\
+    check_return_num("static mut a: [i32; 10];\
                       fn main() {\
                           a[8] = 1;\
                           a[9] = 2;\
                           return a[8] + a[9];\
                       }", 3);
-    check_return_num("static a: i32;\
+    check_return_num("static mut a: i32;\
                       fn main() {\
                           let b: i32;\
                           a = 1;\
                           b = 2;\
                           return a + b;\
                       }", 3);
-    check_return_num("static a: [i32; 2];\
-                      static b: [i32; 2];\
+    check_return_num("static mut a: [i32; 2];\
+                      static mut b: [i32; 2];\
                       fn main() {\
                           a[1] = 1;\
                           b[0] = 2;\
                           return a[1] + b[0];\
                       }", 3);
-    check_return_num("static a: [i8; 4];\
-                      static b: i32;\
+    check_return_num("static mut a: [i8; 4];\
+                      static mut b: i32;\
                       fn main() {\
                           b = 2;\
                           a[3] = 1;\
@@ -263,6 +602,128 @@ fn calc_global_variable() {
                       }", 3);
 }
 
+#[test]
+fn calc_global_initializer() {
+    // The initializers are computed at compile time in each width, and the
+    // other statics stay zero.
+    let source = "static A: i8 = 0 - 100;\
+                  static B: i16 = 300 * 100;\
+                  static mut C: i32 = 4 * 1024;\
+                  static D: i64 = 0 - 5000000000;\
+                  static E: u8 = 255;\
+                  static F: u64 = 18446744073709551615;\
+                  static Z: i32;\
+                  fn main() {\
+                      if D != 0 - 5000000000 { return 1; }\
+                      if F != 0 - 1 { return 2; }\
+                      C = C + 1;\
+                      return A + B / 300 + C / 1024 + E - 255 + Z;\
+                  }";
+    check_return_num(source, 4);
+    check_return_num_with(source, 4, &["--integrated-as"]);
+    let asm = compile_asm(source);
+    for data in ["    .byte -100\n", "    .short 30000\n", "    .long 4096\n",
+                 "    .quad -5000000000\n", "    .byte 255\n", "    .quad -1\n"].iter() {
+        assert!(asm.contains(data), "{} is not in the data", data);
+    }
+    let sections: Vec<&str> = asm.lines()
+        .filter(|line| [".data", ".bss"].contains(line))
+        .collect();
+    assert_eq!(sections, [".data", ".bss"]);
+
+    // The operators fold as they would be computed at runtime.
+    check_return_num("static T: bool = (1 + 2) * 3 - 10 / 4 == 7;\
+                      static N: i32 = (0 - 7) / 2 * 10 + 17 % 5;\
+                      fn main() {\
+                          if T { return 0 - N; }\
+                          return 0;\
+                      }", 28);
+}
+
+#[test]
+fn calc_global_mut() {
+    check_return_num("static mut COUNT: i32 = 10;\
+                      static STEP: i32 = 3;\
+                      fn main() {\
+                          let i: i32 = 0;\
+                          while i < 5 {\
+                              COUNT = COUNT + STEP;\
+                              i = i + 1;\
+                          }\
+                          return COUNT;\
+                      }", 25);
+    // A static which is not `mut` is read-only data, which cannot be
+    // written even through a pointer.
+    check_signal("static LIMIT: i32 = 4096;\
+                  fn main() {\
+                      let p: &i32 = &LIMIT;\
+                      *p = 1;\
+                      return LIMIT;\
+                  }", SIGSEGV);
+}
+
+#[test]
+fn calc_global_reference() {
+    // Globals and their elements are read and written through pointers,
+    // in the widths of their types.
+    check_return_num("static mut A: i8;\
+                      static mut B: i32;\
+                      static mut C: i64;\
+                      static mut D: [i32; 4];\
+                      fn main() {\
+                          let pa: &i8 = &A;\
+                          let pb: &i32 = &B;\
+                          let pc: &i64 = &C;\
+                          let pd: &i32 = &D[2];\
+                          D[1] = 7;\
+                          D[3] = 9;\
+                          *pa = 0 - 2;\
+                          *pb = 100000;\
+                          *pc = 5000000000;\
+                          *pd = 0 - 5;\
+                          if A != 0 - 2 { return 1; }\
+                          if B != 100000 { return 2; }\
+                          if C != 5000000000 { return 3; }\
+                          if D[2] != 0 - 5 { return 4; }\
+                          if D[1] != 7 { return 5; }\
+                          if D[3] != 9 { return 6; }\
+                          if *pa != 0 - 2 { return 7; }\
+                          if *pb != 100000 { return 8; }\
+                          if *pc != 5000000000 { return 9; }\
+                          if *pd != 0 - 5 { return 10; }\
+                          return 0;\
+                      }", 0);
+    // The pointers are passed to functions which read and write through
+    // them.
+    check_return_num("static mut A: i8;\
+                      static mut B: i32;\
+                      static mut C: i64;\
+                      static mut D: [i32; 4];\
+                      fn set8(p: &i8, v: i8) { *p = v; return 0; }\
+                      fn set32(p: &i32, v: i32) { *p = v; return 0; }\
+                      fn set64(p: &i64, v: i64) { *p = v; return 0; }\
+                      fn get8(p: &i8) -> i8 { return *p; }\
+                      fn get32(p: &i32) -> i32 { return *p; }\
+                      fn get64(p: &i64) -> i64 { return *p; }\
+                      fn main() {\
+                          D[1] = 7;\
+                          set8(&A, 0 - 3);\
+                          set32(&B, 0 - 70000);\
+                          set64(&C, 0 - 6000000000);\
+                          set32(&D[0], 11);\
+                          if A != 0 - 3 { return 1; }\
+                          if B != 0 - 70000 { return 2; }\
+                          if C != 0 - 6000000000 { return 3; }\
+                          if D[0] != 11 { return 4; }\
+                          if D[1] != 7 { return 5; }\
+                          if get8(&A) != 0 - 3 { return 6; }\
+                          if get32(&B) != 0 - 70000 { return 7; }\
+                          if get64(&C) != 0 - 6000000000 { return 8; }\
+                          if get32(&D[1]) != 7 { return 9; }\
+                          return 0;\
+                      }", 0);
+}
+
 #[test]
 fn calc_control() {
     check_return_num("fn main() {\
@@ -324,13 +785,13 @@ fn calc_control() {
 
 #[test]
 fn calc_func() {
-    check_return_num("fn foo() {\
+    check_return_num("fn foo() -> i32 {\
                           return 3;\
                       }\
                       fn main() {\
                           return foo();\
                       }", 3);
-    check_return_num("fn foo() {\
+    check_return_num("fn foo() -> i32 {\
                           let c: i32;\
                           let d: i32;\
                           c = 3;\
@@ -344,7 +805,7 @@ fn calc_func() {
                           b = 2;\
                           return a + b + foo();\
                       }", 10);
-    check_return_num("fn foo() {\
+    check_return_num("fn foo() -> i32 {\
                           let a: i32;\
                           let b: i32;\
                           a = 3;\
@@ -358,13 +819,13 @@ fn calc_func() {
                           b = 2;\
                           return a + b + foo();\
                       }", 10);
-    check_return_num("fn foo(a: i32) {\
+    check_return_num("fn foo(a: i32) -> i32 {\
                           return a * 2;\
                       }\
                       fn main() {\
                           return foo(2);\
                       }", 4);
-    check_return_num("fn foo(a: i32, b: i32, c: i32, d: i32, e: i32, f: i32) {\
+    check_return_num("fn foo(a: i32, b: i32, c: i32, d: i32, e: i32, f: i32) -> i32 {\
                           return (a + b + c + d + e + f) * 2;\
                       }\
                       fn main() {\
@@ -377,6 +838,119 @@ fn calc_func() {
                       fn main() {\
                           return foo(4 + 3, 2 + 1);\
                       }", 10);
+    // A variable can have the same name as a function.
+    check_return_num("fn foo() -> i32 {\
+                          return 3;\
+                      }\
+                      fn main() {\
+                          let foo: i32;\
+                          foo = 2;\
+                          return foo * foo();\
+                      }", 6);
+    // Return values are truncated to the return type, and compared
+    // with variables of the type.
+    check_return_num("fn f() -> i8 {\
+                          return 300;\
+                      }\
+                      fn g() -> i16 {\
+                          return 0 - 65537;\
+                      }\
+                      fn h() -> u16 {\
+                          return 0 - 1;\
+                      }\
+                      fn k() -> i32 {\
+                          let a: i32 = 2147483647;\
+                          return a + 1;\
+                      }\
+                      fn main() {\
+                          let a: i8 = 44;\
+                          let b: i16 = 0 - 1;\
+                          let c: u16 = 65535;\
+                          let d: i32 = 0 - 2147483647 - 1;\
+                          if f() != a { return 1; }\
+                          if g() != b { return 2; }\
+                          if h() != c { return 3; }\
+                          if k() != d { return 4; }\
+                          if f() + 1 != 45 { return 5; }\
+                          return 0;\
+                      }", 0);
+    // A function without a return type is called as a statement, and
+    // leaves nothing on the stack.
+    check_return_num("static mut N: i64;\
+                      fn count(a: i64) {\
+                          N = N + a;\
+                      }\
+                      fn main() {\
+                          let i: i64 = 0;\
+                          while i < 1000000 {\
+                              count(i % 3);\
+                              i = i + 1;\
+                          }\
+                          return N / 10000;\
+                      }", 99);
+    // Reaching the end of `main` exits with 0.
+    check_return_num("fn foo() {\
+                          let _a: i64 = 3;\
+                      }\
+                      fn main() {\
+                          foo();\
+                      }", 0);
+    // A slice returned from a function is bound to a variable.
+    check_output("fn write(fd: i32, s: &str) -> i64;\
+                  fn name(n: i64) -> &str {\
+                      if n == 1 { return \"one\"; }\
+                      return \"three\";\
+                  }\
+                  fn main() -> i64 {\
+                      let s: &str = name(1);\
+                      let t: &str = name(3);\
+                      name(2);\
+                      return write(1, s) * 10 + write(1, t);\
+                  }", "onethree", 35);
+    // The exit status is the lower 8 bits of the return value of `main`.
+    check_return_num("fn main() {\
+                          return 256;\
+                      }", 0);
+    check_return_num("fn main() -> i64 {\
+                          return 257;\
+                      }", 1);
+}
+
+#[test]
+fn calc_nested_call() {
+    // A call in each argument position must not break the arguments
+    // evaluated before it.
+    let funcs = "fn id(a: i64) -> i64 {\
+                     return a;\
+                 }\
+                 fn sub(a: i64, b: i64) -> i64 {\
+                     return a - b;\
+                 }\
+                 fn foo(a: i64, b: i64, c: i64, d: i64, e: i64, f: i64) -> i64 {\
+                     return a * 32 + b * 16 + c * 8 + d * 4 + e * 2 + f;\
+                 }";
+    for pos in 0..6 {
+        let args: Vec<&str> = (0..6).map(|i| if i == pos { "id(1)" } else { "0" }).collect();
+        let source = format!("{}fn main() -> i64 {{ return foo({}); }}", funcs, args.join(", "));
+        check_return_num(&source, 1 << (5 - pos));
+    }
+    check_return_num(&format!("{}fn main() -> i64 {{\
+                                   return foo(id(1), sub(3, 2), 1, id(1), sub(id(5), 4), 1);\
+                               }}", funcs), 63);
+    check_return_num(&format!("{}fn main() -> i64 {{\
+                                   return foo(sub(id(3), sub(4, id(2))), 0, sub(sub(9, 5), id(3)), 0, 0, id(id(1)));\
+                               }}", funcs), 41);
+    // A slice takes two registers among the others.
+    check_return_num("fn id(a: i64) -> i64 {\
+                          return a;\
+                      }\
+                      fn foo(a: i64, s: &str, b: i64, t: &str) -> i64 {\
+                          return a * 10 + b;\
+                      }\
+                      fn main() -> i64 {\
+                          let s: &str = \"ab\";\
+                          return foo(id(4), s, id(id(2)), s);\
+                      }", 42);
 }
 
 #[test]
@@ -388,7 +962,7 @@ fn calc_reference() {
                           b = &a;\
                           return *b;\
                       }", 2);
-    check_return_num("fn foo() {\
+    check_return_num("fn foo() -> i32 {\
                           let a: i32;\
                           let b: &i32;\
                           b = &a;\
@@ -398,6 +972,37 @@ fn calc_reference() {
                       fn main() {\
                           return foo();\
                       }", 3);
+    // Only the pointee is read, not the variables next to it.
+    check_return_num("fn main() {\
+                          let a: [i8; 8];\
+                          let b: [i16; 4];\
+                          let c: [i32; 2];\
+                          a[0] = 1; a[1] = 1; a[2] = 1; a[3] = 1;\
+                          a[4] = 1; a[5] = 1; a[6] = 1; a[7] = 1;\
+                          b[0] = 2; b[1] = 1; b[2] = 1; b[3] = 1;\
+                          c[0] = 3; c[1] = 1;\
+                          let p: &i8 = &a[0];\
+                          let q: &i16 = &b[0];\
+                          let r: &i32 = &c[0];\
+                          let x: i64 = *p;\
+                          let y: i64 = *q;\
+                          let z: i64 = *r;\
+                          return x / 256 + y / 65536 + z / 4294967296 + x + y + z;\
+                      }", 6);
+    check_return_num("fn get8(p: &i8) -> i8 {\
+                          return *p;\
+                      }\
+                      fn get16(p: &i16) -> i16 {\
+                          return *p;\
+                      }\
+                      fn main() -> i64 {\
+                          let a: [i8; 2];\
+                          let b: [i16; 2];\
+                          a[0] = 7; a[1] = 1;\
+                          b[0] = 30; b[1] = 1;\
+                          let x: i64 = get8(&a[0]) + get16(&b[0]);\
+                          return x / 256 + x;\
+                      }", 37);
 }
 
 #[test]
@@ -409,7 +1014,7 @@ fn calc_array() {
                           a[2] = 3;\
                           return a[0] + a[1] + a[2];\
                       }", 6);
-    check_return_num("fn foo() {\
+    check_return_num("fn foo() -> i32 {\
                           let a: [i32; 4];\
                           a[2] = 3;\
                           return a[2];\
@@ -426,6 +1031,168 @@ fn calc_array() {
                       }", 5);
 }
 
+#[test]
+fn calc_compare_order() {
+    // The left operand is evaluated first whatever the operator is.
+    let funcs = "static mut order: i32;\
+                 fn left(v: i32) -> i32 {\
+                     order = order * 10 + 1;\
+                     return v;\
+                 }\
+                 fn right(v: i32) -> i32 {\
+                     order = order * 10 + 2;\
+                     return v;\
+                 }";
+    for &(op, expect) in [("<", 0), ("<=", 0), (">", 1), (">=", 1)].iter() {
+        let source = format!("{}fn main() -> i32 {{\
+                                  let c: bool = left(3) {} right(2);\
+                                  if c {{ return order + 100; }}\
+                                  return order;\
+                              }}", funcs, op);
+        check_return_num(&source, 12 + expect * 100);
+    }
+}
+
+#[test]
+fn calc_compare_width() {
+    // Operands are compared in the bytes of the wider one with a type, so
+    // a result of arithmetic wraps around as it does when it is stored.
+    check_return_num("fn main() -> i32 {\
+                          let a: i32 = 2147483647;\
+                          if a + 1 > a { return 1; }\
+                          if 0 < a + 1 { return 2; }\
+                          if 4294967296 + a != a { return 3; }\
+                          let b: i8 = 100;\
+                          if b * 2 != 0 - 56 { return 4; }\
+                          return 5;\
+                      }", 5);
+    // The narrower operand is extended, and never truncates the other.
+    check_return_num("fn main() -> i32 {\
+                          let a: i32 = 1;\
+                          let b: i64 = 4294967297;\
+                          let c: i16 = 0;\
+                          let d: i64 = 65536;\
+                          let e: i8 = 1;\
+                          let f: i64 = 256;\
+                          if a == b { return 1; }\
+                          if c == d { return 2; }\
+                          if e >= f { return 3; }\
+                          if f <= e { return 4; }\
+                          let x: i32 = 2147483647;\
+                          if x + 1 != 0 - 2147483648 + b - b { return 5; }\
+                          let y: i64 = x + 1;\
+                          if y > 0 { return 6; }\
+                          return 7;\
+                      }", 7);
+}
+
+#[test]
+fn calc_discard() {
+    check_return_num("static mut count: i32;\
+                      fn side_effect() -> i32 {\
+                          count = count + 1;\
+                          return 5;\
+                      }\
+                      fn second(_: i32, b: i32) -> i32 {\
+                          return b;\
+                      }\
+                      fn main() {\
+                          let _: i32 = side_effect();\
+                          _ = side_effect();\
+                          _ = 1 + 2;\
+                          let _: i32;\
+                          return count + second(7, 3);\
+                      }", 5);
+    // The values of expression statements do not pile up on the stack.
+    check_return_num("fn main() {\
+                          let s: &str = \"ab\";\
+                          let i: i32 = 0;\
+                          let n: i32 = 0;\
+                          while i < 1000 {\
+                              let j: i32 = 0;\
+                              while j < 1000 {\
+                                  j + 1;\
+                                  s;\
+                                  \"cd\";\
+                                  j = j + 1;\
+                              }\
+                              if i < 10 { n + 1; *&n; n = n + 1; }\
+                              i = i + 1;\
+                          }\
+                          return n;\
+                      }", 10);
+
+    // `_` has no slot.
+    let asm = compile_asm("fn main() {\
+                               let _: i64 = 1;\
+                               let _: [i32; 4];\
+                               return 1;\
+                           }");
+    assert!(asm.contains("    sub rsp, 0\n"));
+}
+
+#[test]
+fn calc_many_locals() {
+    let source = "fn main() {\
+                      let a: i8;\
+                      let b: i64;\
+                      let c: i16;\
+                      let d: i32;\
+                      let e: u8;\
+                      let f: [i32; 3];\
+                      let g: &str;\
+                      let h: i32;\
+                      a = 1; b = 2; c = 3; d = 4; e = 5;\
+                      f[0] = 6; f[1] = 7; f[2] = 8;\
+                      g = \"xyz\";\
+                      h = 9;\
+                      return a + b + c + d + e + f[0] + f[1] + f[2] + h;\
+                  }";
+    check_return_num(source, 45);
+
+    // Each local is aligned to its type and the frame to 16 bytes.
+    let asm = compile_asm(source);
+    assert!(asm.contains("    sub rsp, 64\n"));
+}
+
+#[test]
+fn calc_block_scope() {
+    let source = "fn main() {\
+                      let x: i32;\
+                      x = 1;\
+                      if x == 1 {\
+                          let a: [i8; 128];\
+                          a[127] = 3;\
+                          x = x + a[127];\
+                      } else {\
+                          let b: [i8; 128];\
+                          b[0] = 5;\
+                          x = x + b[0];\
+                      }\
+                      if x == 4 {\
+                          let c: [i8; 128];\
+                          c[0] = 2;\
+                          x = x + c[0];\
+                      }\
+                      return x;\
+                  }";
+    check_return_num(source, 6);
+
+    // Arrays in disjoint blocks share their slots.
+    let asm = compile_asm(source);
+    assert!(asm.contains("    sub rsp, 144\n"));
+
+    check_return_num("fn main() {\
+                          let a: i32;\
+                          a = 1;\
+                          if a == 1 {\
+                              let a: i32;\
+                              a = 5;\
+                          }\
+                          return a;\
+                      }", 1);
+}
+
 #[test]
 fn check_comment() {
     check_return_num("fn main() {\
@@ -447,6 +1214,55 @@ fn check_comment() {
                           /**/\
                           return 1;\
                       }", 1);
+    check_return_num("fn main() {\
+                          /* * / */\
+                          /*/ return 2; */\
+                          /***/\
+                          return 1;\
+                      } /* End of file */", 1);
+}
+
+#[test]
+fn check_doc_comment() {
+    let source = "/// Counter.\n\
+                  static mut c: i32;\n\
+                  /// Entry point.\n\
+                  /// Returns three.\n\
+                  fn main() {\n\
+                      /** Not attached. */\n\
+                      c = 3;\n\
+                      return c;\n\
+                  }\n";
+    let plain = "static mut c: i32;\n\
+                 fn main() {\n\
+                     c = 3;\n\
+                     return c;\n\
+                 }\n";
+    check_return_num(source, 3);
+    check_return_num("/** Entry point. */fn main() { return 3; }", 3);
+    // Only the line numbers in the comments of `-s` differ.
+    assert_eq!(compile_str(source).unwrap(), compile_str(plain).unwrap());
+
+    let ast = emit_ast_json(source);
+    let items = ast["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["kind"], "DeclareGlobal");
+    assert_eq!(items[0]["doc"], " Counter.");
+    assert_eq!(items[1]["kind"], "Function");
+    assert_eq!(items[1]["name"], "main");
+    assert_eq!(items[1]["doc"], " Entry point.\n Returns three.");
+    assert_eq!(items[1]["block"]["nodes"][1]["kind"], "Return");
+
+    let ast = emit_ast_json(plain);
+    assert!(ast["items"][1]["doc"].is_null());
+}
+
+#[test]
+fn check_shebang() {
+    check_return_num("#!/usr/bin/env yrc-run\n\
+                      fn main() {\
+                          return 3;\
+                      }", 3);
 }
 
 #[test]
@@ -457,4 +1273,1053 @@ fn check_format() {
     check_return_num("fn main() { return (1+2)+3; }", 6);
     check_return_num("fn main() { return 1+(2+3); }", 6);
     check_return_num("fn main() { return (1+2+3); }", 6);
+    check_return_num("fn main() { return 7<=8==1; }", 1);
+    check_return_num("fn main() { return 7>=8!=1; }", 1);
+    check_return_num("fn main() { let a: i32; a=7; return a>=7; }", 1);
+}
+
+#[test]
+fn calc_large_number() {
+    check_return_num("fn main() {\
+                          let a: u32;\
+                          a = 4294967295;\
+                          return a == 4294967295;\
+                      }", 1);
+    check_return_num("fn main() {\
+                          let a: u64;\
+                          a = 18446744073709551615;\
+                          return a == 18446744073709551615;\
+                      }", 1);
+    check_return_num("fn main() {\
+                          let a: i64;\
+                          a = 4294967296;\
+                          return a / 1073741824;\
+                      }", 4);
+}
+
+#[test]
+fn check_string() {
+    check_return_num("fn main() {\
+                          let s: &str;\
+                          s = \"abc\";\
+                          return 1;\
+                      }", 1);
+    check_return_num("fn main() {\
+                          let s: &str;\
+                          s = \"multiple\n\
+                               line\";\
+                          return 1;\
+                      }", 1);
+}
+
+#[test]
+fn check_empty_string() {
+    let source = "fn foo(_s: &str) -> i32 {\
+                      return 3;\
+                  }\
+                  fn main() {\
+                      let s: &str;\
+                      s = \"\";\
+                      return foo(\"\");\
+                  }";
+    check_return_num(source, 3);
+
+    let asm = compile_asm(source);
+    assert_eq!(asm.matches("    .ascii \"\"\n").count(), 2);
+    assert_eq!(asm.matches("    lea rax, QWORD PTR .LC0[rip]\n\
+                                \x20   push rax\n\
+                                \x20   push 0\n").count(), 1);
+}
+
+#[test]
+fn check_escaped_string() {
+    let source = "fn main() {\
+                      let s: &str;\
+                      s = \"he said \\\"hi\\\"\";\
+                      s = \"\\\\\\n\\t\\0\";\
+                      return 1;\
+                  }";
+    check_return_num(source, 1);
+
+    let asm = compile_asm(source);
+    assert!(asm.contains("    .ascii \"he said \\\"hi\\\"\"\n"));
+    assert!(asm.contains("    .ascii \"\\\\\\n\\t\\000\"\n"));
+    assert!(asm.contains("    push 12\n"));
+    assert!(asm.contains("    push 4\n"));
+
+    // The lengths are the bytes of the literals after the escapes, as
+    // `write` returns them.
+    check_output("fn write(fd: i32, s: &str) -> i64;\
+                  fn main() -> i64 {\
+                      let s: &str = \"a\\\"b\\\\c\\nd\\0e\";\
+                      return write(1, s) * 10 + write(1, \"\\\"\\\"\\0\");\
+                  }", "a\"b\\c\nd\0e\"\"\0", 93);
+}
+
+#[test]
+fn check_slice_arguments() {
+    // A literal, a slice local and a slice returned by a call are passed
+    // alike, in whichever position they take.
+    check_output("fn write(fd: i32, s: &str) -> i64;\
+                  fn make(n: i64) -> &str {\
+                      if n == 1 { return \"one\"; }\
+                      return \"three\";\
+                  }\
+                  fn take(a: i64, s: &str, b: i64, t: &str) -> i64 {\
+                      return a * 1000 + write(1, s) * 100 + b * 10 + write(1, t);\
+                  }\
+                  fn main() -> i64 {\
+                      let s: &str = \"four\";\
+                      if take(1, make(1), 2, make(3)) != 1325 { return 1; }\
+                      if take(3, \"ab\", 4, s) != 3244 { return 2; }\
+                      if take(5, s, 6, make(1)) != 5463 { return 3; }\
+                      if take(7, make(3), 8, \"x\") != 7581 { return 4; }\
+                      return write(1, make(1));\
+                  }", "onethreeabfourfouronethreexone", 3);
+}
+
+// Compiles the source into an object at the level.
+fn compile_object(source_code: &str, object_file: &TestArtifact, level: &str) {
+    let input_file = object_file.with_suffix(".rs");
+    fs::write(&input_file, source_code).unwrap();
+    let args = vec!["compiler".to_string(),
+                    input_file.to_string(),
+                    "-c".to_string(),
+                    level.to_string(),
+                    "-o".to_string(),
+                    object_file.to_string()];
+    assert_eq!(compiler_main(args), 0);
+}
+
+#[test]
+fn check_slice_return_abi() {
+    if !cfg!(target_arch = "x86_64") {
+        return;
+    }
+    // A slice is returned as a struct of the pointer and the length, the
+    // same at every level, so that the objects and C code can be linked.
+    let callee = "pub fn pick(n: i64) -> &str {\n\
+                      if n == 1 { return \"one\"; }\n\
+                      return \"three\";\n\
+                  }\n";
+    let caller = "fn write(fd: i32, s: &str) -> i64;\n\
+                  fn pick(n: i64) -> &str;\n\
+                  fn main() -> i64 {\n\
+                      return write(1, pick(1)) + write(1, pick(3));\n\
+                  }\n";
+    let c_caller = "#include <stdio.h>\n\
+                    struct str { const char *ptr; long len; };\n\
+                    struct str pick(long n);\n\
+                    int main(void) {\n\
+                        struct str s = pick(3);\n\
+                        return printf(\"%.*s\", (int)s.len, s.ptr);\n\
+                    }\n";
+    for (callee_level, caller_level) in [("-O0", "-O1"), ("-O1", "-O0"), ("-O2", "-O0")].iter() {
+        let output_file = TestArtifact::new("");
+        let callee_file = output_file.with_suffix("-callee.o");
+        let caller_file = output_file.with_suffix("-caller.o");
+        compile_object(callee, &callee_file, callee_level);
+        compile_object(caller, &caller_file, caller_level);
+        let status = Command::new("cc")
+            .args([callee_file.path(), caller_file.path(), "-o", output_file.path()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(output_file.path()).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "onethree");
+        assert_eq!(output.status.code(), Some(8));
+
+        let c_file = output_file.with_suffix(".c");
+        fs::write(&c_file, c_caller).unwrap();
+        let status = Command::new("cc")
+            .args([callee_file.path(), c_file.path(), "-o", output_file.path()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(output_file.path()).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "three");
+    }
+}
+
+#[test]
+fn check_raw_string() {
+    let source = "fn main() {\
+                      let s: &str;\
+                      s = r#\"C:\\dir \"q\"\"#;\
+                      let r: i32;\
+                      r = 1;\
+                      return r;\
+                  }";
+    check_return_num(source, 1);
+
+    // No escape is processed in a raw string.
+    let asm = compile_asm(source);
+    assert!(asm.contains("    .ascii \"C:\\\\dir \\\"q\\\"\"\n"));
+    assert!(asm.contains("    push 10\n"));
+}
+
+#[test]
+fn check_c_string() {
+    let source = "fn puts(s: &i8) -> i32;\
+                  fn main() {\
+                      let p: &i8 = c\"hello\\tworld\";\
+                      puts(p);\
+                      puts(c\"\\\"bye\\\"\");\
+                      return 0;\
+                  }";
+    check_output(source, "hello\tworld\n\"bye\"\n", 0);
+
+    // The bytes end with the NUL, and no length is pushed.
+    let asm = compile_asm(source);
+    assert!(asm.contains("    .ascii \"hello\\tworld\\000\"\n"));
+    assert!(!asm.contains("    push 12\n"));
+}
+
+#[test]
+fn check_write_str() {
+    // The pointer and the length of a slice are the buffer and the count
+    // of write(2).
+    let source = "fn write(fd: i32, s: &str) -> i64;\n\
+                  fn greet(name: &str) -> i64 {\n\
+                      return write(1, \"hello, \") + write(1, name) + write(1, \"!\\n\");\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let n: i64 = greet(\"world\");\n\
+                      write(2, \"\");\n\
+                      return n;\n\
+                  }\n";
+    check_output(source, "hello, world!\n", 14);
+}
+
+#[test]
+fn check_printf() {
+    let source = "fn printf(format: &i8, n: i64) -> i32;\n\
+                  fn fact(n: i64) -> i64 {\n\
+                      if n == 0 { return 1; } else { return n * fact(n - 1); }\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let i: i64 = 0;\n\
+                      while i < 5 {\n\
+                          printf(c\"%ld! = \", i);\n\
+                          printf(c\"%ld\\n\", fact(i));\n\
+                          i = i + 1;\n\
+                      }\n\
+                      return 3;\n\
+                  }\n";
+    check_output(source, "0! = 1\n1! = 1\n2! = 2\n3! = 6\n4! = 24\n", 3);
+
+    // al is cleared for the declared functions, which may be variadic, and
+    // left alone for the others.
+    for level in ["-O0", "-O1", "-O2"].iter() {
+        let asm = compile_asm_at(source, level);
+        assert_eq!(asm.matches("    mov eax, 0\n    call printf@PLT\n").count(), 2);
+        assert!(!asm.contains("    mov eax, 0\n    call fact@PLT\n"));
+    }
+}
+
+#[test]
+fn check_utf8() {
+    let source = "fn main() {\n\
+                      // コメント\n\
+                      /* héllo */\n\
+                      let s: &str;\n\
+                      s = \"héllo\";\n\
+                      return 1;\n\
+                  }\n";
+    check_return_num(source, 1);
+
+    // The length of a slice is the number of bytes.
+    let asm = compile_asm(source);
+    assert!(asm.contains("    .ascii \"h\\303\\251llo\"\n"));
+    assert!(asm.contains("    push 6\n"));
+}
+
+#[test]
+fn check_reproducible() {
+    let source = "static mut G: i32;\n\
+                  fn foo(a: i32) -> i32 {\n\
+                      let s: &str = \"foo\";\n\
+                      if a > 1 { return a; }\n\
+                      return 0;\n\
+                  }\n\
+                  fn main() {\n\
+                      let b: &str = \"bar\";\n\
+                      let c: &str = \"baz\";\n\
+                      while G < 3 { G = G + 1; }\n\
+                      return foo(G);\n\
+                  }\n";
+    let asm = compile_str(source).unwrap();
+    assert_eq!(asm, compile_str(source).unwrap());
+
+    // Literals are labeled in the order of appearance.
+    let foo = asm.find(".LC0:\n    .ascii \"foo\"\n").unwrap();
+    let bar = asm.find(".LC1:\n    .ascii \"bar\"\n").unwrap();
+    let baz = asm.find(".LC2:\n    .ascii \"baz\"\n").unwrap();
+    assert!(foo < bar && bar < baz);
+
+    // The temporary file names do not leak into the executable.
+    let input_file = TestArtifact::with_contents(".rs", source);
+    let mut binaries = Vec::new();
+    for _ in 0..2 {
+        let output_file = TestArtifact::new("");
+        let args = vec!["compiler".to_string(),
+                        input_file.to_string(),
+                        "-o".to_string(),
+                        output_file.to_string()];
+        assert_eq!(compiler_main(args), 0);
+        binaries.push(fs::read(&output_file).unwrap());
+    }
+    assert!(binaries[0] == binaries[1]);
+}
+
+#[test]
+fn check_section_layout() {
+    // Each section appears once whatever order the items are in.
+    let globals_first = "static a: i32; fn foo() -> i32 { return a; }\
+                         static b: [u8; 3]; fn main() { let _s: &str = \"x\"; return foo(); }";
+    let functions_first = "fn foo() -> i32 { return 0; } static a: i32;\
+                           fn main() { let _s: &str = \"x\"; return foo() + a; } static b: [u8; 3];";
+    for source in [globals_first, functions_first].iter() {
+        check_return_num(source, 0);
+        for level in ["-O0", "-O1"].iter() {
+            let asm = compile_asm_at(source, level);
+            let sections: Vec<&str> = asm.lines()
+                .filter(|line| [".section", ".bss", ".data", ".text"].iter().any(|s| line.starts_with(s)))
+                .collect();
+            assert_eq!(sections, [".section .rodata", ".bss", ".text",
+                                  ".section .note.GNU-stack,\"\",@progbits"]);
+            let labels: Vec<&str> = asm.lines()
+                .filter(|line| ["a:", "b:", "foo:", "main:", ".LC0:"].contains(line))
+                .collect();
+            assert_eq!(labels, [".LC0:", "a:", "b:", "foo:", "main:"]);
+        }
+    }
+}
+
+#[test]
+fn check_function_labels() {
+    // The same bodies have the same labels but for the function names.
+    let body = "{ while a > 3 { a = a - 1; } if a == 2 { return 1; } else { return 0; } }";
+    let source = format!("fn foo(a: i64) -> i64 {}\
+                          fn bar(a: i64) -> i64 {}\
+                          fn main() {{ return foo(5) + bar(2) * 2; }}", body, body);
+    check_return_num(&source, 2);
+    for level in ["-O0", "-O1"].iter() {
+        let asm = compile_asm_at(&source, level);
+        let labels: Vec<&str> = asm.lines()
+            .filter(|line| line.starts_with(".L") && line.ends_with(':'))
+            .collect();
+        let mut unique = labels.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), labels.len());
+        let (foo, bar): (Vec<&str>, Vec<&str>) = labels.iter()
+            .filter(|label| !label.starts_with(".LC"))
+            .partition(|label| label.starts_with(".Lfoo_"));
+        assert!(!foo.is_empty());
+        assert_eq!(foo.iter().map(|label| &label[5..]).collect::<Vec<_>>(),
+                   bar.iter().map(|label| &label[5..]).collect::<Vec<_>>());
+    }
+    assert!(compile_asm(&source).contains(".Lbar_end0:\n"));
+}
+
+#[test]
+fn check_asm_in_memory() {
+    let source = "fn main() {\n    let s: &str = \"hi\";\n    return 7;\n}\n";
+    let mut interner = Interner::new();
+    let mut tokens = Tokens::new(tokenize(source, &mut interner).unwrap());
+    let mut parser = Parser::new(&interner);
+    let ast = parser.program(&mut tokens).unwrap();
+    let literals = parser.literals();
+
+    let mut out: Vec<u8> = Vec::new();
+    AsmGenerator::new(&ast, &interner, CodegenOptions::default()).gen_asm(&mut out, literals).unwrap();
+    let asm = String::from_utf8(out).unwrap();
+    assert!(asm.starts_with(".intel_syntax noprefix\n"));
+    assert!(asm.contains(".LC0:\n    .ascii \"hi\"\n"));
+    assert!(asm.contains("main:\n"));
+    assert!(asm.contains("    push 7\n"));
+
+    let mut generator = AsmGenerator::new(&ast, &interner, CodegenOptions::default());
+    assert_eq!(generator.gen_asm_to_string(literals).unwrap(), asm);
+    assert_eq!(compile_str(source).unwrap(), asm);
+}
+
+// Takes nothing, as a full disk.
+struct FullWriter;
+
+impl Write for FullWriter {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn check_asm_write_error() {
+    let source = "fn main() {\n    return 7;\n}\n";
+    let mut interner = Interner::new();
+    let mut tokens = Tokens::new(tokenize(source, &mut interner).unwrap());
+    let mut parser = Parser::new(&interner);
+    let ast = parser.program(&mut tokens).unwrap();
+    let literals = parser.literals();
+
+    // The whole output fits in the buffer, so the write fails only when
+    // the generator flushes it.
+    let mut out = BufWriter::with_capacity(1 << 20, FullWriter);
+    let mut generator = AsmGenerator::new(&ast, &interner, CodegenOptions::default());
+    let e = generator.gen_asm(&mut out, literals).unwrap_err();
+    assert_eq!(e.code(), "E0201");
+}
+
+#[test]
+fn check_peephole() {
+    let source = "static mut G: i32;\n\
+                  fn add(a: i32, b: i32) -> i32 {\n\
+                      return a + b;\n\
+                  }\n\
+                  fn same(s: &str) -> &str {\n\
+                      return s;\n\
+                  }\n\
+                  fn main() {\n\
+                      let x: i32 = 3;\n\
+                      let s: &str = \"abc\";\n\
+                      s = same(s);\n\
+                      G = add(x, 4);\n\
+                      while x < 5 { x = x + 1; }\n\
+                      return G + x;\n\
+                  }\n";
+    // Blank lines do not separate instructions.
+    let lines = |asm: String| -> Vec<String> {
+        asm.lines().filter(|l| !l.is_empty()).map(|l| l.trim().to_string()).collect()
+    };
+    let plain = lines(compile_asm_at(source, "-O0"));
+    let optimized = lines(compile_asm_at(source, "-O1"));
+    assert!(optimized.len() < plain.len());
+    for pair in optimized.windows(2) {
+        let (first, second) = (&pair[0], &pair[1]);
+        assert!(!(first.starts_with("push ") && second.starts_with("pop ")), "{:?}", pair);
+        assert!(!(first == "mov rax, rbp" && second.starts_with("sub rax, ")), "{:?}", pair);
+    }
+    check_return_num(source, 12);
+}
+
+#[test]
+fn check_const_fold() {
+    let source = "fn main() -> i64 {\n\
+                      let x: i64 = 1;\n\
+                      return 2 * 3 + 6 / 2 + x;\n\
+                  }\n";
+    let plain = compile_asm_at(source, "-O0");
+    assert!(plain.contains("idiv"));
+    let folded = compile_asm_at(source, "-O1");
+    assert_eq!(folded.matches(", 9\n").count(), 1);
+    assert!(!folded.contains("imul") && !folded.contains("idiv"));
+    check_return_num(source, 10);
+
+    // Arithmetic wraps around like the generated code.
+    check_return_num("fn main() -> i64 { return (0 - 1) * 3 + 9223372036854775807 * 2 + 8; }", 3);
+    check_return_num("fn main() -> u8 { return 255 + 2; }", 1);
+    check_return_num("fn main() -> bool { return 0 - 1 < 0; }", 1);
+
+    let source = "fn main() -> i32 {\n\
+                      let x: i32 = 1;\n\
+                      if 1 > 2 { x = 10; } else { x = x + 4; }\n\
+                      if 3 == 3 { x = x * 2; }\n\
+                      while 1 != 1 { x = 0; }\n\
+                      return x;\n\
+                  }\n";
+    let folded = compile_asm_at(source, "-O1");
+    assert!(!folded.contains(".LB") && !folded.contains("cmp"));
+    check_return_num(source, 10);
+}
+
+#[test]
+fn check_dead_code() {
+    let source = "fn helper(a: i32) -> i32 {\n\
+                      return a * 2;\n\
+                  }\n\
+                  fn twice(a: i32) -> i32 {\n\
+                      return a + a;\n\
+                  }\n\
+                  fn used(a: i32) -> i32 {\n\
+                      return twice(a) + 1;\n\
+                  }\n\
+                  pub fn api() -> i32 {\n\
+                      return 5;\n\
+                  }\n\
+                  fn main() -> i32 {\n\
+                      let x: i32 = used(3);\n\
+                      if x > 0 {\n\
+                          return x;\n\
+                          x = 77;\n\
+                      } else {\n\
+                          return 0;\n\
+                      }\n\
+                      x = 88;\n\
+                      return x;\n\
+                  }\n";
+    let plain = compile_asm_at(source, "-O0");
+    assert!(plain.contains("\nhelper:\n"));
+    assert!(plain.contains("push 77\n") && plain.contains("push 88\n"));
+
+    let optimized = compile_asm_at(source, "-O2");
+    assert!(!optimized.contains("helper"));
+    for label in ["twice:", "used:", "api:", "main:"].iter() {
+        assert!(optimized.contains(&format!("\n{}\n", label)), "{} is removed", label);
+    }
+    assert!(!optimized.contains(", 77\n") && !optimized.contains(", 88\n"));
+    check_return_num(source, 7);
+}
+
+#[test]
+fn check_strength_reduction() {
+    let source = "fn main() -> i64 {\n\
+                      let x: i64 = 0 - 7;\n\
+                      return x * 8 + x / 4 + x % 16;\n\
+                  }\n";
+    let plain = compile_asm_at(source, "-O0");
+    assert!(plain.contains("imul") && plain.contains("idiv"));
+    let reduced = compile_asm_at(source, "-O1");
+    assert!(!reduced.contains("imul") && !reduced.contains("idiv"));
+    let shift = |op: &str, n: u32| reduced.lines().any(|l| l.starts_with(&format!("    {} ", op)) && l.ends_with(&format!(", {}", n)));
+    assert!(shift("sal", 3) && shift("sar", 2));
+    check_return_num(source, 192);
+
+    // Every result is compared with the division by a variable, which is
+    // never reduced.
+    let powers: [u64; 8] = [2, 4, 8, 16, 1024, 1 << 31, 1 << 32, 1 << 62];
+    let mut source = String::from("fn main() -> i64 {\n    let bad: i64 = 0;\n");
+    for (i, p) in powers.iter().enumerate() {
+        source.push_str(&format!("    let d{}: i64 = {};\n", i, p));
+    }
+    source.push_str("    let m: i64 = 0 - 9223372036854775807 - 1;\n\
+                     let x: i64 = 0 - 300;\n\
+                     while x <= 301 {\n\
+                         let y: i64 = x * 30000000000000000;\n\
+                         if x == 301 { y = m; }\n");
+    for (i, p) in powers.iter().enumerate() {
+        for v in ["x", "y"].iter() {
+            source.push_str(&format!("        if {v} / {p} != {v} / d{i} {{ bad = bad + 1; }}\n\
+                                      if {v} % {p} != {v} % d{i} {{ bad = bad + 1; }}\n\
+                                      if {v} * {p} != {v} * d{i} {{ bad = bad + 1; }}\n\
+                                      if {p} * {v} != d{i} * {v} {{ bad = bad + 1; }}\n",
+                                     v = v, p = p, i = i));
+        }
+    }
+    source.push_str("        x = x + 1;\n\
+                     }\n\
+                     if bad != 0 { return 1; }\n\
+                     return 0;\n\
+                     }\n");
+    check_return_num(&source, 0);
+}
+
+#[test]
+fn check_register_codegen() {
+    let source = "fn poly(x: i64, y: i64) -> i64 {\n\
+                      return x * x * x + 3 * x * y - y * y / 2 + (x - y) * (x + y) % 7;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      return poly(5, 3);\n\
+                  }\n";
+    let count = |asm: &str| asm.lines().filter(|l| l.starts_with("    ")).count();
+    let plain = compile_asm_at(source, "-O0");
+    let registers = compile_asm_at(source, "-O2");
+    assert!(count(&registers) * 2 < count(&plain), "{} -> {}", count(&plain), count(&registers));
+    assert!(!registers.contains("push 3\n"));
+    check_return_num(source, 168);
+
+    // Deep expressions spill to the stack, and calls save the registers
+    // in use.
+    let source = "static mut G: i16;\n\
+                  fn f(a: i64, b: i64) -> i64 {\n\
+                      return a * 10 + b;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let a: i64 = 3;\n\
+                      let b: i32 = 0 - 7;\n\
+                      let c: i8 = 5;\n\
+                      let p: &i64 = &a;\n\
+                      G = 0 - 300;\n\
+                      let r: i64 = a + (b + (c + (a * (b - (c + (a - (b * (c + (a + (b - 1))))))))));\n\
+                      let s: i64 = 100 - (a * (r / (b + 1)) % (c + 2)) + f(a, 2 + f(b, c)) * (1 + (2 + (3 + (4 + (5 + (6 + (7 + f(1, 2))))))));\n\
+                      let t: i64 = *p + G / 3 + (G % 7) * (a < b) + (c <= a) * 2 + (b == b) + (a != 3) * 100;\n\
+                      *p = *p + 1;\n\
+                      return (s == 41) + (r % 256) + t + a;\n\
+                  }\n";
+    assert!(compile_asm_at(source, "-O2").contains("push r10\n"));
+    check_return_num(source, 120);
+}
+
+#[test]
+fn check_emit_ir() {
+    let source = "fn id(s: &str) -> &str {\n\
+                      return s;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let x: i64 = 2 * 3;\n\
+                      let s: &str = \"hi\";\n\
+                      s = id(s);\n\
+                      while x < 10 {\n\
+                          x = x + 1;\n\
+                      }\n\
+                      if x == 10 { x = x / 4; }\n\
+                      return x;\n\
+                  }\n";
+    let plain = emit_ir(source, "-O0");
+    assert!(plain.contains("    v2 = mul v0, v1\n") && plain.contains(" = div8 "));
+    let ir = [
+        "fn id (stack 16)",
+        "bb0:",
+        "    store8 [rbp-16], arg0",
+        "    store8 [rbp-8], arg1",
+        "    # line 2: return s;",
+        "    v0 = load8 [rbp-16]",
+        "    v1 = load8 [rbp-8]",
+        "    ret v0, v1",
+        "",
+        "fn main (stack 32)",
+        "bb0:",
+        "    # line 5: let x: i64 = 2 * 3;",
+        "    v0 = const 6",
+        "    store8 [rbp-8], v0",
+        "    # line 6: let s: &str = \"hi\";",
+        "    v1 = str .LC0",
+        "    v2 = const 2",
+        "    store8 [rbp-24], v1",
+        "    store8 [rbp-16], v2",
+        "    # line 7: s = id(s);",
+        "    v3 = load8 [rbp-24]",
+        "    v4 = load8 [rbp-16]",
+        "    v5, v6 = call id(v3, v4)",
+        "    store8 [rbp-24], v5",
+        "    store8 [rbp-16], v6",
+        "    # line 8: while x < 10 {",
+        "    jmp bb1",
+        "bb1:",
+        "    v7 = load8 [rbp-8]",
+        "    v8 = lt8 v7, 10",
+        "    br v8, bb2, bb3",
+        "bb2:",
+        "    # line 9: x = x + 1;",
+        "    v9 = load8 [rbp-8]",
+        "    v10 = add v9, 1",
+        "    store8 [rbp-8], v10",
+        "    jmp bb1",
+        "bb3:",
+        "    # line 11: if x == 10 { x = x / 4; }",
+        "    v11 = load8 [rbp-8]",
+        "    v12 = eq8 v11, 10",
+        "    br v12, bb4, bb5",
+        "bb4:",
+        "    # line 11: x = x / 4;",
+        "    v13 = load8 [rbp-8]",
+        "    v14 = sar v13, 63",
+        "    v15 = shr v14, 62",
+        "    v16 = add v13, v15",
+        "    v17 = sar v16, 2",
+        "    store8 [rbp-8], v17",
+        "    jmp bb5",
+        "bb5:",
+        "    # line 12: return x;",
+        "    v18 = load8 [rbp-8]",
+        "    ret v18",
+    ];
+    assert_eq!(emit_ir(source, "-O1").lines().collect::<Vec<_>>(), ir);
+    check_return_num(source, 2);
+}
+
+#[test]
+fn check_att_syntax() {
+    let source = "static mut G: i32;\n\
+                  static mut S: &str;\n\
+                  fn id(s: &str) -> &str {\n\
+                      return s;\n\
+                  }\n\
+                  fn sum(a: i8, b: i16, c: i32, d: i64, e: u8, f: i32) -> i64 {\n\
+                      return a + b + c + d + e + f;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let x: i32 = 5;\n\
+                      let p: &i32 = &x;\n\
+                      let big: i64 = 5000000000;\n\
+                      let s: &str = \"abc\";\n\
+                      S = id(s);\n\
+                      G = *p / 2 + x % 4;\n\
+                      while x < 10 { x = x + 1; }\n\
+                      if x >= 10 { G = G + 1; }\n\
+                      return sum(1, 2, 3, 4, 5, G) + x + big / 1000000000 - 5 * (x == 10);\n\
+                  }\n";
+    let asm = compile_asm_at(source, "--asm-syntax=att");
+    assert!(!asm.contains(".intel_syntax") && !asm.contains(" PTR "));
+    assert!(asm.contains("    movq %rsp, %rbp\n") && asm.contains("    cqto\n"));
+    assert!(asm.contains("    movsbq (%rax), %rax\n") && asm.contains("    movslq "));
+    for level in ["-O0", "-O1", "-O2"].iter() {
+        check_return_num_at(source, 29, level);
+        check_return_num_with(source, 29, &[level, "--asm-syntax=att"]);
+    }
+}
+
+#[test]
+fn check_integrated_as() {
+    let source = "static mut G: i16;\n\
+                  static mut B: i64;\n\
+                  fn dist(m: i64, n: i64) -> i64 {\n\
+                      if n > m { return n; } else { return m - n; }\n\
+                  }\n\
+                  fn sum(a: i8, b: i16, c: i32, d: i64, e: u8, f: i32) -> i64 {\n\
+                      return a + b + c + d + e + f;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let x: i8 = 0 - 3;\n\
+                      let p: &i16 = &G;\n\
+                      let s: &str = \"abc\\n\";\n\
+                      *p = 1000;\n\
+                      B = 5000000000;\n\
+                      while x < 4 { x = x + 1; }\n\
+                      G = G / 7 + G % 7 + (x <= 4) + (G != 1000) + B / 1000000000;\n\
+                      let q: i64 = dist(0, 0 - 5);\n\
+                      return sum(x, G, 3, 4, 5, 6) * 4 / 8 - 60 + q;\n\
+                  }\n";
+    for level in ["-O0", "-O1", "-O2"].iter() {
+        check_return_num_with(source, 33, &[level, "--integrated-as"]);
+    }
+
+    // The object is linked with the C compiler like an assembled one.
+    let output_file = TestArtifact::new("");
+    let input_file = output_file.with_suffix(".rs");
+    let object_file = output_file.with_suffix(".o");
+    fs::write(&input_file, source).unwrap();
+    let args = vec!["compiler".to_string(),
+                    input_file.to_string(),
+                    "-c".to_string(),
+                    "--integrated-as".to_string(),
+                    "-o".to_string(),
+                    object_file.to_string()];
+    assert_eq!(compiler_main(args), 0);
+    let object = fs::read(&object_file).unwrap();
+    assert!(object.starts_with(b"\x7fELF\x02\x01\x01"));
+    let status = Command::new("gcc")
+        .args([object_file.path(), "-o", output_file.path()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new(output_file.path()).status().unwrap();
+    assert_eq!(status.code(), Some(33));
+}
+
+#[test]
+fn check_assembler_as() {
+    let source = "fn puts(s: &i8) -> i32;\n\
+                  fn main() -> i64 {\n\
+                      puts(c\"assembled by as\");\n\
+                      return 12;\n\
+                  }\n";
+    for level in ["-O0", "-O1", "-O2"].iter() {
+        check_output_with(source, "assembled by as\n", 12, &[level, "--assembler=as"]);
+    }
+
+    // Without the C compiler, `ld` links the C runtime itself.
+    let find = |name: &str| env::split_paths(&env::var_os("PATH").unwrap())
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file());
+    let (Some(assembler), Some(linker)) = (find("as"), find("ld")) else {
+        return;
+    };
+    let bin = TestArtifact::dir();
+    unix_fs::symlink(assembler, bin.join("as")).unwrap();
+    unix_fs::symlink(linker, bin.join("ld")).unwrap();
+    let input_file = TestArtifact::with_contents(".rs", source);
+    let output_file = TestArtifact::new("");
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .args(["--assembler=as", input_file.path(), "-o", output_file.path()])
+        .env("PATH", bin.path())
+        .env_remove("YRC_CC")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = Command::new(output_file.path()).output().unwrap();
+    assert_eq!(output.stdout, b"assembled by as\n");
+    assert_eq!(output.status.code(), Some(12));
+}
+
+#[test]
+fn check_freestanding() {
+    // Nothing from libc is called, and main's value is the exit status.
+    let source = "static mut N: i64;\n\
+                  fn fib(n: i64) -> i64 {\n\
+                      if n < 2 { return n; } else { return fib(n - 1) + fib(n - 2); }\n\
+                  }\n\
+                  fn main() -> i32 {\n\
+                      N = 10;\n\
+                      return fib(N) - 13;\n\
+                  }\n";
+    let asm = compile_asm_with(source, &["--freestanding"]);
+    assert!(asm.contains(".global _start\n.type _start, @function\n_start:\n    call main\n"));
+    assert!(asm.contains("    mov eax, 60\n    syscall\n.size _start, .-_start\n"));
+    let options: [&[&str]; 3] = [&[], &["--assembler=as"], &["--integrated-as"]];
+    for level in ["-O0", "-O1", "-O2"].iter() {
+        for extra in options.iter() {
+            let mut args = vec![*level, "--freestanding"];
+            args.extend(extra.iter());
+            check_return_num_with(source, 42, &args);
+        }
+    }
+
+    // No dynamic linker or libc is named in the executable.
+    let output_file = TestArtifact::new("");
+    let _input_file = build_native(source, &output_file, &["--freestanding", "--assembler=as"]);
+    let exe = fs::read(&output_file).unwrap();
+    assert!(!exe.windows(4).any(|w| w == b"libc" || w == b"ld-l"));
+
+    // Only the object of main has the entry point.
+    let asm = compile_asm_with("pub fn one() -> i32 { return 1; }\n", &["--freestanding"]);
+    assert!(!asm.contains("_start"));
+}
+
+#[test]
+fn check_symbol_info() {
+    let source = "static G: [i16; 3];\n\
+                  fn foo() -> i64 { return 3; }\n\
+                  fn main() { return foo(); }\n";
+    let asm = compile_asm(source);
+    assert!(asm.contains(".global G\n.type G, @object\n.balign 2\nG:\n    .zero 6\n.size G, 6\n"));
+    assert!(asm.contains(".global main\n.type main, @function\n"));
+    assert!(asm.contains(".size foo, .-foo\n"));
+    assert!(asm.contains(".ident \"yrc "));
+
+    let has = |program: &str| Command::new(program).arg("--version").output().is_ok();
+    if !cfg!(target_arch = "x86_64") || !has("readelf") {
+        return;
+    }
+    let output_file = TestArtifact::new("");
+    let input_file = output_file.with_suffix(".rs");
+    let object_file = output_file.with_suffix(".o");
+    fs::write(&input_file, source).unwrap();
+    for options in [&[][..], &["--integrated-as"][..]].iter() {
+        let mut args = vec!["compiler".to_string(),
+                            input_file.to_string(),
+                            "-c".to_string(),
+                            "-o".to_string(),
+                            object_file.to_string()];
+        args.extend(options.iter().map(|opt| opt.to_string()));
+        assert_eq!(compiler_main(args), 0);
+
+        let output = Command::new("readelf").args(["-s", "-p", ".comment", object_file.path()]).output().unwrap();
+        let symbols = String::from_utf8(output.stdout).unwrap();
+        let symbol = |name: &str| symbols.lines()
+            .find(|line| line.ends_with(&format!(" {}", name)))
+            .map(|line| line.split_whitespace().skip(2).take(2).collect::<Vec<_>>().join(" "))
+            .unwrap();
+        assert_eq!(symbol("G"), "6 OBJECT");
+        // The prologue, `push 3`, `pop rax` and the epilogue, and then
+        // `mov rax, 0` and the epilogue at the end.
+        assert_eq!(symbol("foo"), "28 FUNC");
+        assert!(symbol("main").ends_with(" FUNC"));
+        assert!(symbols.contains("yrc "));
+
+        // The object is linked without warnings.
+        let output = Command::new("gcc").args([object_file.path(), "-o", output_file.path()]).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    }
+}
+
+#[test]
+fn check_static_align() {
+    // A follows the string literal in .rodata, and C follows B in .data.
+    let source = "static A: i64 = 7;\n\
+                  static mut B: u8 = 1;\n\
+                  static mut C: i64 = 2;\n\
+                  fn main() -> i64 { let _s: &str = \"hi\"; C = C + B; return A + C; }\n";
+    check_return_num(source, 10);
+    for options in [&["-O0"][..], &["-O1"][..], &["--target=aarch64"][..]].iter() {
+        let asm = compile_asm_with(source, options);
+        assert!(asm.contains(".balign 8\nA:\n"));
+        assert!(asm.contains("object\nB:\n"));
+        assert!(asm.contains(".balign 8\nC:\n"));
+    }
+
+    let has = |program: &str| Command::new(program).arg("--version").output().is_ok();
+    if !cfg!(target_arch = "x86_64") || !has("readelf") {
+        return;
+    }
+    let output_file = TestArtifact::new("");
+    let input_file = output_file.with_suffix(".rs");
+    let object_file = output_file.with_suffix(".o");
+    fs::write(&input_file, source).unwrap();
+    for options in [&[][..], &["--integrated-as"][..]].iter() {
+        let mut args = vec!["compiler".to_string(),
+                            input_file.to_string(),
+                            "-c".to_string(),
+                            "-o".to_string(),
+                            object_file.to_string()];
+        args.extend(options.iter().map(|opt| opt.to_string()));
+        assert_eq!(compiler_main(args), 0);
+
+        let output = Command::new("readelf").args(["-s", object_file.path()]).output().unwrap();
+        let symbols = String::from_utf8(output.stdout).unwrap();
+        let value = |name: &str| symbols.lines()
+            .find(|line| line.ends_with(&format!(" {}", name)))
+            .and_then(|line| u64::from_str_radix(line.split_whitespace().nth(1)?, 16).ok())
+            .unwrap();
+        assert_eq!((value("A"), value("B"), value("C")), (8, 0, 8));
+    }
+}
+
+#[test]
+fn check_windows_target() {
+    let source = "fn sub(a: i32, s: &str, b: i8) -> i64 {\n\
+                      return a - b;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let s: &str = \"abc\";\n\
+                      return sub(10, s, 3) * 6 / 7;\n\
+                  }\n";
+    for level in ["-O0", "-O1"].iter() {
+        let asm = compile_asm_with(source, &[level, "--target=windows-gnu"]);
+        assert!(!asm.contains("@PLT") && !asm.contains(".note.GNU-stack"));
+        assert!(asm.contains(".def main; .scl 2; .type 32; .endef\n"));
+        assert!(asm.contains(".section .rdata,\"dr\"\n"));
+        // The arguments are in rcx, rdx, r8 and r9 with 32 bytes of shadow
+        // space, and rdi and rsi are kept.
+        assert!(asm.contains("    sub rsp, 32\n    call sub\n    add rsp, 32\n"));
+        assert!(asm.contains(", ecx\n") && asm.contains(", r9b\n"));
+        assert!(asm.contains(", rdi\n") && asm.contains("    mov rsi, QWORD PTR [rbp-"));
+    }
+
+    // An end-to-end run needs MinGW, and Wine unless on Windows.
+    let has = |program: &str| Command::new(program).arg("--version").output().is_ok();
+    if cfg!(windows) || !has("x86_64-w64-mingw32-gcc") {
+        return;
+    }
+    let output_file = TestArtifact::new(".exe");
+    let input_file = output_file.with_suffix(".rs");
+    fs::write(&input_file, source).unwrap();
+    let args = vec!["compiler".to_string(),
+                    input_file.to_string(),
+                    "-o".to_string(),
+                    output_file.to_string(),
+                    "--target=windows-gnu".to_string()];
+    assert_eq!(compiler_main(args), 0);
+    if has("wine") {
+        let status = Command::new("wine").arg(output_file.path()).status().unwrap();
+        assert_eq!(status.code(), Some(6));
+    }
+}
+
+#[test]
+fn check_aarch64_target() {
+    let source = "static mut G: i16;\n\
+                  fn pick(s: &str, t: &str, n: i64) -> &str {\n\
+                      if n > 0 { return s; } else { return t; }\n\
+                  }\n\
+                  fn sum(a: i8, b: i16, c: i32, d: i64, e: u8, f: i32, g: i64, h: i64) -> i64 {\n\
+                      return a + b + c + d + e + f + g + h;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let x: i8 = 0 - 3;\n\
+                      let p: &i16 = &G;\n\
+                      let _s: &str = pick(\"abc\", \"de\", 0);\n\
+                      *p = 1000;\n\
+                      while x < 4 { x = x + 1; }\n\
+                      G = G / 7 + G % 7 + (x <= 4) + (G != 1000);\n\
+                      return sum(x, G, 3, 5000000000 / 1000000000, 5, 6, 7, 8) - 100;\n\
+                  }\n";
+    for level in ["-O0", "-O1"].iter() {
+        let asm = compile_asm_with(source, &[level, "--target=aarch64"]);
+        assert!(!asm.contains(".intel_syntax") && !asm.contains("rbp"));
+        assert!(asm.contains("    stp x29, x30, [sp, #-16]!\n    mov x29, sp\n"));
+        assert!(asm.contains("    mov sp, x29\n    ldp x29, x30, [sp], #16\n    ret\n"));
+        assert!(asm.contains("    mov x7, ") && asm.contains("    bl sum\n"));
+        assert!(asm.contains("    adrp x8, G\n    add x8, x8, :lo12:G\n"));
+        assert!(asm.contains("    ldursb x") && asm.contains("    ldursw x") && asm.contains("    sxtb w16, w"));
+        assert!(asm.contains("    msub ") && asm.contains("    cset "));
+        assert!(asm.contains("// line 13: while x < 4 { x = x + 1; }\n"));
+    }
+
+    // Elsewhere, the program runs under qemu-user with a cross compiler.
+    let has = |program: &str| Command::new(program).arg("--version").output().is_ok();
+    if cfg!(target_arch = "aarch64") {
+        check_return_num(source, 87);
+    } else if has("aarch64-linux-gnu-gcc") && has("qemu-aarch64") {
+        for level in ["-O0", "-O1", "-O2"].iter() {
+            let output_file = TestArtifact::new("");
+            let input_file = output_file.with_suffix(".rs");
+            fs::write(&input_file, source).unwrap();
+            let args = vec!["compiler".to_string(),
+                            input_file.to_string(),
+                            "-o".to_string(),
+                            output_file.to_string(),
+                            level.to_string(),
+                            "--target=aarch64".to_string()];
+            assert_eq!(compiler_main(args), 0);
+            let status = Command::new("qemu-aarch64")
+                .args(["-L", "/usr/aarch64-linux-gnu", output_file.path()])
+                .status()
+                .unwrap();
+            assert_eq!(status.code(), Some(87));
+        }
+    }
+}
+
+#[test]
+fn check_riscv64_target() {
+    let source = "static mut G: i16;\n\
+                  fn pick(s: &str, t: &str, n: i64) -> &str {\n\
+                      if n > 0 { return s; } else { return t; }\n\
+                  }\n\
+                  fn sum(a: i8, b: i16, c: i32, d: i64, e: u8, f: i32, g: i64, h: i64) -> i64 {\n\
+                      return a + b + c + d + e + f + g + h;\n\
+                  }\n\
+                  fn main() -> i64 {\n\
+                      let x: i8 = 0 - 3;\n\
+                      let p: &i16 = &G;\n\
+                      let _s: &str = pick(\"abc\", \"de\", 0);\n\
+                      *p = 1000;\n\
+                      while x < 4 { x = x + 1; }\n\
+                      G = G / 7 + G % 7 + (x <= 4) + (G != 1000);\n\
+                      return sum(x, G, 3, 5000000000 / 1000000000, 5, 6, 7, 8) - 100;\n\
+                  }\n";
+    for level in ["-O0", "-O1"].iter() {
+        let asm = compile_asm_with(source, &[level, "--target=riscv64"]);
+        assert!(!asm.contains(".intel_syntax") && !asm.contains("rbp"));
+        assert!(asm.contains("    addi sp, sp, -16\n    sd ra, 8(sp)\n    sd s0, 0(sp)\n    mv s0, sp\n"));
+        assert!(asm.contains("    mv sp, s0\n    ld ra, 8(sp)\n    ld s0, 0(sp)\n    addi sp, sp, 16\n    ret\n"));
+        assert!(asm.contains("    mv a7, ") && asm.contains("    call sum\n"));
+        assert!(asm.contains("    lla t2, G\n"));
+        assert!(asm.contains("    sb a0, ") && asm.contains("    sh a1, ") && asm.contains("    sw a2, "));
+        assert!(asm.contains("    lb ") && asm.contains("    lw ") && asm.contains("    ld "));
+        assert!(asm.contains("    slt ") && asm.contains("    xori ") && asm.contains("    snez "));
+        assert!(asm.contains("    beqz ") && asm.contains("    remw "));
+        assert!(asm.contains("# line 13: while x < 4 { x = x + 1; }\n"));
+    }
+
+    // Elsewhere, the program runs under qemu-user with a cross compiler.
+    let has = |program: &str| Command::new(program).arg("--version").output().is_ok();
+    if cfg!(target_arch = "riscv64") {
+        check_return_num(source, 87);
+    } else if has("riscv64-linux-gnu-gcc") && has("qemu-riscv64") {
+        for level in ["-O0", "-O1", "-O2"].iter() {
+            let output_file = TestArtifact::new("");
+            let input_file = output_file.with_suffix(".rs");
+            fs::write(&input_file, source).unwrap();
+            let args = vec!["compiler".to_string(),
+                            input_file.to_string(),
+                            "-o".to_string(),
+                            output_file.to_string(),
+                            level.to_string(),
+                            "--target=riscv64".to_string()];
+            assert_eq!(compiler_main(args), 0);
+            let status = Command::new("qemu-riscv64")
+                .args(["-L", "/usr/riscv64-linux-gnu", output_file.path()])
+                .status()
+                .unwrap();
+            assert_eq!(status.code(), Some(87));
+        }
+    }
 }
@@ -0,0 +1,587 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use common::TestArtifact;
+use common::compile_stdin;
+use common::compile_output_with;
+
+use yrc::assembly::AsmSyntax;
+use yrc::assembly::Target;
+use yrc::cli::Assembler;
+use yrc::cli::Emit;
+use yrc::cli::Mode;
+use yrc::cli::OutputKind;
+use yrc::parse_args;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+fn parse_error(line: &str) -> String {
+    parse_args(args(line)).unwrap_err()
+}
+
+#[test]
+fn parse_build() {
+    let config = parse_args(args("yrc foo.rs")).unwrap();
+    assert_eq!(config.mode, Mode::Build);
+    assert_eq!(config.input_files, ["foo.rs"]);
+    assert_eq!(config.output_file, None);
+    assert_eq!(config.kind, OutputKind::Executable);
+    assert!(!config.force);
+
+    let config = parse_args(args("yrc build -s -o out.s foo.rs -f")).unwrap();
+    assert_eq!(config.mode, Mode::Build);
+    assert_eq!(config.output_file.as_deref(), Some("out.s"));
+    assert_eq!(config.kind, OutputKind::Asm);
+    assert!(config.force);
+
+    let config = parse_args(args("yrc -c foo.rs bar.rs --cc clang -l m --no-pie")).unwrap();
+    assert_eq!(config.kind, OutputKind::Object);
+    assert_eq!(config.input_files, ["foo.rs", "bar.rs"]);
+    assert_eq!(config.cc.as_deref(), Some("clang"));
+    assert_eq!(config.link_args, ["-no-pie", "-lm"]);
+
+    let config = parse_args(args("yrc -g foo.rs")).unwrap();
+    assert!(config.debug_info);
+    assert_eq!(config.asm_syntax, AsmSyntax::Intel);
+    assert_eq!(config.target, Target::Linux);
+
+    let config = parse_args(args("yrc run --asm-syntax=att foo.rs")).unwrap();
+    assert_eq!(config.asm_syntax, AsmSyntax::Att);
+
+    let config = parse_args(args("yrc -c --target windows-gnu foo.rs")).unwrap();
+    assert_eq!(config.target, Target::WindowsGnu);
+    let config = parse_args(args("yrc --target=aarch64 foo.rs")).unwrap();
+    assert_eq!(config.target, Target::Aarch64);
+    let config = parse_args(args("yrc --target=riscv64 foo.rs")).unwrap();
+    assert_eq!(config.target, Target::Riscv64);
+    let config = parse_args(args("yrc -c --integrated-as foo.rs")).unwrap();
+    assert!(config.integrated_as);
+    let config = parse_args(args("yrc foo.rs")).unwrap();
+    assert_eq!(config.assembler, Assembler::Cc);
+    assert!(!config.freestanding);
+    let config = parse_args(args("yrc run --assembler=as --freestanding foo.rs")).unwrap();
+    assert_eq!(config.assembler, Assembler::As);
+    assert!(config.freestanding);
+
+    let config = parse_args(args("yrc --emit ast-json foo.rs")).unwrap();
+    assert_eq!(config.emit, Some(Emit::AstJson));
+
+    let config = parse_args(args("yrc --emit=ir -O2 foo.rs")).unwrap();
+    assert_eq!(config.emit, Some(Emit::Ir));
+}
+
+#[test]
+fn parse_run() {
+    let config = parse_args(args("yrc run foo.rs -L lib -- -a b")).unwrap();
+    assert_eq!(config.mode, Mode::Run);
+    assert_eq!(config.input_files, ["foo.rs"]);
+    assert_eq!(config.link_args, ["-Llib"]);
+    assert_eq!(config.run_args, ["-a", "b"]);
+    assert!(config.force);
+
+    let config = parse_args(args("yrc run foo.rs")).unwrap();
+    assert!(config.run_args.is_empty());
+
+    let config = parse_args(args("yrc -e 1+2 -O2 -e 3 --include lib.rs")).unwrap();
+    assert_eq!(config.mode, Mode::Run);
+    assert_eq!(config.eval, ["1+2", "3"]);
+    assert_eq!(config.includes, ["lib.rs"]);
+    assert!(config.input_files.is_empty());
+}
+
+#[test]
+fn parse_check() {
+    let config = parse_args(args("yrc check foo.rs bar.rs -A warnings")).unwrap();
+    assert_eq!(config.mode, Mode::Check);
+    assert_eq!(config.input_files, ["foo.rs", "bar.rs"]);
+
+    let config = parse_args(args("yrc foo.rs --check - -D warnings")).unwrap();
+    assert_eq!(config.mode, Mode::Check);
+    assert_eq!(config.input_files, ["foo.rs", "-"]);
+    assert_eq!(parse_error("yrc --check -o foo foo.rs"), "Unknown option `-o`!");
+}
+
+#[test]
+fn parse_info() {
+    assert_eq!(parse_args(args("yrc --version")).unwrap().mode, Mode::Version);
+    assert_eq!(parse_args(args("yrc -V")).unwrap().mode, Mode::Version);
+    assert_eq!(parse_args(args("yrc --explain E0001")).unwrap().mode, Mode::Explain("E0001".to_string()));
+
+    match parse_args(args("yrc -h")).unwrap().mode {
+        Mode::Help(text) => {
+            assert!(text.contains("Examples:"));
+            assert!(text.contains("--emit"));
+        },
+        mode => panic!("{:?}", mode),
+    }
+    match parse_args(args("yrc run -h")).unwrap().mode {
+        Mode::Help(text) => {
+            assert!(text.starts_with("Usage: yrc run"));
+            assert!(!text.contains("--emit"));
+        },
+        mode => panic!("{:?}", mode),
+    }
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(parse_error("yrc"), "Input file is needed!");
+    assert_eq!(parse_error("yrc run"), "Input file is needed!");
+    assert_eq!(parse_error("yrc --foo foo.rs"), "Unknown option `--foo`!");
+    assert_eq!(parse_error("yrc -x foo.rs"), "Unknown option `-x`!");
+    assert_eq!(parse_error("yrc foo.rs -o"), "Option `-o` needs an argument!");
+    assert_eq!(parse_error("yrc check -s foo.rs"), "Unknown option `-s`!");
+    assert_eq!(parse_error("yrc run --emit tokens foo.rs"), "Unknown option `--emit`!");
+    assert_eq!(parse_error("yrc -c -s foo.rs"), "-c and -s cannot be used together!");
+    assert_eq!(parse_error("yrc --color=rainbow foo.rs"), "Invalid color choice! (rainbow)");
+    assert!(parse_error("yrc foo.rs bar.rs").starts_with("Multiple input files"));
+    assert!(parse_error("yrc run foo.rs bar.rs").starts_with("Multiple input files"));
+    assert!(parse_error("yrc -A no-such-lint foo.rs").contains("no-such-lint"));
+    assert_eq!(parse_error("yrc --integrated-as --target=aarch64 foo.rs"),
+               "--integrated-as is supported only for x86_64-linux!");
+    assert_eq!(parse_error("yrc --integrated-as -g foo.rs"), "--integrated-as and -g cannot be used together!");
+    assert_eq!(parse_error("yrc -e 1 -o foo"), "-e makes no output file!");
+    assert_eq!(parse_error("yrc run -e 1 foo.rs"), "-e takes no input file!");
+    assert_eq!(parse_error("yrc --include lib.rs foo.rs"), "--include is only for -e!");
+    assert_eq!(parse_error("yrc check -e 1 foo.rs"), "Unknown option `-e`!");
+    assert_eq!(parse_error("yrc --assembler=nasm foo.rs"), "Invalid assembler! (nasm)");
+    assert_eq!(parse_error("yrc --assembler=as --integrated-as foo.rs"),
+               "--assembler=as and --integrated-as cannot be used together!");
+    assert_eq!(parse_error("yrc --assembler=as --target=riscv64 foo.rs"),
+               "--assembler=as is supported only for x86_64-linux!");
+    assert_eq!(parse_error("yrc --freestanding --target=windows-gnu foo.rs"),
+               "--freestanding is supported only for x86_64-linux!");
+}
+
+#[test]
+fn parse_opt_level() {
+    assert_eq!(parse_args(args("yrc foo.rs")).unwrap().opt_level, 0);
+    assert_eq!(parse_args(args("yrc foo.rs -O")).unwrap().opt_level, 1);
+    assert_eq!(parse_args(args("yrc -O2 foo.rs")).unwrap().opt_level, 2);
+    assert_eq!(parse_args(args("yrc run -O0 foo.rs")).unwrap().opt_level, 0);
+    assert_eq!(parse_error("yrc -O3 foo.rs"), "Invalid optimization level! (3)");
+    assert_eq!(parse_error("yrc --asm-syntax=masm foo.rs"), "Invalid assembly syntax! (masm)");
+    assert_eq!(parse_error("yrc check --asm-syntax=att foo.rs"), "Unknown option `--asm-syntax`!");
+    assert_eq!(parse_error("yrc --target=windows-msvc foo.rs"), "Invalid target! (windows-msvc)");
+    assert_eq!(parse_error("yrc --asm-syntax=att --target=aarch64 foo.rs"),
+               "--asm-syntax=att is supported only for x86_64 targets!");
+    assert_eq!(parse_error("yrc --asm-syntax=att --target=riscv64 foo.rs"),
+               "--asm-syntax=att is supported only for x86_64 targets!");
+    let config = parse_args(args("yrc --asm-syntax=att --target=windows-gnu foo.rs")).unwrap();
+    assert_eq!(config.asm_syntax, AsmSyntax::Att);
+}
+
+#[test]
+fn opt_levels() {
+    let source = "fn two() -> i32 {\n    return 2;\n}\n\
+                  fn main() {\n    let a: i32 = 5;\n    return a * two();\n}\n";
+    let count_insns = |asm: &str| asm.lines().filter(|l| l.starts_with("    ")).count();
+
+    let (asm0, _) = compile_stdin(source, &["-s", "-o", "-", "-O0"]);
+    let (asm2, _) = compile_stdin(source, &["-s", "-o", "-", "-O2"]);
+    assert!(count_insns(&asm2) < count_insns(&asm0));
+
+    for level in ["-O0", "-O2"].iter() {
+        let input_file = TestArtifact::with_contents(".rs", source);
+        let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+            .args(["run", level, input_file.path()])
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(10));
+    }
+}
+
+#[test]
+fn time_passes() {
+    let source = "fn main() {\n    let a: i32 = 2;\n    return a;\n}\n";
+
+    let (_, output, built) = compile_output_with(source, &["--time-passes"]);
+    assert!(built);
+    let phases: Vec<&str> = output.lines()
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(phases, ["tokenize", "parse", "codegen", "link", "total"]);
+    assert!(output.contains(" 17 tokens\n"));
+
+    let (_, output, built) = compile_output_with(source, &[]);
+    assert!(built);
+    assert_eq!(output, "");
+}
+
+#[test]
+fn no_partial_output() {
+    let dir = TestArtifact::dir();
+    let input_file = dir.join("prog");
+    let output_file = dir.join("prog.out");
+    let asm_file = dir.join("prog.s");
+    fs::write(&output_file, "previous").unwrap();
+    fs::write(&asm_file, "previous").unwrap();
+
+    // An error in code generation.
+    fs::write(&input_file, "fn main() {\n    1 = 2;\n    return 0;\n}\n").unwrap();
+    for options in [&["-f", "-o", &output_file][..], &["-f", "-c", "-o", &output_file],
+                    &["-f", "-s", "-o", &asm_file], &["-f", "-o", &output_file, "--save-temps"]].iter() {
+        let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+            .arg(&input_file)
+            .args(*options)
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "previous");
+    }
+    assert_eq!(fs::read_to_string(&asm_file).unwrap(), "previous");
+    let (answer, _) = compile_stdin("fn main() {\n    1 = 2;\n    return 0;\n}\n", &["-s", "-o", "-"]);
+    assert_eq!(answer, "");
+
+    // An error in linking.
+    fs::write(&input_file, "fn undefined() -> i32;\nfn main() {\n    return undefined();\n}\n").unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg(&input_file)
+        .args(["-f", "-o", &output_file])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert_eq!(fs::read_to_string(&output_file).unwrap(), "previous");
+
+    let temps: Vec<String> = fs::read_dir(&dir).unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("yrc"))
+        .collect();
+    assert!(temps.is_empty());
+}
+
+#[test]
+fn refuse_overwrite() {
+    let dir = TestArtifact::dir();
+    let input_file = dir.join("prog");
+    let output_file = dir.join("prog.out");
+    fs::write(&input_file, "fn main() {\n    return 0;\n}\n").unwrap();
+    let compile = |options: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+            .arg(&input_file)
+            .args(options)
+            .output()
+            .unwrap();
+        (output.status.code(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    // An unrelated file is kept.
+    fs::write(&output_file, "unrelated").unwrap();
+    for options in [&["-o", &output_file][..], &["-s", "-o", &output_file],
+                    &["-c", "-o", &output_file]].iter() {
+        let (status, stderr) = compile(options);
+        assert_eq!(status, Some(1));
+        assert!(stderr.contains("Output file already exists!"));
+        assert!(stderr.contains("--force"));
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "unrelated");
+    }
+    let (status, _) = compile(&["-o", &output_file, "--save-temps", "-f"]);
+    assert_eq!(status, Some(0));
+    let asm_file = dir.join("prog.s");
+    fs::write(&asm_file, "unrelated").unwrap();
+    let (status, _) = compile(&["-o", &output_file, "--save-temps"]);
+    assert_eq!(status, Some(1));
+    assert_eq!(fs::read_to_string(&asm_file).unwrap(), "unrelated");
+    fs::remove_file(&asm_file).unwrap();
+
+    // --force overwrites it, and outputs of yrc are overwritten without it.
+    for options in [&["-s", "-o", &output_file][..], &["-c", "-o", &output_file],
+                    &["-o", &output_file]].iter() {
+        fs::write(&output_file, "unrelated").unwrap();
+        let (status, _) = compile(&[options, &["--force"][..]].concat());
+        assert_eq!(status, Some(0));
+        let (status, _) = compile(options);
+        assert_eq!(status, Some(0));
+    }
+    fs::remove_file(&output_file).unwrap();
+
+    // A directory is kept too.
+    fs::create_dir(&output_file).unwrap();
+    let (status, _) = compile(&["-o", &output_file]);
+    assert_eq!(status, Some(1));
+    assert!(Path::new(&output_file).is_dir());
+    fs::remove_dir(&output_file).unwrap();
+
+    // The input is never overwritten, even with --force.
+    let source = fs::read_to_string(&input_file).unwrap();
+    for options in [&["-o", &input_file][..], &["-s", "-f", "-o", &input_file],
+                    &["--emit", "tokens", "-o", &input_file]].iter() {
+        let (status, stderr) = compile(options);
+        assert_eq!(status, Some(1));
+        assert!(stderr.contains("Output file is the same as the input file!"));
+        assert_eq!(fs::read_to_string(&input_file).unwrap(), source);
+    }
+}
+
+#[test]
+fn debug_line_info() {
+    let source = "fn main() {\n    let a: i32 = 1;\n\n    \
+                  if a == 1 {\n        a = 2;\n    }\n    return a;\n}\n";
+    let (asm, _) = compile_stdin(source, &["-s", "-o", "-", "-g"]);
+    assert!(asm.contains(".file 1 \"<stdin>\"\n"));
+    assert!(asm.contains("    .loc 1 2 5\n"));
+    assert!(asm.contains("    .loc 1 5 9\n"));
+    let (asm, _) = compile_stdin(source, &["-s", "-o", "-"]);
+    assert!(!asm.contains(".loc"));
+
+    let input_file = TestArtifact::with_contents("", source);
+    let output_file = input_file.with_suffix(".out");
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .args([input_file.path(), "-g", "-o", output_file.path()])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+    let output = Command::new("objdump")
+        .args(["--dwarf=decodedline", output_file.path()])
+        .output()
+        .unwrap();
+    let table = String::from_utf8(output.stdout).unwrap();
+    // Each row is "FILE LINE ADDRESS", and the end of a sequence has "-".
+    let mut lines: Vec<usize> = table.lines()
+        .filter(|l| l.starts_with(input_file.file_name()))
+        .filter_map(|l| l.split_whitespace().nth(1)?.parse().ok())
+        .collect();
+    lines.dedup();
+    assert_eq!(lines, [1, 2, 4, 5, 7]);
+}
+
+#[test]
+fn asm_comments() {
+    let source = "fn two() -> i32 {\n    return 2;\n}\n\
+                  fn main() {\n    let a: i32 = 5;\n    \
+                  if a == 5 {\n        a = a * two();\n    }\n    return a;\n}\n";
+    let input_file = TestArtifact::with_contents("", source);
+    let asm_file = input_file.with_suffix(".s");
+    let obj_file = input_file.with_suffix(".o");
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .args([input_file.path(), "-s", "-o", asm_file.path()])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+
+    let asm = fs::read_to_string(&asm_file).unwrap();
+    let comments: Vec<&str> = asm.lines().filter(|l| l.trim_start().starts_with('#')).collect();
+    assert_eq!(comments, [
+        "# fn two() -> i32 (frame: 0 bytes)",
+        "    # line 2: return 2;",
+        "# fn main() (frame: 16 bytes)",
+        "    # line 5: let a: i32 = 5;",
+        "    # line 6: if a == 5 {",
+        "    # line 7: a = a * two();",
+        "    # line 9: return a;",
+    ]);
+    let status = Command::new("cc")
+        .args(["-c", asm_file.path(), "-o", obj_file.path()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn compile_object() {
+    let dir = TestArtifact::dir();
+    let input_files = ["a.rs", "b.rs"];
+    let files = [input_files[0], input_files[1], "main.c"];
+    let sources = ["fn add(a: i32, b: i32) -> i32 {\n    return a + b;\n}\n",
+                   "fn sub(a: i32, b: i32) -> i32 {\n    return a - b;\n}\n",
+                   "int add(int, int);\nint sub(int, int);\n\
+                    int main(void) { return sub(add(4, 5), 2); }\n"];
+    for (file, source) in files.iter().zip(sources.iter()) {
+        fs::write(dir.join(file), source).unwrap();
+    }
+
+    // Each input is compiled to an object named after it.
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("-c")
+        .args(input_files)
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+
+    let status = Command::new("gcc")
+        .args(["main.c", "a.o", "b.o", "-o", "main"])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new(dir.join("main")).status().unwrap();
+    assert_eq!(status.code(), Some(7));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("-c")
+        .arg("-s")
+        .arg(input_files[0])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+}
+
+#[test]
+fn save_temps() {
+    let input_file = TestArtifact::with_contents("", "fn main() { return 5; }");
+    let output_file = input_file.with_suffix(".out");
+    let asm_file = input_file.with_suffix(".s");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg(input_file.path())
+        .arg("-o")
+        .arg(output_file.path())
+        .arg("--save-temps")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stderr).unwrap().contains(asm_file.path()));
+    fs::remove_file(&output_file).unwrap();
+
+    // The saved assembly is built as is.
+    let status = Command::new("gcc")
+        .arg(asm_file.path())
+        .arg("-o")
+        .arg(output_file.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new(output_file.path()).status().unwrap();
+    assert_eq!(status.code(), Some(5));
+}
+
+// Writes a C compiler which only saves its arguments into `args_file`.
+fn write_shim(shim: &str, args_file: &str) {
+    fs::write(shim, format!("#!/bin/sh\necho \"$@\" > {}\n", args_file)).unwrap();
+    Command::new("chmod").arg("+x").arg(shim).status().unwrap();
+}
+
+// Arguments the shim was called with. The output is written into a
+// temporary file first, whose name is replaced with OUT.
+fn shim_args(args_file: &str) -> String {
+    let args = fs::read_to_string(args_file).unwrap();
+    fs::remove_file(args_file).unwrap();
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let pos = words.iter().position(|w| *w == "-o").unwrap();
+    words[pos + 1] = "OUT";
+    words.join(" ")
+}
+
+#[test]
+fn select_c_compiler() {
+    let base = TestArtifact::new("");
+    let input_file = base.with_suffix(".rs");
+    let shim = base.with_suffix(".sh");
+    let args_file = base.with_suffix(".args");
+    let output_file = base.with_suffix(".out");
+    fs::write(&input_file, "fn main() { return 0; }").unwrap();
+    let (shim, args_file, output_file) = (shim.path(), args_file.path(), output_file.path());
+    write_shim(shim, args_file);
+
+    let compile = |options: &[&str], cc: Option<&str>| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_yrc"));
+        cmd.arg(input_file.path()).args(options).env_remove("YRC_CC");
+        if let Some(cc) = cc {
+            cmd.env("YRC_CC", cc);
+        }
+        cmd.output().unwrap()
+    };
+
+    let output = compile(&["-o", output_file, "--cc", shim], None);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(shim_args(args_file).ends_with(".s -o OUT"));
+
+    let output = compile(&["-c", "-o", output_file, "-f"], Some(shim));
+    assert_eq!(output.status.code(), Some(0));
+    let args = shim_args(args_file);
+    assert!(args.starts_with("-c "));
+    assert!(args.ends_with(".s -o OUT"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg(input_file.path())
+        .env_remove("YRC_CC")
+        .env("PATH", "")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let diag = String::from_utf8(output.stderr).unwrap();
+    assert!(diag.contains("No C compiler found"));
+
+    let output = compile(&["--cc", "no-such-cc"], None);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn pass_link_args() {
+    let dir = TestArtifact::dir();
+    let lib_dir = dir.join("lib");
+    let input_file = dir.join("prog.rs");
+    let output_file = dir.join("prog");
+    fs::create_dir_all(&lib_dir).unwrap();
+    fs::write(format!("{}/triple.c", lib_dir), "int triple(int a) { return a * 3; }\n").unwrap();
+    let status = Command::new("gcc")
+        .args(["-c", "triple.c"])
+        .current_dir(&lib_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new("ar")
+        .args(["rcs", "libtriple.a", "triple.o"])
+        .current_dir(&lib_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::write(&input_file, "fn triple(a: i32) -> i32;\n\
+                            fn main() {\n    return triple(14);\n}\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg(&input_file)
+        .args(["-o", &output_file, "-L", &lib_dir, "-l", "triple", "--no-pie"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+    let status = Command::new(&output_file).status().unwrap();
+    assert_eq!(status.code(), Some(42));
+    fs::remove_file(&output_file).unwrap();
+
+    // Each kind of the flags keeps its place in the command line.
+    let shim = dir.join("cc.sh");
+    let args_file = dir.join("cc.args");
+    write_shim(&shim, &args_file);
+    let status = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg(&input_file)
+        .args(["-o", &output_file, "--cc", &shim, "-O2", "--link-arg", "-static", "-l", "a",
+                "-L", "dir", "--link-arg=-s", "-l", "b", "--no-pie"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+    assert!(shim_args(&args_file).ends_with(".s -o OUT -O2 -no-pie -static -la -Ldir -s -lb"));
+}
+
+#[test]
+fn run_program() {
+    let input_file = TestArtifact::new(".rs");
+    fs::write(&input_file, "fn write(fd: i32, s: &str) -> i64;\n\
+                            fn main() {\n    let s: &str = \"hello\\n\";\n    \
+                            write(1, s);\n    return 42;\n}\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("run")
+        .arg(input_file.path())
+        .args(["--", "arg"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(42));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello\n");
+
+    // Build errors are not mixed up with the status of the program.
+    fs::write(&input_file, "fn main() { return $; }").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("run")
+        .arg(input_file.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
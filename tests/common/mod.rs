@@ -0,0 +1,146 @@
+// Helpers shared by the integration tests. Each test crate uses only some
+// of them.
+#![allow(dead_code)]
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use rand::prelude::*;
+
+static ARTIFACTS: AtomicUsize = AtomicUsize::new(0);
+
+// File or directory which a test makes, under the temporary directory so
+// that the source tree is left clean. The name is unique among the threads
+// and the processes running tests, and the file is removed when the guard
+// is dropped, even when the test panics.
+pub struct TestArtifact {
+    path: String,
+}
+
+impl TestArtifact {
+    // Nothing is created yet. The name ends with the suffix, e.g. ".rs".
+    pub fn new(suffix: &str) -> Self {
+        TestArtifact::new_in(&env::temp_dir(), suffix)
+    }
+
+    pub fn new_in(dir: &Path, suffix: &str) -> Self {
+        let name = format!("yrc-test-{}-{}-{:08x}{}",
+                           process::id(),
+                           ARTIFACTS.fetch_add(1, Ordering::Relaxed),
+                           rand::thread_rng().gen::<u32>(),
+                           suffix);
+        TestArtifact { path: dir.join(name).to_str().unwrap().to_string() }
+    }
+
+    // Empty directory, for a test which makes several files or looks at
+    // what is left in the directory.
+    pub fn dir() -> Self {
+        let dir = TestArtifact::new("");
+        fs::create_dir(&dir.path).unwrap();
+        dir
+    }
+
+    // Artifact named after this one, e.g. the source `prog.rs` of `prog`.
+    pub fn with_suffix(&self, suffix: &str) -> Self {
+        TestArtifact { path: format!("{}{}", self.path, suffix) }
+    }
+
+    // Artifact with the contents written.
+    pub fn with_contents(suffix: &str, contents: &str) -> Self {
+        let artifact = TestArtifact::new(suffix);
+        fs::write(&artifact.path, contents).unwrap();
+        artifact
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Path of a file in the directory.
+    pub fn join(&self, name: &str) -> String {
+        format!("{}/{}", self.path, name)
+    }
+
+    // Name without the directory, for a file which a command makes in its
+    // working directory.
+    pub fn file_name(&self) -> &str {
+        Path::new(&self.path).file_name().unwrap().to_str().unwrap()
+    }
+}
+
+impl Drop for TestArtifact {
+    fn drop(&mut self) {
+        let path = Path::new(&self.path);
+        let _ = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+    }
+}
+
+impl fmt::Display for TestArtifact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+impl AsRef<Path> for TestArtifact {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.path)
+    }
+}
+
+// Compiles the source from stdin, and returns stdout and stderr.
+pub fn compile_stdin(source_code: &str, options: &[&str]) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("-")
+        .args(options)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The compiler may exit without reading the source, e.g. on a wrong
+    // option, which closes the pipe before the write.
+    if let Err(e) = child.stdin.take().unwrap().write_all(source_code.as_bytes()) {
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    }
+    let output = child.wait_with_output().unwrap();
+
+    let answer = String::from_utf8(output.stdout).unwrap();
+    let diag = String::from_utf8(output.stderr).unwrap();
+    (answer, diag)
+}
+
+// Compiles the source file into an executable, and returns the name of the
+// source, stderr and whether the executable is built.
+pub fn compile_output_with(source_code: &str, options: &[&str]) -> (String, String, bool) {
+    let output_file = TestArtifact::new("");
+    let input_file = output_file.with_suffix(".rs");
+    fs::write(&input_file, source_code).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg(input_file.path())
+        .arg("-o")
+        .arg(output_file.path())
+        .args(options)
+        .output()
+        .unwrap();
+
+    let built = Path::new(output_file.path()).exists();
+
+    let answer = String::from_utf8(output.stdout).unwrap();
+    let diag = String::from_utf8(output.stderr).unwrap();
+    println!("{}{}", answer, diag);
+    (input_file.to_string(), diag, built)
+}
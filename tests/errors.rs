@@ -0,0 +1,902 @@
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+
+use common::TestArtifact;
+use common::compile_stdin;
+use common::compile_output_with;
+
+use yrc::compile_str;
+use yrc::explain;
+use yrc::AsmError;
+use yrc::CompileError;
+use yrc::LineTable;
+use yrc::parse::ParseErrorKind::*;
+use yrc::token::TokenErrorKind::*;
+
+fn compile_error_output_with(source_code: &str, options: &[&str]) -> (String, String) {
+    let (input_file, diag, built) = compile_output_with(source_code, options);
+    assert!(!built);
+    (input_file, diag)
+}
+
+fn compile_error_output(source_code: &str) -> String {
+    let (_, diag) = compile_error_output_with(source_code, &[]);
+    diag
+}
+
+#[test]
+fn report_multiple_errors() {
+    let output = compile_error_output("fn main() {\n\
+                                           let a: i32\n\
+                                           a = 1;\n\
+                                           let b: i32;\n\
+                                           b = (a + 2;\n\
+                                           return c;\n\
+                                       }\n");
+    assert_eq!(output.matches("Semicolon is needed!").count(), 1);
+    assert_eq!(output.matches("Parentheses are not closed!").count(), 1);
+    assert_eq!(output.matches("Unknown variable!").count(), 1);
+}
+
+#[test]
+fn report_errors_in_multiple_functions() {
+    let output = compile_error_output("fn foo(a i32) {\n\
+                                           return a;\n\
+                                       }\n\
+                                       fn main() {\n\
+                                           return 1 +;\n\
+                                       }\n");
+    assert_eq!(output.matches("Colon is needed!").count(), 1);
+    assert_eq!(output.matches("Invalid expression!").count(), 1);
+    assert!(!output.contains("Cannot use in top level!"));
+}
+
+#[test]
+fn report_unclosed_block() {
+    let output = compile_error_output("fn main() {\n\
+                                           return 1;\n");
+    assert!(output.contains("Parentheses are not closed!"));
+}
+
+#[test]
+fn report_error_code() {
+    let output = compile_error_output("fn main() { return 1 }");
+    assert!(output.contains("error[E0101]: Semicolon is needed!"));
+
+    let output = compile_error_output("fn main() { return 1; } $");
+    assert!(output.contains("error[E0001]: Unexpected character `$`!"));
+
+    let output = compile_error_output("fn main() { 1 = 2; return 1; }");
+    assert!(output.contains("error[E0202]: Lvalue is not assignable!"));
+
+    // A slice takes two of the six registers.
+    let source = "fn f(a: i64, b: &str, c: i64, d: i64, e: i64, g: i64) -> i64 { return a; }\n\
+                  fn main() { let s: &str = \"x\"; return f(1, s, 3, 4, 5, 6); }";
+    for level in ["-O0", "-O1"].iter() {
+        let (_, diag) = compile_error_output_with(source, &[level]);
+        assert!(diag.contains("error[E0204]: Too many arguments for the registers!"));
+    }
+    let source = "fn f(a: i64, b: i64, c: i64, d: i64, e: i64) -> i64 { return a; }\n\
+                  fn main() { return f(1, 2, 3, 4, 5); }";
+    let (_, diag) = compile_error_output_with(source, &["--target=windows-gnu", "-s"]);
+    assert!(diag.contains("error[E0204]"));
+}
+
+#[test]
+fn emit_tokens() {
+    let input_file = TestArtifact::with_contents(".rs", "fn main() {\n    return \"a\nb\" + 1;\n}\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg(input_file.path())
+        .arg("--emit=tokens")
+        .output()
+        .unwrap();
+
+    let answer = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = answer.lines().collect();
+    assert_eq!(lines, ["1:1 rsv fn", "1:4 idt main", "1:8 op (", "1:9 op )", "1:11 op {",
+                       "2:5 rsv return", "2:12 str \"a\\nb\"", "3:4 op +", "3:6 num 1",
+                       "3:7 op ;", "4:1 op }", "5:1 end"]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn trace_phases() {
+    let source = "fn main() {\n    let a: i32 = 2;\n    return a;\n}\n";
+
+    let (_, output, built) = compile_output_with(source, &[]);
+    assert!(built);
+    assert_eq!(output, "");
+
+    let (_, output, built) = compile_output_with(source, &["-v"]);
+    assert!(built);
+    assert!(output.contains("[tokenize] 17 tokens\n"));
+    assert!(output.contains("[parse] fn main (0 args, stack 16)\n"));
+    assert!(output.contains("[codegen] "));
+    assert!(!output.contains("3:12 idt a\n"));
+
+    let (_, output, built) = compile_output_with(source, &["-vv"]);
+    assert!(built);
+    assert!(output.contains("3:12 idt a\n"));
+    assert!(output.contains("Function main (stack 16)\n  Block\n"));
+
+    let (_, output, _) = compile_output_with(source, &["--trace"]);
+    assert!(output.contains("[tokenize] 17 tokens\n"));
+}
+
+#[test]
+fn read_stdin() {
+    let output_file = TestArtifact::new("");
+    compile_stdin("fn main() { return 3; }", &["-o", output_file.path()]);
+    let status = Command::new(output_file.path()).status().unwrap();
+    fs::remove_file(&output_file).unwrap();
+    assert_eq!(status.code(), Some(3));
+
+    let (_, diag) = compile_stdin("fn main() { return $; }", &["-o", output_file.path()]);
+    assert!(diag.contains(" --> <stdin>:1:20\n"));
+    assert!(fs::remove_file(&output_file).is_err());
+
+    let (answer, _) = compile_stdin("fn main() {}", &["--emit=tokens"]);
+    assert_eq!(answer, "1:1 rsv fn\n1:4 idt main\n1:8 op (\n1:9 op )\n\
+                        1:11 op {\n1:12 op }\n1:13 end\n");
+}
+
+#[test]
+fn explain_error_code() {
+    let codes = ["E0001", "E0002", "E0003", "E0004", "E0005", "E0006",
+                 "E0101", "E0102", "E0103", "E0104", "E0105", "E0106", "E0107",
+                 "E0108", "E0109", "E0110", "E0111", "E0112", "E0113", "E0114",
+                 "E0115", "E0116", "E0117", "E0118", "E0119", "E0120", "E0121",
+                 "E0122", "E0123", "E0124",
+                 "E0201", "E0202", "E0203", "E0204", "E0205"];
+    for code in codes.iter() {
+        assert!(explain(code).is_some(), "{} is not explained", code);
+    }
+    assert!(explain("E9999").is_none());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("--explain")
+        .arg("E0101")
+        .output()
+        .unwrap();
+    let answer = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(answer.trim(), explain("E0101").unwrap());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("--explain")
+        .arg("E9999")
+        .output()
+        .unwrap();
+    assert!(output.stdout.is_empty());
+    let diag = String::from_utf8(output.stderr).unwrap();
+    assert!(diag.contains("Unknown error code!"));
+}
+
+#[test]
+fn report_json_format() {
+    let (input_file, output) = compile_error_output_with("fn main() {\n\
+                                                              let a: i32\n\
+                                                              a = 1;\n\
+                                                              return b;\n\
+                                                          }\n",
+                                                          &["--error-format=json"]);
+    let lines: Vec<serde_json::Value> = output.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+
+    assert_eq!(lines[0]["code"], "E0101");
+    assert_eq!(lines[0]["severity"], "error");
+    assert_eq!(lines[0]["message"], "Semicolon is needed!");
+    assert_eq!(lines[0]["file"], input_file.as_str());
+    assert_eq!(lines[0]["line"], 3);
+    assert_eq!(lines[0]["column"], 1);
+    assert_eq!(lines[0]["span_len"], 1);
+
+    assert_eq!(lines[1]["code"], "E0111");
+    assert_eq!(lines[1]["line"], 4);
+    assert_eq!(lines[1]["column"], 8);
+
+    let (_, output) = compile_error_output_with("fn main() { 1 = 2; return 1; }",
+                                                &["--error-format=json"]);
+    let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(line["code"], "E0202");
+    assert!(line["line"].is_null());
+    assert!(line["column"].is_null());
+}
+
+#[test]
+fn report_without_color() {
+    let (input_file, output) = compile_error_output_with("fn main() {\n    return 1\n}\n",
+                                                          &["--color=never"]);
+    let expect = format!("error[E0101]: Semicolon is needed!\n\
+                          \x20--> {}:3:1\n\
+                          \x20 |\n\
+                          3 | }}\n\
+                          \x20 | ^\n\
+                          \n\
+                          error: aborting due to 1 previous error\n\
+                          \n", input_file);
+    assert_eq!(output, expect);
+}
+
+#[test]
+fn report_with_color() {
+    let (_, output) = compile_error_output_with("fn main() {\n    return 1\n}\n",
+                                                &["--color=always"]);
+    assert!(output.contains("\x1b[1;31merror[E0101]\x1b[0m: Semicolon is needed!"));
+    assert!(output.contains("\x1b[2m"));
+    assert!(output.contains("\x1b[1;31m^\x1b[0m"));
+
+    // Diagnostics are not colored when stderr is not a terminal.
+    let (_, output) = compile_error_output_with("fn main() {\n    return 1\n}\n",
+                                                &["--color=auto"]);
+    assert!(!output.contains("\x1b["));
+}
+
+const LINT_SOURCE: &str = "fn main() {\n\
+                               let a: i32;\n\
+                               let _b: i32;\n\
+                               return 1;\n\
+                               return 2;\n\
+                           }\n";
+
+#[test]
+fn report_lint_warnings() {
+    let (_, output, built) = compile_output_with(LINT_SOURCE, &[]);
+    assert!(built);
+    assert!(output.contains("warning[unused-variables]: Variable `a` is never used!"));
+    assert!(!output.contains("`_b`"));
+    assert!(output.contains("warning[unreachable-code]: Unreachable statement!"));
+    assert!(output.contains("warning: 2 warnings emitted"));
+}
+
+#[test]
+fn allow_lint() {
+    let (_, output, built) = compile_output_with(LINT_SOURCE, &["-A", "unused-variables"]);
+    assert!(built);
+    assert!(!output.contains("unused-variables"));
+    assert!(output.contains("warning[unreachable-code]"));
+
+    let (_, output, built) = compile_output_with(LINT_SOURCE, &["-A", "warnings"]);
+    assert!(built);
+    assert_eq!(output, "");
+}
+
+#[test]
+fn deny_lint() {
+    let (_, output) = compile_error_output_with(LINT_SOURCE, &["--deny", "warnings"]);
+    assert!(output.contains("error[unused-variables]: Variable `a` is never used!"));
+    assert!(output.contains("error[unreachable-code]: Unreachable statement!"));
+    assert!(output.contains("error: aborting due to 2 previous errors"));
+
+    // Later flags take precedence.
+    let (_, output) = compile_error_output_with(LINT_SOURCE, &["-D", "warnings",
+                                                               "-W", "unused-variables"]);
+    assert!(output.contains("warning[unused-variables]"));
+    assert!(output.contains("error[unreachable-code]"));
+    assert!(output.contains("error: aborting due to 1 previous error; 1 warning emitted"));
+}
+
+#[test]
+fn unknown_lint() {
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("-A")
+        .arg("unused-varaibles")
+        .arg("foo.rs")
+        .output()
+        .unwrap();
+    assert!(output.stdout.is_empty());
+    let diag = String::from_utf8(output.stderr).unwrap();
+    assert!(diag.contains("Unknown lint! (unused-varaibles)"));
+    assert!(diag.contains("unused-variables"));
+}
+
+fn first_diagnostic(source_code: &str) -> serde_json::Value {
+    let (_, output) = compile_error_output_with(source_code, &["--error-format=json"]);
+    serde_json::from_str(output.lines().next().unwrap()).unwrap()
+}
+
+fn assert_diagnostic(source_code: &str, code: &str, message: &str, line: u64, column: u64) {
+    let diag = first_diagnostic(source_code);
+    assert_eq!(diag["code"], code);
+    assert_eq!(diag["message"], message);
+    assert_eq!(diag["line"], line);
+    assert_eq!(diag["column"], column);
+}
+
+#[test]
+fn report_token_errors() {
+    assert_diagnostic("fn main() {\n    return 1 $ 2;\n}\n",
+                      "E0001", "Unexpected character `$`!", 2, 14);
+    assert_diagnostic("fn main() {\n    return \"abc;\n}\n",
+                      "E0002", "String literal is not terminated!", 2, 12);
+    assert_diagnostic("fn main() {\n    /* comment\n    return 1;\n}\n",
+                      "E0003", "Block comment is not terminated!", 2, 5);
+    assert_diagnostic("fn main() {\n    return \"a\\qb\";\n}\n",
+                      "E0004", "Unknown escape sequence `\\q`!", 2, 14);
+    assert_diagnostic("fn main() {\n    return 99999999999999999999999;\n}\n",
+                      "E0005", "Number is too large!", 2, 12);
+    assert_diagnostic("fn main() {\n    return 12ab;\n}\n",
+                      "E0006", "Invalid digit `a` in number!", 2, 14);
+}
+
+#[test]
+fn report_too_large_number() {
+    assert_diagnostic("fn main() {\n    let a: u64;\n    a = 1234567890123456789012345;\n    return 0;\n}\n",
+                      "E0005", "Number is too large!", 3, 9);
+    assert_diagnostic("fn main() {\n    return 18446744073709551616;\n}\n",
+                      "E0005", "Number is too large!", 2, 12);
+}
+
+#[test]
+fn report_unterminated_string() {
+    assert_diagnostic("fn main() { return \"oops",
+                      "E0002", "String literal is not terminated!", 1, 20);
+    assert_diagnostic("fn main() { return \"oops\\",
+                      "E0002", "String literal is not terminated!", 1, 20);
+    assert_diagnostic("fn main() {\n    let s: &str;\n    s = \"abc\n    return 0;\n}\n",
+                      "E0002", "String literal is not terminated!", 3, 9);
+
+    // A string literal at the very end of file is not a tokenize error.
+    let diag = first_diagnostic("fn main() { return 0; } \"abc\"");
+    assert_eq!(diag["code"], "E0112");
+    assert_eq!(diag["column"], 25);
+}
+
+#[test]
+fn report_unterminated_comment() {
+    assert_diagnostic("fn main() { return 1; } /*",
+                      "E0003", "Block comment is not terminated!", 1, 25);
+    assert_diagnostic("fn main() { return 1; } /* *",
+                      "E0003", "Block comment is not terminated!", 1, 25);
+    assert_diagnostic("fn main() {\n    return 1; /*/\n}\n",
+                      "E0003", "Block comment is not terminated!", 2, 15);
+}
+
+#[test]
+fn report_operator_at_end_of_file() {
+    assert_diagnostic("fn main() { return 1 /",
+                      "E0114", "Invalid expression!", 1, 23);
+    assert_diagnostic("fn main() { return 1 -",
+                      "E0114", "Invalid expression!", 1, 23);
+    assert_diagnostic("fn main() { return 1 <",
+                      "E0114", "Invalid expression!", 1, 23);
+    assert_diagnostic("fn main() { return 1 !",
+                      "E0101", "Semicolon is needed!", 1, 22);
+    assert_diagnostic("/",
+                      "E0112", "Cannot use in top level!", 1, 1);
+}
+
+#[test]
+fn report_split_comparison_operators() {
+    // `=!` is `=` followed by `!`, not a single operator.
+    assert_diagnostic("fn main() { let a: i32; a=!1; return 1; }",
+                      "E0114", "Invalid expression!", 1, 27);
+    assert_diagnostic("fn main() { return 1==!2; }",
+                      "E0114", "Invalid expression!", 1, 23);
+    assert_diagnostic("fn main() { return 1<=>2; }",
+                      "E0114", "Invalid expression!", 1, 23);
+    // `< =` is not `<=`.
+    assert_diagnostic("fn main() { return 1< =2; }",
+                      "E0114", "Invalid expression!", 1, 23);
+}
+
+#[test]
+fn report_non_ascii_character() {
+    assert_diagnostic("fn main() {\n    let é: i32;\n    return 1;\n}\n",
+                      "E0001", "Unexpected character `é`!", 2, 9);
+    assert_diagnostic("fn main() {\n    /* é */ let a: i32; a = 1 → 2;\n}\n",
+                      "E0001", "Unexpected character `→`!", 2, 31);
+    assert_diagnostic("fn main() {\n    return \"é\\é\";\n}\n",
+                      "E0004", "Unknown escape sequence `\\é`!", 2, 14);
+}
+
+#[test]
+fn report_discard_read() {
+    assert_diagnostic("fn main() {\n    let _: i32 = 1;\n    return _;\n}\n",
+                      "E0111", "Unknown variable!", 3, 12);
+    assert_diagnostic("fn foo(_: i32) -> i32 {\n    return _;\n}\n",
+                      "E0111", "Unknown variable!", 2, 12);
+
+    let (_, output, built) = compile_output_with("fn main() {\n\
+                                                      let _tmp: i32;\n\
+                                                      let _: i32;\n\
+                                                      return 1;\n\
+                                                  }\n", &[]);
+    assert!(built);
+    assert_eq!(output, "");
+}
+
+#[test]
+fn report_keyword_as_identifier() {
+    assert_diagnostic("fn main() {\n    let if: i32;\n    return 1;\n}\n",
+                      "E0115", "Keyword `if` cannot be used as an identifier!", 2, 9);
+    assert_diagnostic("fn foo(a: i32, return: i32) -> i32 {\n    return a;\n}\n",
+                      "E0115", "Keyword `return` cannot be used as an identifier!", 1, 16);
+    assert_diagnostic("fn while() {\n    return 1;\n}\n",
+                      "E0115", "Keyword `while` cannot be used as an identifier!", 1, 4);
+    assert_diagnostic("fn main() {\n    let pub: i32;\n    return 1;\n}\n",
+                      "E0115", "Keyword `pub` cannot be used as an identifier!", 2, 9);
+    assert_diagnostic("fn main() {\n    return i32 + 1;\n}\n",
+                      "E0115", "Keyword `i32` cannot be used as an identifier!", 2, 12);
+}
+
+#[test]
+fn report_division_by_zero() {
+    assert_diagnostic("fn main() {\n    return 1 / 0;\n}\n",
+                      "E0116", "Division by zero!", 2, 16);
+    // The divisor is evaluated at every optimization level.
+    assert_diagnostic("fn main() {\n    let a: i32 = 6 / (2 - 2);\n    return a;\n}\n",
+                      "E0116", "Division by zero!", 2, 22);
+    assert_diagnostic("fn main() {\n    return 7 % false;\n}\n",
+                      "E0116", "Division by zero!", 2, 16);
+}
+
+#[test]
+fn report_assign_as_value() {
+    assert_diagnostic("fn main() {\n    let a: i32;\n    let b: i32;\n    a = b = 3;\n    return a;\n}\n",
+                      "E0117", "Assignment cannot be used as a value!", 4, 9);
+    assert_diagnostic("fn main() {\n    let n: i32;\n    if (n = 1) != 0 {\n        return n;\n    }\n    return 0;\n}\n",
+                      "E0117", "Assignment cannot be used as a value!", 3, 9);
+    assert_diagnostic("fn main() {\n    let a: i32;\n    let b: i32 = a = 1;\n    return b;\n}\n",
+                      "E0117", "Assignment cannot be used as a value!", 3, 18);
+    assert_diagnostic("fn main() {\n    let a: i32;\n    return a = 1;\n}\n",
+                      "E0117", "Assignment cannot be used as a value!", 3, 12);
+}
+
+#[test]
+fn report_unit_as_value() {
+    let foo = "fn foo() {\n    let _a: i32 = 1;\n}\n";
+    assert_diagnostic(&format!("{}fn main() {{\n    return foo() + 1;\n}}\n", foo),
+                      "E0118", "Function without a return type cannot be used as a value!", 5, 12);
+    assert_diagnostic(&format!("{}fn main() {{\n    let a: i32 = foo();\n    return a;\n}}\n", foo),
+                      "E0118", "Function without a return type cannot be used as a value!", 5, 18);
+    assert_diagnostic(&format!("{}fn bar(a: i32) -> i32 {{\n    return a;\n}}\nfn main() {{\n    bar(foo());\n}}\n", foo),
+                      "E0118", "Function without a return type cannot be used as a value!", 8, 9);
+    assert_diagnostic(&format!("{}fn main() {{\n    foo() == 1;\n}}\n", foo),
+                      "E0118", "Function without a return type cannot be used as a value!", 5, 5);
+}
+
+#[test]
+fn report_index_out_of_bounds() {
+    assert_diagnostic("fn main() {\n    let a: [i32; 3];\n    a[3] = 1;\n}\n",
+                      "E0119", "Index is out of bounds!", 3, 7);
+    assert_diagnostic("static G: [i8; 2];\nfn main() -> i8 {\n    return G[2];\n}\n",
+                      "E0119", "Index is out of bounds!", 3, 14);
+}
+
+#[test]
+fn report_too_deep() {
+    let source = format!("fn main() -> i64 {{\n    return {}1{};\n}}\n", "(".repeat(200), ")".repeat(200));
+    assert_diagnostic(&source, "E0120", "Nesting is too deep!", 2, 140);
+}
+
+#[test]
+fn report_after_shebang() {
+    assert_diagnostic("#!/usr/bin/env yrc-run\nfn main() {\n    return 1 $ 2;\n}\n",
+                      "E0001", "Unexpected character `$`!", 3, 14);
+    // Only the first line can be a shebang.
+    assert_diagnostic("\n#!/usr/bin/env yrc-run\nfn main() {\n    return 1;\n}\n",
+                      "E0001", "Unexpected character `#`!", 2, 1);
+}
+
+fn assert_span(source_code: &str, code: &str, column: u64, span_len: u64) {
+    let diag = first_diagnostic(source_code);
+    assert_eq!(diag["code"], code);
+    assert_eq!(diag["column"], column);
+    assert_eq!(diag["span_len"], span_len);
+}
+
+#[test]
+fn report_spans() {
+    // Identifier
+    assert_span("fn main() { return foo; }", "E0111", 20, 3);
+    // Multi-character operator
+    assert_span("fn main() { let a: i32 <= 1; }", "E0101", 24, 2);
+    // String literal
+    assert_span("fn main() { return 1 \"abc\"; }", "E0101", 22, 5);
+    assert_span("fn main() { return 1 \"é\"; }", "E0101", 22, 3);
+    // Number literal
+    assert_span("fn main() { return 99999999999999999999999; }", "E0005", 20, 23);
+    // Escape sequence
+    assert_span("fn main() { return \"a\\qb\"; }", "E0004", 22, 2);
+    // End of file
+    assert_span("fn main() {", "E0103", 12, 1);
+
+    let (_, output) = compile_error_output_with("fn main() {\n    return value;\n}\n",
+                                                &["--color=never"]);
+    assert!(output.contains("2 |     return value;\n  |            ^^^^^\n"));
+
+    let (_, output, _) = compile_output_with(LINT_SOURCE, &["--color=never"]);
+    assert!(output.contains("5 | return 2;\n  | ^^^^^^^^^\n"));
+}
+
+#[test]
+fn report_truncated_programs() {
+    assert_diagnostic("fn main() {",
+                      "E0103", "Parentheses are not closed!", 1, 12);
+    assert_diagnostic("fn foo(a: i32, b: i32);\nfn main() { foo(1,",
+                      "E0103", "Parentheses are not closed!", 2, 16);
+    assert_diagnostic("fn foo(a: i32, b: i32);\nfn main() { foo(1",
+                      "E0103", "Parentheses are not closed!", 2, 16);
+    assert_diagnostic("fn foo(a: i32,",
+                      "E0103", "Parentheses are not closed!", 1, 7);
+    assert_diagnostic("fn main() { let a:",
+                      "E0108", "Type is expected here!", 1, 19);
+    assert_diagnostic("fn",
+                      "E0106", "Function is expected here!", 1, 3);
+    assert_diagnostic("static",
+                      "E0107", "Variable is expected here!", 1, 7);
+}
+
+#[test]
+fn report_unknown_name_positions() {
+    assert_span("fn main() { a = 1; return 0; }", "E0111", 13, 1);
+    assert_span("fn main() { return foo(1, 2); }", "E0111", 20, 3);
+    assert_span("fn main() { let a: i32; return a[0]; }", "E0110", 32, 4);
+    assert_span("fn main() { let s: str; return 1; }", "E0113", 20, 3);
+    assert_span("static s: str;", "E0113", 11, 3);
+}
+
+#[test]
+fn report_static_initializer_errors() {
+    assert_diagnostic("static A: i32 = 1;\nstatic B: i32 = 2 * A + 1;\n",
+                      "E0121", "Static must be initialized with a constant!", 2, 21);
+    assert_span("fn f() -> i32;\nstatic B: i32 = 2 * f();", "E0121", 21, 3);
+    assert_span("static B: i32 = 1 + \"a\";", "E0121", 17, 7);
+    assert_diagnostic("static A: i32;\nstatic P: &i32 = &A;\n",
+                      "E0122", "Address cannot be used to initialize a static!", 2, 18);
+    assert_diagnostic("static B: u8 = 256;\n",
+                      "E0123", "Value does not fit in the type!", 1, 16);
+    assert_span("static B: i8 = 0 - 129;", "E0123", 16, 7);
+    assert_span("static B: u32 = 0 - 1;", "E0123", 17, 5);
+    assert_span("static B: bool = 1;", "E0110", 18, 1);
+    assert_span("static B: i32 = 1 < 2;", "E0110", 17, 5);
+    assert_span("static B: [i32; 2] = 0;", "E0110", 8, 11);
+    assert_span("static B: i32 = C;", "E0111", 17, 1);
+}
+
+#[test]
+fn report_assign_to_static() {
+    assert_diagnostic("static N: i32;\nfn main() {\n    N = 1;\n}\n",
+                      "E0124", "Cannot assign to a static which is not `mut`!", 3, 5);
+    assert_span("static A: [i8; 4];\nfn main() { A[2] = 1; }", "E0124", 13, 4);
+    assert_span("static LIMIT: i32 = 4;\nfn main() { LIMIT = LIMIT + 1; }", "E0124", 13, 5);
+    // Only statics can be `mut`.
+    assert_diagnostic("fn main() { let mut a: i32; }",
+                      "E0115", "Keyword `mut` cannot be used as an identifier!", 1, 17);
+}
+
+#[test]
+fn report_unclosed_parenthesis() {
+    // The error points at the unclosed parenthesis after going back to it.
+    let (_, output) = compile_error_output_with("fn foo(a: i32) -> i32;\n\
+                                                 fn main() {\n\
+                                                 \x20   return foo(1 + 2\n",
+                                                &["--error-format=json"]);
+    let lines: Vec<serde_json::Value> = output.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["code"], "E0103");
+    assert_eq!(lines[0]["line"], 3);
+    assert_eq!(lines[0]["column"], 15);
+    // The block is not closed either.
+    assert_eq!(lines[1]["code"], "E0103");
+    assert_eq!(lines[1]["line"], 4);
+    assert_eq!(lines[1]["column"], 1);
+
+    // Unknown functions are reported before the arguments.
+    assert_span("fn main() { return bar(x); }", "E0111", 20, 3);
+}
+
+// The first error of the source code, compiled in memory without any
+// file or C compiler, and its line and column.
+fn compile_error(source_code: &str) -> (CompileError, Option<(usize, usize)>) {
+    let e = compile_str(source_code).unwrap_err();
+    let position = e.span().map(|span| LineTable::new(source_code).line_col(span.start));
+    (e, position)
+}
+
+#[test]
+fn in_process_token_errors() {
+    let cases = [
+        ("fn main() {\n    return 1 $ 2;\n}\n", UnexpectedChar('$'), 2, 14),
+        ("fn main() {\n    return \"abc;\n}\n", UnterminatedString, 2, 12),
+        ("fn main() { return 1; } /*", UnterminatedComment, 1, 25),
+        ("fn main() {\n    return \"a\\qb\";\n}\n", InvalidEscape('q'), 2, 14),
+        ("fn main() {\n    return 99999999999999999999999;\n}\n", NumberTooLarge, 2, 12),
+        ("fn main() {\n    return 12ab;\n}\n", InvalidDigit('a'), 2, 14),
+    ];
+    for (source, kind, line, column) in cases.iter() {
+        match compile_error(source) {
+            (CompileError::Token(e), position) => {
+                assert_eq!(e.kind(), kind, "{:?}", source);
+                assert_eq!(position, Some((*line, *column)), "{:?}", source);
+            },
+            (e, _) => panic!("{:?}: unexpected error {:?}", source, e),
+        }
+    }
+}
+
+#[test]
+fn in_process_parse_errors() {
+    let cases = [
+        // Missing semicolon
+        ("fn main() {\n    return 1\n}\n", ScolonExpected, 3, 1),
+        ("fn main() {\n    let a: i32 = 1\n    return a;\n}\n", ScolonExpected, 3, 5),
+        // Unclosed parenthesis
+        ("fn main() {\n    return (1 + 2;\n}\n", ParenExpected, 2, 18),
+        ("fn main() {", ParenExpected, 1, 12),
+        // Unknown variable
+        ("fn main() {\n    return value;\n}\n", UnknownVariable, 2, 12),
+        ("fn main() {\n    a = 1;\n}\n", UnknownVariable, 2, 5),
+        // Misused type keywords
+        ("fn main() {\n    return i32 + 1;\n}\n", KeywordAsIdentifier("i32"), 2, 12),
+        ("fn main() {\n    let a: foo;\n}\n", TypeExpected, 2, 12),
+        ("fn main() {\n    let s: str;\n}\n", NotSized, 2, 12),
+    ];
+    for (source, kind, line, column) in cases.iter() {
+        match compile_error(source) {
+            (CompileError::Parse(errors), position) => {
+                assert_eq!(errors[0].kind(), kind, "{:?}", source);
+                assert_eq!(position, Some((*line, *column)), "{:?}", source);
+            },
+            (e, _) => panic!("{:?}: unexpected error {:?}", source, e),
+        }
+    }
+}
+
+#[test]
+fn in_process_asm_errors() {
+    // Found while generating the code, so no position is known.
+    let cases = [
+        "fn main() {\n    1 = 2;\n}\n",
+        "fn one() -> i32 {\n    return 1;\n}\nfn main() {\n    one() = 2;\n}\n",
+    ];
+    for source in cases.iter() {
+        let (e, position) = compile_error(source);
+        assert!(matches!(e, CompileError::Asm(AsmError::Context)), "{:?}: {:?}", source, e);
+        assert_eq!(e.code(), Some("E0202"));
+        assert_eq!(position, None);
+    }
+}
+
+#[test]
+fn asm_to_stdout() {
+    let (answer, diag) = compile_stdin("fn main() { return 3; }", &["-s", "-o", "-"]);
+    assert!(answer.starts_with(".intel_syntax noprefix\n"));
+    assert!(answer.contains("main:\n"));
+    assert_eq!(diag, "");
+
+    let (answer, diag) = compile_stdin("fn main() { return $; }", &["-s", "-o", "-"]);
+    assert_eq!(answer, "");
+    assert!(diag.contains(" --> <stdin>:1:20\n"));
+}
+
+#[test]
+fn exit_status() {
+    let exit_code = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_yrc"))
+            .args(args)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+
+    let output_file = TestArtifact::new("");
+    let input_file = output_file.with_suffix(".rs");
+    fs::write(&input_file, "fn main() { return 0; }").unwrap();
+    assert_eq!(exit_code(&[input_file.path(), "-o", output_file.path()]), Some(0));
+    fs::remove_file(&output_file).unwrap();
+
+    fs::write(&input_file, "fn main() { return $; }").unwrap();
+    assert_eq!(exit_code(&[input_file.path(), "-o", output_file.path()]), Some(1));
+    fs::remove_file(&input_file).unwrap();
+
+    assert_eq!(exit_code(&[input_file.path(), "-o", output_file.path()]), Some(2));
+    assert_eq!(exit_code(&["--no-such-option"]), Some(1));
+    assert_eq!(exit_code(&["--explain", "E0001"]), Some(0));
+}
+
+#[test]
+fn diagnostics_on_stderr() {
+    let (answer, diag) = compile_stdin("fn main() { return $; }", &["-o", "-", "-s"]);
+    assert_eq!(answer, "");
+    assert!(!diag.is_empty());
+
+    let (answer, diag) = compile_stdin("fn main() { return $; }", &["--emit=tokens"]);
+    assert_eq!(answer, "");
+    assert!(!diag.is_empty());
+
+    let (answer, diag) = compile_stdin("fn main() { return 0; }", &["--emit=ast-json"]);
+    assert!(!answer.is_empty());
+    assert_eq!(diag, "");
+
+    let (answer, diag) = compile_stdin("fn main() { return 0; }", &["--no-such-option"]);
+    assert_eq!(answer, "");
+    assert!(diag.contains("Unknown option `--no-such-option`!"));
+}
+
+#[test]
+fn temp_files_cleaned_up() {
+    let base = TestArtifact::dir();
+    let work_dir = Path::new(base.path()).join("work");
+    let temp_dir = Path::new(base.path()).join("temp");
+    fs::create_dir_all(&work_dir).unwrap();
+    fs::create_dir_all(&temp_dir).unwrap();
+    let list = |dir: &std::path::Path| {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+
+    let compile = |source: &str| {
+        fs::write(work_dir.join("prog.rs"), source).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_yrc"))
+            .arg("prog.rs")
+            .current_dir(&work_dir)
+            .env("TMPDIR", &temp_dir)
+            .status()
+            .unwrap()
+    };
+
+    let status = compile("fn main() { return 0; }");
+    assert!(status.success());
+    assert_eq!(list(&work_dir), ["prog", "prog.rs"]);
+    assert!(list(&temp_dir).is_empty());
+    fs::remove_file(work_dir.join("prog")).unwrap();
+
+    let status = compile("fn main() { return $; }");
+    assert_eq!(status.code(), Some(1));
+    assert_eq!(list(&work_dir), ["prog.rs"]);
+    assert!(list(&temp_dir).is_empty());
+}
+
+#[test]
+fn move_asm_output() {
+    // tmpfs is usually another file system than the working directory.
+    let shm = std::path::Path::new("/dev/shm");
+    let parent = if shm.is_dir() { shm.to_path_buf() } else { std::env::temp_dir() };
+    let temp_dir = TestArtifact::new_in(&parent, "");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    let input_file = TestArtifact::with_contents("", "fn main() { return 0; }");
+    let output_file = input_file.with_suffix(".out");
+    let asm_file = input_file.with_suffix(".s");
+    let compile = |output: &str| {
+        Command::new(env!("CARGO_BIN_EXE_yrc"))
+            .arg(input_file.path())
+            .arg("-s")
+            .arg("-o")
+            .arg(output)
+            .env("TMPDIR", temp_dir.path())
+            .output()
+            .unwrap()
+    };
+
+    let output = compile(asm_file.path());
+    assert_eq!(output.status.code(), Some(0));
+    assert!(fs::read_to_string(&asm_file).unwrap().contains("main:\n"));
+    assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+
+    // Failures to move the output are reported as IO errors.
+    let output = compile(&format!("{}/no/such/dir.s", output_file));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!output.stderr.is_empty());
+    assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn eval_expressions() {
+    let eval = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_yrc")).args(args).output().unwrap();
+        (output.status.code(),
+         String::from_utf8(output.stdout).unwrap(),
+         String::from_utf8(output.stderr).unwrap())
+    };
+    assert_eq!(eval(&["-e", "1 + 2 * 3"]), (Some(0), "7\n".to_string(), String::new()));
+    assert_eq!(eval(&["run", "-O2", "-e", "(1 - 5) * 8 / 3"]), (Some(0), "-10\n".to_string(), String::new()));
+
+    // The statements before the last expression are run in order.
+    let (status, stdout, _) = eval(&["-e", "let x: i64 = 0; while x < 5 { x = x + 1; }", "-e", "x * 2"]);
+    assert_eq!((status, stdout.as_str()), (Some(0), "10\n"));
+    let (status, stdout, _) = eval(&["-e", "let x: i64 = 1; if x == 1 { x = 7; }"]);
+    assert_eq!((status, stdout.as_str()), (Some(0), ""));
+    let (status, stdout, _) = eval(&["-e", "let x: i32 = 0 - 1;", "-e", "x"]);
+    assert_eq!((status, stdout.as_str()), (Some(0), "-1\n"));
+
+    let lib_file = TestArtifact::with_contents(".rs", "fn fib(n: i64) -> i64 {\n\
+                                                           if n < 2 { return n; }\n\
+                                                           return fib(n - 1) + fib(n - 2);\n\
+                                                       }\n");
+    let (status, stdout, _) = eval(&["-e", "fib(10)", "--include", lib_file.path()]);
+    assert_eq!((status, stdout.as_str()), (Some(0), "55\n"));
+
+    // Errors point into the snippet as it is given, and into the included
+    // files.
+    let (status, stdout, stderr) = eval(&["-e", "let x: i64 = 3; x * $"]);
+    assert_eq!(status, Some(1));
+    assert!(stdout.is_empty());
+    assert!(stderr.contains(" --> <-e>:1:21\n  |\n1 | let x: i64 = 3; x * $\n"));
+    let (status, _, stderr) = eval(&["-e", "let x: i64 = 3;", "-e", "y * x"]);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("Unknown variable!") && stderr.contains(" --> <-e>:2:1\n  |\n2 | y * x\n"));
+    let (status, _, stderr) = eval(&["-e", "1 +"]);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains(" --> <-e>:1:4\n  |\n1 | 1 +\n"));
+    let (status, _, stderr) = eval(&["-e", "fib(z)", "--include", lib_file.path()]);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains(" --> <-e>:1:5\n"));
+    let (status, _, stderr) = eval(&["--error-format=json", "-e", "2 * y", "--include", lib_file.path()]);
+    assert_eq!(status, Some(1));
+    assert!(stderr.contains("\"file\":\"<-e>\",\"line\":1,\"column\":5,"));
+}
+
+#[test]
+fn check_and_version() {
+    let input_file = TestArtifact::with_contents(".rs", "fn main() { let a: i32 = 1; return $; }");
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .args(["check", input_file.path()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("error"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yrc"))
+        .arg("--version")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("yrc {}\n", env!("CARGO_PKG_VERSION")));
+}
+
+#[test]
+fn check_touches_nothing() {
+    let good_file = TestArtifact::with_contents(".rs", "fn main() -> i32 {\n    return 0;\n}\n");
+    let bad_file = TestArtifact::with_contents(".rs", "fn main() {\n    return $;\n}\n");
+    // Neither a temporary file nor a program in PATH is needed.
+    let tmp_dir = TestArtifact::dir();
+    let work_dir = TestArtifact::dir();
+    let check = |args: &[&str], source_code: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_yrc"))
+            .args(args)
+            .current_dir(&work_dir)
+            .env("TMPDIR", tmp_dir.path())
+            .env("PATH", "")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(source_code.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.stdout.is_empty());
+        (output.status.code(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    let source = fs::read_to_string(&good_file).unwrap();
+    assert_eq!(check(&["--check", good_file.path(), "-"], &source), (Some(0), String::new()));
+
+    // Every file is checked even after an error.
+    let (status, diag) = check(&["check", bad_file.path(), good_file.path()], "");
+    assert_eq!(status, Some(1));
+    assert!(diag.contains(&format!(" --> {}:2:12\n", bad_file)));
+    assert!(!diag.contains(good_file.path()));
+
+    let (status, diag) = check(&["-", "--check"], "fn main() {\n    return $;\n}\n");
+    assert_eq!(status, Some(1));
+    assert!(diag.contains(" --> <stdin>:2:12\n"));
+
+    assert_eq!(fs::read_dir(&tmp_dir).unwrap().count(), 0);
+    assert_eq!(fs::read_dir(&work_dir).unwrap().count(), 0);
+}
+
@@ -0,0 +1,97 @@
+use std::thread;
+
+use yrc::compile_str;
+
+// Stack of the main thread, where the compiler runs.
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// Code of the first error compiling the source code, or None if it is
+// compiled. Either is fine for an input, but a panic is not.
+fn first_error(source: String) -> Option<&'static str> {
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || compile_str(&source).err().map(|e| e.code().unwrap()))
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+fn assert_error(source: &str, code: &str) {
+    assert_eq!(first_error(source.to_string()), Some(code), "{:?}", source);
+}
+
+#[test]
+fn unsized_types() {
+    assert_error("fn myprint(s: &str);\nfn print(a: i32, s: str, b: i32);\n", "E0113");
+    assert_error("fn f() -> str;\n", "E0113");
+    assert_error("fn main() { let a: [str; 2]; }", "E0113");
+    assert_error("static A: &[str; 2];", "E0113");
+}
+
+#[test]
+fn huge_arrays() {
+    assert_error("fn main() { let a: [i32; 18446744073709551615]; }", "E0113");
+    assert_error("static A: [i64; 4611686018427387904];", "E0113");
+    assert_error("fn main() { let a: [[i32; 4294967296]; 4294967296]; }", "E0113");
+    assert_error("fn main() { let a: [i8; 2147483648]; }", "E0113");
+}
+
+#[test]
+fn index_out_of_bounds() {
+    assert_error("fn main() { let a: [i8; 3]; a[4] = 5; }", "E0119");
+    assert_error("fn main() { let a: [i8; 3]; a[3] = 5; }", "E0119");
+    assert_error("fn main() -> i64 { let a: [i64; 2]; return a[18446744073709551615]; }", "E0119");
+    assert_error("static G: [i32; 4];\nfn main() -> i32 { return G[4]; }", "E0119");
+    // Found after recovering from another error.
+    assert_error("static G: [i32t; 4];\nfn main() { let a: [i8; 3]; a[4] = 5; }", "E0108");
+}
+
+#[test]
+fn arrays_as_values() {
+    assert_error("fn main() { let a: [i8; 3]; a; }", "E0110");
+    assert_error("static mut G: [i32; 4];\nfn main() { let a: [i8; 3]; G[3] = a; }", "E0110");
+    assert_error("fn main() { let a: [i64; 3]; let b: [i64; 3]; a = b; }", "E0110");
+    assert_error("fn main() { let a: [i8; 3] = 1; }", "E0110");
+    assert_error("static G: [i64; 2];\nfn main() -> i64 { return *G; }", "E0110");
+    assert_error("fn f(a: [i32; 3]) -> i32 { return a[0]; }", "E0110");
+    assert_error("fn f() -> [i32; 2];", "E0110");
+    assert_eq!(first_error("fn g(p: &[i8; 3]) -> i8 { return 0; }\n\
+                            fn main() { let a: [i8; 3]; g(&a); }".to_string()), None);
+}
+
+#[test]
+fn truncated_programs() {
+    let source = "fn main() -> i64 { let a: [i32; 2]; a[1] = \"x\" + 1; return a[1] / 2; }";
+    for end in 0..source.len() {
+        first_error(source[..end].to_string());
+    }
+    assert_error("fn main() { return 1 /", "E0114");
+    assert_error("fn main() { return 99999999999999999999999; }", "E0005");
+    assert_error("fn main() { return \"oops\\", "E0002");
+    assert_error("fn main() { return 1; } /* *", "E0003");
+    assert_error("/", "E0112");
+}
+
+#[test]
+fn deep_nesting() {
+    let deep = 100000;
+    let cases = [
+        format!("fn main() -> i64 {{ return {}1{}; }}", "(".repeat(deep), ")".repeat(deep)),
+        format!("fn main() -> i64 {{ return 1{}; }}", "+1".repeat(deep)),
+        format!("fn main() -> i64 {{ let a: i64; return {}a; }}", "*&".repeat(deep)),
+        format!("fn main() {{ let a: {}i32; }}", "&".repeat(deep)),
+        format!("fn main() {{ let a: {}i32{}; }}", "[".repeat(deep), "; 1]".repeat(deep)),
+        format!("fn main() {{ {} return; }}", "if 1 == 1 ".repeat(deep)),
+        format!("fn main() {{ {}1; }}", "_ = ".repeat(deep)),
+        format!("fn f(a: i64) -> i64 {{ return a; }}\nfn main() -> i64 {{ return {}1{}; }}",
+                "f(".repeat(deep), ")".repeat(deep)),
+    ];
+    for source in cases.iter() {
+        assert_eq!(first_error(source.clone()), Some("E0120"));
+    }
+
+    // Up to the limit.
+    assert_eq!(first_error(format!("fn main() -> i64 {{ return 1{}; }}", "+1".repeat(128))), None);
+    assert_eq!(first_error(format!("fn main() {{ {} let _a: i32 = 1; {} }}",
+                                   "if 1 == 1 { ".repeat(127), "}".repeat(127))), None);
+}
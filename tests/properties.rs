@@ -0,0 +1,447 @@
+extern crate yrc;
+
+mod common;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+
+use proptest::prelude::*;
+
+use common::TestArtifact;
+
+use yrc::compiler_main;
+use yrc::intern::Interner;
+use yrc::token::tokenize;
+use yrc::token::Tokens;
+use yrc::parse::Ast;
+use yrc::parse::Node;
+use yrc::parse::NodeId;
+use yrc::parse::Parser;
+use yrc::parse::type_size;
+use yrc::parse::BinaryOpKind::*;
+
+// Random functions of integer arithmetic are evaluated from the AST which
+// the parser builds for them, and the result must be what the generated
+// program computes. With RUN_GENERATED=1, the programs are also built and
+// run at every optimization level, which needs the C compiler.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(u64),
+    Var(usize),
+    Neg(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>),
+}
+
+// The division traps, as `idiv` does.
+#[derive(Debug, PartialEq)]
+struct Trap;
+
+const PARAMS: [&str; 3] = ["a", "b", "c"];
+
+const SIGFPE: i32 = 8;
+
+// Bits of the integers an operation works on are kept, and the value is
+// sign extended to 64 bits.
+fn wrap(val: i64, size: usize) -> i64 {
+    match size {
+        4 => val as i32 as i64,
+        _ => val,
+    }
+}
+
+fn apply(op: Op, l: i64, r: i64, size: usize) -> Result<i64, Trap> {
+    let (l, r) = (wrap(l, size), wrap(r, size));
+    let min = if size == 4 { i32::MIN as i64 } else { i64::MIN };
+    let val = match op {
+        Op::Add => l.wrapping_add(r),
+        Op::Sub => l.wrapping_sub(r),
+        Op::Mul => l.wrapping_mul(r),
+        Op::Div | Op::Rem if r == 0 || (r == -1 && l == min) => {
+            return Err(Trap);
+        },
+        Op::Div => l / r,
+        Op::Rem => l % r,
+    };
+    Ok(wrap(val, size))
+}
+
+fn compare(cmp: Cmp, l: i64, r: i64, size: usize) -> bool {
+    let (l, r) = (wrap(l, size), wrap(r, size));
+    match cmp {
+        Cmp::Eq => l == r,
+        Cmp::Ne => l != r,
+        Cmp::Lt => l < r,
+        Cmp::Le => l <= r,
+        Cmp::Gt => l > r,
+        Cmp::Ge => l >= r,
+    }
+}
+
+impl Op {
+    fn text(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Rem => "%",
+        }
+    }
+
+    fn precedence(self) -> usize {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div | Op::Rem => 2,
+        }
+    }
+}
+
+impl Cmp {
+    fn text(self) -> &'static str {
+        match self {
+            Cmp::Eq => "==",
+            Cmp::Ne => "!=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        }
+    }
+}
+
+impl Expr {
+    fn typed(&self) -> bool {
+        match self {
+            Expr::Num(_) => false,
+            Expr::Var(_) => true,
+            Expr::Neg(e) => e.typed(),
+            Expr::Bin(_, l, r) => l.typed() || r.typed(),
+        }
+    }
+
+    // An expression with parameters in it works on integers of the widest
+    // of their types, and one of literals alone on 64 bits integers.
+    fn size(&self, sizes: &[usize; 3]) -> Option<usize> {
+        match self {
+            Expr::Num(_) => None,
+            Expr::Var(i) => Some(sizes[*i]),
+            Expr::Neg(e) => e.size(sizes),
+            Expr::Bin(_, l, r) => l.size(sizes).max(r.size(sizes)),
+        }
+    }
+
+    fn eval(&self, args: &[i64; 3], sizes: &[usize; 3]) -> Result<i64, Trap> {
+        let size = self.size(sizes).unwrap_or(8);
+        match self {
+            Expr::Num(val) => Ok(*val as i64),
+            Expr::Var(i) => Ok(args[*i]),
+            Expr::Neg(e) => apply(Op::Sub, 0, e.eval(args, sizes)?, size),
+            Expr::Bin(op, l, r) => {
+                let lval = l.eval(args, sizes)?;
+                let rval = r.eval(args, sizes)?;
+                apply(*op, lval, rval, size)
+            },
+        }
+    }
+
+    // Literals divide by zero, which the parser reports.
+    fn divides_by_zero(&self) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Var(_) => false,
+            Expr::Neg(e) => e.divides_by_zero(),
+            Expr::Bin(op, l, r) => {
+                let zero = matches!(op, Op::Div | Op::Rem) && !r.typed() && r.eval(&[0; 3], &[8; 3]) == Ok(0);
+                zero || l.divides_by_zero() || r.divides_by_zero()
+            },
+        }
+    }
+
+    fn precedence(&self) -> usize {
+        match self {
+            Expr::Bin(op, _, _) => op.precedence(),
+            _ => 3,
+        }
+    }
+}
+
+// Parentheses only where the precedence and the left associativity of the
+// operators need them.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Num(val) => write!(f, "{}", val),
+            Expr::Var(i) => write!(f, "{}", PARAMS[*i]),
+            Expr::Neg(e) => match **e {
+                Expr::Num(_) | Expr::Var(_) => write!(f, "-{}", e),
+                _ => write!(f, "-({})", e),
+            },
+            Expr::Bin(op, l, r) => {
+                if l.precedence() < op.precedence() {
+                    write!(f, "({})", l)?;
+                } else {
+                    write!(f, "{}", l)?;
+                }
+                write!(f, " {} ", op.text())?;
+                if r.precedence() <= op.precedence() {
+                    write!(f, "({})", r)
+                } else {
+                    write!(f, "{}", r)
+                }
+            },
+        }
+    }
+}
+
+// The parameters may be of different types, and `ty` is the type of the
+// result.
+#[derive(Clone)]
+struct Program {
+    ty: &'static str,
+    types: [&'static str; 3],
+    cond: (Cmp, Expr, Expr),
+    then: Expr,
+    other: Expr,
+    args: [i64; 3],
+}
+
+impl Program {
+    fn size(&self) -> usize {
+        if self.ty == "i32" { 4 } else { 8 }
+    }
+
+    fn sizes(&self) -> [usize; 3] {
+        let size = |ty| if ty == "i32" { 4 } else { 8 };
+        [size(self.types[0]), size(self.types[1]), size(self.types[2])]
+    }
+
+    fn exprs(&self) -> [&Expr; 4] {
+        [&self.cond.1, &self.cond.2, &self.then, &self.other]
+    }
+
+    fn eval(&self) -> Result<i64, Trap> {
+        let sizes = self.sizes();
+        let (cmp, l, r) = &self.cond;
+        let cmp_size = l.size(&sizes).max(r.size(&sizes)).unwrap_or(8);
+        let cond = compare(*cmp, l.eval(&self.args, &sizes)?, r.eval(&self.args, &sizes)?, cmp_size);
+        let body = if cond { &self.then } else { &self.other };
+        Ok(wrap(body.eval(&self.args, &sizes)?, self.size()))
+    }
+
+    fn source(&self) -> String {
+        let (cmp, l, r) = &self.cond;
+        let format = if self.ty == "i32" { "%d" } else { "%ld" };
+        let args: Vec<String> = self.args.iter().map(|arg| arg.to_string()).collect();
+        format!("fn printf(format: &i8, n: {ty}) -> i32;\n\
+                 fn f(a: {}, b: {}, c: {}) -> {ty} {{\n\
+                 \x20   if {} {} {} {{\n\
+                 \x20       return {};\n\
+                 \x20   }}\n\
+                 \x20   return {};\n\
+                 }}\n\
+                 fn main() -> i64 {{\n\
+                 \x20   printf(c\"{}\\n\", f({}));\n\
+                 \x20   return 0;\n\
+                 }}\n",
+                self.types[0], self.types[1], self.types[2],
+                l, cmp.text(), r, self.then, self.other, format, args.join(", "), ty = self.ty)
+    }
+}
+
+// A failing case is shown as the program, which is shrunk to the smallest
+// one that still fails.
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\n{}", self.source())
+    }
+}
+
+fn expr() -> impl Strategy<Value = Expr> {
+    let num = prop_oneof![
+        0..10u64,
+        prop::sample::select(vec![2147483647, 2147483648, 4294967295, 9223372036854775807]),
+    ];
+    let leaf = prop_oneof![
+        num.prop_map(Expr::Num),
+        (0..PARAMS.len()).prop_map(Expr::Var),
+    ];
+    let op = prop::sample::select(vec![Op::Add, Op::Sub, Op::Mul, Op::Div, Op::Rem]);
+    leaf.prop_recursive(4, 24, 2, move |inner| prop_oneof![
+        1 => inner.clone().prop_map(|e| Expr::Neg(Box::new(e))),
+        4 => (op.clone(), inner.clone(), inner).prop_map(|(op, l, r)| Expr::Bin(op, Box::new(l), Box::new(r))),
+    ])
+}
+
+fn program() -> impl Strategy<Value = Program> {
+    let cmp = prop::sample::select(vec![Cmp::Eq, Cmp::Ne, Cmp::Lt, Cmp::Le, Cmp::Gt, Cmp::Ge]);
+    let i32_arg = prop_oneof![
+        -3..4i64,
+        any::<i32>().prop_map(|v| v as i64),
+        prop::sample::select(vec![i32::MIN as i64, i32::MAX as i64]),
+    ];
+    let i64_arg = prop_oneof![
+        -3..4i64,
+        any::<i64>(),
+        prop::sample::select(vec![i64::MIN, i64::MAX]),
+    ];
+    // Operations and comparisons on parameters of different widths work in
+    // the wider type.
+    let args = prop_oneof![
+        (Just(("i32", ["i32"; 3])), [i32_arg.clone(), i32_arg.clone(), i32_arg.clone()].boxed()),
+        (Just(("i64", ["i64"; 3])), [i64_arg.clone(), i64_arg.clone(), i64_arg.clone()].boxed()),
+        (Just(("i64", ["i32", "i64", "i32"])),
+         (i32_arg.clone(), i64_arg, i32_arg).prop_map(|(a, b, c)| [a, b, c]).boxed()),
+    ];
+    (args, cmp, expr(), expr(), expr(), expr()).prop_map(|(((ty, types), args), cmp, l, r, then, other)| {
+        Program { ty, types, cond: (cmp, l, r), then, other, args }
+    })
+}
+
+fn eval_node(ast: &Ast, vars: &HashMap<usize, i64>, node: NodeId) -> Result<i64, Trap> {
+    match &ast[node] {
+        Node::Number { val } => Ok(*val as i64),
+        Node::LocalVariable { offset, ty: _ } => Ok(vars[offset]),
+        Node::BinaryOperator { kind, lhs, rhs } => {
+            let (size, _) = ast.int_type(*lhs, *rhs);
+            let l = eval_node(ast, vars, *lhs)?;
+            let r = eval_node(ast, vars, *rhs)?;
+            let cmp = match kind {
+                BinaryOpAdd => return apply(Op::Add, l, r, size),
+                BinaryOpSub => return apply(Op::Sub, l, r, size),
+                BinaryOpMul => return apply(Op::Mul, l, r, size),
+                BinaryOpDiv => return apply(Op::Div, l, r, size),
+                BinaryOpRem => return apply(Op::Rem, l, r, size),
+                BinaryOpEq => Cmp::Eq,
+                BinaryOpNe => Cmp::Ne,
+                BinaryOpLt => Cmp::Lt,
+                BinaryOpLe => Cmp::Le,
+                BinaryOpGr => Cmp::Gt,
+                BinaryOpGe => Cmp::Ge,
+                BinaryOpAsn => unreachable!(),
+            };
+            Ok(compare(cmp, l, r, size) as i64)
+        },
+        node => panic!("not generated: {:?}", node),
+    }
+}
+
+// Value of the first `return` reached.
+fn run_node(ast: &Ast, vars: &HashMap<usize, i64>, node: NodeId) -> Result<Option<i64>, Trap> {
+    match &ast[node] {
+        Node::Block { nodes } => {
+            for stmt in nodes.iter() {
+                if let Some(val) = run_node(ast, vars, *stmt)? {
+                    return Ok(Some(val));
+                }
+            }
+            Ok(None)
+        },
+        Node::If { cond, ibody } => {
+            if eval_node(ast, vars, *cond)? != 0 {
+                run_node(ast, vars, *ibody)
+            } else {
+                Ok(None)
+            }
+        },
+        Node::Return { rhs, ty } => Ok(Some(wrap(eval_node(ast, vars, *rhs)?, type_size(ty)))),
+        node => panic!("not generated: {:?}", node),
+    }
+}
+
+// Calls `f` of the program as the parser understands it, or returns the
+// code of the error which the parser reports.
+fn eval_parsed(program: &Program) -> Result<Result<i64, Trap>, &'static str> {
+    let source = program.source();
+    let mut interner = Interner::new();
+    let mut tokens = Tokens::new(tokenize(&source, &mut interner).unwrap());
+    let mut parser = Parser::new(&interner);
+    let ast = parser.program(&mut tokens).map_err(|errors| errors[0].code())?;
+
+    for item in ast.items() {
+        if let Node::Function { name, args, stack: _, block } = &ast[*item] {
+            if interner.resolve(*name) != "f" {
+                continue;
+            }
+            let mut vars = HashMap::new();
+            for (arg, val) in args.iter().zip(program.args.iter()) {
+                if let Node::LocalVariable { offset, ty } = &ast[*arg] {
+                    vars.insert(*offset, wrap(*val, type_size(ty)));
+                }
+            }
+            return Ok(run_node(&ast, &vars, *block).map(|val| val.unwrap()));
+        }
+    }
+    panic!("no function f");
+}
+
+// Output, exit status and terminating signal of the program built at the
+// level.
+fn run_program(program: &Program, level: &str) -> (String, Option<i32>, Option<i32>) {
+    let output_file = TestArtifact::new("");
+    let input_file = output_file.with_suffix(".rs");
+    fs::write(&input_file, program.source()).unwrap();
+
+    let args = vec!["compiler".to_string(),
+                    input_file.to_string(),
+                    "-o".to_string(),
+                    output_file.to_string(),
+                    "--allow=warnings".to_string(),
+                    level.to_string()];
+    assert_eq!(compiler_main(args), 0);
+    let output = Command::new(output_file.path()).output().unwrap();
+    (String::from_utf8(output.stdout).unwrap(), output.status.code(), output.status.signal())
+}
+
+proptest! {
+    #[test]
+    fn parsed_as_generated(program in program()) {
+        let parsed = eval_parsed(&program);
+        if program.exprs().iter().any(|e| e.divides_by_zero()) {
+            prop_assert_eq!(parsed, Err("E0116"));
+        } else {
+            prop_assert_eq!(parsed, Ok(program.eval()));
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn runs_as_parsed(program in program()) {
+        if !env::var("RUN_GENERATED").is_ok_and(|v| v == "1") {
+            return Ok(());
+        }
+        let expect = match eval_parsed(&program) {
+            Ok(Ok(val)) => (format!("{}\n", val), Some(0), None),
+            Ok(Err(Trap)) => (String::new(), None, Some(SIGFPE)),
+            Err(_) => return Ok(()),
+        };
+        for level in ["-O0", "-O1", "-O2"].iter() {
+            prop_assert_eq!(&run_program(&program, level), &expect, "at {}", level);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use yrc::intern::Interner;
+use yrc::token::tokenize;
+use yrc::token::tokenize_with_docs;
+use yrc::token::Span;
+use yrc::token::Tokens;
+use yrc::token::TokenKind;
+use yrc::token::TokenKind::*;
+
+fn tokens(source_code: &str, interner: &mut Interner) -> Tokens {
+    Tokens::new(tokenize(source_code, interner).unwrap())
+}
+
+// Describe a token as "kind:text" to compare token sequences.
+fn describe(kind: &TokenKind, interner: &Interner) -> String {
+    match kind {
+        TokenOp(op) => format!("op:{}", op),
+        TokenNum(num) => format!("num:{}", num),
+        TokenIdt(name) => format!("idt:{}", interner.resolve(*name)),
+        TokenStr(s) => format!("str:{}", interner.resolve(*s)),
+        TokenCStr(s) => format!("cstr:{}", interner.resolve(*s)),
+        TokenRsv(word) => format!("rsv:{}", word),
+        TokenDoc(doc) => format!("doc:{}", doc),
+        TokenEnd => "end".to_string(),
+    }
+}
+
+fn check_kinds(source_code: &str, expect: &[&str]) {
+    let mut interner = Interner::new();
+    let tokens = tokens(source_code, &mut interner);
+    let kinds: Vec<String> = tokens.iter()
+        .map(|(kind, _)| describe(kind, &interner))
+        .collect();
+    assert_eq!(kinds, expect);
+}
+
+#[test]
+fn tokenize_function() {
+    check_kinds("fn main() -> i32 {\n    return 1 + 23;\n}\n",
+                &["rsv:fn", "idt:main", "op:(", "op:)", "op:->", "rsv:i32", "op:{",
+                  "rsv:return", "num:1", "op:+", "num:23", "op:;",
+                  "op:}", "end"]);
+    check_kinds("static a: [u8; 4];",
+                &["rsv:static", "idt:a", "op::", "op:[", "rsv:u8", "op:;",
+                  "num:4", "op:]", "op:;", "end"]);
+    check_kinds("let s: &str; s = \"a\\tb\";",
+                &["rsv:let", "idt:s", "op::", "op:&", "rsv:str", "op:;",
+                  "idt:s", "op:=", "str:a\tb", "op:;", "end"]);
+}
+
+#[test]
+fn tokenize_operators() {
+    check_kinds("a==b!=c<=d>=e<f>g-h->i",
+                &["idt:a", "op:==", "idt:b", "op:!=", "idt:c", "op:<=", "idt:d",
+                  "op:>=", "idt:e", "op:<", "idt:f", "op:>", "idt:g", "op:-",
+                  "idt:h", "op:->", "idt:i", "end"]);
+    check_kinds("a=!b", &["idt:a", "op:=", "op:!", "idt:b", "end"]);
+    check_kinds("a==!b", &["idt:a", "op:==", "op:!", "idt:b", "end"]);
+    check_kinds("a< =b", &["idt:a", "op:<", "op:=", "idt:b", "end"]);
+    check_kinds("a/", &["idt:a", "op:/", "end"]);
+    check_kinds("a%b*c", &["idt:a", "op:%", "idt:b", "op:*", "idt:c", "end"]);
+}
+
+#[test]
+fn tokenize_literals_and_comments() {
+    check_kinds("foo(\"\")",
+                &["idt:foo", "op:(", "str:", "op:)", "end"]);
+    check_kinds("true false 18446744073709551615",
+                &["rsv:true", "rsv:false", "num:18446744073709551615", "end"]);
+    check_kinds("a // comment\n/* block\n comment */ b",
+                &["idt:a", "idt:b", "end"]);
+    check_kinds("", &["end"]);
+    check_kinds("r\"a\\n\" r#\"say \"hi\"\"# r##\"a\"#b\"##",
+                &["str:a\\n", "str:say \"hi\"", "str:a\"#b", "end"]);
+    check_kinds("r = r + br;",
+                &["idt:r", "op:=", "idt:r", "op:+", "idt:br", "op:;", "end"]);
+    check_kinds("c\"a\\n\" c \"\" abc\"\"",
+                &["cstr:a\n\0", "idt:c", "str:", "idt:abc", "str:", "end"]);
+}
+
+#[test]
+fn tokenize_block_comments() {
+    check_kinds("a /**/ b /** x */ c /***/ d /* * / */ e /*/ f */ g",
+                &["idt:a", "idt:b", "idt:c", "idt:d", "idt:e", "idt:g", "end"]);
+    check_kinds("/** doc */fn", &["rsv:fn", "end"]);
+    check_kinds("/// doc\n//// not doc\nfn", &["rsv:fn", "end"]);
+}
+
+#[test]
+fn tokenize_doc_comments() {
+    let mut interner = Interner::new();
+    let tokens = Tokens::new(tokenize_with_docs("/// line\n//// not doc\n/** block */\
+                                                 /**/ /*** not doc */fn f",
+                                                &mut interner).unwrap());
+    let kinds: Vec<String> = tokens.iter()
+        .map(|(kind, _)| describe(kind, &interner))
+        .collect();
+    assert_eq!(kinds, ["doc: line", "doc: block ", "rsv:fn", "idt:f", "end"]);
+    let spans: Vec<Span> = tokens.iter()
+        .map(|(_, span)| span)
+        .take(2)
+        .collect();
+    assert_eq!(spans, [Span::new(0, 8), Span::new(22, 34)]);
+}
+
+#[test]
+fn token_spans() {
+    let mut interner = Interner::new();
+    let tokens = tokens("let abc: i32;\nabc = \"é\";", &mut interner);
+    let spans: Vec<Span> = tokens.iter()
+        .map(|(_, span)| span)
+        .collect();
+    assert_eq!(spans, vec![Span::new(0, 3), Span::new(4, 7), Span::new(7, 8),
+                           Span::new(9, 12), Span::new(12, 13),
+                           Span::new(14, 17), Span::new(18, 19), Span::new(20, 24),
+                           Span::new(24, 25), Span::new(25, 25)]);
+
+    assert_eq!(tokens.len(), 10);
+    let token = tokens.get(1).unwrap();
+    assert_eq!(describe(token.kind(), &interner), "idt:abc");
+    assert_eq!(token.span(), Span::new(4, 7));
+    assert!(tokens.get(10).is_none());
+
+    // Iterating does not consume tokens.
+    assert_eq!((&tokens).into_iter().count(), 10);
+    assert_eq!(tokens.peek(), &TokenRsv("let"));
+}
+
+#[test]
+fn tokenize_errors() {
+    let e = tokenize("a $ b", &mut Interner::new()).unwrap_err();
+    assert_eq!(e.code(), "E0001");
+    assert_eq!(e.span(), Span::new(2, 3));
+
+    let e = tokenize("a = \"abc", &mut Interner::new()).unwrap_err();
+    assert_eq!(e.code(), "E0002");
+    assert_eq!(e.span(), Span::new(4, 5));
+
+    let e = tokenize("a = r#\"abc\"", &mut Interner::new()).unwrap_err();
+    assert_eq!(e.code(), "E0002");
+    assert_eq!(e.span(), Span::new(4, 7));
+
+    let e = tokenize("a /* b", &mut Interner::new()).unwrap_err();
+    assert_eq!(e.code(), "E0003");
+    assert_eq!(e.span(), Span::new(2, 4));
+}
+
+#[test]
+fn intern_names() {
+    let mut interner = Interner::new();
+    let tokens = tokens("abc = \"abc\"; abc = def;", &mut interner);
+    let names: Vec<&TokenKind> = tokens.iter()
+        .map(|(kind, _)| kind)
+        .filter(|kind| matches!(kind, TokenIdt(_) | TokenStr(_)))
+        .collect();
+    assert_eq!(names.len(), 4);
+    // The same text is interned once regardless of the token kind.
+    assert_eq!(names[0], &TokenIdt(interner.intern("abc")));
+    assert_eq!(names[1], &TokenStr(interner.intern("abc")));
+    assert_eq!(names[2], names[0]);
+    assert_ne!(names[3], names[0]);
+    let def = interner.intern("def");
+    assert_eq!(interner.resolve(def), "def");
+}